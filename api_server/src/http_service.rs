@@ -6,6 +6,7 @@ use std::result;
 use std::str;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use futures::future::{self, Either};
 use futures::{Future, Stream};
@@ -13,18 +14,23 @@ use futures::{Future, Stream};
 use hyper::{self, Chunk, Headers, Method, StatusCode};
 use serde_json;
 
-use logger::{Metric, METRICS};
+use logger::openmetrics::OpenMetrics;
+use logger::{Metric, LOGGER, METRICS};
 use mmds::data_store::{self, Mmds};
 use request::actions::ActionBody;
 use request::drive::PatchDrivePayload;
-use request::{GenerateHyperResponse, IntoParsedRequest, ParsedRequest};
+use request::{sync_request, GenerateHyperResponse, IntoParsedRequest, ParsedRequest};
 use sys_util::EventFd;
+use vmm::vmm_config::balloon::{BalloonConfig, BalloonUpdateConfig};
 use vmm::vmm_config::boot_source::BootSourceConfig;
 use vmm::vmm_config::drive::BlockDeviceConfig;
-use vmm::vmm_config::instance_info::InstanceInfo;
-use vmm::vmm_config::logger::LoggerConfig;
+use vmm::vmm_config::instance_info::{InstanceInfo, InstanceState};
+use vmm::vmm_config::logger::{LoggerConfig, LoggerUpdateConfig};
 use vmm::vmm_config::machine_config::VmConfig;
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceUpdateConfig,
+};
+use vmm::vmm_config::snapshot::SnapshotConfig;
 #[cfg(feature = "vsock")]
 use vmm::vmm_config::vsock::VsockDeviceConfig;
 use vmm::VmmAction;
@@ -56,6 +62,61 @@ pub fn json_response<T: Into<hyper::Body>>(status: StatusCode, body: T) -> hyper
     build_response_base(status, Some(headers), Some(body))
 }
 
+// The content type of the metrics, in the OpenMetrics text format.
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// The states that the instance_state gauge is reported for.
+const INSTANCE_STATES: [InstanceState; 6] = [
+    InstanceState::Uninitialized,
+    InstanceState::Starting,
+    InstanceState::Running,
+    InstanceState::Paused,
+    InstanceState::Halting,
+    InstanceState::Halted,
+];
+
+// An HTTP response with the totals of the metrics in the OpenMetrics text format, labeled with
+// the ID of the microVM, and with its state as a gauge that is 1 for the current state.
+fn metrics_response(shared_info: &InstanceInfo) -> hyper::Response {
+    let mut exposition = OpenMetrics::new(&[("instance_id", shared_info.id.as_str())]);
+    if let Err(e) = exposition.add_metrics("firecracker", &*METRICS) {
+        return json_response(
+            StatusCode::InternalServerError,
+            json_fault_message(e.to_string()),
+        );
+    }
+    for state in INSTANCE_STATES.iter() {
+        let name = format!("{:?}", state);
+        let value = u64::from(*state == shared_info.state);
+        exposition.add_gauge("firecracker_instance_state", &[("state", &name)], value);
+    }
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", OPENMETRICS_CONTENT_TYPE);
+    build_response_base(StatusCode::Ok, Some(headers), Some(exposition.to_string()))
+}
+
+// An HTTP response with the lines kept in a ring buffer by the logger, if `name` (the logs or
+// the metrics) are kept in one.
+fn buffered_lines_response(name: &str, lines: Option<Vec<String>>) -> hyper::Response {
+    let lines = match lines {
+        Some(lines) => lines,
+        None => {
+            return json_response(
+                StatusCode::BadRequest,
+                json_fault_message(format!("The {} are not kept in a ring buffer.", name)),
+            )
+        }
+    };
+    match serde_json::to_string(&lines) {
+        Ok(body) => json_response(StatusCode::Ok, body),
+        Err(e) => json_response(
+            StatusCode::InternalServerError,
+            json_fault_message(e.to_string()),
+        ),
+    }
+}
+
 // Builds a string that looks like (where $ stands for substitution):
 //  {
 //    "$k": "$v"
@@ -150,6 +211,45 @@ fn checked_id(id: &str) -> Result<&str> {
     Ok(id)
 }
 
+// Turns a GET/PUT/PATCH /balloon HTTP request into a ParsedRequest
+fn parse_balloon_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.balloon_count.inc();
+            Ok(sync_request(VmmAction::GetBalloonConfig))
+        }
+        0 if method == Method::Put => {
+            METRICS.put_api_requests.balloon_count.inc();
+            Ok(serde_json::from_slice::<BalloonConfig>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.balloon_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.balloon_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
+        0 if method == Method::Patch => {
+            METRICS.patch_api_requests.balloon_count.inc();
+            Ok(serde_json::from_slice::<BalloonUpdateConfig>(body)
+                .map_err(|e| {
+                    METRICS.patch_api_requests.balloon_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.patch_api_requests.balloon_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
 // Turns a GET/PUT /boot-source HTTP request into a ParsedRequest
 fn parse_boot_source_req<'a>(
     path: &'a str,
@@ -159,6 +259,10 @@ fn parse_boot_source_req<'a>(
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
 
     match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.boot_source_count.inc();
+            Ok(sync_request(VmmAction::GetBootSource))
+        }
         0 if method == Method::Put => {
             METRICS.put_api_requests.boot_source_count.inc();
             Ok(serde_json::from_slice::<BootSourceConfig>(body)
@@ -210,6 +314,12 @@ fn parse_drives_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     };
 
     match path_tokens[1..].len() {
+        1 if method == Method::Get => {
+            METRICS.get_api_requests.drive_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::GetBlockDevice(id_from_path.to_string(), sender)
+            }))
+        }
         1 if method == Method::Put => {
             METRICS.put_api_requests.drive_count.inc();
 
@@ -241,6 +351,13 @@ fn parse_drives_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
             })?)
         }
 
+        1 if method == Method::Delete => {
+            METRICS.delete_api_requests.drive_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::RemoveBlockDevice(id_from_path.to_string(), sender)
+            }))
+        }
+
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -250,6 +367,18 @@ fn parse_logger_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
 
     match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.logger_count.inc();
+            Ok(sync_request(VmmAction::GetLoggerConfiguration))
+        }
+        1 if method == Method::Get && path_tokens[1] == "logs" => {
+            METRICS.get_api_requests.logger_count.inc();
+            Ok(ParsedRequest::GetBufferedLogs)
+        }
+        1 if method == Method::Get && path_tokens[1] == "metrics" => {
+            METRICS.get_api_requests.logger_count.inc();
+            Ok(ParsedRequest::GetBufferedMetrics)
+        }
         0 if method == Method::Put => {
             METRICS.put_api_requests.logger_count.inc();
             Ok(serde_json::from_slice::<LoggerConfig>(body)
@@ -263,6 +392,19 @@ fn parse_logger_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
                     Error::Generic(StatusCode::BadRequest, s)
                 })?)
         }
+        0 if method == Method::Patch => {
+            METRICS.patch_api_requests.logger_count.inc();
+            Ok(serde_json::from_slice::<LoggerUpdateConfig>(body)
+                .map_err(|e| {
+                    METRICS.patch_api_requests.logger_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.patch_api_requests.logger_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -283,6 +425,7 @@ fn parse_machine_config_req<'a>(
                 mem_size_mib: None,
                 ht_enabled: None,
                 cpu_template: None,
+                hotplug_slots: None,
             };
             Ok(empty_machine_config
                 .into_parsed_request(None, method)
@@ -333,6 +476,12 @@ fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a
     };
 
     match path_tokens[1..].len() {
+        1 if method == Method::Get => {
+            METRICS.get_api_requests.network_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::GetNetworkInterface(id_from_path.to_string(), sender)
+            }))
+        }
         1 if method == Method::Put => {
             METRICS.put_api_requests.network_count.inc();
 
@@ -361,6 +510,84 @@ fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a
                     Error::Generic(StatusCode::BadRequest, s)
                 })?)
         }
+        1 if method == Method::Delete => {
+            METRICS.delete_api_requests.network_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::RemoveNetworkInterface(id_from_path.to_string(), sender)
+            }))
+        }
+        2 if path_tokens[2] == "capture" && method == Method::Put => {
+            METRICS.put_api_requests.network_count.inc();
+
+            Ok(serde_json::from_slice::<NetworkCaptureConfig>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.network_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(Some(id_from_path.to_string()), method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.network_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
+        2 if path_tokens[2] == "capture" && method == Method::Delete => {
+            METRICS.delete_api_requests.network_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::StopNetworkCapture(id_from_path.to_string(), sender)
+            }))
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a PUT /snapshot/create or /snapshot/load HTTP request into a ParsedRequest
+fn parse_snapshot_req<'a>(
+    path: &'a str,
+    method: Method,
+    body: &Chunk,
+) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        1 if method == Method::Put && (path_tokens[1] == "create" || path_tokens[1] == "load") => {
+            METRICS.put_api_requests.snapshot_count.inc();
+            Ok(serde_json::from_slice::<SnapshotConfig>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.snapshot_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(Some(path_tokens[1].to_string()), method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.snapshot_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /metrics HTTP request into a ParsedRequest.
+fn parse_metrics_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.metrics_count.inc();
+            Ok(ParsedRequest::GetMetrics)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /vm/config HTTP request into a ParsedRequest
+fn parse_vm_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        1 if method == Method::Get && path_tokens[1] == "config" => {
+            METRICS.get_api_requests.vm_config_count.inc();
+            Ok(sync_request(VmmAction::GetMicrovmConfiguration))
+        }
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -376,6 +603,12 @@ fn parse_vsocks_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     };
 
     match path_tokens[1..].len() {
+        1 if method == Method::Get => {
+            METRICS.get_api_requests.vsock_count.inc();
+            Ok(sync_request(|sender| {
+                VmmAction::GetVsockDevice(id_from_path.to_string(), sender)
+            }))
+        }
         1 if method == Method::Put => Ok(serde_json::from_slice::<VsockDeviceConfig>(body)
             .map_err(Error::SerdeJson)?
             .into_parsed_request(Some(id_from_path.to_string()), method)
@@ -426,12 +659,16 @@ fn parse_request<'a>(method: Method, path: &'a str, body: &Chunk) -> Result<'a,
 
     match path_tokens[0] {
         "actions" => parse_actions_req(path, method, body),
+        "balloon" => parse_balloon_req(path, method, body),
         "boot-source" => parse_boot_source_req(path, method, body),
         "drives" => parse_drives_req(path, method, body),
         "logger" => parse_logger_req(path, method, body),
         "machine-config" => parse_machine_config_req(path, method, body),
+        "metrics" => parse_metrics_req(path, method),
         "network-interfaces" => parse_netif_req(path, method, body),
         "mmds" => parse_mmds_request(path, method, body),
+        "snapshot" => parse_snapshot_req(path, method, body),
+        "vm" => parse_vm_req(path, method),
         #[cfg(feature = "vsock")]
         "vsocks" => parse_vsocks_req(path, method, body),
         _ => Err(Error::InvalidPathMethod(path, method)),
@@ -498,6 +735,7 @@ impl hyper::server::Service for ApiServerHttpService {
         let shared_info_lock = self.vmm_shared_info.clone();
         let api_request_sender = self.api_request_sender.clone();
         let vmm_send_event = self.vmm_send_event.clone();
+        let received_at = Instant::now();
 
         // for nice looking match arms
         use request::ParsedRequest::*;
@@ -505,7 +743,7 @@ impl hyper::server::Service for ApiServerHttpService {
         // The request body is itself a future (a stream of Chunks to be more precise),
         // so we have to define a future that waits for all the pieces first (via concat2),
         // and then does something with the newly available body (via and_then).
-        Box::new(req.body().concat2().and_then(move |b| {
+        let response = req.body().concat2().and_then(move |b| {
             // When this will be executed, the body is available. We start by parsing the request.
             match parse_request(method, path.as_ref(), &b) {
                 Ok(parsed_req) => match parsed_req {
@@ -530,6 +768,23 @@ impl hyper::server::Service for ApiServerHttpService {
                             }
                         }
                     }
+                    GetBufferedLogs => {
+                        log_received_api_request(describe(&method_copy, &path, &None));
+                        let lines = LOGGER.buffered_logs();
+                        Either::A(future::ok(buffered_lines_response("logs", lines)))
+                    }
+                    GetBufferedMetrics => {
+                        log_received_api_request(describe(&method_copy, &path, &None));
+                        let lines = LOGGER.buffered_metrics();
+                        Either::A(future::ok(buffered_lines_response("metrics", lines)))
+                    }
+                    GetMetrics => {
+                        log_received_api_request(describe(&method_copy, &path, &None));
+                        let shared_info = shared_info_lock
+                            .read()
+                            .expect("Failed to read shared_info due to poisoned lock");
+                        Either::A(future::ok(metrics_response(&shared_info)))
+                    }
                     PatchMMDS(json_value) => {
                         // Requests on /mmds should not have the body in the logs as the data
                         // store contains customer data.
@@ -591,7 +846,7 @@ impl hyper::server::Service for ApiServerHttpService {
                         // metric-logging related variables for being able to log response details
                         let path_copy = path.clone();
                         let body_desc = match method_copy {
-                            Method::Get => None,
+                            Method::Get | Method::Delete => None,
                             _ => Some(String::from_utf8_lossy(&b.to_vec()).to_string()),
                         };
 
@@ -643,6 +898,15 @@ impl hyper::server::Service for ApiServerHttpService {
                 },
                 Err(e) => Either::A(future::ok(e.into())),
             }
+        });
+
+        // The handling time covers the failed requests as well.
+        Box::new(response.then(move |response| {
+            METRICS
+                .api_server
+                .request_latency_us
+                .record(received_at.elapsed());
+            response
         }))
     }
 }
@@ -690,8 +954,10 @@ mod tests {
     use futures::sync::oneshot;
     use hyper::header::{ContentType, Headers};
     use hyper::Body;
-    use vmm::vmm_config::logger::LoggerLevel;
+    use vmm::vmm_config::drive::{CacheType, DiskImageFormat, IoEngine};
+    use vmm::vmm_config::logger::{LoggerFormat, LoggerLevel};
     use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::net::FilterAction;
     use vmm::VmmAction;
 
     impl<'a> PartialEq for Error<'a> {
@@ -932,6 +1198,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_balloon_req() {
+        let json = r#"{
+                "amount_mib": 64,
+                "deflate_on_oom": true,
+                "stats_polling_interval_s": 5
+              }"#;
+        let body: Chunk = Chunk::from(json);
+
+        // GET
+        match parse_balloon_req("/balloon", Method::Get, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetBalloonConfig(sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // PUT
+        match parse_balloon_req("/balloon", Method::Put, &body) {
+            Ok(pr) => {
+                let balloon_cfg = BalloonConfig {
+                    amount_mib: 64,
+                    deflate_on_oom: true,
+                    stats_polling_interval_s: 5,
+                };
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::InsertBalloonDevice(balloon_cfg, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // PATCH
+        match parse_balloon_req(
+            "/balloon",
+            Method::Patch,
+            &Chunk::from(r#"{"amount_mib": 32}"#),
+        ) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 32 }, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // Error cases
+        // Test case for invalid path.
+        let expected_err = Error::InvalidPathMethod("/balloon/foo", Method::Get);
+        assert!(parse_balloon_req("/balloon/foo", Method::Get, &body) == Err(expected_err));
+
+        // Test case for invalid method.
+        let expected_err = Error::InvalidPathMethod("/balloon", Method::Delete);
+        assert!(parse_balloon_req("/balloon", Method::Delete, &body) == Err(expected_err));
+
+        // Test cases for invalid bodies (serde errors).
+        assert!(
+            parse_balloon_req("/balloon", Method::Put, &Chunk::from("foo"))
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+        assert!(
+            parse_balloon_req("/balloon", Method::Patch, &body)
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+
+        // The balloon requests are routed by parse_request.
+        assert!(parse_request(Method::Get, "/balloon", &body).is_ok());
+    }
+
     #[test]
     fn test_parse_boot_source_req() {
         let boot_source_path = "/boot-source";
@@ -966,10 +1309,22 @@ mod tests {
                 == Err(expected_err)
         );
 
-        // Test case for invalid method (GET).
-        let expected_err = Error::InvalidPathMethod(boot_source_path, Method::Get);
+        // GET
+        match parse_boot_source_req(boot_source_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetBootSource(sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // Test case for invalid method (PATCH).
+        let expected_err = Error::InvalidPathMethod(boot_source_path, Method::Patch);
         assert!(
-            parse_boot_source_req(boot_source_path, Method::Get, &Chunk::from("{}"))
+            parse_boot_source_req(boot_source_path, Method::Patch, &Chunk::from("{}"))
                 == Err(expected_err)
         );
 
@@ -983,6 +1338,20 @@ mod tests {
     #[test]
     fn test_parse_drives_req() {
         let valid_drive_path = "/drives/id_1";
+
+        // GET
+        match parse_drives_req(valid_drive_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetBlockDevice(String::from("id_1"), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        assert!(parse_drives_req("/drives", Method::Get, &Chunk::from("")) == Err(Error::EmptyID));
+
         let json = "{
                 \"drive_id\": \"id_1\",
                 \"path_on_host\": \"/foo/bar\",
@@ -999,6 +1368,12 @@ mod tests {
             partuuid: None,
             is_read_only: true,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         match drive_desc.into_parsed_request(Some(String::from("id_1")), Method::Put) {
@@ -1032,6 +1407,21 @@ mod tests {
         let expected_error = Err(Error::InvalidPathMethod(path, Method::Put));
         assert!(parse_drives_req(path, Method::Put, &body) == expected_error);
 
+        // DELETE
+        match parse_drives_req(valid_drive_path, Method::Delete, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::RemoveBlockDevice(String::from("id_1"), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        assert!(
+            parse_drives_req("/drives", Method::Delete, &Chunk::from("")) == Err(Error::EmptyID)
+        );
+
         // PATCH
         let json = r#"{
                 "drive_id": "id_1",
@@ -1104,13 +1494,15 @@ mod tests {
         assert_eq!(logger_config.level, LoggerLevel::Warning);
         assert_eq!(logger_config.show_log_origin, false);
         assert_eq!(logger_config.show_level, false);
+        assert_eq!(logger_config.format, LoggerFormat::Text);
 
         let json = "{
                 \"log_fifo\": \"tmp1\",
                 \"metrics_fifo\": \"tmp2\",
                 \"level\": \"Info\",
                 \"show_level\": true,
-                \"show_log_origin\": true
+                \"show_log_origin\": true,
+                \"format\": \"Json\"
               }";
         let logger_body: Chunk = Chunk::from(json);
 
@@ -1138,6 +1530,72 @@ mod tests {
         // Error Case: Invalid path.
         let expected_err = Err(Error::InvalidPathMethod("/foo/bar", Method::Put));
         assert!(parse_logger_req(&"/foo/bar", Method::Put, &Chunk::from("foo")) == expected_err);
+
+        // GET
+        match parse_logger_req(logger_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetLoggerConfiguration(sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // GET the ring buffers.
+        match parse_logger_req("/logger/logs", Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetBufferedLogs)),
+            _ => assert!(false),
+        }
+        match parse_logger_req("/logger/metrics", Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetBufferedMetrics)),
+            _ => assert!(false),
+        }
+        let expected_err = Err(Error::InvalidPathMethod("/logger/logs", Method::Put));
+        assert!(parse_logger_req("/logger/logs", Method::Put, &Chunk::from("")) == expected_err);
+        let expected_err = Err(Error::InvalidPathMethod("/logger/foo", Method::Get));
+        assert!(parse_logger_req("/logger/foo", Method::Get, &Chunk::from("")) == expected_err);
+
+        // PATCH
+        let logger_body = Chunk::from(r#"{"level": "Debug", "show_level": true}"#);
+        let logger_update = LoggerUpdateConfig {
+            level: Some(LoggerLevel::Debug),
+            show_level: Some(true),
+            ..Default::default()
+        };
+        match parse_logger_req(logger_path, Method::Patch, &logger_body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::UpdateLogger(logger_update, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        // Error Case: the log destination cannot be changed.
+        assert!(
+            parse_logger_req(
+                logger_path,
+                Method::Patch,
+                &Chunk::from(r#"{"log_fifo": "tmp1"}"#)
+            ) == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+    }
+
+    #[test]
+    fn test_buffered_lines_response() {
+        let resp = buffered_lines_response("logs", Some(vec![String::from("a \"line\"")]));
+        assert_eq!(resp.status(), StatusCode::Ok);
+        assert_eq!(body_to_string(resp.body()), "[\"a \\\"line\\\"\"]");
+
+        let resp = buffered_lines_response("logs", None);
+        assert_eq!(resp.status(), StatusCode::BadRequest);
+        assert_eq!(
+            body_to_string(resp.body()),
+            json_fault_message("The logs are not kept in a ring buffer.")
+        );
     }
 
     #[test]
@@ -1165,6 +1623,7 @@ mod tests {
             mem_size_mib: Some(1025),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
 
         match vm_config.into_parsed_request(None, Method::Put) {
@@ -1181,6 +1640,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            hotplug_slots: None,
         };
         let body = r#"{
             "vcpu_count": 32
@@ -1237,6 +1697,23 @@ mod tests {
         assert!(parse_machine_config_req(path, Method::Put, &body) == expected_err);
     }
 
+    #[cfg(feature = "vsock")]
+    #[test]
+    fn test_parse_vsocks_req() {
+        let get_count = METRICS.get_api_requests.vsock_count.count();
+        match parse_vsocks_req("/vsocks/id_1", Method::Get, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetVsockDevice(String::from("id_1"), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        assert!(METRICS.get_api_requests.vsock_count.count() > get_count);
+    }
+
     #[test]
     fn test_parse_netif_req() {
         let path = "/network-interfaces/id_1";
@@ -1256,9 +1733,39 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
 
+        // GET
+        match parse_netif_req(&path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetNetworkInterface(net_id.clone(), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // DELETE
+        match parse_netif_req(&path, Method::Delete, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::RemoveNetworkInterface(net_id.clone(), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
         match netif.into_parsed_request(Some(net_id), Method::Put) {
             Ok(pr) => match parse_netif_req(&path, Method::Put, &body) {
                 Ok(pr_netif) => assert!(pr.eq(&pr_netif)),
@@ -1296,6 +1803,8 @@ mod tests {
             iface_id: "1".to_string(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            ingress_filter: None,
+            egress_filter: None,
         }
         .into_parsed_request(Some("2".to_string()), Method::Patch)
         .is_err());
@@ -1319,6 +1828,37 @@ mod tests {
             _ => assert!(false),
         };
 
+        let json = r#"{
+            "iface_id": "1",
+            "ingress_filter": {
+                "default_policy": "Deny",
+                "rules": [
+                    {
+                        "action": "Allow",
+                        "protocol": "Tcp",
+                        "cidr": "10.0.0.0/8",
+                        "ports": {
+                            "start": 22,
+                            "end": 22
+                        }
+                    }
+                ]
+            }
+        }"#;
+        let body = Chunk::from(json);
+        let nuc = serde_json::from_slice::<NetworkInterfaceUpdateConfig>(json.as_bytes()).unwrap();
+        let filter = nuc.ingress_filter.clone().unwrap();
+        assert_eq!(filter.default_policy, FilterAction::Deny);
+        assert_eq!(filter.rules[0].cidr.unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(nuc.egress_filter, None);
+        let nuc_pr = nuc
+            .into_parsed_request(Some("1".to_string()), Method::Patch)
+            .unwrap();
+        match parse_netif_req(&"/network-interfaces/1", Method::Patch, &body) {
+            Ok(pr) => assert!(nuc_pr.eq(&pr)),
+            _ => assert!(false),
+        };
+
         let json = r#"{
             "iface_id": "1",
             "invalid_key": true
@@ -1331,6 +1871,43 @@ mod tests {
         }"#;
         let body = Chunk::from(json);
         assert!(parse_netif_req(&"/network-interfaces/2", Method::Patch, &body).is_err());
+
+        // Capture tests
+        let path = "/network-interfaces/1/capture";
+        let json = r#"{
+            "path": "/tmp/1.pcap",
+            "max_size": 1048576,
+            "max_files": 2
+        }"#;
+        let body = Chunk::from(json);
+        let capture_cfg = NetworkCaptureConfig {
+            path: String::from("/tmp/1.pcap"),
+            max_size: 1_048_576,
+            max_files: 2,
+        };
+        match parse_netif_req(path, Method::Put, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::StartNetworkCapture("1".to_string(), capture_cfg, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        match parse_netif_req(path, Method::Delete, &Chunk::from("")) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::StopNetworkCapture("1".to_string(), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        assert!(parse_netif_req(path, Method::Put, &Chunk::from("{}")).is_err());
+        assert!(parse_netif_req(path, Method::Get, &body).is_err());
+        assert!(parse_netif_req(&"/network-interfaces/1/foo", Method::Put, &body).is_err());
     }
 
     #[test]
@@ -1395,6 +1972,128 @@ mod tests {
         assert!(parse_mmds_request(path, Method::Get, &body) == expected_err);
     }
 
+    #[test]
+    fn test_parse_snapshot_req() {
+        let json = r#"{
+                "snapshot_path": "/foo/snapshot",
+                "mem_file_path": "/foo/mem"
+              }"#;
+        let body: Chunk = Chunk::from(json);
+        let snapshot_cfg = SnapshotConfig {
+            snapshot_path: String::from("/foo/snapshot"),
+            mem_file_path: String::from("/foo/mem"),
+        };
+
+        // PUT /snapshot/create
+        match parse_snapshot_req("/snapshot/create", Method::Put, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::CreateSnapshot(snapshot_cfg.clone(), sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // PUT /snapshot/load
+        match parse_snapshot_req("/snapshot/load", Method::Put, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::LoadSnapshot(snapshot_cfg, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // Error cases
+        // Test cases for invalid paths.
+        for path in &["/snapshot", "/snapshot/foo", "/snapshot/create/foo"] {
+            let expected_err = Error::InvalidPathMethod(path, Method::Put);
+            assert!(parse_snapshot_req(path, Method::Put, &body) == Err(expected_err));
+        }
+
+        // Test case for invalid method (GET).
+        let expected_err = Error::InvalidPathMethod("/snapshot/create", Method::Get);
+        assert!(parse_snapshot_req("/snapshot/create", Method::Get, &body) == Err(expected_err));
+
+        // Test case for invalid body (serde error).
+        assert!(
+            parse_snapshot_req("/snapshot/load", Method::Put, &Chunk::from("foo"))
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+
+        // The snapshot requests are routed by parse_request.
+        assert!(parse_request(Method::Put, "/snapshot/create", &body).is_ok());
+    }
+
+    #[test]
+    fn test_parse_metrics_req() {
+        match parse_metrics_req("/metrics", Method::Get) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetMetrics)),
+            _ => assert!(false),
+        }
+        assert!(parse_request(Method::Get, "/metrics", &Chunk::from("")).is_ok());
+
+        // Error cases
+        let expected_err = Error::InvalidPathMethod("/metrics/foo", Method::Get);
+        assert!(parse_metrics_req("/metrics/foo", Method::Get) == Err(expected_err));
+        let expected_err = Error::InvalidPathMethod("/metrics", Method::Put);
+        assert!(parse_metrics_req("/metrics", Method::Put) == Err(expected_err));
+    }
+
+    #[test]
+    fn test_metrics_response() {
+        let shared_info = InstanceInfo {
+            id: String::from("vm0"),
+            state: InstanceState::Paused,
+            vmm_version: String::from("0.1"),
+        };
+        METRICS.get_api_requests.instance_info_count.inc();
+        let resp = metrics_response(&shared_info);
+        assert_eq!(resp.status(), StatusCode::Ok);
+        assert_eq!(
+            resp.headers().get_raw("Content-Type").unwrap().one(),
+            Some(OPENMETRICS_CONTENT_TYPE.as_bytes())
+        );
+
+        let body = body_to_string(resp.body());
+        assert!(body.contains("# TYPE firecracker_get_api_requests_instance_info_count counter\n"));
+        assert!(body.contains("# TYPE firecracker_instance_state gauge\n"));
+        assert!(
+            body.contains("firecracker_instance_state{instance_id=\"vm0\",state=\"Paused\"} 1\n")
+        );
+        assert!(
+            body.contains("firecracker_instance_state{instance_id=\"vm0\",state=\"Running\"} 0\n")
+        );
+        assert!(body.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_parse_vm_req() {
+        match parse_vm_req("/vm/config", Method::Get) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::GetMicrovmConfiguration(sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+        assert!(parse_request(Method::Get, "/vm/config", &Chunk::from("")).is_ok());
+
+        // Error cases
+        for path in &["/vm", "/vm/foo", "/vm/config/foo"] {
+            let expected_err = Error::InvalidPathMethod(path, Method::Get);
+            assert!(parse_vm_req(path, Method::Get) == Err(expected_err));
+        }
+        let expected_err = Error::InvalidPathMethod("/vm/config", Method::Put);
+        assert!(parse_vm_req("/vm/config", Method::Put) == Err(expected_err));
+    }
+
     #[test]
     fn test_parse_request() {
         let body: Chunk = Chunk::from("{ \"foo\": \"bar\" }");
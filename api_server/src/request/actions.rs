@@ -18,6 +18,8 @@ enum ActionType {
     BlockDeviceRescan,
     FlushMetrics,
     InstanceStart,
+    Pause,
+    Resume,
     SendCtrlAltDel,
 }
 
@@ -48,8 +50,12 @@ fn validate_payload(action_body: &ActionBody) -> Result<(), String> {
                 None => Err("Payload is required for block device rescan.".to_string()),
             }
         }
-        ActionType::FlushMetrics | ActionType::InstanceStart | ActionType::SendCtrlAltDel => {
-            // Neither FlushMetrics nor InstanceStart should have a payload.
+        ActionType::FlushMetrics
+        | ActionType::InstanceStart
+        | ActionType::Pause
+        | ActionType::Resume
+        | ActionType::SendCtrlAltDel => {
+            // None of these actions should have a payload.
             if action_body.payload.is_some() {
                 return Err(format!(
                     "{:?} does not support a payload.",
@@ -92,6 +98,20 @@ impl IntoParsedRequest for ActionBody {
                     sync_receiver,
                 ))
             }
+            ActionType::Pause => {
+                let (sync_sender, sync_receiver) = oneshot::channel();
+                Ok(ParsedRequest::Sync(
+                    VmmAction::PauseVcpus(sync_sender),
+                    sync_receiver,
+                ))
+            }
+            ActionType::Resume => {
+                let (sync_sender, sync_receiver) = oneshot::channel();
+                Ok(ParsedRequest::Sync(
+                    VmmAction::ResumeVcpus(sync_sender),
+                    sync_receiver,
+                ))
+            }
             ActionType::SendCtrlAltDel => {
                 let (sync_sender, sync_receiver) = oneshot::channel();
                 Ok(ParsedRequest::Sync(
@@ -170,6 +190,21 @@ mod tests {
             payload: Some(Value::String("dummy-payload".to_string())),
         };
         assert!(validate_payload(&action_body).is_err());
+
+        // Test Pause and Resume.
+        for action_type in vec![ActionType::Pause, ActionType::Resume] {
+            let action_body = ActionBody {
+                action_type: action_type.clone(),
+                payload: None,
+            };
+            assert!(validate_payload(&action_body).is_ok());
+            // Error case: payload is not supported.
+            let action_body = ActionBody {
+                action_type,
+                payload: Some(Value::String("dummy-payload".to_string())),
+            };
+            assert!(validate_payload(&action_body).is_err());
+        }
     }
 
     #[test]
@@ -227,6 +262,38 @@ mod tests {
                 .eq(&req));
         }
 
+        {
+            let json = r#"{
+                "action_type": "Pause"
+            }"#;
+
+            let (sender, receiver) = oneshot::channel();
+            let req: ParsedRequest = ParsedRequest::Sync(VmmAction::PauseVcpus(sender), receiver);
+            let result: Result<ActionBody, serde_json::Error> = serde_json::from_str(json);
+            assert!(result.is_ok());
+            assert!(result
+                .unwrap()
+                .into_parsed_request(None, Method::Put)
+                .unwrap()
+                .eq(&req));
+        }
+
+        {
+            let json = r#"{
+                "action_type": "Resume"
+            }"#;
+
+            let (sender, receiver) = oneshot::channel();
+            let req: ParsedRequest = ParsedRequest::Sync(VmmAction::ResumeVcpus(sender), receiver);
+            let result: Result<ActionBody, serde_json::Error> = serde_json::from_str(json);
+            assert!(result.is_ok());
+            assert!(result
+                .unwrap()
+                .into_parsed_request(None, Method::Put)
+                .unwrap()
+                .eq(&req));
+        }
+
         {
             let json = r#"{
                 "action_type": "FlushMetrics"
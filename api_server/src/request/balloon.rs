@@ -0,0 +1,71 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use futures::sync::oneshot;
+use hyper::Method;
+
+use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::balloon::{BalloonConfig, BalloonUpdateConfig};
+use vmm::VmmAction;
+
+impl IntoParsedRequest for BalloonConfig {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::InsertBalloonDevice(self, sender),
+            receiver,
+        ))
+    }
+}
+
+impl IntoParsedRequest for BalloonUpdateConfig {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::UpdateBalloon(self, sender),
+            receiver,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_parsed_request() {
+        let body = BalloonConfig {
+            amount_mib: 64,
+            deflate_on_oom: true,
+            stats_polling_interval_s: 5,
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(body
+            .clone()
+            .into_parsed_request(None, Method::Put)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::InsertBalloonDevice(body, sender),
+                receiver
+            ))));
+
+        let body = BalloonUpdateConfig { amount_mib: 32 };
+        let (sender, receiver) = oneshot::channel();
+        assert!(body
+            .clone()
+            .into_parsed_request(None, Method::Patch)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::UpdateBalloon(body, sender),
+                receiver
+            ))));
+    }
+}
@@ -134,6 +134,8 @@ mod tests {
     use serde_json::Number;
     use std::path::PathBuf;
 
+    use vmm::vmm_config::drive::{CacheType, DiskImageFormat, IoEngine};
+
     #[test]
     fn test_patch_into_parsed_request() {
         // PATCH with invalid fields.
@@ -255,6 +257,12 @@ mod tests {
             is_read_only: true,
             partuuid: None,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(
             desc.into_parsed_request(Some(String::from("foo")), Method::Options)
@@ -269,6 +277,12 @@ mod tests {
             is_read_only: true,
             partuuid: None,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         let same_desc = BlockDeviceConfig {
             drive_id: String::from("foo"),
@@ -277,6 +291,12 @@ mod tests {
             is_read_only: true,
             partuuid: None,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         let (sender, receiver) = oneshot::channel();
         assert!(desc
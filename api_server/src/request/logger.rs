@@ -7,7 +7,7 @@ use futures::sync::oneshot;
 use hyper::Method;
 
 use request::{IntoParsedRequest, ParsedRequest};
-use vmm::vmm_config::logger::LoggerConfig;
+use vmm::vmm_config::logger::{LoggerConfig, LoggerUpdateConfig};
 use vmm::VmmAction;
 
 impl IntoParsedRequest for LoggerConfig {
@@ -24,22 +24,39 @@ impl IntoParsedRequest for LoggerConfig {
     }
 }
 
+impl IntoParsedRequest for LoggerUpdateConfig {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::UpdateLogger(self, sender),
+            receiver,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[cfg(target_arch = "x86_64")]
     use serde_json::Value;
-    use vmm::vmm_config::logger::LoggerLevel;
+    use vmm::vmm_config::logger::{LoggerFormat, LoggerLevel};
 
     #[test]
     fn test_into_parsed_request() {
         let desc = LoggerConfig {
-            log_fifo: String::from("log"),
-            metrics_fifo: String::from("metrics"),
+            log_fifo: Some(String::from("log")),
+            metrics_fifo: Some(String::from("metrics")),
+            log_destination: None,
+            metrics_destination: None,
             level: LoggerLevel::Warning,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Text,
             #[cfg(target_arch = "x86_64")]
             options: Value::Array(vec![]),
         };
@@ -53,5 +70,18 @@ mod tests {
                 VmmAction::ConfigureLogger(desc, sender),
                 receiver
             ))));
+
+        let update = LoggerUpdateConfig {
+            level: Some(LoggerLevel::Debug),
+            ..Default::default()
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(update
+            .clone()
+            .into_parsed_request(None, Method::Patch)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::UpdateLogger(update, sender),
+                receiver
+            ))));
     }
 }
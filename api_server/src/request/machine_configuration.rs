@@ -47,6 +47,7 @@ impl IntoParsedRequest for VmConfig {
                     && self.mem_size_mib.is_none()
                     && self.cpu_template.is_none()
                     && self.ht_enabled.is_none()
+                    && self.hotplug_slots.is_none()
                 {
                     return Err(String::from("Empty PATCH request."));
                 }
@@ -88,6 +89,7 @@ mod tests {
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
         let (sender, receiver) = oneshot::channel();
         assert!(body
@@ -103,6 +105,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            hotplug_slots: None,
         };
         assert!(uninitialized
             .clone()
@@ -121,6 +124,7 @@ mod tests {
             mem_size_mib: Some(1024),
             ht_enabled: None,
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
         match body.into_parsed_request(None, Method::Put) {
             Ok(_) => assert!(false),
@@ -2,32 +2,46 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod actions;
+pub mod balloon;
 pub mod boot_source;
 pub mod drive;
 pub mod logger;
 pub mod machine_configuration;
 pub mod net;
+pub mod snapshot;
 #[cfg(feature = "vsock")]
 pub mod vsock;
 
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{self, Value};
 use std::result;
 
+use futures::sync::oneshot;
 use hyper;
 use hyper::{Method, StatusCode};
 
 use http_service::{empty_response, json_fault_message, json_response};
-use vmm::{ErrorKind, OutcomeReceiver, VmmAction, VmmActionError, VmmData};
+use vmm::{ErrorKind, OutcomeReceiver, OutcomeSender, VmmAction, VmmActionError, VmmData};
 
 #[allow(clippy::large_enum_variant)]
 pub enum ParsedRequest {
+    GetBufferedLogs,
+    GetBufferedMetrics,
     GetInstanceInfo,
+    GetMetrics,
     GetMMDS,
     PatchMMDS(Value),
     PutMMDS(Value),
     Sync(VmmAction, OutcomeReceiver),
 }
 
+// Builds a synchronous request for an action which carries no data besides the outcome sender,
+// such as the GET requests.
+pub fn sync_request<F: FnOnce(OutcomeSender) -> VmmAction>(action: F) -> ParsedRequest {
+    let (sender, receiver) = oneshot::channel();
+    ParsedRequest::Sync(action(sender), receiver)
+}
+
 pub trait IntoParsedRequest {
     fn into_parsed_request(
         self,
@@ -56,12 +70,33 @@ impl GenerateHyperResponse for result::Result<VmmData, VmmActionError> {
 impl GenerateHyperResponse for VmmData {
     fn generate_response(&self) -> hyper::Response {
         match *self {
+            VmmData::BalloonConfig(ref balloon) => serialized_response(balloon),
+            VmmData::BlockDevice(ref drive) => serialized_response(drive),
+            VmmData::BootSource(ref boot_source) => serialized_response(boot_source),
+            VmmData::Logger(ref logger) => serialized_response(logger),
             VmmData::MachineConfiguration(ref machine_config) => machine_config.generate_response(),
+            VmmData::MicrovmConfiguration(ref microvm_config) => {
+                serialized_response(microvm_config)
+            }
+            VmmData::NetworkInterface(ref iface) => serialized_response(iface),
+            #[cfg(feature = "vsock")]
+            VmmData::Vsock(ref vsock) => serialized_response(vsock),
             VmmData::Empty => empty_response(StatusCode::NoContent),
         }
     }
 }
 
+// An HTTP 200 response holding the JSON representation of `data`.
+fn serialized_response<T: Serialize>(data: &T) -> hyper::Response {
+    match serde_json::to_string(data) {
+        Ok(body) => json_response(StatusCode::Ok, body),
+        Err(e) => json_response(
+            StatusCode::InternalServerError,
+            json_fault_message(e.to_string()),
+        ),
+    }
+}
+
 impl GenerateHyperResponse for VmmActionError {
     fn generate_response(&self) -> hyper::Response {
         use self::ErrorKind::*;
@@ -83,7 +118,10 @@ impl PartialEq for ParsedRequest {
                 &ParsedRequest::Sync(ref sync_req, _),
                 &ParsedRequest::Sync(ref other_sync_req, _),
             ) => sync_req == other_sync_req,
+            (&ParsedRequest::GetBufferedLogs, &ParsedRequest::GetBufferedLogs) => true,
+            (&ParsedRequest::GetBufferedMetrics, &ParsedRequest::GetBufferedMetrics) => true,
             (&ParsedRequest::GetInstanceInfo, &ParsedRequest::GetInstanceInfo) => true,
+            (&ParsedRequest::GetMetrics, &ParsedRequest::GetMetrics) => true,
             (&ParsedRequest::GetMMDS, &ParsedRequest::GetMMDS) => true,
             (&ParsedRequest::PutMMDS(ref val), &ParsedRequest::PutMMDS(ref other_val)) => {
                 val == other_val
@@ -111,12 +149,14 @@ mod tests {
 
     use std::io;
 
-    use vmm::vmm_config::boot_source::BootSourceConfigError;
+    use vmm::vmm_config::balloon::{BalloonConfig, BalloonError};
+    use vmm::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
     use vmm::vmm_config::drive::DriveError;
-    use vmm::vmm_config::instance_info::StartMicrovmError;
+    use vmm::vmm_config::instance_info::{PauseResumeError, StartMicrovmError};
     use vmm::vmm_config::logger::LoggerConfigError;
     use vmm::vmm_config::machine_config::{VmConfig, VmConfigError};
     use vmm::vmm_config::net::NetworkInterfaceError;
+    use vmm::vmm_config::snapshot::SnapshotError;
 
     use futures::{Future, Stream};
     use hyper::{Body, Response};
@@ -169,6 +209,35 @@ mod tests {
         let vm_config_json: serde_json::Value = serde_json::from_str(vm_config_json).unwrap();
         assert_eq!(get_body(hyper_resp).unwrap(), vm_config_json);
 
+        // Test OK response from VMM that contains the boot source.
+        let vmm_resp = Ok(VmmData::BootSource(BootSourceConfig {
+            kernel_image_path: String::from("/foo/kernel"),
+            boot_args: None,
+        }));
+        let hyper_resp = vmm_resp.generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        let boot_source_json = r#"{
+            "kernel_image_path": "/foo/kernel"
+        }"#;
+        let boot_source_json: serde_json::Value = serde_json::from_str(boot_source_json).unwrap();
+        assert_eq!(get_body(hyper_resp).unwrap(), boot_source_json);
+
+        // Test OK response from VMM that contains the balloon configuration.
+        let vmm_resp = Ok(VmmData::BalloonConfig(BalloonConfig {
+            amount_mib: 64,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+        }));
+        let hyper_resp = vmm_resp.generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        let balloon_json = r#"{
+            "amount_mib": 64,
+            "deflate_on_oom": false,
+            "stats_polling_interval_s": 0
+        }"#;
+        let balloon_json: serde_json::Value = serde_json::from_str(balloon_json).unwrap();
+        assert_eq!(get_body(hyper_resp).unwrap(), balloon_json);
+
         // Tests Error Cases
         // Tests for BootSource Errors.
         let vmm_resp =
@@ -386,5 +455,40 @@ mod tests {
             StartMicrovmError::KernelLoader(kernel::loader::Error::SeekProgramHeader),
         );
         check_error_response(vmm_resp, StatusCode::BadRequest);
+
+        // Tests for PauseResume Errors.
+        let vmm_resp =
+            VmmActionError::PauseResume(ErrorKind::User, PauseResumeError::MicroVMNotRunning);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp =
+            VmmActionError::PauseResume(ErrorKind::User, PauseResumeError::MicroVMNotPaused);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+
+        // Tests for the errors of GET requests on resources that were not configured.
+        let vmm_resp =
+            VmmActionError::BootSource(ErrorKind::User, BootSourceConfigError::NotConfigured);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::Logger(ErrorKind::User, LoggerConfigError::NotConfigured);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+
+        // Tests for Balloon Errors.
+        let vmm_resp = VmmActionError::Balloon(ErrorKind::User, BalloonError::DeviceNotFound);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::Balloon(
+            ErrorKind::Internal,
+            BalloonError::UpdateFailed(String::from("foo")),
+        );
+        check_error_response(vmm_resp, StatusCode::InternalServerError);
+
+        // Tests for Snapshot Errors.
+        let vmm_resp = VmmActionError::Snapshot(ErrorKind::User, SnapshotError::MicroVMNotPaused);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::Snapshot(ErrorKind::User, SnapshotError::DevicesMismatch);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::Snapshot(
+            ErrorKind::Internal,
+            SnapshotError::WriteMemory(String::from("foo")),
+        );
+        check_error_response(vmm_resp, StatusCode::InternalServerError);
     }
 }
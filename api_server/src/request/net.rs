@@ -7,7 +7,9 @@ use futures::sync::oneshot;
 use hyper::Method;
 
 use request::{IntoParsedRequest, ParsedRequest};
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceUpdateConfig,
+};
 use vmm::VmmAction;
 
 impl IntoParsedRequest for NetworkInterfaceConfig {
@@ -52,11 +54,27 @@ impl IntoParsedRequest for NetworkInterfaceUpdateConfig {
     }
 }
 
+impl IntoParsedRequest for NetworkCaptureConfig {
+    fn into_parsed_request(
+        self,
+        id_from_path: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::StartNetworkCapture(id_from_path.unwrap_or_default(), self, sender),
+            receiver,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate net_util;
     extern crate vmm;
 
+    use std::net::Ipv4Addr;
+
     use self::net_util::MacAddr;
     use super::*;
 
@@ -76,7 +94,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         }
     }
 
@@ -120,7 +144,13 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: true,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
 
         // This is the json encoding of the netif variable.
@@ -144,6 +174,56 @@ mod tests {
             "host_dev_name": "bar"
         }"#;
 
-        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_no_mac).is_ok())
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_no_mac).is_ok());
+
+        let jstr_anti_spoofing = r#"{
+            "iface_id": "foo",
+            "host_dev_name": "bar",
+            "guest_mac": "12:34:56:78:9A:bc",
+            "anti_spoofing": {
+                "allowed_ips": ["10.0.0.2", "0.0.0.0"]
+            }
+        }"#;
+        let netif: NetworkInterfaceConfig = serde_json::from_str(jstr_anti_spoofing).unwrap();
+        assert_eq!(
+            netif.anti_spoofing.unwrap().allowed_ips.unwrap(),
+            vec![Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(0, 0, 0, 0)]
+        );
+        let jstr_bad_ip = r#"{
+            "iface_id": "foo",
+            "host_dev_name": "bar",
+            "anti_spoofing": {
+                "allowed_ips": ["10.0.0.256"]
+            }
+        }"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_bad_ip).is_err());
+    }
+
+    #[test]
+    fn test_capture_into_parsed_request() {
+        let capture_cfg = NetworkCaptureConfig {
+            path: String::from("/tmp/foo.pcap"),
+            max_size: 1024,
+            max_files: 0,
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(capture_cfg
+            .clone()
+            .into_parsed_request(Some(String::from("foo")), Method::Put)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::StartNetworkCapture(String::from("foo"), capture_cfg, sender),
+                receiver
+            ))));
+
+        // The number of rotated files is optional.
+        let jstr = r#"{
+            "path": "/tmp/foo.pcap",
+            "max_size": 1024
+        }"#;
+        let capture_cfg: NetworkCaptureConfig = serde_json::from_str(jstr).unwrap();
+        assert_eq!(capture_cfg.max_files, 0);
+        assert!(
+            serde_json::from_str::<NetworkCaptureConfig>(r#"{"path": "/tmp/foo.pcap"}"#).is_err()
+        );
     }
 }
@@ -0,0 +1,71 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use futures::sync::oneshot;
+use hyper::Method;
+
+use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::snapshot::SnapshotConfig;
+use vmm::VmmAction;
+
+// The snapshot operation is given by the last path segment: `create` or `load`.
+impl IntoParsedRequest for SnapshotConfig {
+    fn into_parsed_request(
+        self,
+        operation: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        match operation.as_ref().map(String::as_str) {
+            Some("create") => Ok(ParsedRequest::Sync(
+                VmmAction::CreateSnapshot(self, sender),
+                receiver,
+            )),
+            Some("load") => Ok(ParsedRequest::Sync(
+                VmmAction::LoadSnapshot(self, sender),
+                receiver,
+            )),
+            _ => Err(String::from("Invalid snapshot operation.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_config() -> SnapshotConfig {
+        SnapshotConfig {
+            snapshot_path: String::from("/foo/snapshot"),
+            mem_file_path: String::from("/foo/mem"),
+        }
+    }
+
+    #[test]
+    fn test_into_parsed_request() {
+        let (sender, receiver) = oneshot::channel();
+        assert!(snapshot_config()
+            .into_parsed_request(Some(String::from("create")), Method::Put)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::CreateSnapshot(snapshot_config(), sender),
+                receiver
+            ))));
+
+        let (sender, receiver) = oneshot::channel();
+        assert!(snapshot_config()
+            .into_parsed_request(Some(String::from("load")), Method::Put)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::LoadSnapshot(snapshot_config(), sender),
+                receiver
+            ))));
+
+        assert!(snapshot_config()
+            .into_parsed_request(Some(String::from("foo")), Method::Put)
+            .is_err());
+        assert!(snapshot_config()
+            .into_parsed_request(None, Method::Put)
+            .is_err());
+    }
+}
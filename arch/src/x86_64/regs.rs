@@ -19,8 +19,17 @@ const PML4_START: usize = 0x9000;
 const PDPTE_START: usize = 0xa000;
 const PDE_START: usize = 0xb000;
 
+// KVM paravirtual MSRs. See arch/x86/include/uapi/asm/kvm_para.h in the kernel code.
+const MSR_KVM_WALL_CLOCK_NEW: u32 = 0x4b56_4d00;
+const MSR_KVM_SYSTEM_TIME_NEW: u32 = 0x4b56_4d01;
+const MSR_KVM_ASYNC_PF_EN: u32 = 0x4b56_4d02;
+const MSR_KVM_STEAL_TIME: u32 = 0x4b56_4d03;
+const MSR_KVM_PV_EOI_EN: u32 = 0x4b56_4d04;
+
 #[derive(Debug)]
 pub enum Error {
+    /// Failed to get MSRs for this CPU.
+    GetModelSpecificRegisters(io::Error),
     /// Failed to get SREGs for this CPU.
     GetStatusRegisters(io::Error),
     /// Failed to set base registers for this CPU.
@@ -66,27 +75,75 @@ pub fn setup_fpu(vcpu: &VcpuFd) -> Result<()> {
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
 pub fn setup_msrs(vcpu: &VcpuFd) -> Result<()> {
-    let entry_vec = create_msr_entries();
-    let vec_size_bytes =
-        mem::size_of::<kvm_msrs>() + (entry_vec.len() * mem::size_of::<kvm_msr_entry>());
-    let vec: Vec<u8> = Vec::with_capacity(vec_size_bytes);
+    set_msrs(vcpu, &create_msr_entries())
+}
+
+/// Reads the Model Specific Registers (MSRs) which make up the saved state of a given CPU.
+///
+/// MSRs that the host does not support are left out of the returned entries.
+///
+/// # Arguments
+///
+/// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
+pub fn get_msrs(vcpu: &VcpuFd) -> Result<Vec<kvm_msr_entry>> {
+    let mut entry_vec: Vec<kvm_msr_entry> = create_state_msr_indices()
+        .into_iter()
+        .map(|index| kvm_msr_entry {
+            index,
+            ..Default::default()
+        })
+        .collect();
+    let mut buffer = msrs_buffer(entry_vec.len());
+    let msrs = msrs_from_buffer(&mut buffer, &entry_vec);
+    // `KVM_GET_MSRS` stops at the first MSR it cannot read and returns how many it read.
+    let nmsrs = vcpu
+        .get_msrs(msrs)
+        .map_err(Error::GetModelSpecificRegisters)? as usize;
+    let len = entry_vec.len();
+    unsafe {
+        // The flexible array was sized to hold `len` entries.
+        entry_vec.copy_from_slice(msrs.entries.as_slice(len));
+    }
+    entry_vec.truncate(nmsrs);
+    Ok(entry_vec)
+}
+
+/// Writes the given Model Specific Registers (MSRs) of a given CPU.
+///
+/// # Arguments
+///
+/// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
+/// * `entries` - The MSR indices and their values.
+pub fn set_msrs(vcpu: &VcpuFd, entries: &[kvm_msr_entry]) -> Result<()> {
+    let mut buffer = msrs_buffer(entries.len());
+    vcpu.set_msrs(msrs_from_buffer(&mut buffer, entries))
+        .map_err(Error::SetModelSpecificRegisters)
+}
+
+// Allocates zeroed storage for a `kvm_msrs` structure with `len` entries. Using `u64` elements
+// keeps the structure and its entries properly aligned.
+fn msrs_buffer(len: usize) -> Vec<u64> {
+    let size_bytes = mem::size_of::<kvm_msrs>() + len * mem::size_of::<kvm_msr_entry>();
+    vec![0; (size_bytes + mem::size_of::<u64>() - 1) / mem::size_of::<u64>()]
+}
+
+// Lays out a `kvm_msrs` structure holding `entries` over `buffer`.
+fn msrs_from_buffer<'a>(buffer: &'a mut [u64], entries: &[kvm_msr_entry]) -> &'a mut kvm_msrs {
     #[allow(clippy::cast_ptr_alignment)]
     let msrs: &mut kvm_msrs = unsafe {
-        // Converting the vector's memory to a struct is unsafe.  Carefully using the read-only
-        // vector to size and set the members ensures no out-of-bounds errors below.
-        &mut *(vec.as_ptr() as *mut kvm_msrs)
+        // The buffer was sized by `msrs_buffer` for this many entries, and its alignment is at
+        // least as strict as the one of `kvm_msrs`.
+        &mut *(buffer.as_mut_ptr() as *mut kvm_msrs)
     };
-
     unsafe {
         // Mapping the unsized array to a slice is unsafe because the length isn't known.
-        // Providing the length used to create the struct guarantees the entire slice is valid.
-        let entries: &mut [kvm_msr_entry] = msrs.entries.as_mut_slice(entry_vec.len());
-        entries.copy_from_slice(&entry_vec);
+        // The buffer was sized to hold `entries.len()` entries.
+        msrs.entries
+            .as_mut_slice(entries.len())
+            .copy_from_slice(entries);
     }
-    msrs.nmsrs = entry_vec.len() as u32;
-
-    vcpu.set_msrs(msrs)
-        .map_err(Error::SetModelSpecificRegisters)
+    msrs.nmsrs = entries.len() as u32;
+    msrs
 }
 
 /// Configure base registers for a given CPU.
@@ -284,6 +341,25 @@ fn create_msr_entries() -> Vec<kvm_msr_entry> {
     entries
 }
 
+// The MSRs saved and restored along with the rest of the vCPU state. Besides the ones set up at
+// boot time, these include MSRs the guest kernel programs itself, such as the kvmclock ones.
+fn create_state_msr_indices() -> Vec<u32> {
+    let mut indices: Vec<u32> = create_msr_entries()
+        .iter()
+        .map(|entry| entry.index)
+        .collect();
+    indices.extend_from_slice(&[
+        msr_index::MSR_IA32_CR_PAT,
+        msr_index::MSR_IA32_TSC_DEADLINE,
+        MSR_KVM_WALL_CLOCK_NEW,
+        MSR_KVM_SYSTEM_TIME_NEW,
+        MSR_KVM_ASYNC_PF_EN,
+        MSR_KVM_STEAL_TIME,
+        MSR_KVM_PV_EOI_EN,
+    ]);
+    indices
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -416,6 +492,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_set_msrs() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let vcpu = vm.create_vcpu(0).unwrap();
+        setup_msrs(&vcpu).unwrap();
+
+        // The MSRs configured at boot time come first, in the same order.
+        let boot_entries = create_msr_entries();
+        let entries = get_msrs(&vcpu).unwrap();
+        assert!(entries.len() >= boot_entries.len());
+        assert!(entries.len() <= create_state_msr_indices().len());
+        assert_eq!(
+            entries[..boot_entries.len()]
+                .iter()
+                .find(|e| e.index == msr_index::MSR_IA32_MISC_ENABLE),
+            boot_entries
+                .iter()
+                .find(|e| e.index == msr_index::MSR_IA32_MISC_ENABLE)
+        );
+
+        // Values written with `set_msrs` are read back by `get_msrs`.
+        let mut new_entries = entries.clone();
+        for entry in new_entries.iter_mut() {
+            if entry.index == msr_index::MSR_LSTAR {
+                entry.data = 0xffff_ffff_8100_0000;
+            }
+        }
+        set_msrs(&vcpu, &new_entries).unwrap();
+        let lstar = get_msrs(&vcpu)
+            .unwrap()
+            .into_iter()
+            .find(|e| e.index == msr_index::MSR_LSTAR)
+            .unwrap();
+        assert_eq!(lstar.data, 0xffff_ffff_8100_0000);
+    }
+
     #[test]
     fn test_setup_regs() {
         let kvm = Kvm::new().unwrap();
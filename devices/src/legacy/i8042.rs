@@ -19,6 +19,7 @@ pub enum Error {
     KbdInterruptDisabled,
     KbdInterruptFailure(io::Error),
     InternalBufferFull,
+    InvalidState,
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -37,6 +38,7 @@ impl fmt::Display for Error {
                 io_err.to_string()
             ),
             Error::InternalBufferFull => write!(f, "i8042 internal buffer full."),
+            Error::InvalidState => write!(f, "Invalid i8042 device state."),
         }
     }
 }
@@ -75,6 +77,25 @@ const KEY_DEL: u16 = 0xE071;
 /// Internal i8042 buffer size, in bytes
 const BUF_SIZE: usize = 16;
 
+/// The guest-visible state of an `I8042Device`, as saved in a microVM snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct I8042State {
+    /// The i8042 status register.
+    pub status: u8,
+    /// The i8042 control register.
+    pub control: u8,
+    /// The i8042 output port.
+    pub outp: u8,
+    /// The last command sent to port 0x64.
+    pub cmd: u8,
+    /// The internal i8042 data buffer.
+    pub buf: Vec<u8>,
+    /// Read position in `buf`.
+    pub bhead: usize,
+    /// Write position in `buf`.
+    pub btail: usize,
+}
+
 /// A i8042 PS/2 controller that emulates just enough to shutdown the machine.
 pub struct I8042Device {
     /// CPU reset eventfd. We will set this event when the guest issues CMD_RESET_CPU.
@@ -122,6 +143,34 @@ impl I8042Device {
         self.reset_evt.try_clone().map_err(Error::CloneCpuResetEvt)
     }
 
+    /// Returns the guest-visible state of the device.
+    pub fn save_state(&self) -> I8042State {
+        I8042State {
+            status: self.status,
+            control: self.control,
+            outp: self.outp,
+            cmd: self.cmd,
+            buf: self.buf.to_vec(),
+            bhead: self.bhead.0,
+            btail: self.btail.0,
+        }
+    }
+
+    /// Restores the state saved by `save_state()`. The events of the device are left untouched.
+    pub fn restore_state(&mut self, state: &I8042State) -> Result<()> {
+        if state.buf.len() != BUF_SIZE || state.btail.wrapping_sub(state.bhead) > BUF_SIZE {
+            return Err(Error::InvalidState);
+        }
+        self.status = state.status;
+        self.control = state.control;
+        self.outp = state.outp;
+        self.cmd = state.cmd;
+        self.buf.copy_from_slice(&state.buf);
+        self.bhead = Wrapping(state.bhead);
+        self.btail = Wrapping(state.btail);
+        Ok(())
+    }
+
     pub fn trigger_kbd_interrupt(&self) -> Result<()> {
         if (self.control & CB_KBD_INT) == 0 {
             return Err(Error::KbdInterruptDisabled);
@@ -514,4 +563,38 @@ mod tests {
             Error::KbdInterruptDisabled
         )
     }
+
+    #[test]
+    fn test_i8042_save_restore_state() {
+        let mut i8042 = I8042Device::new(EventFd::new().unwrap(), EventFd::new().unwrap());
+        i8042.write(OFS_STATUS, &[CMD_WRITE_OUTP]);
+        i8042.push_byte(0xAA).unwrap();
+        i8042.push_byte(0xBB).unwrap();
+        assert_eq!(i8042.pop_byte().unwrap(), 0xAA);
+
+        let state = i8042.save_state();
+        assert_eq!(state.cmd, CMD_WRITE_OUTP);
+        assert_eq!(state.buf.len(), BUF_SIZE);
+        assert_eq!((state.bhead, state.btail), (1, 2));
+
+        let mut restored = I8042Device::new(EventFd::new().unwrap(), EventFd::new().unwrap());
+        restored.restore_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pop_byte().unwrap(), 0xBB);
+        assert!(restored.pop_byte().is_none());
+
+        // Inconsistent buffers are rejected.
+        let mut bad_state = state.clone();
+        bad_state.buf.pop();
+        assert_eq!(
+            restored.restore_state(&bad_state).unwrap_err(),
+            Error::InvalidState
+        );
+        let mut bad_state = state;
+        bad_state.btail = bad_state.bhead + BUF_SIZE + 1;
+        assert_eq!(
+            restored.restore_state(&bad_state).unwrap_err(),
+            Error::InvalidState
+        );
+    }
 }
@@ -11,7 +11,7 @@ mod rtc_pl031;
 mod serial;
 
 pub use self::i8042::Error as I8042DeviceError;
-pub use self::i8042::I8042Device;
+pub use self::i8042::{I8042Device, I8042State};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTC;
-pub use self::serial::Serial;
+pub use self::serial::{Serial, SerialState};
@@ -51,6 +51,29 @@ const DEFAULT_MODEM_CONTROL: u8 = 0x8; // Auxiliary output 2
 const DEFAULT_MODEM_STATUS: u8 = 0x20 | 0x10 | 0x80; // data ready, clear to send, carrier detect
 const DEFAULT_BAUD_DIVISOR: u16 = 12; // 9600 bps
 
+/// The guest-visible state of a `Serial` device, as saved in a microVM snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SerialState {
+    /// Interrupt Enable Register.
+    pub interrupt_enable: u8,
+    /// Interrupt Identification Register.
+    pub interrupt_identification: u8,
+    /// Line Control Register.
+    pub line_control: u8,
+    /// Line Status Register.
+    pub line_status: u8,
+    /// Modem Control Register.
+    pub modem_control: u8,
+    /// Modem Status Register.
+    pub modem_status: u8,
+    /// Scratch Register.
+    pub scratch: u8,
+    /// Baud rate divisor latch.
+    pub baud_divisor: u16,
+    /// Input bytes not yet read by the guest.
+    pub in_buffer: Vec<u8>,
+}
+
 /// Emulates serial COM ports commonly seen on x86 I/O ports 0x3f8/0x2f8/0x3e8/0x2e8.
 ///
 /// This can optionally write the guest's output to a Write trait object. To send input to the
@@ -117,6 +140,35 @@ impl Serial {
         Ok(())
     }
 
+    /// Returns the guest-visible state of the device.
+    pub fn save_state(&self) -> SerialState {
+        SerialState {
+            interrupt_enable: self.interrupt_enable,
+            interrupt_identification: self.interrupt_identification,
+            line_control: self.line_control,
+            line_status: self.line_status,
+            modem_control: self.modem_control,
+            modem_status: self.modem_status,
+            scratch: self.scratch,
+            baud_divisor: self.baud_divisor,
+            in_buffer: self.in_buffer.iter().cloned().collect(),
+        }
+    }
+
+    /// Restores the state saved by `save_state()`. The output and the interrupt event of the
+    /// device are left untouched.
+    pub fn restore_state(&mut self, state: &SerialState) {
+        self.interrupt_enable = state.interrupt_enable;
+        self.interrupt_identification = state.interrupt_identification;
+        self.line_control = state.line_control;
+        self.line_status = state.line_status;
+        self.modem_control = state.modem_control;
+        self.modem_status = state.modem_status;
+        self.scratch = state.scratch;
+        self.baud_divisor = state.baud_divisor;
+        self.in_buffer = state.in_buffer.iter().cloned().collect();
+    }
+
     fn is_dlab_set(&self) -> bool {
         (self.line_control & LCR_DLAB_BIT) != 0
     }
@@ -421,4 +473,32 @@ mod tests {
         // metric stays the same.
         assert_eq!(missed_writes_before, missed_writes_after - 1);
     }
+
+    #[test]
+    fn test_serial_save_restore_state() {
+        let mut serial = Serial::new_sink(EventFd::new().unwrap(), None);
+        serial.write(u64::from(IER), &[IER_RECV_BIT]);
+        serial.write(u64::from(SCR), &[0x42]);
+        serial.queue_input_bytes(&[b'a', b'b']).unwrap();
+
+        let state = serial.save_state();
+        assert_eq!(state.interrupt_enable, IER_RECV_BIT);
+        assert_eq!(state.scratch, 0x42);
+        assert_eq!(state.in_buffer, vec![b'a', b'b']);
+
+        let serial_out = SharedBuffer::new();
+        let mut restored =
+            Serial::new_out(EventFd::new().unwrap(), Box::new(serial_out.clone()), None);
+        restored.restore_state(&state);
+        assert_eq!(restored.save_state(), state);
+
+        // The guest reads the pending input from where it left off.
+        let mut data = [0u8];
+        restored.read(u64::from(LSR), &mut data[..]);
+        assert_ne!(data[0] & LSR_DATA_BIT, 0);
+        restored.read(u64::from(DATA), &mut data[..]);
+        assert_eq!(data[0], b'a');
+        restored.read(u64::from(DATA), &mut data[..]);
+        assert_eq!(data[0], b'b');
+    }
 }
@@ -9,7 +9,11 @@
 extern crate byteorder;
 extern crate epoll;
 extern crate libc;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate time;
+extern crate timerfd;
 
 extern crate dumbo;
 #[macro_use]
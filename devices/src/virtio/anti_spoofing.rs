@@ -0,0 +1,234 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks the source addresses of the frames sent by the guest, so that a guest cannot
+//! impersonate other hosts on the network of the tap device.
+
+use std::net::Ipv4Addr;
+
+use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::IPv4Packet;
+use net_util::{MacAddr, MAC_ADDR_LEN};
+
+const IPV4_ADDR_LEN: u8 = 4;
+const ARP_HTYPE_ETHERNET: u16 = 1;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+// The tags of IEEE 802.1Q (VLAN) and IEEE 802.1ad (QinQ), which come before the actual ethertype.
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+/// The reasons for dropping a frame sent by the guest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpoofedFrame {
+    /// The source MAC address, or the sender MAC address of an ARP frame, is not the MAC address
+    /// of the guest.
+    Mac,
+    /// The source address of an IPv4 packet, or the sender address of an ARP frame, is not
+    /// allowed. Since only IPv4 addresses can be allowed, the IPv6 packets are not either.
+    Ip,
+    /// The frame carries VLAN tags, which hide the addresses to check.
+    Tagged,
+    /// The frame is too short or malformed, so that its source addresses cannot be read.
+    Malformed,
+}
+
+/// Drops the frames sent by the guest with a source MAC address other than the MAC address of
+/// the guest, and optionally the frames with a source IPv4 address outside an allow-list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AntiSpoofing {
+    guest_mac: MacAddr,
+    allowed_ips: Option<Vec<Ipv4Addr>>,
+}
+
+impl AntiSpoofing {
+    /// Creates a filter for the frames sent by the guest with the `guest_mac` MAC address. When
+    /// `allowed_ips` is given, the IPv4 packets and the ARP frames must also come from one of
+    /// these addresses, and the IPv6 packets and the VLAN tagged frames are dropped, while the
+    /// frames of other protocols are only checked for their MAC address.
+    pub fn new(guest_mac: MacAddr, allowed_ips: Option<Vec<Ipv4Addr>>) -> Self {
+        AntiSpoofing {
+            guest_mac,
+            allowed_ips,
+        }
+    }
+
+    /// Checks the Ethernet `frame` sent by the guest, without its VNET header. Returns why the
+    /// frame must be dropped, if it must.
+    pub fn check(&self, frame: &[u8]) -> Result<(), SpoofedFrame> {
+        let eth_frame = EthernetFrame::from_bytes(frame).map_err(|_| SpoofedFrame::Malformed)?;
+        if eth_frame.src_mac() != self.guest_mac {
+            return Err(SpoofedFrame::Mac);
+        }
+
+        match eth_frame.ethertype() {
+            ETHERTYPE_ARP => self.check_arp(eth_frame.payload()),
+            ETHERTYPE_IPV4 => self.check_ipv4(eth_frame.payload()),
+            // The guest could send the packets it is not allowed to send over IPv6 or within VLAN
+            // tags otherwise.
+            ETHERTYPE_IPV6 if self.allowed_ips.is_some() => Err(SpoofedFrame::Ip),
+            ETHERTYPE_VLAN | ETHERTYPE_QINQ if self.allowed_ips.is_some() => {
+                Err(SpoofedFrame::Tagged)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The ARP replies are checked as well as the requests, since they are the ones which poison
+    // the caches of the other hosts.
+    fn check_arp(&self, payload: &[u8]) -> Result<(), SpoofedFrame> {
+        if payload.len() < ETH_IPV4_FRAME_LEN {
+            return Err(SpoofedFrame::Malformed);
+        }
+        // The payload may hold the padding of the Ethernet frame after the ARP frame.
+        let arp_frame = EthIPv4ArpFrame::from_bytes_unchecked(&payload[..ETH_IPV4_FRAME_LEN]);
+        if arp_frame.htype() != ARP_HTYPE_ETHERNET
+            || arp_frame.ptype() != ETHERTYPE_IPV4
+            || arp_frame.hlen() != MAC_ADDR_LEN as u8
+            || arp_frame.plen() != IPV4_ADDR_LEN
+        {
+            return Err(SpoofedFrame::Malformed);
+        }
+        if arp_frame.sha() != self.guest_mac {
+            return Err(SpoofedFrame::Mac);
+        }
+        self.check_ip(arp_frame.spa())
+    }
+
+    fn check_ipv4(&self, payload: &[u8]) -> Result<(), SpoofedFrame> {
+        if self.allowed_ips.is_none() {
+            return Ok(());
+        }
+        let packet = IPv4Packet::from_header_bytes(payload).map_err(|_| SpoofedFrame::Malformed)?;
+        self.check_ip(packet.source_address())
+    }
+
+    fn check_ip(&self, addr: Ipv4Addr) -> Result<(), SpoofedFrame> {
+        match self.allowed_ips {
+            Some(ref allowed_ips) if !allowed_ips.contains(&addr) => Err(SpoofedFrame::Ip),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+
+    use super::*;
+    use dumbo::pdu::ipv4::IPV4_MIN_HEADER_LEN;
+
+    // Writes an Ethernet frame from `src_mac` with the given ethertype and payload, padded to the
+    // minimum Ethernet frame size.
+    fn eth_frame(src_mac: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; cmp::max(60, 14 + payload.len())];
+        {
+            let mut frame = EthernetFrame::write_incomplete(
+                buf.as_mut_slice(),
+                MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap(),
+                src_mac,
+                ethertype,
+            )
+            .ok()
+            .unwrap();
+            frame.inner_mut().payload_mut()[..payload.len()].copy_from_slice(payload);
+        }
+        buf
+    }
+
+    fn arp_payload(sha: MacAddr, spa: Ipv4Addr) -> Vec<u8> {
+        let mut buf = vec![0u8; ETH_IPV4_FRAME_LEN];
+        EthIPv4ArpFrame::write_reply(
+            buf.as_mut_slice(),
+            sha,
+            spa,
+            MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap(),
+            Ipv4Addr::new(10, 0, 0, 1),
+        )
+        .ok()
+        .unwrap();
+        buf
+    }
+
+    fn ipv4_payload(src: Ipv4Addr) -> Vec<u8> {
+        let mut buf = vec![0u8; IPV4_MIN_HEADER_LEN + 8];
+        IPv4Packet::write_header(buf.as_mut_slice(), 17, src, Ipv4Addr::new(10, 0, 0, 1))
+            .ok()
+            .unwrap()
+            .with_payload_len_unchecked(8, true);
+        buf
+    }
+
+    #[test]
+    fn test_anti_spoofing() {
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let other_mac = MacAddr::parse_str("12:34:56:78:9a:bd").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let other_ip = Ipv4Addr::new(10, 0, 0, 3);
+
+        let mac_only = AntiSpoofing::new(guest_mac, None);
+        let with_ips = AntiSpoofing::new(guest_mac, Some(vec![guest_ip]));
+
+        // Frames too short for an Ethernet header.
+        assert_eq!(mac_only.check(&[0u8; 13]), Err(SpoofedFrame::Malformed));
+
+        // The source MAC address is always checked.
+        let frame = eth_frame(other_mac, 0x86dd, &[]);
+        assert_eq!(mac_only.check(&frame), Err(SpoofedFrame::Mac));
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Mac));
+        let frame = eth_frame(guest_mac, 0x88cc, &[]);
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Ok(()));
+
+        // IPv6 packets are only allowed without an IPv4 allow-list.
+        let frame = eth_frame(guest_mac, ETHERTYPE_IPV6, &[0x60; 40]);
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Ip));
+
+        // Tagged frames are dropped with an allow-list, whatever they carry.
+        for ethertype in &[ETHERTYPE_VLAN, ETHERTYPE_QINQ] {
+            for (inner_ethertype, inner_payload) in &[
+                (ETHERTYPE_IPV4, ipv4_payload(other_ip)),
+                (ETHERTYPE_IPV4, ipv4_payload(guest_ip)),
+                (ETHERTYPE_ARP, arp_payload(guest_mac, other_ip)),
+            ] {
+                // The tag control information, then the encapsulated ethertype and payload.
+                let mut payload = vec![0x00, 0x2a];
+                payload.extend_from_slice(&inner_ethertype.to_be_bytes());
+                payload.extend_from_slice(inner_payload);
+                let frame = eth_frame(guest_mac, *ethertype, &payload);
+                assert_eq!(mac_only.check(&frame), Ok(()));
+                assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Tagged));
+            }
+        }
+
+        // IPv4 packets.
+        let frame = eth_frame(guest_mac, ETHERTYPE_IPV4, &ipv4_payload(guest_ip));
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Ok(()));
+        let frame = eth_frame(guest_mac, ETHERTYPE_IPV4, &ipv4_payload(other_ip));
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Ip));
+        let mut frame = eth_frame(guest_mac, ETHERTYPE_IPV4, &[]);
+        frame.truncate(14 + IPV4_MIN_HEADER_LEN - 1);
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Malformed));
+        let frame = eth_frame(guest_mac, ETHERTYPE_IPV4, &[0x65; IPV4_MIN_HEADER_LEN]);
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Malformed));
+
+        // ARP frames, which are padded.
+        let frame = eth_frame(guest_mac, ETHERTYPE_ARP, &arp_payload(guest_mac, guest_ip));
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Ok(()));
+        let frame = eth_frame(guest_mac, ETHERTYPE_ARP, &arp_payload(other_mac, guest_ip));
+        assert_eq!(mac_only.check(&frame), Err(SpoofedFrame::Mac));
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Mac));
+        let frame = eth_frame(guest_mac, ETHERTYPE_ARP, &arp_payload(guest_mac, other_ip));
+        assert_eq!(mac_only.check(&frame), Ok(()));
+        assert_eq!(with_ips.check(&frame), Err(SpoofedFrame::Ip));
+        let mut frame = eth_frame(guest_mac, ETHERTYPE_ARP, &[]);
+        frame.truncate(14 + ETH_IPV4_FRAME_LEN - 1);
+        assert_eq!(mac_only.check(&frame), Err(SpoofedFrame::Malformed));
+        let frame = eth_frame(guest_mac, ETHERTYPE_ARP, &[0u8; ETH_IPV4_FRAME_LEN]);
+        assert_eq!(mac_only.check(&frame), Err(SpoofedFrame::Malformed));
+    }
+}
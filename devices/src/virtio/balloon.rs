@@ -0,0 +1,945 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use epoll;
+use std::cmp;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+use super::super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, DescriptorChain, Queue, VirtioDevice, TYPE_BALLOON,
+    VIRTIO_MMIO_INT_VRING,
+};
+use logger::{Metric, StoreMetric, METRICS};
+use memory_model::{GuestAddress, GuestMemory, GuestMemoryError};
+use sys_util::EventFd;
+use virtio::EpollConfigConstructor;
+use {DeviceEventT, EpollHandler};
+
+// See include/uapi/linux/virtio_config.h and include/uapi/linux/virtio_balloon.h.
+const VIRTIO_F_VERSION_1: u32 = 32;
+const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1;
+const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
+
+const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+const VIRTIO_BALLOON_S_HTLB_PGALLOC: u16 = 8;
+const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
+
+// The balloon always works with 4 KiB pages, whatever the page size of the guest.
+const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
+/// Size of the pages the balloon is inflated and deflated with.
+pub const BALLOON_PAGE_SIZE: usize = 1 << VIRTIO_BALLOON_PFN_SHIFT;
+
+// The size of a page frame number sent on the inflate and deflate queues.
+const PFN_SIZE: usize = 4;
+// A statistic is a 16-bit tag followed by a 64-bit value, with no padding in between.
+const STAT_SIZE: usize = 10;
+
+// The number of pages the host asks for, followed by the number of pages held by the guest.
+const CONFIG_SPACE_SIZE: usize = 8;
+const QUEUE_SIZE: u16 = 256;
+// The inflate and deflate queues, followed by the statistics queue if polling is enabled.
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
+const NUM_QUEUES_NO_STATS: usize = 2;
+const INFLATE_QUEUE: usize = 0;
+const DEFLATE_QUEUE: usize = 1;
+const STATS_QUEUE: usize = 2;
+
+// New page frame numbers are pending on the inflate queue.
+const INFLATE_QUEUE_EVENT: DeviceEventT = 0;
+// New page frame numbers are pending on the deflate queue.
+const DEFLATE_QUEUE_EVENT: DeviceEventT = 1;
+// The guest posted new memory statistics.
+const STATS_QUEUE_EVENT: DeviceEventT = 2;
+// It is time to ask the guest for new memory statistics.
+const STATS_TIMER_EVENT: DeviceEventT = 3;
+// Number of DeviceEventT events supported by this implementation.
+pub const BALLOON_EVENTS_COUNT: usize = 4;
+
+#[derive(Debug)]
+enum Error {
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Guest gave us offsets that would have overflowed a usize.
+    CheckedOffset(GuestAddress, usize),
+    /// Guest gave us a write only descriptor that protocol says to read from.
+    UnexpectedWriteOnlyDescriptor,
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Reads the page frame numbers in a descriptor.
+fn read_desc_pfns(desc: &DescriptorChain, mem: &GuestMemory, pfns: &mut Vec<u32>) -> Result<()> {
+    if desc.is_write_only() {
+        return Err(Error::UnexpectedWriteOnlyDescriptor);
+    }
+    for offset in (0..desc.len as usize / PFN_SIZE).map(|i| i * PFN_SIZE) {
+        let addr = mem
+            .checked_offset(desc.addr, offset)
+            .ok_or(Error::CheckedOffset(desc.addr, offset))?;
+        pfns.push(
+            mem.read_obj_from_addr::<u32>(addr)
+                .map_err(Error::GuestMemory)?,
+        );
+    }
+    Ok(())
+}
+
+/// Reads the page frame numbers from all the descriptors of a chain.
+fn read_pfns(avail_desc: &DescriptorChain, mem: &GuestMemory) -> Result<Vec<u32>> {
+    let mut pfns = Vec::new();
+
+    read_desc_pfns(avail_desc, mem, &mut pfns)?;
+    let mut next_desc = avail_desc.next_descriptor();
+    while let Some(desc) = next_desc {
+        read_desc_pfns(&desc, mem, &mut pfns)?;
+        next_desc = desc.next_descriptor();
+    }
+
+    Ok(pfns)
+}
+
+/// Turns a list of page frame numbers into ranges of contiguous pages, given as
+/// (first page frame number, number of pages).
+fn page_ranges(mut pfns: Vec<u32>) -> Vec<(u32, usize)> {
+    pfns.sort_unstable();
+    pfns.dedup();
+
+    let mut ranges: Vec<(u32, usize)> = Vec::new();
+    for pfn in pfns {
+        match ranges.last_mut() {
+            Some((start, count)) if u64::from(*start) + *count as u64 == u64::from(pfn) => {
+                *count += 1;
+            }
+            _ => ranges.push((pfn, 1)),
+        }
+    }
+    ranges
+}
+
+/// Reads the memory statistics in a descriptor and stores them in the balloon metrics.
+fn update_stats(desc: &DescriptorChain, mem: &GuestMemory) -> Result<()> {
+    if desc.is_write_only() {
+        return Err(Error::UnexpectedWriteOnlyDescriptor);
+    }
+
+    for offset in (0..desc.len as usize / STAT_SIZE).map(|i| i * STAT_SIZE) {
+        let addr = mem
+            .checked_offset(desc.addr, offset)
+            .ok_or(Error::CheckedOffset(desc.addr, offset))?;
+        let mut stat = [0u8; STAT_SIZE];
+        mem.read_slice_at_addr(&mut stat, addr)
+            .map_err(Error::GuestMemory)?;
+
+        let mut tag = [0u8; 2];
+        tag.copy_from_slice(&stat[..2]);
+        let mut val = [0u8; 8];
+        val.copy_from_slice(&stat[2..]);
+
+        let metric = match u16::from_le_bytes(tag) {
+            VIRTIO_BALLOON_S_SWAP_IN => &METRICS.balloon.stats.swap_in,
+            VIRTIO_BALLOON_S_SWAP_OUT => &METRICS.balloon.stats.swap_out,
+            VIRTIO_BALLOON_S_MAJFLT => &METRICS.balloon.stats.major_faults,
+            VIRTIO_BALLOON_S_MINFLT => &METRICS.balloon.stats.minor_faults,
+            VIRTIO_BALLOON_S_MEMFREE => &METRICS.balloon.stats.free_memory,
+            VIRTIO_BALLOON_S_MEMTOT => &METRICS.balloon.stats.total_memory,
+            VIRTIO_BALLOON_S_AVAIL => &METRICS.balloon.stats.available_memory,
+            VIRTIO_BALLOON_S_CACHES => &METRICS.balloon.stats.disk_caches,
+            VIRTIO_BALLOON_S_HTLB_PGALLOC => &METRICS.balloon.stats.hugetlb_allocations,
+            VIRTIO_BALLOON_S_HTLB_PGFAIL => &METRICS.balloon.stats.hugetlb_failures,
+            // Newer guests may report statistics we don't know about.
+            _ => continue,
+        };
+        metric.store(u64::from_le_bytes(val) as usize);
+    }
+
+    Ok(())
+}
+
+pub struct BalloonEpollHandler {
+    queues: Vec<Queue>,
+    mem: GuestMemory,
+    interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    queue_evts: Vec<EventFd>,
+    stats_timer: Option<TimerFd>,
+    // The descriptor the guest last reported its memory statistics in. It is held until new
+    // statistics are needed, and handing it back to the guest is the request for them.
+    stats_desc_index: Option<u16>,
+}
+
+impl BalloonEpollHandler {
+    fn process_inflate_queue(&mut self) -> bool {
+        let queue = &mut self.queues[INFLATE_QUEUE];
+        let mut used_any = false;
+        let mut pfns = Vec::new();
+
+        while let Some(head) = queue.pop(&self.mem) {
+            match read_pfns(&head, &self.mem) {
+                Ok(mut head_pfns) => pfns.append(&mut head_pfns),
+                Err(e) => {
+                    error!("Failed to read the inflated pages: {:?}", e);
+                    METRICS.balloon.inflate_fails.inc();
+                }
+            }
+            queue.add_used(&self.mem, head.index, 0);
+            used_any = true;
+        }
+
+        METRICS.balloon.inflated_pages_count.add(pfns.len());
+        for (pfn, count) in page_ranges(pfns) {
+            let addr = GuestAddress((pfn as usize) << VIRTIO_BALLOON_PFN_SHIFT);
+            if let Err(e) = self
+                .mem
+                .remove_range(addr, count << VIRTIO_BALLOON_PFN_SHIFT)
+            {
+                error!("Failed to release the inflated pages: {:?}", e);
+                METRICS.balloon.inflate_fails.inc();
+            }
+        }
+
+        used_any
+    }
+
+    fn process_deflate_queue(&mut self) -> bool {
+        let queue = &mut self.queues[DEFLATE_QUEUE];
+        let mut used_any = false;
+
+        // The guest can use the pages right away; they are faulted back in on the first access.
+        while let Some(head) = queue.pop(&self.mem) {
+            match read_pfns(&head, &self.mem) {
+                Ok(pfns) => METRICS.balloon.deflated_pages_count.add(pfns.len()),
+                Err(e) => {
+                    error!("Failed to read the deflated pages: {:?}", e);
+                    METRICS.balloon.event_fails.inc();
+                }
+            }
+            queue.add_used(&self.mem, head.index, 0);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    fn process_stats_queue(&mut self) -> bool {
+        let queue = &mut self.queues[STATS_QUEUE];
+        let mut used_any = false;
+
+        while let Some(head) = queue.pop(&self.mem) {
+            // The guest only ever posts one statistics buffer, so a held one is stale.
+            if let Some(index) = self.stats_desc_index.take() {
+                queue.add_used(&self.mem, index, 0);
+                used_any = true;
+            }
+            match update_stats(&head, &self.mem) {
+                Ok(()) => METRICS.balloon.stats_updates_count.inc(),
+                Err(e) => {
+                    error!("Failed to read the memory statistics: {:?}", e);
+                    METRICS.balloon.stats_update_fails.inc();
+                }
+            }
+            self.stats_desc_index = Some(head.index);
+        }
+
+        used_any
+    }
+
+    fn request_stats(&mut self) -> bool {
+        match self.stats_desc_index.take() {
+            Some(index) => {
+                self.queues[STATS_QUEUE].add_used(&self.mem, index, 0);
+                true
+            }
+            // The guest hasn't sent back the previous statistics yet.
+            None => false,
+        }
+    }
+
+    fn read_queue_evt(&self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.queue_evts[queue_index]
+            .read()
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to get queue event: {:?}", e);
+                METRICS.balloon.event_fails.inc();
+                DeviceError::FailedReadingQueue {
+                    event_type: "queue event",
+                    underlying: e,
+                }
+            })
+    }
+
+    fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            METRICS.balloon.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+}
+
+impl EpollHandler for BalloonEpollHandler {
+    fn handle_event(
+        &mut self,
+        device_event: DeviceEventT,
+        _evset: epoll::Events,
+    ) -> result::Result<(), DeviceError> {
+        let used_any = match device_event {
+            INFLATE_QUEUE_EVENT => {
+                self.read_queue_evt(INFLATE_QUEUE)?;
+                self.process_inflate_queue()
+            }
+            DEFLATE_QUEUE_EVENT => {
+                self.read_queue_evt(DEFLATE_QUEUE)?;
+                self.process_deflate_queue()
+            }
+            STATS_QUEUE_EVENT if self.stats_timer.is_some() => {
+                self.read_queue_evt(STATS_QUEUE)?;
+                self.process_stats_queue()
+            }
+            STATS_TIMER_EVENT if self.stats_timer.is_some() => {
+                if let Some(timer) = self.stats_timer.as_mut() {
+                    timer.read();
+                }
+                self.request_stats()
+            }
+            unknown => {
+                return Err(DeviceError::UnknownEvent {
+                    device: "balloon",
+                    event: unknown,
+                })
+            }
+        };
+
+        if used_any {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub struct EpollConfig {
+    inflate_q_token: u64,
+    deflate_q_token: u64,
+    stats_q_token: u64,
+    stats_timer_token: u64,
+    epoll_raw_fd: RawFd,
+    sender: mpsc::Sender<Box<EpollHandler>>,
+}
+
+impl EpollConfigConstructor for EpollConfig {
+    fn new(first_token: u64, epoll_raw_fd: RawFd, sender: mpsc::Sender<Box<EpollHandler>>) -> Self {
+        EpollConfig {
+            inflate_q_token: first_token + u64::from(INFLATE_QUEUE_EVENT),
+            deflate_q_token: first_token + u64::from(DEFLATE_QUEUE_EVENT),
+            stats_q_token: first_token + u64::from(STATS_QUEUE_EVENT),
+            stats_timer_token: first_token + u64::from(STATS_TIMER_EVENT),
+            epoll_raw_fd,
+            sender,
+        }
+    }
+}
+
+/// Builds the config space of a balloon asked to hold `num_pages` pages.
+fn build_config_space(num_pages: u32) -> Vec<u8> {
+    // The config space is little endian. The number of pages actually held by the balloon
+    // is written by the guest.
+    let mut config = Vec::with_capacity(CONFIG_SPACE_SIZE);
+    config.extend_from_slice(&num_pages.to_le_bytes());
+    config.extend_from_slice(&[0u8; 4]);
+    config
+}
+
+/// Virtio device through which the guest gives memory back to the host.
+pub struct Balloon {
+    avail_features: u64,
+    acked_features: u64,
+    config_space: Vec<u8>,
+    stats_polling_interval: Option<Duration>,
+    stats_timer: Option<TimerFd>,
+    epoll_config: EpollConfig,
+    activated: bool,
+}
+
+impl Balloon {
+    /// Create a new virtio balloon device, asked to hold `num_pages` pages of guest memory.
+    ///
+    /// The guest reports its memory statistics every `stats_polling_interval_s` seconds, or never
+    /// if the interval is 0.
+    pub fn new(
+        num_pages: u32,
+        deflate_on_oom: bool,
+        stats_polling_interval_s: u16,
+        epoll_config: EpollConfig,
+    ) -> io::Result<Balloon> {
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+        if deflate_on_oom {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+        }
+
+        let (stats_polling_interval, stats_timer) = if stats_polling_interval_s > 0 {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+            (
+                Some(Duration::from_secs(u64::from(stats_polling_interval_s))),
+                Some(TimerFd::new_custom(ClockId::Monotonic, true, true)?),
+            )
+        } else {
+            (None, None)
+        };
+
+        Ok(Balloon {
+            avail_features,
+            acked_features: 0u64,
+            config_space: build_config_space(num_pages),
+            stats_polling_interval,
+            stats_timer,
+            epoll_config,
+            activated: false,
+        })
+    }
+
+    fn num_queues(&self) -> usize {
+        // The guest sets up the statistics queue whenever the feature is offered.
+        if self.stats_polling_interval.is_some() {
+            QUEUE_SIZES.len()
+        } else {
+            NUM_QUEUES_NO_STATS
+        }
+    }
+
+    fn register_fd(&self, fd: RawFd, token: u64) -> ActivateResult {
+        epoll::ctl(
+            self.epoll_config.epoll_raw_fd,
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            fd,
+            epoll::Event::new(epoll::Events::EPOLLIN, token),
+        )
+        .map_err(|e| {
+            METRICS.balloon.activate_fails.inc();
+            ActivateError::EpollCtl(e)
+        })
+    }
+}
+
+impl VirtioDevice for Balloon {
+    fn device_type(&self) -> u32 {
+        TYPE_BALLOON
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &QUEUE_SIZES[..self.num_queues()]
+    }
+
+    fn features(&self, page: u32) -> u32 {
+        match page {
+            // Get the lower 32-bits of the features bitfield.
+            0 => self.avail_features as u32,
+            // Get the upper 32-bits of the features bitfield.
+            1 => (self.avail_features >> 32) as u32,
+            _ => {
+                warn!("Received request for unknown features page.");
+                0u32
+            }
+        }
+    }
+
+    fn ack_features(&mut self, page: u32, value: u32) {
+        let mut v = match page {
+            0 => u64::from(value),
+            1 => u64::from(value) << 32,
+            _ => {
+                warn!("Cannot acknowledge unknown features page.");
+                0u64
+            }
+        };
+
+        // Check if the guest is ACK'ing a feature that we didn't claim to have.
+        let unrequested_features = v & !self.avail_features;
+        if unrequested_features != 0 {
+            warn!("Received acknowledge request for unknown feature.");
+
+            // Don't count these features as acked.
+            v &= !unrequested_features;
+        }
+        self.acked_features |= v;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_len = self.config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            METRICS.balloon.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&self.config_space[offset as usize..cmp::min(end, config_len) as usize])
+                .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let data_len = data.len() as u64;
+        let config_len = self.config_space.len() as u64;
+        if offset + data_len > config_len {
+            error!("Failed to write config space");
+            METRICS.balloon.cfg_fails.inc();
+            return;
+        }
+        let (_, right) = self.config_space.split_at_mut(offset as usize);
+        right[..data.len()].copy_from_slice(&data[..]);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemory,
+        interrupt_evt: EventFd,
+        status: Arc<AtomicUsize>,
+        queues: Vec<Queue>,
+        queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        let num_queues = self.num_queues();
+        if self.activated || queues.len() != num_queues || queue_evts.len() != num_queues {
+            error!(
+                "Cannot perform activate. Expected {} queue(s), got {}",
+                num_queues,
+                queues.len()
+            );
+            METRICS.balloon.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+
+        // Polling starts when the guest is ready to report statistics.
+        let mut stats_timer = self.stats_timer.take();
+        if let (Some(timer), Some(interval)) = (stats_timer.as_mut(), self.stats_polling_interval) {
+            timer.set_state(
+                TimerState::Periodic {
+                    current: interval,
+                    interval,
+                },
+                SetTimeFlags::Default,
+            );
+        }
+
+        let queue_raw_fds: Vec<RawFd> = queue_evts.iter().map(|evt| evt.as_raw_fd()).collect();
+        let stats_timer_raw_fd = stats_timer.as_ref().map(|timer| timer.as_raw_fd());
+
+        let handler = BalloonEpollHandler {
+            queues,
+            mem,
+            interrupt_status: status,
+            interrupt_evt,
+            queue_evts,
+            stats_timer,
+            stats_desc_index: None,
+        };
+
+        // The channel should be open at this point.
+        self.epoll_config
+            .sender
+            .send(Box::new(handler))
+            .expect("Failed to send through the channel");
+        self.activated = true;
+
+        let queue_tokens = [
+            self.epoll_config.inflate_q_token,
+            self.epoll_config.deflate_q_token,
+            self.epoll_config.stats_q_token,
+        ];
+        for (&fd, &token) in queue_raw_fds.iter().zip(queue_tokens.iter()) {
+            self.register_fd(fd, token)?;
+        }
+        if let Some(fd) = stats_timer_raw_fd {
+            self.register_fd(fd, self.epoll_config.stats_timer_token)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libc;
+    use std::sync::mpsc::Receiver;
+
+    use virtio::queue::tests::*;
+
+    const EPOLLIN: epoll::Events = epoll::Events::EPOLLIN;
+
+    /// Will read $metric, run the code in $block, then assert metric has increased by $delta.
+    macro_rules! check_metric_after_block {
+        ($metric:expr, $delta:expr, $block:expr) => {{
+            let before = $metric.count();
+            let _ = $block;
+            assert_eq!($metric.count(), before + $delta, "unexpected metric value");
+        }};
+    }
+
+    struct DummyBalloon {
+        balloon: Balloon,
+        epoll_raw_fd: i32,
+        _receiver: Receiver<Box<EpollHandler>>,
+    }
+
+    impl DummyBalloon {
+        fn new(num_pages: u32, stats_polling_interval_s: u16) -> Self {
+            let epoll_raw_fd = epoll::create(true).unwrap();
+            let (sender, _receiver) = mpsc::channel();
+
+            let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+
+            DummyBalloon {
+                balloon: Balloon::new(num_pages, true, stats_polling_interval_s, epoll_config)
+                    .unwrap(),
+                epoll_raw_fd,
+                _receiver,
+            }
+        }
+
+        fn balloon(&mut self) -> &mut Balloon {
+            &mut self.balloon
+        }
+    }
+
+    impl Drop for DummyBalloon {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.epoll_raw_fd) };
+        }
+    }
+
+    fn default_test_balloonepollhandler<'a>(
+        mem: &'a GuestMemory,
+        with_stats: bool,
+    ) -> (BalloonEpollHandler, Vec<VirtQueue<'a>>) {
+        let num_queues = if with_stats { 3 } else { 2 };
+        let vqs: Vec<VirtQueue> = (0..num_queues)
+            .map(|i| VirtQueue::new(GuestAddress(i * 0x1000), &mem, 16))
+            .collect();
+        let stats_timer = if with_stats {
+            Some(TimerFd::new_custom(ClockId::Monotonic, true, true).unwrap())
+        } else {
+            None
+        };
+
+        (
+            BalloonEpollHandler {
+                queues: vqs.iter().map(|vq| vq.create_queue()).collect(),
+                mem: mem.clone(),
+                interrupt_status: Arc::new(AtomicUsize::new(0)),
+                interrupt_evt: EventFd::new().unwrap(),
+                queue_evts: (0..num_queues).map(|_| EventFd::new().unwrap()).collect(),
+                stats_timer,
+                stats_desc_index: None,
+            },
+            vqs,
+        )
+    }
+
+    // Posts a single readable descriptor on the queue, and signals the queue event.
+    fn post_buffer(
+        h: &BalloonEpollHandler,
+        vq: &VirtQueue,
+        queue_index: usize,
+        addr: u64,
+        len: u32,
+    ) {
+        let avail_idx = vq.avail.idx.get();
+        let desc_index = avail_idx % 16;
+        vq.dtable[desc_index as usize].set(addr, len, 0, 0);
+        vq.avail.ring[desc_index as usize].set(desc_index);
+        vq.avail.idx.set(avail_idx + 1);
+        h.queue_evts[queue_index].write(1).unwrap();
+    }
+
+    #[test]
+    fn test_page_ranges() {
+        assert!(page_ranges(vec![]).is_empty());
+        assert_eq!(page_ranges(vec![7]), vec![(7, 1)]);
+        assert_eq!(
+            page_ranges(vec![12, 3, 10, 4, 11, 5, 3, 20]),
+            vec![(3, 3), (10, 3), (20, 1)]
+        );
+        assert_eq!(
+            page_ranges(vec![u32::max_value(), 0, u32::max_value() - 1]),
+            vec![(0, 1), (u32::max_value() - 1, 2)]
+        );
+    }
+
+    #[test]
+    fn test_build_config_space() {
+        assert_eq!(
+            build_config_space(0x0102_0304),
+            vec![0x04, 0x03, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::cognitive_complexity)]
+    fn test_virtio_device() {
+        let mut dummy = DummyBalloon::new(0x100, 0);
+        let b = dummy.balloon();
+
+        // Test `device_type()`.
+        assert_eq!(b.device_type(), TYPE_BALLOON);
+
+        // Test `queue_max_sizes()`.
+        assert_eq!(b.queue_max_sizes(), &[QUEUE_SIZE, QUEUE_SIZE]);
+        assert_eq!(
+            DummyBalloon::new(0x100, 1).balloon().queue_max_sizes(),
+            &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE]
+        );
+
+        // Test `features()` and `ack_features()`.
+        {
+            assert_eq!(b.features(0), 1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM);
+            assert_eq!(b.features(1), 1 << (VIRTIO_F_VERSION_1 - 32));
+            assert_eq!(b.features(2), 0);
+            assert_eq!(
+                DummyBalloon::new(0x100, 1).balloon().features(0),
+                (1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) | (1 << VIRTIO_BALLOON_F_STATS_VQ)
+            );
+
+            // The stats feature is not offered, so it is not acked.
+            b.ack_features(
+                0,
+                (1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) | (1 << VIRTIO_BALLOON_F_STATS_VQ),
+            );
+            b.ack_features(1, 1 << (VIRTIO_F_VERSION_1 - 32));
+            b.ack_features(2, 0xffff_ffff);
+            assert_eq!(
+                b.acked_features,
+                (1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) | (1 << VIRTIO_F_VERSION_1)
+            );
+        }
+
+        // Test `read_config()` and `write_config()`.
+        {
+            let mut num_pages = [0u8; 4];
+            b.read_config(0, &mut num_pages);
+            assert_eq!(num_pages, [0x00, 0x01, 0x00, 0x00]);
+
+            // The guest reports the pages it holds.
+            b.write_config(4, &[0x80, 0x00, 0x00, 0x00]);
+            let mut actual = [0u8; 4];
+            b.read_config(4, &mut actual);
+            assert_eq!(actual, [0x80, 0x00, 0x00, 0x00]);
+
+            // Invalid accesses.
+            check_metric_after_block!(
+                &METRICS.balloon.cfg_fails,
+                1,
+                b.read_config(CONFIG_SPACE_SIZE as u64, &mut num_pages)
+            );
+            check_metric_after_block!(&METRICS.balloon.cfg_fails, 1, b.write_config(6, &[0u8; 4]));
+            b.read_config(4, &mut actual);
+            assert_eq!(actual, [0x80, 0x00, 0x00, 0x00]);
+        }
+
+        // Test `activate()`.
+        {
+            let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+            let vq = VirtQueue::new(GuestAddress(0), &m, 16);
+
+            // Wrong number of queues.
+            check_metric_after_block!(
+                &METRICS.balloon.activate_fails,
+                1,
+                assert!(b
+                    .activate(
+                        m.clone(),
+                        EventFd::new().unwrap(),
+                        Arc::new(AtomicUsize::new(0)),
+                        vec![vq.create_queue()],
+                        vec![EventFd::new().unwrap()],
+                    )
+                    .is_err())
+            );
+
+            assert!(b
+                .activate(
+                    m.clone(),
+                    EventFd::new().unwrap(),
+                    Arc::new(AtomicUsize::new(0)),
+                    vec![vq.create_queue(), vq.create_queue()],
+                    vec![EventFd::new().unwrap(), EventFd::new().unwrap()],
+                )
+                .is_ok());
+
+            // The device can only be activated once.
+            check_metric_after_block!(
+                &METRICS.balloon.activate_fails,
+                1,
+                assert!(b
+                    .activate(
+                        m.clone(),
+                        EventFd::new().unwrap(),
+                        Arc::new(AtomicUsize::new(0)),
+                        vec![vq.create_queue(), vq.create_queue()],
+                        vec![EventFd::new().unwrap(), EventFd::new().unwrap()],
+                    )
+                    .is_err())
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_event_handler() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _vqs) = default_test_balloonepollhandler(&m, false);
+
+        // The statistics events are only valid when polling is enabled.
+        for event in &[
+            STATS_QUEUE_EVENT,
+            STATS_TIMER_EVENT,
+            BALLOON_EVENTS_COUNT as u16,
+        ] {
+            match h.handle_event(*event, EPOLLIN) {
+                Err(DeviceError::UnknownEvent { event: e, device }) => {
+                    assert_eq!(e, *event);
+                    assert_eq!(device, "balloon");
+                }
+                _ => panic!("Unexpected result for event {}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn test_inflate_deflate() {
+        let m = GuestMemory::new_private(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vqs) = default_test_balloonepollhandler(&m, false);
+
+        // Fill pages 8 to 10 and the first bytes of page 12.
+        for offset in (0x8000..0xb000).step_by(8) {
+            m.write_obj_at_addr::<u64>(0xdead_beef, GuestAddress(offset))
+                .unwrap();
+        }
+        m.write_obj_at_addr::<u64>(0xdead_beef, GuestAddress(0xc000))
+            .unwrap();
+
+        // Inflate pages 8, 9 and 10, out of order.
+        for (i, pfn) in [10u32, 8, 9].iter().enumerate() {
+            m.write_obj_at_addr::<u32>(*pfn, GuestAddress(0x7000 + i * 4))
+                .unwrap();
+        }
+        post_buffer(&h, &vqs[INFLATE_QUEUE], INFLATE_QUEUE, 0x7000, 12);
+
+        check_metric_after_block!(
+            &METRICS.balloon.inflated_pages_count,
+            3,
+            h.handle_event(INFLATE_QUEUE_EVENT, EPOLLIN).unwrap()
+        );
+        assert_eq!(h.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(vqs[INFLATE_QUEUE].used.idx.get(), 1);
+        for offset in (0x8000..0xb000).step_by(8) {
+            assert_eq!(
+                m.read_obj_from_addr::<u64>(GuestAddress(offset)).unwrap(),
+                0
+            );
+        }
+        assert_eq!(
+            m.read_obj_from_addr::<u64>(GuestAddress(0xc000)).unwrap(),
+            0xdead_beef
+        );
+
+        // A page outside of the guest memory can't be released.
+        m.write_obj_at_addr::<u32>(0x100, GuestAddress(0x7000))
+            .unwrap();
+        post_buffer(&h, &vqs[INFLATE_QUEUE], INFLATE_QUEUE, 0x7000, 4);
+        check_metric_after_block!(
+            &METRICS.balloon.inflate_fails,
+            1,
+            h.handle_event(INFLATE_QUEUE_EVENT, EPOLLIN).unwrap()
+        );
+        assert_eq!(vqs[INFLATE_QUEUE].used.idx.get(), 2);
+
+        // Deflating only returns the descriptors.
+        post_buffer(&h, &vqs[DEFLATE_QUEUE], DEFLATE_QUEUE, 0x7000, 12);
+        check_metric_after_block!(
+            &METRICS.balloon.deflated_pages_count,
+            3,
+            h.handle_event(DEFLATE_QUEUE_EVENT, EPOLLIN).unwrap()
+        );
+        assert_eq!(vqs[DEFLATE_QUEUE].used.idx.get(), 1);
+        assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+
+        // A queue event with nothing to read fails.
+        check_metric_after_block!(
+            &METRICS.balloon.event_fails,
+            1,
+            assert!(h.handle_event(DEFLATE_QUEUE_EVENT, EPOLLIN).is_err())
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vqs) = default_test_balloonepollhandler(&m, true);
+        let stats_vq = &vqs[STATS_QUEUE];
+
+        // Nothing to hand back before the guest sent its first statistics.
+        h.handle_event(STATS_TIMER_EVENT, EPOLLIN).unwrap();
+        assert_eq!(stats_vq.used.idx.get(), 0);
+
+        // Report the total memory, a statistic we don't know about, and the free memory.
+        let stats: &[(u16, u64)] = &[
+            (VIRTIO_BALLOON_S_MEMTOT, 0x1_0000_0000),
+            (0x100, 1),
+            (VIRTIO_BALLOON_S_MEMFREE, 0x1234_5678),
+        ];
+        for (i, (tag, val)) in stats.iter().enumerate() {
+            let addr = GuestAddress(0x8000 + i * STAT_SIZE);
+            m.write_slice_at_addr(&tag.to_le_bytes(), addr).unwrap();
+            m.write_slice_at_addr(&val.to_le_bytes(), addr.unchecked_add(2))
+                .unwrap();
+        }
+        post_buffer(&h, stats_vq, STATS_QUEUE, 0x8000, 3 * STAT_SIZE as u32);
+
+        check_metric_after_block!(
+            &METRICS.balloon.stats_updates_count,
+            1,
+            h.handle_event(STATS_QUEUE_EVENT, EPOLLIN).unwrap()
+        );
+        assert_eq!(METRICS.balloon.stats.total_memory.fetch(), 0x1_0000_0000);
+        assert_eq!(METRICS.balloon.stats.free_memory.fetch(), 0x1234_5678);
+        // The descriptor is held until new statistics are needed.
+        assert_eq!(stats_vq.used.idx.get(), 0);
+        assert_eq!(h.stats_desc_index, Some(0));
+
+        h.handle_event(STATS_TIMER_EVENT, EPOLLIN).unwrap();
+        assert_eq!(stats_vq.used.idx.get(), 1);
+        assert_eq!(stats_vq.used.ring[0].get().id, 0);
+        assert_eq!(h.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(h.stats_desc_index, None);
+
+        // A write only buffer can't hold statistics.
+        post_buffer(&h, stats_vq, STATS_QUEUE, 0x8000, STAT_SIZE as u32);
+        stats_vq.dtable[1].flags.set(VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            &METRICS.balloon.stats_update_fails,
+            1,
+            h.handle_event(STATS_QUEUE_EVENT, EPOLLIN).unwrap()
+        );
+        assert_eq!(h.stats_desc_index, Some(1));
+    }
+}
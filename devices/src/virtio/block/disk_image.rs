@@ -0,0 +1,198 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use libc;
+
+use super::overlay::OverlayImage;
+use super::qcow::QcowFile;
+
+/// The format of the image backing a block device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum DiskImageFormat {
+    /// The guest sectors are stored as is in the file.
+    Raw,
+    /// QEMU copy-on-write image, version 2 or 3.
+    Qcow2,
+}
+
+impl Default for DiskImageFormat {
+    fn default() -> DiskImageFormat {
+        DiskImageFormat::Raw
+    }
+}
+
+/// How the writes of a block device reach the disk.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CacheType {
+    /// The writes go to the host page cache, and the flushes of the guest are ignored. The data
+    /// can be lost if the host crashes, even after the guest flushed it.
+    Unsafe,
+    /// The writes go to the host page cache, and reach the disk when the guest flushes them.
+    Writeback,
+    /// The writes reach the disk before they complete, so the guest has nothing to flush.
+    Writethrough,
+}
+
+impl Default for CacheType {
+    fn default() -> CacheType {
+        CacheType::Writeback
+    }
+}
+
+/// The disk seen by the guest. Reading, writing and seeking are done in the address space of the
+/// guest disk, and the end of the stream is the end of the disk.
+pub trait DiskImage: Read + Write + Seek + Send {
+    /// Returns the host file holding the image.
+    fn file(&self) -> &File;
+
+    /// Returns the host file if the offsets in the guest disk are also the offsets in the file,
+    /// which is required for handing the I/O directly to the kernel.
+    fn raw_file(&self) -> Option<&File> {
+        None
+    }
+
+    /// Lets the image release the space used by `len` bytes at `offset` in the guest disk, which
+    /// read as anything afterwards. The images that cannot release space ignore it.
+    fn discard(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Makes `len` bytes at `offset` in the guest disk read as zeroes. With `unmap`, the image may
+    /// also release the space they use.
+    fn write_zeroes(&mut self, offset: u64, len: u64, _unmap: bool) -> io::Result<()> {
+        write_zero_buffers(self, offset, len)
+    }
+
+    /// Makes the writes done so far reach the disk. Unlike `flush`, which only empties the
+    /// buffers of the image, this waits for the host to write the data back.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+// Writes zeroes the slow way, for the images and the files that cannot do better.
+fn write_zero_buffers<T: Write + Seek + ?Sized>(
+    image: &mut T,
+    offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    const ZEROES_SIZE: u64 = 64 << 10;
+    let zeroes = vec![0u8; cmp::min(len, ZEROES_SIZE) as usize];
+    image.seek(SeekFrom::Start(offset))?;
+    let mut done = 0;
+    while done < len {
+        let chunk = cmp::min(len - done, ZEROES_SIZE) as usize;
+        image.write_all(&zeroes[..chunk])?;
+        done += chunk as u64;
+    }
+    Ok(())
+}
+
+// Runs `fallocate` with `mode` on the range, without changing the size of the file. Returns
+// `Ok(false)` if the file system or the block device does not support the operation.
+fn fallocate(file: &File, mode: libc::c_int, offset: u64, len: u64) -> io::Result<bool> {
+    // This is safe because only the range of the file is affected, and the return value is
+    // checked.
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            mode | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+// Adds a signed offset to a position in a disk image, for implementing `Seek`.
+pub(super) fn checked_add_signed(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+impl DiskImage for File {
+    fn file(&self) -> &File {
+        self
+    }
+
+    fn raw_file(&self) -> Option<&File> {
+        Some(self)
+    }
+
+    fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        fallocate(self, libc::FALLOC_FL_PUNCH_HOLE, offset, len).map(|_| ())
+    }
+
+    fn write_zeroes(&mut self, offset: u64, len: u64, unmap: bool) -> io::Result<()> {
+        // Punched holes read as zeroes.
+        let mode = if unmap {
+            libc::FALLOC_FL_PUNCH_HOLE
+        } else {
+            libc::FALLOC_FL_ZERO_RANGE
+        };
+        if fallocate(self, mode, offset, len)? {
+            return Ok(());
+        }
+        write_zero_buffers(self, offset, len)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Opens the image of format `format` found at `path`. Any backing file of the image is opened
+/// read-only.
+///
+/// With an `overlay_path`, the image is opened read-only as well, and the writes go to the
+/// overlay file instead, which is created if it does not exist. With the `Writethrough` cache
+/// type, the written file is opened with `O_DSYNC`.
+pub fn open_disk_image(
+    path: &Path,
+    is_read_only: bool,
+    format: DiskImageFormat,
+    overlay_path: Option<&Path>,
+    cache_type: CacheType,
+) -> io::Result<Box<dyn DiskImage>> {
+    let sync_flags = match cache_type {
+        CacheType::Writethrough => libc::O_DSYNC,
+        CacheType::Unsafe | CacheType::Writeback => 0,
+    };
+    let is_base_written = !is_read_only && overlay_path.is_none();
+    let file = OpenOptions::new()
+        .read(true)
+        .write(is_base_written)
+        .custom_flags(if is_base_written { sync_flags } else { 0 })
+        .open(path)?;
+    let image: Box<dyn DiskImage> = match format {
+        DiskImageFormat::Raw => Box::new(file),
+        DiskImageFormat::Qcow2 => Box::new(QcowFile::new(file, path)?),
+    };
+    match overlay_path {
+        Some(overlay_path) => {
+            let overlay = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .custom_flags(sync_flags)
+                .open(overlay_path)?;
+            Ok(Box::new(OverlayImage::new(image, overlay)?))
+        }
+        None => Ok(image),
+    }
+}
@@ -0,0 +1,2957 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Portions Copyright 2017 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the THIRD-PARTY file.
+
+use byteorder::{ByteOrder, LittleEndian};
+use epoll;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::linux::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, DescriptorChain, Queue, VirtioDevice, TYPE_BLOCK,
+    VIRTIO_MMIO_INT_VRING,
+};
+use logger::metrics::BlockDeviceMetrics;
+use logger::{DeviceMetrics, Metric};
+use memory_model::{GuestAddress, GuestMemory, GuestMemoryError};
+use rate_limiter::{RateLimiter, TokenType};
+use sys_util::{EventFd, IoUring, Operation};
+use virtio::EpollConfigConstructor;
+use virtio_gen::virtio_blk::*;
+use {DeviceEventT, EpollHandler};
+
+mod disk_image;
+mod overlay;
+mod qcow;
+
+pub use self::disk_image::*;
+
+// Size of the config space, up to the write zeroes limits.
+const CONFIG_SPACE_SIZE: usize = 60;
+const SECTOR_SHIFT: u8 = 9;
+pub const SECTOR_SIZE: u64 = (0x01 as u64) << SECTOR_SHIFT;
+// Limits of the discard and write zeroes requests. Each request holds a single range of sectors,
+// and the ranges are aligned on pages.
+const MAX_DISCARD_SECTORS: u32 = u32::max_value() >> SECTOR_SHIFT;
+const MAX_DISCARD_SEGMENTS: u32 = 1;
+const DISCARD_SECTOR_ALIGNMENT: u32 = 4096 >> SECTOR_SHIFT;
+// Size of a range of sectors of a discard or write zeroes request.
+const DISCARD_SEGMENT_SIZE: u32 = 16;
+// The guest kernel does not support logical blocks larger than its pages.
+const MAX_LOGICAL_BLOCK_SIZE: u32 = 4096;
+const QUEUE_SIZE: u16 = 256;
+/// The maximum number of request queues of a block device.
+pub const BLOCK_MAX_QUEUES: u16 = 16;
+
+// Rate limiter budget is now available.
+const RATE_LIMITER_EVENT: DeviceEventT = 0;
+// Requests submitted to the io_uring have completed.
+const COMPLETION_EVENT: DeviceEventT = 1;
+// New descriptors are pending on the first virtio queue. The events of the other queues follow.
+const QUEUE_AVAIL_EVENT: DeviceEventT = 2;
+
+/// Number of DeviceEventT events supported by a block device with `num_queues` queues.
+pub fn block_events_count(num_queues: u16) -> usize {
+    QUEUE_AVAIL_EVENT as usize + num_queues as usize
+}
+
+/// The engine used by a block device for performing the disk I/O.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum IoEngine {
+    /// Blocking I/O, performed in the thread handling the device events.
+    Sync,
+    /// Asynchronous I/O, submitted through an io_uring.
+    Async,
+}
+
+impl Default for IoEngine {
+    fn default() -> IoEngine {
+        IoEngine::Sync
+    }
+}
+
+/// The block sizes a block device reports to the guest, in bytes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockTopology {
+    /// The smallest unit the guest can address, and the alignment of its requests. A power of two
+    /// between 512 and 4096.
+    pub logical_block_size: u32,
+    /// The smallest unit the disk writes without a read-modify-write cycle. A power of two, at
+    /// least as large as the logical block size.
+    pub physical_block_size: u32,
+    /// The preferred size of the requests, a multiple of the physical block size. 0 if the disk
+    /// has no preference.
+    #[serde(default)]
+    pub optimal_io_size: u32,
+}
+
+impl BlockTopology {
+    /// Checks the constraints on the block sizes.
+    pub fn is_valid(&self) -> bool {
+        self.logical_block_size.is_power_of_two()
+            && self.logical_block_size >= SECTOR_SIZE as u32
+            && self.logical_block_size <= MAX_LOGICAL_BLOCK_SIZE
+            && self.physical_block_size.is_power_of_two()
+            && self.physical_block_size >= self.logical_block_size
+            && self.optimal_io_size % self.physical_block_size == 0
+    }
+}
+
+impl Default for BlockTopology {
+    fn default() -> BlockTopology {
+        BlockTopology {
+            logical_block_size: SECTOR_SIZE as u32,
+            physical_block_size: SECTOR_SIZE as u32,
+            optimal_io_size: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Guest gave us offsets that would have overflowed a usize.
+    CheckedOffset(GuestAddress, usize),
+    /// Guest gave us a write only descriptor that protocol says to read from.
+    UnexpectedWriteOnlyDescriptor,
+    /// Guest gave us a read only descriptor that protocol says to write to.
+    UnexpectedReadOnlyDescriptor,
+    /// Guest gave us too few descriptors in a descriptor chain.
+    DescriptorChainTooShort,
+    /// Guest gave us a descriptor that was too short to use.
+    DescriptorLengthTooSmall,
+    /// Getting a block's metadata fails for any reason.
+    GetFileMetadata,
+    /// The requested operation would cause a seek beyond disk end.
+    InvalidOffset,
+    /// Guest gave us a discard or write zeroes request with a bad number of ranges.
+    InvalidSegmentCount,
+    /// Guest gave us a read or write request that is not aligned on logical blocks.
+    UnalignedRequest,
+}
+
+#[derive(Debug)]
+enum ExecuteError {
+    BadRequest(Error),
+    Flush(io::Error),
+    Read(GuestMemoryError),
+    Seek(io::Error),
+    Write(GuestMemoryError),
+    Submit(io::Error),
+    Discard(io::Error),
+    WriteZeroes(io::Error),
+    Unsupported(u32),
+}
+
+impl ExecuteError {
+    fn status(&self) -> u32 {
+        match *self {
+            ExecuteError::BadRequest(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Flush(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Read(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Seek(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Write(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Submit(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Discard(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::WriteZeroes(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Unsupported(_) => VIRTIO_BLK_S_UNSUPP,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RequestType {
+    In,
+    Out,
+    Flush,
+    GetDeviceID,
+    Discard,
+    WriteZeroes,
+    Unsupported(u32),
+}
+
+fn request_type(mem: &GuestMemory, desc_addr: GuestAddress) -> result::Result<RequestType, Error> {
+    let type_ = mem
+        .read_obj_from_addr(desc_addr)
+        .map_err(Error::GuestMemory)?;
+    match type_ {
+        VIRTIO_BLK_T_IN => Ok(RequestType::In),
+        VIRTIO_BLK_T_OUT => Ok(RequestType::Out),
+        VIRTIO_BLK_T_FLUSH => Ok(RequestType::Flush),
+        VIRTIO_BLK_T_GET_ID => Ok(RequestType::GetDeviceID),
+        VIRTIO_BLK_T_DISCARD => Ok(RequestType::Discard),
+        VIRTIO_BLK_T_WRITE_ZEROES => Ok(RequestType::WriteZeroes),
+        t => Ok(RequestType::Unsupported(t)),
+    }
+}
+
+fn sector(mem: &GuestMemory, desc_addr: GuestAddress) -> result::Result<u64, Error> {
+    const SECTOR_OFFSET: usize = 8;
+    let addr = match mem.checked_offset(desc_addr, SECTOR_OFFSET) {
+        Some(v) => v,
+        None => return Err(Error::CheckedOffset(desc_addr, SECTOR_OFFSET)),
+    };
+
+    mem.read_obj_from_addr(addr).map_err(Error::GuestMemory)
+}
+
+fn build_device_id(disk_image: &File) -> result::Result<String, Error> {
+    let blk_metadata = match disk_image.metadata() {
+        Err(_) => return Err(Error::GetFileMetadata),
+        Ok(m) => m,
+    };
+    // This is how kvmtool does it.
+    let device_id = format!(
+        "{}{}{}",
+        blk_metadata.st_dev(),
+        blk_metadata.st_rdev(),
+        blk_metadata.st_ino()
+    )
+    .to_owned();
+    Ok(device_id)
+}
+
+fn build_disk_image_id(disk_image: &File) -> Vec<u8> {
+    let mut default_disk_image_id = vec![0; VIRTIO_BLK_ID_BYTES as usize];
+    match build_device_id(disk_image) {
+        Err(_) => {
+            warn!("Could not generate device id. We'll use a default.");
+        }
+        Ok(m) => {
+            // The kernel only knows to read a maximum of VIRTIO_BLK_ID_BYTES.
+            // This will also zero out any leftover bytes.
+            let disk_id = m.as_bytes();
+            let bytes_to_copy = cmp::min(disk_id.len(), VIRTIO_BLK_ID_BYTES as usize);
+            default_disk_image_id[..bytes_to_copy].clone_from_slice(&disk_id[..bytes_to_copy])
+        }
+    }
+    default_disk_image_id
+}
+
+struct Request {
+    request_type: RequestType,
+    sector: u64,
+    data_addr: GuestAddress,
+    data_len: u32,
+    status_addr: GuestAddress,
+}
+
+impl Request {
+    fn parse(avail_desc: &DescriptorChain, mem: &GuestMemory) -> result::Result<Request, Error> {
+        // The head contains the request type which MUST be readable.
+        if avail_desc.is_write_only() {
+            return Err(Error::UnexpectedWriteOnlyDescriptor);
+        }
+
+        let mut req = Request {
+            request_type: request_type(&mem, avail_desc.addr)?,
+            sector: sector(&mem, avail_desc.addr)?,
+            data_addr: GuestAddress(0),
+            data_len: 0,
+            status_addr: GuestAddress(0),
+        };
+
+        let data_desc;
+        let status_desc;
+        let desc = avail_desc
+            .next_descriptor()
+            .ok_or(Error::DescriptorChainTooShort)?;
+
+        if !desc.has_next() {
+            status_desc = desc;
+            // Only flush requests are allowed to skip the data descriptor.
+            if req.request_type != RequestType::Flush {
+                return Err(Error::DescriptorChainTooShort);
+            }
+        } else {
+            data_desc = desc;
+            status_desc = data_desc
+                .next_descriptor()
+                .ok_or(Error::DescriptorChainTooShort)?;
+
+            if data_desc.is_write_only() && req.is_write() {
+                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            }
+            if !data_desc.is_write_only() && req.request_type == RequestType::In {
+                return Err(Error::UnexpectedReadOnlyDescriptor);
+            }
+            if !data_desc.is_write_only() && req.request_type == RequestType::GetDeviceID {
+                return Err(Error::UnexpectedReadOnlyDescriptor);
+            }
+
+            req.data_addr = data_desc.addr;
+            req.data_len = data_desc.len;
+        }
+
+        // The status MUST always be writable.
+        if !status_desc.is_write_only() {
+            return Err(Error::UnexpectedReadOnlyDescriptor);
+        }
+
+        if status_desc.len < 1 {
+            return Err(Error::DescriptorLengthTooSmall);
+        }
+
+        req.status_addr = status_desc.addr;
+
+        Ok(req)
+    }
+
+    // Reads and writes start on a logical block. With blocks larger than a sector, they also
+    // cover whole blocks, while the default 512-byte blocks keep accepting partial sectors.
+    fn check_alignment(&self, logical_block_size: u32) -> result::Result<(), ExecuteError> {
+        if self.request_type != RequestType::In && self.request_type != RequestType::Out {
+            return Ok(());
+        }
+        let logical_block_size = u64::from(logical_block_size);
+        if (self.sector << SECTOR_SHIFT) % logical_block_size != 0
+            || (logical_block_size > SECTOR_SIZE
+                && u64::from(self.data_len) % logical_block_size != 0)
+        {
+            return Err(ExecuteError::BadRequest(Error::UnalignedRequest));
+        }
+        Ok(())
+    }
+
+    fn check_offset(&self, disk_nsectors: u64) -> result::Result<(), ExecuteError> {
+        let mut top: u64 = u64::from(self.data_len) / SECTOR_SIZE;
+        if u64::from(self.data_len) % SECTOR_SIZE != 0 {
+            top += 1;
+        }
+        top = top
+            .checked_add(self.sector)
+            .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+        if top > disk_nsectors {
+            return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+        }
+        Ok(())
+    }
+
+    // The requests reading their data from the guest, and modifying the disk.
+    fn is_write(&self) -> bool {
+        match self.request_type {
+            RequestType::Out | RequestType::Discard | RequestType::WriteZeroes => true,
+            _ => false,
+        }
+    }
+
+    // Only the requests accessing the disk are worth being performed asynchronously.
+    fn is_async(&self) -> bool {
+        match self.request_type {
+            RequestType::In | RequestType::Out | RequestType::Flush => true,
+            _ => false,
+        }
+    }
+
+    #[allow(clippy::ptr_arg)]
+    fn execute<T: DiskImage + ?Sized>(
+        &self,
+        mut disk: &mut T,
+        disk_nsectors: u64,
+        logical_block_size: u32,
+        mem: &GuestMemory,
+        disk_id: &Vec<u8>,
+        metrics: &DeviceMetrics<BlockDeviceMetrics>,
+    ) -> result::Result<u32, ExecuteError> {
+        match self.request_type {
+            RequestType::Discard | RequestType::WriteZeroes => {
+                return self.execute_segments(disk, disk_nsectors, mem, metrics);
+            }
+            _ => (),
+        }
+
+        self.check_offset(disk_nsectors)?;
+        self.check_alignment(logical_block_size)?;
+
+        disk.seek(SeekFrom::Start(self.sector << SECTOR_SHIFT))
+            .map_err(ExecuteError::Seek)?;
+
+        match self.request_type {
+            RequestType::In => {
+                mem.read_to_memory(self.data_addr, &mut disk, self.data_len as usize)
+                    .map_err(ExecuteError::Read)?;
+                metrics.update(|m| {
+                    m.read_bytes.add(self.data_len as usize);
+                    m.read_count.inc();
+                });
+                return Ok(self.data_len);
+            }
+            RequestType::Out => {
+                mem.write_from_memory(self.data_addr, &mut disk, self.data_len as usize)
+                    .map_err(ExecuteError::Write)?;
+                metrics.update(|m| {
+                    m.write_bytes.add(self.data_len as usize);
+                    m.write_count.inc();
+                });
+            }
+            RequestType::Flush => match disk.sync() {
+                Ok(_) => {
+                    metrics.update(|m| m.flush_count.inc());
+                    return Ok(0);
+                }
+                Err(e) => return Err(ExecuteError::Flush(e)),
+            },
+            RequestType::GetDeviceID => {
+                if (self.data_len as usize) < disk_id.len() {
+                    return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+                }
+                mem.write_slice_at_addr(&disk_id.as_slice(), self.data_addr)
+                    .map_err(ExecuteError::Write)?;
+            }
+            // Handled above.
+            RequestType::Discard | RequestType::WriteZeroes => (),
+            RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
+        };
+        Ok(0)
+    }
+
+    // Performs a discard or write zeroes request on each of the ranges of sectors it holds.
+    fn execute_segments<T: DiskImage + ?Sized>(
+        &self,
+        disk: &mut T,
+        disk_nsectors: u64,
+        mem: &GuestMemory,
+        metrics: &DeviceMetrics<BlockDeviceMetrics>,
+    ) -> result::Result<u32, ExecuteError> {
+        if self.data_len == 0
+            || self.data_len % DISCARD_SEGMENT_SIZE != 0
+            || self.data_len / DISCARD_SEGMENT_SIZE > MAX_DISCARD_SEGMENTS
+        {
+            return Err(ExecuteError::BadRequest(Error::InvalidSegmentCount));
+        }
+
+        for i in 0..self.data_len / DISCARD_SEGMENT_SIZE {
+            let segment =
+                DiscardSegment::read(mem, self.data_addr, i).map_err(ExecuteError::BadRequest)?;
+            let end = segment
+                .sector
+                .checked_add(u64::from(segment.num_sectors))
+                .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+            if segment.num_sectors > MAX_DISCARD_SECTORS || end > disk_nsectors {
+                return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+            }
+
+            let offset = segment.sector << SECTOR_SHIFT;
+            let len = u64::from(segment.num_sectors) << SECTOR_SHIFT;
+            if self.request_type == RequestType::Discard {
+                // The unmap flag is only valid for write zeroes requests.
+                if segment.flags != 0 {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_DISCARD));
+                }
+                disk.discard(offset, len).map_err(ExecuteError::Discard)?;
+            } else {
+                if segment.flags & !VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_WRITE_ZEROES));
+                }
+                let unmap = segment.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0;
+                disk.write_zeroes(offset, len, unmap)
+                    .map_err(ExecuteError::WriteZeroes)?;
+            }
+        }
+
+        if self.request_type == RequestType::Discard {
+            metrics.update(|m| m.discard_count.inc());
+        } else {
+            metrics.update(|m| m.write_zeroes_count.inc());
+        }
+        Ok(0)
+    }
+}
+
+// A range of sectors of a discard or write zeroes request.
+struct DiscardSegment {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+impl DiscardSegment {
+    // Reads the range of index `index` from the data of the request at `data_addr`.
+    fn read(
+        mem: &GuestMemory,
+        data_addr: GuestAddress,
+        index: u32,
+    ) -> result::Result<DiscardSegment, Error> {
+        let offset = (index * DISCARD_SEGMENT_SIZE) as usize;
+        let addr = mem
+            .checked_offset(data_addr, offset)
+            .ok_or(Error::CheckedOffset(data_addr, offset))?;
+        let read_u32_at = |field_offset: usize| {
+            mem.checked_offset(addr, field_offset)
+                .ok_or(Error::CheckedOffset(addr, field_offset))
+                .and_then(|field_addr| {
+                    mem.read_obj_from_addr::<u32>(field_addr)
+                        .map_err(Error::GuestMemory)
+                })
+        };
+        Ok(DiscardSegment {
+            sector: mem.read_obj_from_addr(addr).map_err(Error::GuestMemory)?,
+            num_sectors: read_u32_at(8)?,
+            flags: read_u32_at(12)?,
+        })
+    }
+}
+
+// Counts the time taken by a read, write or flush request in the metrics of the device.
+fn record_latency(
+    metrics: &DeviceMetrics<BlockDeviceMetrics>,
+    request_type: RequestType,
+    latency: Duration,
+) {
+    match request_type {
+        RequestType::In => metrics.update(|m| m.read_latency_us.record(latency)),
+        RequestType::Out => metrics.update(|m| m.write_latency_us.record(latency)),
+        RequestType::Flush => metrics.update(|m| m.flush_latency_us.record(latency)),
+        _ => (),
+    }
+}
+
+// Counts a successful read or write request in the metrics of the queue it was received on.
+fn record_queue_request(
+    metrics: &DeviceMetrics<BlockDeviceMetrics>,
+    queue_index: usize,
+    request_type: RequestType,
+) {
+    match request_type {
+        RequestType::In => metrics.update(|m| m.queues.get(queue_index).read_count.inc()),
+        RequestType::Out => metrics.update(|m| m.queues.get(queue_index).write_count.inc()),
+        _ => (),
+    }
+}
+
+// A request submitted to the io_uring, which completes when its completion is popped.
+struct PendingRequest {
+    queue_index: usize,
+    request_type: RequestType,
+    data_len: u32,
+    status_addr: GuestAddress,
+    submitted_at: Instant,
+}
+
+// The io_uring of a block device using the `Async` engine, shared by all its queues. The requests
+// in flight are indexed by their user data, which holds their queue and the head of their
+// descriptor chain.
+struct AsyncIo {
+    ring: IoUring,
+    completion_evt: EventFd,
+    pending: HashMap<u64, PendingRequest>,
+}
+
+impl AsyncIo {
+    fn new(num_queues: u16) -> io::Result<AsyncIo> {
+        // A request holds its descriptor chain until it completes, so there cannot be more
+        // requests in flight than descriptors in the queues.
+        let ring = IoUring::new(u32::from(QUEUE_SIZE) * u32::from(num_queues))?;
+        let completion_evt = EventFd::new()?;
+        ring.register_eventfd(&completion_evt)?;
+        Ok(AsyncIo {
+            ring,
+            completion_evt,
+            pending: HashMap::new(),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn submit(
+        &mut self,
+        request: &Request,
+        queue_index: usize,
+        index: u16,
+        disk: &File,
+        disk_nsectors: u64,
+        logical_block_size: u32,
+        mem: &GuestMemory,
+    ) -> result::Result<(), ExecuteError> {
+        request.check_offset(disk_nsectors)?;
+        request.check_alignment(logical_block_size)?;
+
+        let offset = request.sector << SECTOR_SHIFT;
+        let user_data = ((queue_index as u64) << 16) | u64::from(index);
+        let op = match request.request_type {
+            RequestType::In => {
+                let buf = mem
+                    .get_host_address_range(request.data_addr, request.data_len as usize)
+                    .map_err(ExecuteError::Read)?;
+                Operation::read(disk.as_raw_fd(), buf, request.data_len, offset, user_data)
+            }
+            RequestType::Out => {
+                let buf = mem
+                    .get_host_address_range(request.data_addr, request.data_len as usize)
+                    .map_err(ExecuteError::Write)?;
+                Operation::write(disk.as_raw_fd(), buf, request.data_len, offset, user_data)
+            }
+            _ => Operation::fsync(disk.as_raw_fd(), user_data),
+        };
+        // This is safe because the buffer is guest memory, which outlives the io_uring, and the
+        // guest does not get the descriptor chain back before the request completes.
+        unsafe { self.ring.push(op) }.map_err(ExecuteError::Submit)?;
+
+        self.pending.insert(
+            user_data,
+            PendingRequest {
+                queue_index,
+                request_type: request.request_type,
+                data_len: request.data_len,
+                status_addr: request.status_addr,
+                submitted_at: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Handler that drives the execution of the Block devices
+pub struct BlockEpollHandler {
+    queues: Vec<Queue>,
+    mem: GuestMemory,
+    disk_image: Box<dyn DiskImage>,
+    disk_nsectors: u64,
+    interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    queue_evts: Vec<EventFd>,
+    // The queue processed first after the rate limiter gets unblocked.
+    next_queue: usize,
+    rate_limiter: RateLimiter,
+    disk_image_id: Vec<u8>,
+    async_io: Option<AsyncIo>,
+    cache_type: CacheType,
+    logical_block_size: u32,
+    metrics: DeviceMetrics<BlockDeviceMetrics>,
+}
+
+impl BlockEpollHandler {
+    fn process_queue(&mut self, queue_index: usize) -> bool {
+        let queue = &mut self.queues[queue_index];
+        let mut used_any = false;
+
+        while let Some(head) = queue.pop(&self.mem) {
+            let len;
+            match Request::parse(&head, &self.mem) {
+                Ok(request) => {
+                    // If limiter.consume() fails it means there is no more TokenType::Ops
+                    // budget and rate limiting is in effect.
+                    if !self.rate_limiter.consume(1, TokenType::Ops) {
+                        // Stop processing the queue and return this descriptor chain to the
+                        // avail ring, for later processing.
+                        queue.undo_pop();
+                        break;
+                    }
+                    // Exercise the rate limiter only if this request is of data transfer type.
+                    if request.request_type == RequestType::In
+                        || request.request_type == RequestType::Out
+                    {
+                        // If limiter.consume() fails it means there is no more TokenType::Bytes
+                        // budget and rate limiting is in effect.
+                        if !self
+                            .rate_limiter
+                            .consume(u64::from(request.data_len), TokenType::Bytes)
+                        {
+                            // Revert the OPS consume().
+                            self.rate_limiter.manual_replenish(1, TokenType::Ops);
+                            // Stop processing the queue and return this descriptor chain to the
+                            // avail ring, for later processing.
+                            queue.undo_pop();
+                            break;
+                        }
+                    }
+                    let start = Instant::now();
+                    let result = match (self.async_io.as_mut(), self.disk_image.raw_file()) {
+                        // The flushes complete right away with the `Unsafe` cache type.
+                        _ if request.request_type == RequestType::Flush
+                            && self.cache_type == CacheType::Unsafe =>
+                        {
+                            Ok(Some(0))
+                        }
+                        (Some(async_io), Some(disk)) if request.is_async() => async_io
+                            .submit(
+                                &request,
+                                queue_index,
+                                head.index,
+                                disk,
+                                self.disk_nsectors,
+                                self.logical_block_size,
+                                &self.mem,
+                            )
+                            .map(|_| None),
+                        _ => request
+                            .execute(
+                                self.disk_image.as_mut(),
+                                self.disk_nsectors,
+                                self.logical_block_size,
+                                &self.mem,
+                                &self.disk_image_id,
+                                &self.metrics,
+                            )
+                            .map(Some),
+                    };
+                    let status = match result {
+                        // The request is added to the used ring once it completes.
+                        Ok(None) => continue,
+                        Ok(Some(l)) => {
+                            len = l;
+                            record_queue_request(&self.metrics, queue_index, request.request_type);
+                            VIRTIO_BLK_S_OK
+                        }
+                        Err(e) => {
+                            error!("Failed to execute request: {:?}", e);
+                            self.metrics.update(|m| m.invalid_reqs_count.inc());
+                            len = 1; // We need at least 1 byte for the status.
+                            e.status()
+                        }
+                    };
+                    record_latency(&self.metrics, request.request_type, start.elapsed());
+                    // We use unwrap because the request parsing process already checked that the
+                    // status_addr was valid.
+                    self.mem
+                        .write_obj_at_addr(status, request.status_addr)
+                        .unwrap();
+                }
+                Err(e) => {
+                    error!("Failed to parse available descriptor chain: {:?}", e);
+                    self.metrics.update(|m| m.execute_fails.inc());
+                    len = 0;
+                }
+            }
+            queue.add_used(&self.mem, head.index, len);
+            used_any = true;
+        }
+
+        if let Some(ref mut async_io) = self.async_io {
+            if let Err(e) = async_io.ring.submit() {
+                error!("Failed to submit requests: {:?}", e);
+                self.metrics.update(|m| m.execute_fails.inc());
+            }
+        }
+
+        used_any
+    }
+
+    // Processes all the queues, which share the budget of the rate limiter. A different queue is
+    // processed first each time, so that none of them is starved by the others.
+    fn process_queues(&mut self) -> bool {
+        let num_queues = self.queues.len();
+        let mut used_any = false;
+        for i in 0..num_queues {
+            used_any |= self.process_queue((self.next_queue + i) % num_queues);
+        }
+        self.next_queue = (self.next_queue + 1) % num_queues;
+        used_any
+    }
+
+    // Adds the completed asynchronous requests to the used rings of their queues.
+    fn process_completions(&mut self) -> bool {
+        let async_io = match self.async_io {
+            Some(ref mut async_io) => async_io,
+            None => return false,
+        };
+        let mut used_any = false;
+
+        while let Some(completion) = async_io.ring.pop() {
+            let request = match async_io.pending.remove(&completion.user_data) {
+                Some(request) => request,
+                None => {
+                    error!("Unknown request completed: {}", completion.user_data);
+                    continue;
+                }
+            };
+            let index = completion.user_data as u16;
+            let expected_len = match request.request_type {
+                RequestType::In | RequestType::Out => request.data_len,
+                _ => 0,
+            };
+            let (status, len) = match completion.result() {
+                Ok(transferred) if transferred == expected_len => {
+                    match request.request_type {
+                        RequestType::In => {
+                            self.metrics.update(|m| {
+                                m.read_bytes.add(request.data_len as usize);
+                                m.read_count.inc();
+                            });
+                        }
+                        RequestType::Out => {
+                            self.metrics.update(|m| {
+                                m.write_bytes.add(request.data_len as usize);
+                                m.write_count.inc();
+                            });
+                        }
+                        _ => self.metrics.update(|m| m.flush_count.inc()),
+                    }
+                    record_queue_request(&self.metrics, request.queue_index, request.request_type);
+                    let len = if request.request_type == RequestType::In {
+                        request.data_len
+                    } else {
+                        0
+                    };
+                    (VIRTIO_BLK_S_OK, len)
+                }
+                result => {
+                    error!("Failed to execute request: {:?}", result);
+                    self.metrics.update(|m| m.invalid_reqs_count.inc());
+                    // We need at least 1 byte for the status.
+                    (VIRTIO_BLK_S_IOERR, 1)
+                }
+            };
+            record_latency(
+                &self.metrics,
+                request.request_type,
+                request.submitted_at.elapsed(),
+            );
+            // We use unwrap because the request parsing process already checked that the
+            // status_addr was valid.
+            self.mem
+                .write_obj_at_addr(status, request.status_addr)
+                .unwrap();
+            self.queues[request.queue_index].add_used(&self.mem, index, len);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            self.metrics.update(|m| m.event_fails.inc());
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Update the backing file for the Block device
+    pub fn update_disk_image(
+        &mut self,
+        disk_image: Box<dyn DiskImage>,
+    ) -> result::Result<(), DeviceError> {
+        self.disk_image = disk_image;
+        self.disk_nsectors = self
+            .disk_image
+            .seek(SeekFrom::End(0))
+            .map_err(DeviceError::IoError)?
+            / SECTOR_SIZE;
+        self.disk_image_id = build_disk_image_id(self.disk_image.file());
+        self.metrics.update(|m| m.update_count.inc());
+        Ok(())
+    }
+}
+
+impl EpollHandler for BlockEpollHandler {
+    fn handle_event(
+        &mut self,
+        device_event: DeviceEventT,
+        _evset: epoll::Events,
+    ) -> result::Result<(), DeviceError> {
+        match device_event {
+            RATE_LIMITER_EVENT => {
+                self.metrics.update(|m| m.rate_limiter_event_count.inc());
+                // Upon rate limiter event, call the rate limiter handler
+                // and restart processing the queues.
+                match self.rate_limiter.event_handler() {
+                    Ok(throttled) => {
+                        self.metrics
+                            .update(|m| m.rate_limiter_throttled_us.record(throttled));
+                        if self.process_queues() {
+                            self.signal_used_queue()
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    Err(_) => Ok(()),
+                }
+            }
+            COMPLETION_EVENT => {
+                if let Some(ref async_io) = self.async_io {
+                    if let Err(e) = async_io.completion_evt.read() {
+                        error!("Failed to get completion event: {:?}", e);
+                        self.metrics.update(|m| m.event_fails.inc());
+                        return Err(DeviceError::FailedReadingQueue {
+                            event_type: "completion event",
+                            underlying: e,
+                        });
+                    }
+                }
+                if self.process_completions() {
+                    self.signal_used_queue()
+                } else {
+                    Ok(())
+                }
+            }
+            queue_event
+                if queue_event >= QUEUE_AVAIL_EVENT
+                    && usize::from(queue_event - QUEUE_AVAIL_EVENT) < self.queues.len() =>
+            {
+                let queue_index = usize::from(queue_event - QUEUE_AVAIL_EVENT);
+                self.metrics.update(|m| {
+                    m.queue_event_count.inc();
+                    m.queues.get(queue_index).queue_event_count.inc();
+                });
+                if let Err(e) = self.queue_evts[queue_index].read() {
+                    error!("Failed to get queue event: {:?}", e);
+                    self.metrics.update(|m| m.event_fails.inc());
+                    Err(DeviceError::FailedReadingQueue {
+                        event_type: "queue event",
+                        underlying: e,
+                    })
+                } else if !self.rate_limiter.is_blocked() && self.process_queue(queue_index) {
+                    self.signal_used_queue()
+                } else {
+                    // While limiter is blocked, don't process any more requests.
+                    Ok(())
+                }
+            }
+            unknown => Err(DeviceError::UnknownEvent {
+                device: "block",
+                event: unknown,
+            }),
+        }
+    }
+}
+
+pub struct EpollConfig {
+    rate_limiter_token: u64,
+    completion_token: u64,
+    // The token of the first queue. The tokens of the other queues follow.
+    q_avail_token: u64,
+    epoll_raw_fd: RawFd,
+    sender: mpsc::Sender<Box<EpollHandler>>,
+}
+
+impl EpollConfigConstructor for EpollConfig {
+    fn new(first_token: u64, epoll_raw_fd: RawFd, sender: mpsc::Sender<Box<EpollHandler>>) -> Self {
+        EpollConfig {
+            rate_limiter_token: first_token + u64::from(RATE_LIMITER_EVENT),
+            completion_token: first_token + u64::from(COMPLETION_EVENT),
+            q_avail_token: first_token + u64::from(QUEUE_AVAIL_EVENT),
+            epoll_raw_fd,
+            sender,
+        }
+    }
+}
+
+/// Virtio device for exposing block level read/write operations on a host file.
+pub struct Block {
+    disk_image: Option<Box<dyn DiskImage>>,
+    disk_nsectors: u64,
+    avail_features: u64,
+    acked_features: u64,
+    config_space: Vec<u8>,
+    queue_sizes: Vec<u16>,
+    epoll_config: EpollConfig,
+    rate_limiter: Option<RateLimiter>,
+    async_io: Option<AsyncIo>,
+    cache_type: CacheType,
+    logical_block_size: u32,
+    metrics: DeviceMetrics<BlockDeviceMetrics>,
+}
+
+fn build_config_space(disk_size: u64, num_queues: u16, topology: &BlockTopology) -> Vec<u8> {
+    // We support the disk size, which uses the first two words of the configuration space, the
+    // block sizes, the number of queues, and the limits of the discard and write zeroes requests.
+    // The other fields are left zeroed.
+    // If the image is not a multiple of the sector size, the tail bits are not exposed.
+    // The config space is little endian.
+    let mut config = vec![0u8; CONFIG_SPACE_SIZE];
+    LittleEndian::write_u64(&mut config[0..8], disk_size >> SECTOR_SHIFT);
+    LittleEndian::write_u32(&mut config[20..24], topology.logical_block_size);
+    // The topology is expressed in logical blocks, and the physical block as a power of two of
+    // them. The first logical block is aligned on a physical one.
+    let physical_blocks = topology.physical_block_size / topology.logical_block_size;
+    config[24] = physical_blocks.trailing_zeros() as u8;
+    LittleEndian::write_u16(&mut config[26..28], physical_blocks as u16);
+    LittleEndian::write_u32(
+        &mut config[28..32],
+        topology.optimal_io_size / topology.logical_block_size,
+    );
+    LittleEndian::write_u16(&mut config[34..36], num_queues);
+    LittleEndian::write_u32(&mut config[36..40], MAX_DISCARD_SECTORS);
+    LittleEndian::write_u32(&mut config[40..44], MAX_DISCARD_SEGMENTS);
+    LittleEndian::write_u32(
+        &mut config[44..48],
+        cmp::max(
+            DISCARD_SECTOR_ALIGNMENT,
+            topology.physical_block_size >> SECTOR_SHIFT,
+        ),
+    );
+    LittleEndian::write_u32(&mut config[48..52], MAX_DISCARD_SECTORS);
+    LittleEndian::write_u32(&mut config[52..56], MAX_DISCARD_SEGMENTS);
+    // Write zeroes requests with the unmap flag may release the space.
+    config[56] = 1;
+    config
+}
+
+impl Block {
+    /// Create a new virtio block device that operates on the given disk image.
+    ///
+    /// With the `Async` engine, the disk I/O is submitted through an io_uring, which requires a
+    /// raw disk image. The device has `num_queues` request queues, up to `BLOCK_MAX_QUEUES`.
+    /// With the `Writethrough` cache type, the disk image is expected to be opened with `O_DSYNC`,
+    /// and the flush feature is not offered to the guest. Without a `topology`, the guest sees
+    /// 512-byte blocks. The device updates `metrics`, which also count towards the totals of the
+    /// block devices.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mut disk_image: Box<dyn DiskImage>,
+        is_disk_read_only: bool,
+        io_engine: IoEngine,
+        num_queues: u16,
+        cache_type: CacheType,
+        topology: Option<BlockTopology>,
+        epoll_config: EpollConfig,
+        rate_limiter: Option<RateLimiter>,
+        metrics: DeviceMetrics<BlockDeviceMetrics>,
+    ) -> io::Result<Block> {
+        if num_queues == 0 || num_queues > BLOCK_MAX_QUEUES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid number of queues",
+            ));
+        }
+        if !topology.map_or(true, |topology| topology.is_valid()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid block sizes",
+            ));
+        }
+
+        let disk_size = disk_image.seek(SeekFrom::End(0))? as u64;
+        if disk_size % SECTOR_SIZE != 0 {
+            warn!(
+                "Disk size {} is not a multiple of sector size {}; \
+                 the remainder will not be visible to the guest.",
+                disk_size, SECTOR_SIZE
+            );
+        }
+
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+        if cache_type != CacheType::Writethrough {
+            avail_features |= 1u64 << VIRTIO_BLK_F_FLUSH;
+        }
+
+        if is_disk_read_only {
+            avail_features |= 1u64 << VIRTIO_BLK_F_RO;
+        } else {
+            avail_features |= (1u64 << VIRTIO_BLK_F_DISCARD) | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
+        };
+
+        if num_queues > 1 {
+            avail_features |= 1u64 << VIRTIO_BLK_F_MQ;
+        }
+
+        if topology.is_some() {
+            avail_features |= (1u64 << VIRTIO_BLK_F_BLK_SIZE) | (1u64 << VIRTIO_BLK_F_TOPOLOGY);
+        }
+        let topology = topology.unwrap_or_default();
+
+        let async_io = match io_engine {
+            IoEngine::Sync => None,
+            IoEngine::Async if disk_image.raw_file().is_none() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The Async I/O engine requires a raw disk image",
+                ));
+            }
+            IoEngine::Async => Some(AsyncIo::new(num_queues)?),
+        };
+
+        Ok(Block {
+            disk_image: Some(disk_image),
+            disk_nsectors: disk_size / SECTOR_SIZE,
+            avail_features,
+            acked_features: 0u64,
+            config_space: build_config_space(disk_size, num_queues, &topology),
+            queue_sizes: vec![QUEUE_SIZE; usize::from(num_queues)],
+            epoll_config,
+            rate_limiter,
+            async_io,
+            cache_type,
+            logical_block_size: topology.logical_block_size,
+            metrics,
+        })
+    }
+}
+
+impl VirtioDevice for Block {
+    fn device_type(&self) -> u32 {
+        TYPE_BLOCK
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.queue_sizes
+    }
+
+    fn features(&self, page: u32) -> u32 {
+        match page {
+            // Get the lower 32-bits of the features bitfield.
+            0 => self.avail_features as u32,
+            // Get the upper 32-bits of the features bitfield.
+            1 => (self.avail_features >> 32) as u32,
+            _ => {
+                warn!("Received request for unknown features page.");
+                0u32
+            }
+        }
+    }
+
+    fn ack_features(&mut self, page: u32, value: u32) {
+        let mut v = match page {
+            0 => u64::from(value),
+            1 => u64::from(value) << 32,
+            _ => {
+                warn!("Cannot acknowledge unknown features page.");
+                0u64
+            }
+        };
+
+        // Check if the guest is ACK'ing a feature that we didn't claim to have.
+        let unrequested_features = v & !self.avail_features;
+        if unrequested_features != 0 {
+            warn!("Received acknowledge request for unknown feature.");
+
+            // Don't count these features as acked.
+            v &= !unrequested_features;
+        }
+        self.acked_features |= v;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_len = self.config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            self.metrics.update(|m| m.cfg_fails.inc());
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&self.config_space[offset as usize..cmp::min(end, config_len) as usize])
+                .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let data_len = data.len() as u64;
+        let config_len = self.config_space.len() as u64;
+        if offset + data_len > config_len {
+            error!("Failed to write config space");
+            self.metrics.update(|m| m.cfg_fails.inc());
+            return;
+        }
+        self.config_space[offset as usize..(offset + data_len) as usize].copy_from_slice(data);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemory,
+        interrupt_evt: EventFd,
+        status: Arc<AtomicUsize>,
+        queues: Vec<Queue>,
+        queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        let num_queues = self.queue_sizes.len();
+        if queues.len() != num_queues || queue_evts.len() != num_queues {
+            error!(
+                "Cannot perform activate. Expected {} queue(s), got {}",
+                num_queues,
+                queues.len()
+            );
+            self.metrics.update(|m| m.activate_fails.inc());
+            return Err(ActivateError::BadActivate);
+        }
+
+        if let Some(disk_image) = self.disk_image.take() {
+            let queue_evt_raw_fds: Vec<RawFd> =
+                queue_evts.iter().map(|evt| evt.as_raw_fd()).collect();
+
+            let disk_image_id = build_disk_image_id(disk_image.file());
+            let handler = BlockEpollHandler {
+                queues,
+                mem,
+                disk_image,
+                disk_nsectors: self.disk_nsectors,
+                interrupt_status: status,
+                interrupt_evt,
+                queue_evts,
+                next_queue: 0,
+                rate_limiter: self.rate_limiter.take().unwrap_or_default(),
+                disk_image_id,
+                async_io: self.async_io.take(),
+                cache_type: self.cache_type,
+                logical_block_size: self.logical_block_size,
+                metrics: self.metrics.clone(),
+            };
+            let rate_limiter_rawfd = handler.rate_limiter.as_raw_fd();
+            let completion_rawfd = handler
+                .async_io
+                .as_ref()
+                .map(|async_io| async_io.completion_evt.as_raw_fd());
+
+            // The channel should be open at this point.
+            self.epoll_config
+                .sender
+                .send(Box::new(handler))
+                .expect("Failed to send through the channel");
+
+            //TODO: barrier needed here by any chance?
+            for (i, queue_evt_raw_fd) in queue_evt_raw_fds.into_iter().enumerate() {
+                epoll::ctl(
+                    self.epoll_config.epoll_raw_fd,
+                    epoll::ControlOptions::EPOLL_CTL_ADD,
+                    queue_evt_raw_fd,
+                    epoll::Event::new(
+                        epoll::Events::EPOLLIN,
+                        self.epoll_config.q_avail_token + i as u64,
+                    ),
+                )
+                .map_err(|e| {
+                    self.metrics.update(|m| m.activate_fails.inc());
+                    ActivateError::EpollCtl(e)
+                })?;
+            }
+
+            if rate_limiter_rawfd != -1 {
+                epoll::ctl(
+                    self.epoll_config.epoll_raw_fd,
+                    epoll::ControlOptions::EPOLL_CTL_ADD,
+                    rate_limiter_rawfd,
+                    epoll::Event::new(epoll::Events::EPOLLIN, self.epoll_config.rate_limiter_token),
+                )
+                .map_err(|e| {
+                    self.metrics.update(|m| m.activate_fails.inc());
+                    ActivateError::EpollCtl(e)
+                })?;
+            }
+
+            if let Some(completion_rawfd) = completion_rawfd {
+                epoll::ctl(
+                    self.epoll_config.epoll_raw_fd,
+                    epoll::ControlOptions::EPOLL_CTL_ADD,
+                    completion_rawfd,
+                    epoll::Event::new(epoll::Events::EPOLLIN, self.epoll_config.completion_token),
+                )
+                .map_err(|e| {
+                    self.metrics.update(|m| m.activate_fails.inc());
+                    ActivateError::EpollCtl(e)
+                })?;
+            }
+
+            return Ok(());
+        }
+        self.metrics.update(|m| m.activate_fails.inc());
+        Err(ActivateError::BadActivate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::{tempfile, NamedTempFile};
+    use super::*;
+
+    use logger::METRICS;
+
+    use libc;
+    use std::fs::{metadata, OpenOptions};
+    use std::io::Read;
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+    use std::time::Duration;
+    use std::u32;
+
+    use virtio::queue::tests::*;
+
+    const EPOLLIN: epoll::Events = epoll::Events::EPOLLIN;
+
+    /// Will read $metric, run the code in $block, then assert metric has increased by $delta.
+    macro_rules! check_metric_after_block {
+        ($metric:expr, $delta:expr, $block:expr) => {{
+            let before = $metric.count();
+            let _ = $block;
+            assert_eq!($metric.count(), before + $delta, "unexpected metric value");
+        }};
+    }
+
+    fn test_metrics() -> DeviceMetrics<BlockDeviceMetrics> {
+        DeviceMetrics::new(Arc::new(BlockDeviceMetrics::default()), &METRICS.block)
+    }
+
+    impl BlockEpollHandler {
+        fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+
+        fn get_rate_limiter(&self) -> &RateLimiter {
+            &self.rate_limiter
+        }
+
+        fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+            self.rate_limiter = rate_limiter;
+        }
+    }
+
+    struct DummyBlock {
+        block: Block,
+        epoll_raw_fd: i32,
+        _receiver: Receiver<Box<EpollHandler>>,
+    }
+
+    impl DummyBlock {
+        fn new(is_disk_read_only: bool) -> Self {
+            let epoll_raw_fd = epoll::create(true).unwrap();
+            let (sender, _receiver) = mpsc::channel();
+
+            let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+
+            let f: File = tempfile().unwrap();
+            f.set_len(0x1000).unwrap();
+
+            // Rate limiting is enabled but with a high operation rate (10 million ops/s).
+            let rate_limiter = RateLimiter::new(0, None, 0, 100_000, None, 10).unwrap();
+            DummyBlock {
+                block: Block::new(
+                    Box::new(f),
+                    is_disk_read_only,
+                    IoEngine::Sync,
+                    1,
+                    CacheType::Writeback,
+                    None,
+                    epoll_config,
+                    Some(rate_limiter),
+                    test_metrics(),
+                )
+                .unwrap(),
+                epoll_raw_fd,
+                _receiver,
+            }
+        }
+
+        fn block(&mut self) -> &mut Block {
+            &mut self.block
+        }
+    }
+
+    impl Drop for DummyBlock {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.epoll_raw_fd) };
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn default_test_blockepollhandler<'a>(
+        mem: &'a GuestMemory,
+    ) -> (BlockEpollHandler, VirtQueue<'a>) {
+        let mut dummy = DummyBlock::new(false);
+        let b = dummy.block();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+
+        assert!(vq.end().0 < 0x1000);
+
+        let queues = vec![vq.create_queue()];
+        let mut disk_image = b.disk_image.take().unwrap();
+        let disk_nsectors = disk_image.seek(SeekFrom::End(0)).unwrap() / SECTOR_SIZE;
+        let status = Arc::new(AtomicUsize::new(0));
+        let interrupt_evt = EventFd::new().unwrap();
+        let queue_evt = EventFd::new().unwrap();
+
+        let disk_image_id_str = build_device_id(disk_image.file()).unwrap();
+        let mut disk_image_id = vec![0; VIRTIO_BLK_ID_BYTES as usize];
+        let disk_image_id_bytes = disk_image_id_str.as_bytes();
+        let bytes_to_copy = cmp::min(disk_image_id_bytes.len(), VIRTIO_BLK_ID_BYTES as usize);
+        disk_image_id[..bytes_to_copy].clone_from_slice(&disk_image_id_bytes[..bytes_to_copy]);
+        (
+            BlockEpollHandler {
+                queues,
+                mem: mem.clone(),
+                disk_image,
+                disk_nsectors,
+                interrupt_status: status,
+                interrupt_evt,
+                queue_evts: vec![queue_evt],
+                next_queue: 0,
+                rate_limiter: RateLimiter::default(),
+                disk_image_id,
+                async_io: None,
+                cache_type: CacheType::Writeback,
+                logical_block_size: SECTOR_SIZE as u32,
+                metrics: test_metrics(),
+            },
+            vq,
+        )
+    }
+
+    // Waits for the completion of the requests submitted by `h`, and handles it.
+    fn invoke_handler_for_completion_event(h: &mut BlockEpollHandler) {
+        {
+            let async_io = h.async_io.as_mut().unwrap();
+            let in_flight = async_io.ring.in_flight();
+            async_io.ring.submit_and_wait(in_flight).unwrap();
+        }
+        // leave at least one event here so that reading it later won't block
+        h.interrupt_evt.write(1).unwrap();
+        h.handle_event(COMPLETION_EVENT, EPOLLIN).unwrap();
+        assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+    }
+
+    // Helper function for varying the parameters of the function activating a block device.
+    fn activate_block_with_modifiers(
+        b: &mut Block,
+        bad_qlen: bool,
+        bad_evtlen: bool,
+    ) -> ActivateResult {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let ievt = EventFd::new().unwrap();
+        let stat = Arc::new(AtomicUsize::new(0));
+
+        let vq = VirtQueue::new(GuestAddress(0), &m, 16);
+        let mut queues = vec![vq.create_queue()];
+        let mut queue_evts = vec![EventFd::new().unwrap()];
+
+        // Invalidate queues list to test this failure case.
+        if bad_qlen {
+            queues.pop();
+        }
+
+        // Invalidate queue-events list to test this failure case.
+        if bad_evtlen {
+            queue_evts.pop();
+        }
+
+        b.activate(m.clone(), ievt, stat, queues, queue_evts)
+    }
+
+    fn invoke_handler_for_queue_event(h: &mut BlockEpollHandler) {
+        // leave at least one event here so that reading it later won't block
+        h.interrupt_evt.write(1).unwrap();
+        // trigger the queue event
+        h.queue_evts[0].write(1).unwrap();
+        // handle event
+        h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+        // validate the queue operation finished successfully
+        assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_request_type() {
+        let m = &GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let a = GuestAddress(0);
+
+        // We write values associated with different request type at an address in memory,
+        // and verify the request type is parsed correctly.
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, a).unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::In);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, a).unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::Out);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, a).unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::Flush);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_GET_ID, a).unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::GetDeviceID);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_DISCARD, a).unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::Discard);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_WRITE_ZEROES, a)
+            .unwrap();
+        assert_eq!(request_type(m, a).unwrap(), RequestType::WriteZeroes);
+
+        // The value written here should be invalid.
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH + 10, a)
+            .unwrap();
+        assert_eq!(
+            request_type(m, a).unwrap(),
+            RequestType::Unsupported(VIRTIO_BLK_T_FLUSH + 10)
+        );
+
+        // The provided address cannot be read, as it's outside the memory space.
+        let a = GuestAddress(0x1000);
+        assert!(request_type(m, a).is_err())
+    }
+
+    #[test]
+    fn test_sector() {
+        let m = &GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let a = GuestAddress(0);
+
+        // Here we test that a sector number is parsed correctly from memory. The actual sector
+        // number is expected to be found 8 bytes after the address provided as parameter to the
+        // sector() function.
+
+        m.write_obj_at_addr::<u64>(123_454_321, a.checked_add(8).unwrap())
+            .unwrap();
+        assert_eq!(sector(m, a).unwrap(), 123_454_321);
+
+        // Reading from a slightly different address should not lead a correct result in this case.
+        assert_ne!(sector(m, a.checked_add(1).unwrap()).unwrap(), 123_454_321);
+
+        // The provided address is outside the valid memory range.
+        assert!(sector(m, a.checked_add(0x1000).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let m = &GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &m, 16);
+
+        assert!(vq.end().0 < 0x1000);
+
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        {
+            let mut q = vq.create_queue();
+            // write only request type descriptor
+            vq.dtable[0].set(0x1000, 0x1000, VIRTQ_DESC_F_WRITE, 1);
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(114, GuestAddress(0x1000 + 8))
+                .unwrap();
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::UnexpectedWriteOnlyDescriptor) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // chain too short; no data_desc
+            vq.dtable[0].flags.set(0);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::DescriptorChainTooShort) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // chain too short; no status desc
+            vq.dtable[0].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].set(0x2000, 0x1000, 0, 2);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::DescriptorChainTooShort) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // write only data for OUT
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            vq.dtable[2].set(0x3000, 0, 0, 0);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::UnexpectedWriteOnlyDescriptor) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // read only data for IN
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::UnexpectedReadOnlyDescriptor) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // status desc not writable
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::UnexpectedReadOnlyDescriptor) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // status desc too small
+            vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+            assert!(match Request::parse(&q.pop(m).unwrap(), m) {
+                Err(Error::DescriptorLengthTooSmall) => true,
+                _ => false,
+            });
+        }
+
+        {
+            let mut q = vq.create_queue();
+            // should be OK now
+            vq.dtable[2].len.set(0x1000);
+            let r = Request::parse(&q.pop(m).unwrap(), m).unwrap();
+
+            assert_eq!(r.request_type, RequestType::In);
+            assert_eq!(r.sector, 114);
+            assert_eq!(r.data_addr, GuestAddress(0x2000));
+            assert_eq!(r.data_len, 0x1000);
+            assert_eq!(r.status_addr, GuestAddress(0x3000));
+        }
+    }
+
+    #[test]
+    #[allow(clippy::cognitive_complexity)]
+    fn test_virtio_device() {
+        let mut dummy = DummyBlock::new(true);
+        let b = dummy.block();
+
+        // Test `device_type()`.
+        {
+            assert_eq!(b.device_type(), TYPE_BLOCK);
+        }
+
+        // Test `queue_max_sizes()`.
+        {
+            let x = b.queue_max_sizes();
+            assert_eq!(x, &[QUEUE_SIZE]);
+
+            // power of 2?
+            for &y in x {
+                assert!(y > 0 && y & (y - 1) == 0);
+            }
+        }
+
+        // Test `read_config()`.
+        {
+            let mut num_sectors = [0u8; 4];
+            b.read_config(0, &mut num_sectors);
+            // size is 0x1000, so num_sectors is 8 (4096/512).
+            assert_eq!([0x08, 0x00, 0x00, 0x00], num_sectors);
+            let mut msw_sectors = [0u8; 4];
+            b.read_config(4, &mut msw_sectors);
+            // size is 0x1000, so msw_sectors is 0.
+            assert_eq!([0x00, 0x00, 0x00, 0x00], msw_sectors);
+
+            // Invalid read.
+            num_sectors = [0xd, 0xe, 0xa, 0xd];
+            check_metric_after_block!(
+                &METRICS.block.cfg_fails,
+                1,
+                b.read_config(CONFIG_SPACE_SIZE as u64 + 1, &mut num_sectors)
+            );
+            // Validate read failed.
+            assert_eq!(num_sectors, [0xd, 0xe, 0xa, 0xd]);
+
+            // The limits of the discard and write zeroes requests.
+            let mut limits = [0u8; 20];
+            b.read_config(36, &mut limits);
+            assert_eq!(LittleEndian::read_u32(&limits[0..4]), MAX_DISCARD_SECTORS);
+            assert_eq!(LittleEndian::read_u32(&limits[4..8]), 1);
+            assert_eq!(LittleEndian::read_u32(&limits[8..12]), 8);
+            assert_eq!(LittleEndian::read_u32(&limits[12..16]), MAX_DISCARD_SECTORS);
+            assert_eq!(LittleEndian::read_u32(&limits[16..20]), 1);
+        }
+
+        // Test `features()` and `ack_features()`.
+        {
+            let features: u64 = (1u64 << VIRTIO_BLK_F_RO)
+                | (1u64 << VIRTIO_F_VERSION_1)
+                | (1u64 << VIRTIO_BLK_F_FLUSH);
+
+            assert_eq!(b.features(0), features as u32);
+            assert_eq!(b.features(1), (features >> 32) as u32);
+            for i in 2..10 {
+                assert_eq!(b.features(i), 0u32);
+            }
+
+            for i in 0..10 {
+                b.ack_features(i, u32::MAX);
+            }
+            assert_eq!(b.acked_features, features);
+        }
+
+        // Test `activate()`.
+        {
+            // It should fail when not enough queues and/or evts are provided.
+            check_metric_after_block!(
+                &METRICS.block.activate_fails,
+                1,
+                assert!(match activate_block_with_modifiers(b, true, false) {
+                    Err(ActivateError::BadActivate) => true,
+                    _ => false,
+                })
+            );
+            check_metric_after_block!(
+                &METRICS.block.activate_fails,
+                1,
+                assert!(match activate_block_with_modifiers(b, false, true) {
+                    Err(ActivateError::BadActivate) => true,
+                    _ => false,
+                })
+            );
+            check_metric_after_block!(
+                &METRICS.block.activate_fails,
+                1,
+                assert!(match activate_block_with_modifiers(b, true, true) {
+                    Err(ActivateError::BadActivate) => true,
+                    _ => false,
+                })
+            );
+            // Otherwise, it should be ok.
+            assert!(activate_block_with_modifiers(b, false, false).is_ok());
+
+            // Second activate shouldn't be ok anymore.
+            check_metric_after_block!(
+                &METRICS.block.activate_fails,
+                1,
+                assert!(match activate_block_with_modifiers(b, false, false) {
+                    Err(ActivateError::BadActivate) => true,
+                    _ => false,
+                })
+            );
+        }
+
+        // Test `write_config()`.
+        {
+            let new_config: [u8; 8] = [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            b.write_config(0, &new_config);
+            let mut new_config_read = [0u8; 8];
+            b.read_config(0, &mut new_config_read);
+            assert_eq!(new_config, new_config_read);
+            // Invalid write.
+            check_metric_after_block!(
+                &METRICS.block.cfg_fails,
+                1,
+                b.write_config(CONFIG_SPACE_SIZE as u64 - 4, &new_config)
+            );
+            // Make sure nothing got written.
+            new_config_read = [0u8; 8];
+            b.read_config(0, &mut new_config_read);
+            assert_eq!(new_config, new_config_read);
+        }
+    }
+
+    #[test]
+    fn test_invalid_event_handler() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _vq) = default_test_blockepollhandler(&m);
+        let r = h.handle_event(block_events_count(1) as DeviceEventT, EPOLLIN);
+        match r {
+            Err(DeviceError::UnknownEvent { event, device }) => {
+                assert_eq!(event, block_events_count(1) as DeviceEventT);
+                assert_eq!(device, "block");
+            }
+            _ => panic!("invalid"),
+        }
+    }
+
+    // Cannot easily test failures for
+    //  * queue_evt.read
+    //  * interrupt_evt.write
+
+    #[test]
+    #[allow(clippy::cognitive_complexity)]
+    fn test_handler() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+
+        let blk_metadata = h.disk_image.file().metadata();
+
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+
+        vq.dtable[1]
+            .flags
+            .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+
+        // dtable[1] is the data descriptor
+        let data_addr = GuestAddress(vq.dtable[1].addr.get() as usize);
+        // dtable[2] is the status descriptor
+        let status_addr = GuestAddress(vq.dtable[2].addr.get() as usize);
+
+        {
+            // let's start with a request that does not parse
+            // request won't be valid bc the first desc is write-only
+            vq.dtable[0]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+        }
+
+        // now we generate some request execute failures
+
+        {
+            // reset the queue to reuse descriptors & memory
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            // first desc no longer writable
+            vq.dtable[0].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            // let's generate a seek execute error caused by a very large sector number
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(0x000f_ffff_ffff, GuestAddress(0x1000 + 8))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+
+        {
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            // set sector to a valid number but large enough that the full 0x1000 read will fail
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(10, GuestAddress(0x1000 + 8))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+
+        // test unsupported block commands
+        // currently 0, 1, 4, 8 are supported
+
+        {
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            // set sector to 0
+            m.write_obj_at_addr::<u64>(0, GuestAddress(0x1000 + 8))
+                .unwrap();
+            // ... but generate an unsupported request
+            m.write_obj_at_addr::<u32>(16, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_UNSUPP
+            );
+        }
+
+        // now let's write something and read it back
+
+        {
+            // write
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            // make data read only, 8 bytes in len, and set the actual value to be written
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].len.set(8);
+            m.write_obj_at_addr::<u64>(123_456_789, data_addr).unwrap();
+
+            let timed_writes = h.metrics.device().write_latency_us.count();
+            check_metric_after_block!(
+                &METRICS.block.write_count,
+                1,
+                invoke_handler_for_queue_event(&mut h)
+            );
+            // The write is counted for the device as well as in the totals.
+            assert_eq!(h.metrics.device().write_count.count(), 1);
+            assert_eq!(
+                h.metrics.device().write_latency_us.count(),
+                timed_writes + 1
+            );
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+        }
+
+        {
+            // read
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+
+            check_metric_after_block!(
+                &METRICS.block.read_count,
+                1,
+                invoke_handler_for_queue_event(&mut h)
+            );
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, vq.dtable[1].len.get());
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+            assert_eq!(m.read_obj_from_addr::<u64>(data_addr).unwrap(), 123_456_789);
+        }
+
+        {
+            // testing that the flush request completes successfully,
+            // when a data descriptor is provided
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+        }
+
+        {
+            // testing that the flush request completes successfully,
+            // without a data descriptor
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            vq.dtable[0].next.set(2);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+
+            vq.dtable[0].next.set(1);
+        }
+
+        {
+            // testing that the driver receives the correct device id
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            vq.dtable[1].len.set(VIRTIO_BLK_ID_BYTES);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_GET_ID, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+
+            assert!(blk_metadata.is_ok());
+            let blk_meta = blk_metadata.unwrap();
+            let expected_device_id = format!(
+                "{}{}{}",
+                blk_meta.st_dev(),
+                blk_meta.st_rdev(),
+                blk_meta.st_ino()
+            );
+
+            let mut buf = [0; VIRTIO_BLK_ID_BYTES as usize];
+            assert_eq!(
+                m.read_slice_at_addr(&mut buf, data_addr).unwrap(),
+                VIRTIO_BLK_ID_BYTES as usize
+            );
+            let chars_to_trim: &[char] = &['\u{0}'];
+            let received_device_id = String::from_utf8(buf.to_ascii_lowercase())
+                .unwrap()
+                .trim_matches(chars_to_trim)
+                .to_string();
+            assert_eq!(received_device_id, expected_device_id);
+        }
+
+        {
+            // test that a device ID request will fail, if it fails to provide enough buffer space
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            vq.dtable[1].len.set(VIRTIO_BLK_ID_BYTES - 1);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_GET_ID, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+
+        // test the bandwidth rate limiter
+        {
+            // create bandwidth rate limiter that allows only 80 bytes/s with bucket size of 8 bytes
+            let mut rl = RateLimiter::new(8, None, 100, 0, None, 0).unwrap();
+            // use up the budget
+            assert!(rl.consume(8, TokenType::Bytes));
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            h.set_rate_limiter(rl);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            // make data read only, 8 bytes in len, and set the actual value to be written
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].len.set(8);
+            m.write_obj_at_addr::<u64>(123_456_789, data_addr).unwrap();
+
+            // following write procedure should fail because of bandwidth rate limiting
+            {
+                // leave at least one event here so that reading it later won't block
+                h.interrupt_evt.write(1).unwrap();
+                // trigger the attempt to write
+                h.queue_evts[0].write(1).unwrap();
+                h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+
+                // assert that limiter is blocked
+                assert!(h.get_rate_limiter().is_blocked());
+                // assert that no operation actually completed (limiter blocked it)
+                assert_eq!(h.interrupt_evt.read().unwrap(), 1);
+                // make sure the data is still queued for processing
+                assert_eq!(vq.used.idx.get(), 0);
+            }
+
+            // wait for 100ms to give the rate-limiter timer a chance to replenish
+            // wait for an extra 50ms to make sure the timerfd event makes its way from the kernel
+            thread::sleep(Duration::from_millis(150));
+
+            // following write procedure should succeed because bandwidth should now be available
+            {
+                // leave at least one event here so that reading it later won't block
+                h.interrupt_evt.write(1).unwrap();
+                h.handle_event(RATE_LIMITER_EVENT, EPOLLIN).unwrap();
+                // validate the rate_limiter is no longer blocked
+                assert!(!h.get_rate_limiter().is_blocked());
+                // the time spent blocked is counted
+                assert_eq!(h.metrics.device().rate_limiter_throttled_us.count(), 1);
+                // make sure the virtio queue operation completed this time
+                assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+
+                // make sure the data queue advanced
+                assert_eq!(vq.used.idx.get(), 1);
+                assert_eq!(vq.used.ring[0].get().id, 0);
+                assert_eq!(vq.used.ring[0].get().len, 0);
+                assert_eq!(
+                    m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                    VIRTIO_BLK_S_OK
+                );
+            }
+        }
+
+        // test the ops/s rate limiter
+        {
+            // create ops rate limiter that allows only 10 ops/s with bucket size of 1 ops
+            let mut rl = RateLimiter::new(0, None, 0, 1, None, 100).unwrap();
+            // use up the budget
+            assert!(rl.consume(1, TokenType::Ops));
+
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            h.set_rate_limiter(rl);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            // make data read only, 8 bytes in len, and set the actual value to be written
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].len.set(8);
+            m.write_obj_at_addr::<u64>(123_456_789, data_addr).unwrap();
+
+            // following write procedure should fail because of ops rate limiting
+            {
+                // leave at least one event here so that reading it later won't block
+                h.interrupt_evt.write(1).unwrap();
+                // trigger the attempt to write
+                h.queue_evts[0].write(1).unwrap();
+                h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+
+                // assert that limiter is blocked
+                assert!(h.get_rate_limiter().is_blocked());
+                // assert that no operation actually completed (limiter blocked it)
+                assert_eq!(h.interrupt_evt.read().unwrap(), 1);
+                // make sure the data is still queued for processing
+                assert_eq!(vq.used.idx.get(), 0);
+            }
+
+            // do a second write that still fails but this time on the fast path
+            {
+                // leave at least one event here so that reading it later won't block
+                h.interrupt_evt.write(1).unwrap();
+                // trigger the attempt to write
+                h.queue_evts[0].write(1).unwrap();
+                h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+
+                // assert that limiter is blocked
+                assert!(h.get_rate_limiter().is_blocked());
+                // assert that no operation actually completed (limiter blocked it)
+                assert_eq!(h.interrupt_evt.read().unwrap(), 1);
+                // make sure the data is still queued for processing
+                assert_eq!(vq.used.idx.get(), 0);
+            }
+
+            // wait for 100ms to give the rate-limiter timer a chance to replenish
+            // wait for an extra 50ms to make sure the timerfd event makes its way from the kernel
+            thread::sleep(Duration::from_millis(150));
+
+            // following write procedure should succeed because ops budget should now be available
+            {
+                // leave at least one event here so that reading it later won't block
+                h.interrupt_evt.write(1).unwrap();
+                h.handle_event(RATE_LIMITER_EVENT, EPOLLIN).unwrap();
+                // validate the rate_limiter is no longer blocked
+                assert!(!h.get_rate_limiter().is_blocked());
+                // make sure the virtio queue operation completed this time
+                assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+
+                // make sure the data queue advanced
+                assert_eq!(vq.used.idx.get(), 1);
+                assert_eq!(vq.used.ring[0].get().id, 0);
+                assert_eq!(vq.used.ring[0].get().len, 0);
+                assert_eq!(
+                    m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                    VIRTIO_BLK_S_OK
+                );
+            }
+        }
+
+        // test block device update handler
+        {
+            let f = NamedTempFile::new().unwrap();
+            let path = f.path().to_path_buf();
+            let mdata = metadata(&path).unwrap();
+            let mut id = vec![0; VIRTIO_BLK_ID_BYTES as usize];
+            let str_id = format!("{}{}{}", mdata.st_dev(), mdata.st_rdev(), mdata.st_ino());
+            let part_id = str_id.as_bytes();
+            id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)].clone_from_slice(
+                &part_id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)],
+            );
+
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap();
+            h.update_disk_image(Box::new(file)).unwrap();
+
+            assert_eq!(
+                h.disk_image.file().metadata().unwrap().st_ino(),
+                mdata.st_ino()
+            );
+            assert_eq!(h.disk_image_id, id);
+        }
+    }
+
+    #[test]
+    fn test_async_engine() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+        h.async_io = Some(AsyncIo::new(1).unwrap());
+
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+
+        let data_addr = GuestAddress(vq.dtable[1].addr.get() as usize);
+        let status_addr = GuestAddress(vq.dtable[2].addr.get() as usize);
+
+        {
+            // write
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(1, GuestAddress(0x1000 + 8))
+                .unwrap();
+            vq.dtable[1].len.set(8);
+            m.write_obj_at_addr::<u64>(123_456_789, data_addr).unwrap();
+
+            h.queue_evts[0].write(1).unwrap();
+            h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+            // The request is not used before it completes.
+            assert_eq!(vq.used.idx.get(), 0);
+            assert_eq!(h.async_io.as_ref().unwrap().pending.len(), 1);
+
+            check_metric_after_block!(
+                &METRICS.block.write_count,
+                1,
+                invoke_handler_for_completion_event(&mut h)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+            assert!(h.async_io.as_ref().unwrap().pending.is_empty());
+        }
+
+        {
+            // read
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            m.write_obj_at_addr::<u64>(0, data_addr).unwrap();
+
+            h.queue_evts[0].write(1).unwrap();
+            h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+            check_metric_after_block!(
+                &METRICS.block.read_count,
+                1,
+                invoke_handler_for_completion_event(&mut h)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 8);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+            assert_eq!(m.read_obj_from_addr::<u64>(data_addr).unwrap(), 123_456_789);
+        }
+
+        {
+            // flush
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x1000))
+                .unwrap();
+
+            h.queue_evts[0].write(1).unwrap();
+            h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+            check_metric_after_block!(
+                &METRICS.block.flush_count,
+                1,
+                invoke_handler_for_completion_event(&mut h)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+        }
+
+        {
+            // Requests that do not access the disk complete right away.
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            vq.dtable[1].len.set(VIRTIO_BLK_ID_BYTES);
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_GET_ID, GuestAddress(0x1000))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_OK
+            );
+            assert!(h.async_io.as_ref().unwrap().pending.is_empty());
+        }
+
+        {
+            // Requests beyond the end of the disk are never submitted.
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(0x000f_ffff_ffff, GuestAddress(0x1000 + 8))
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+            assert!(h.async_io.as_ref().unwrap().pending.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_qcow_disk_image() {
+        let image = NamedTempFile::new().unwrap();
+        qcow::tests::create_qcow_image(&mut image.reopen().unwrap(), 0x10_0000, None);
+        let open_image = || {
+            open_disk_image(
+                image.path(),
+                false,
+                DiskImageFormat::Qcow2,
+                None,
+                CacheType::Writeback,
+            )
+            .unwrap()
+        };
+
+        // The io_uring cannot access the guest disk inside a qcow2 image.
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+        assert!(Block::new(
+            open_image(),
+            false,
+            IoEngine::Async,
+            1,
+            CacheType::Writeback,
+            None,
+            epoll_config,
+            None,
+            test_metrics()
+        )
+        .is_err());
+        unsafe { libc::close(epoll_raw_fd) };
+
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+        h.update_disk_image(open_image()).unwrap();
+        // The size of the guest disk is the virtual size of the image.
+        assert_eq!(h.disk_nsectors, 0x10_0000 / SECTOR_SIZE);
+
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+
+        let data_addr = GuestAddress(vq.dtable[1].addr.get() as usize);
+        let status_addr = GuestAddress(vq.dtable[2].addr.get() as usize);
+
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+            .unwrap();
+        m.write_obj_at_addr::<u64>(0x7f0, GuestAddress(0x1000 + 8))
+            .unwrap();
+        vq.dtable[1].len.set(8);
+        m.write_obj_at_addr::<u64>(123_456_789, data_addr).unwrap();
+
+        invoke_handler_for_queue_event(&mut h);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(
+            m.read_obj_from_addr::<u32>(status_addr).unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+
+        // The data was written to the guest disk, not at the same offset of the image file.
+        let mut disk = open_image();
+        let mut buf = [0u8; 8];
+        disk.seek(SeekFrom::Start(0x7f0 << SECTOR_SHIFT)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        assert_eq!(u64::from_le_bytes(buf), 123_456_789);
+    }
+
+    #[test]
+    fn test_discard_write_zeroes() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+        let disk_file = NamedTempFile::new().unwrap();
+        disk_file.as_file().write_all(&[0xffu8; 0x4000]).unwrap();
+        h.update_disk_image(Box::new(disk_file.reopen().unwrap()))
+            .unwrap();
+
+        // Writable devices support both requests.
+        let mut dummy = DummyBlock::new(false);
+        let features = dummy.block().avail_features;
+        assert_ne!(features & (1u64 << VIRTIO_BLK_F_DISCARD), 0);
+        assert_ne!(features & (1u64 << VIRTIO_BLK_F_WRITE_ZEROES), 0);
+
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                DISCARD_SEGMENT_SIZE,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+
+        let data_addr = GuestAddress(vq.dtable[1].addr.get() as usize);
+        let status_addr = GuestAddress(vq.dtable[2].addr.get() as usize);
+        // Sends a request for a single range of sectors, and returns its status.
+        let mut send_request = |request_type: u32, sector: u64, num_sectors: u32, flags: u32| {
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            m.write_obj_at_addr::<u32>(request_type, GuestAddress(0x1000))
+                .unwrap();
+            m.write_obj_at_addr::<u64>(sector, data_addr).unwrap();
+            m.write_obj_at_addr::<u32>(num_sectors, data_addr.checked_add(8).unwrap())
+                .unwrap();
+            m.write_obj_at_addr::<u32>(flags, data_addr.checked_add(12).unwrap())
+                .unwrap();
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            m.read_obj_from_addr::<u32>(status_addr).unwrap()
+        };
+        let read_disk = || {
+            let mut data = Vec::new();
+            disk_file.reopen().unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+
+        check_metric_after_block!(
+            &METRICS.block.write_zeroes_count,
+            1,
+            assert_eq!(
+                send_request(VIRTIO_BLK_T_WRITE_ZEROES, 1, 2, 0),
+                VIRTIO_BLK_S_OK
+            )
+        );
+        let data = read_disk();
+        assert!(data[..0x200].iter().all(|b| *b == 0xff));
+        assert!(data[0x200..0x600].iter().all(|b| *b == 0));
+        assert!(data[0x600..].iter().all(|b| *b == 0xff));
+
+        assert_eq!(
+            send_request(
+                VIRTIO_BLK_T_WRITE_ZEROES,
+                8,
+                8,
+                VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP
+            ),
+            VIRTIO_BLK_S_OK
+        );
+        let data = read_disk();
+        assert_eq!(data.len(), 0x4000);
+        assert!(data[0x1000..0x2000].iter().all(|b| *b == 0));
+        assert!(data[0x2000..].iter().all(|b| *b == 0xff));
+
+        check_metric_after_block!(
+            &METRICS.block.discard_count,
+            1,
+            assert_eq!(
+                send_request(VIRTIO_BLK_T_DISCARD, 24, 8, 0),
+                VIRTIO_BLK_S_OK
+            )
+        );
+        assert_eq!(read_disk().len(), 0x4000);
+
+        // Ranges beyond the end of the disk, and unknown flags.
+        assert_eq!(
+            send_request(VIRTIO_BLK_T_DISCARD, 30, 3, 0),
+            VIRTIO_BLK_S_IOERR
+        );
+        assert_eq!(
+            send_request(VIRTIO_BLK_T_WRITE_ZEROES, u64::max_value(), 2, 0),
+            VIRTIO_BLK_S_IOERR
+        );
+        assert_eq!(
+            send_request(
+                VIRTIO_BLK_T_DISCARD,
+                0,
+                1,
+                VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP
+            ),
+            VIRTIO_BLK_S_UNSUPP
+        );
+        assert_eq!(
+            send_request(VIRTIO_BLK_T_WRITE_ZEROES, 0, 1, 2),
+            VIRTIO_BLK_S_UNSUPP
+        );
+
+        // Only one range is allowed per request.
+        vq.dtable[1].len.set(2 * DISCARD_SEGMENT_SIZE);
+        assert_eq!(
+            send_request(VIRTIO_BLK_T_DISCARD, 0, 1, 0),
+            VIRTIO_BLK_S_IOERR
+        );
+    }
+
+    #[test]
+    fn test_multi_queue() {
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let f: File = tempfile().unwrap();
+        f.set_len(0x1000).unwrap();
+
+        // The number of queues is bounded.
+        for num_queues in &[0, BLOCK_MAX_QUEUES + 1] {
+            let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender.clone());
+            assert!(Block::new(
+                Box::new(f.try_clone().unwrap()),
+                false,
+                IoEngine::Sync,
+                *num_queues,
+                CacheType::Writeback,
+                None,
+                epoll_config,
+                None,
+                test_metrics()
+            )
+            .is_err());
+        }
+
+        let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+        let mut b = Block::new(
+            Box::new(f),
+            false,
+            IoEngine::Sync,
+            2,
+            CacheType::Writeback,
+            None,
+            epoll_config,
+            None,
+            test_metrics(),
+        )
+        .unwrap();
+        assert_eq!(b.queue_max_sizes(), &[QUEUE_SIZE, QUEUE_SIZE]);
+        assert_ne!(b.features(0) & (1u32 << VIRTIO_BLK_F_MQ), 0);
+        let mut num_queues = [0u8; 2];
+        b.read_config(34, &mut num_queues);
+        assert_eq!(LittleEndian::read_u16(&num_queues), 2);
+        // Single queue devices do not advertise the feature.
+        assert_eq!(
+            DummyBlock::new(false).block().features(0) & (1u32 << VIRTIO_BLK_F_MQ),
+            0
+        );
+
+        // All the queues are needed for activating the device.
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &m, 16);
+        let activate = |b: &mut Block, num_queues: usize| {
+            b.activate(
+                m.clone(),
+                EventFd::new().unwrap(),
+                Arc::new(AtomicUsize::new(0)),
+                (0..num_queues).map(|_| vq.create_queue()).collect(),
+                (0..num_queues).map(|_| EventFd::new().unwrap()).collect(),
+            )
+        };
+        assert!(activate(&mut b, 1).is_err());
+        assert!(activate(&mut b, 2).is_ok());
+
+        unsafe { libc::close(epoll_raw_fd) };
+    }
+
+    #[test]
+    fn test_multi_queue_handler() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq0) = default_test_blockepollhandler(&m);
+        let vq1 = VirtQueue::new(GuestAddress(0x8000), &m, 16);
+        h.queues.push(vq1.create_queue());
+        h.queue_evts.push(EventFd::new().unwrap());
+
+        // Both queues hold a request for the device ID.
+        for (vq, data_base) in &[(&vq0, 0x1000), (&vq1, 0x9000)] {
+            for i in 0..3 {
+                vq.avail.ring[i].set(i as u16);
+                vq.dtable[i].set(
+                    (data_base + 0x1000 * i) as u64,
+                    VIRTIO_BLK_ID_BYTES,
+                    VIRTQ_DESC_F_NEXT,
+                    (i + 1) as u16,
+                );
+            }
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+            vq.avail.idx.set(1);
+            m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_GET_ID, GuestAddress(*data_base))
+                .unwrap();
+        }
+        let reset_queues = |h: &mut BlockEpollHandler| {
+            vq0.used.idx.set(0);
+            vq1.used.idx.set(0);
+            h.set_queue(0, vq0.create_queue());
+            h.set_queue(1, vq1.create_queue());
+        };
+
+        // Each queue has its own event.
+        h.interrupt_evt.write(1).unwrap();
+        h.queue_evts[1].write(1).unwrap();
+        h.handle_event(QUEUE_AVAIL_EVENT + 1, EPOLLIN).unwrap();
+        assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+        assert_eq!(vq0.used.idx.get(), 0);
+        assert_eq!(vq1.used.idx.get(), 1);
+        assert!(h.handle_event(QUEUE_AVAIL_EVENT + 2, EPOLLIN).is_err());
+        assert_eq!(
+            h.metrics.device().queues.get(0).queue_event_count.count(),
+            0
+        );
+        assert_eq!(
+            h.metrics.device().queues.get(1).queue_event_count.count(),
+            1
+        );
+
+        // The queues share the budget of the rate limiter, and take turns when it gets replenished.
+        reset_queues(&mut h);
+        let mut rl = RateLimiter::new(0, None, 0, 1, None, 100).unwrap();
+        assert!(rl.consume(1, TokenType::Ops));
+        h.set_rate_limiter(rl);
+        for i in 0..2 {
+            h.queue_evts[i].write(1).unwrap();
+            h.handle_event(QUEUE_AVAIL_EVENT + i as DeviceEventT, EPOLLIN)
+                .unwrap();
+        }
+        assert!(h.get_rate_limiter().is_blocked());
+        assert_eq!(vq0.used.idx.get(), 0);
+        assert_eq!(vq1.used.idx.get(), 0);
+
+        thread::sleep(Duration::from_millis(150));
+        h.handle_event(RATE_LIMITER_EVENT, EPOLLIN).unwrap();
+        assert_eq!(vq0.used.idx.get(), 1);
+        assert_eq!(vq1.used.idx.get(), 0);
+
+        thread::sleep(Duration::from_millis(150));
+        h.handle_event(RATE_LIMITER_EVENT, EPOLLIN).unwrap();
+        assert_eq!(vq0.used.idx.get(), 1);
+        assert_eq!(vq1.used.idx.get(), 1);
+        // Each of the requests was signaled.
+        assert_eq!(h.interrupt_evt.read().unwrap(), 2);
+
+        // The completions of the asynchronous requests go to the queues the requests came from.
+        reset_queues(&mut h);
+        h.set_rate_limiter(RateLimiter::default());
+        h.async_io = Some(AsyncIo::new(2).unwrap());
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x9000))
+            .unwrap();
+        h.queue_evts[1].write(1).unwrap();
+        h.handle_event(QUEUE_AVAIL_EVENT + 1, EPOLLIN).unwrap();
+        assert_eq!(vq1.used.idx.get(), 0);
+        invoke_handler_for_completion_event(&mut h);
+        assert_eq!(vq0.used.idx.get(), 0);
+        assert_eq!(vq1.used.idx.get(), 1);
+        assert_eq!(
+            m.read_obj_from_addr::<u32>(GuestAddress(0xb000)).unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+
+        // The reads and writes are counted in the metrics of their queues, whether they are
+        // asynchronous or not.
+        reset_queues(&mut h);
+        vq1.dtable[1].len.set(512);
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x9000))
+            .unwrap();
+        h.queue_evts[1].write(1).unwrap();
+        h.handle_event(QUEUE_AVAIL_EVENT + 1, EPOLLIN).unwrap();
+        invoke_handler_for_completion_event(&mut h);
+        h.async_io = None;
+        vq0.dtable[1].len.set(512);
+        vq0.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(0x1000))
+            .unwrap();
+        h.queue_evts[0].write(1).unwrap();
+        h.handle_event(QUEUE_AVAIL_EVENT, EPOLLIN).unwrap();
+        assert_eq!(vq0.used.idx.get(), 1);
+        assert_eq!(vq1.used.idx.get(), 1);
+        let queues = &h.metrics.device().queues;
+        assert_eq!(queues.get(0).read_count.count(), 0);
+        assert_eq!(queues.get(0).write_count.count(), 1);
+        assert_eq!(queues.get(1).read_count.count(), 1);
+        assert_eq!(queues.get(1).write_count.count(), 0);
+    }
+
+    #[test]
+    fn test_cache_type() {
+        // The flush feature is only offered when the guest has something to flush.
+        for (cache_type, has_flush) in &[
+            (CacheType::Unsafe, true),
+            (CacheType::Writeback, true),
+            (CacheType::Writethrough, false),
+        ] {
+            let epoll_raw_fd = epoll::create(true).unwrap();
+            let (sender, _receiver) = mpsc::channel();
+            let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+            let b = Block::new(
+                Box::new(tempfile().unwrap()),
+                false,
+                IoEngine::Sync,
+                1,
+                *cache_type,
+                None,
+                epoll_config,
+                None,
+                test_metrics(),
+            )
+            .unwrap();
+            assert_eq!(
+                b.features(0) & (1u32 << VIRTIO_BLK_F_FLUSH) != 0,
+                *has_flush
+            );
+            unsafe { libc::close(epoll_raw_fd) };
+        }
+
+        // The written file of a `Writethrough` disk image is opened with O_DSYNC.
+        let image = NamedTempFile::new().unwrap();
+        let overlay_dir = tempfile::tempdir().unwrap();
+        let overlay_path = overlay_dir.path().join("overlay");
+        let is_dsync = |file: &File| {
+            let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+            flags & libc::O_DSYNC != 0
+        };
+        for (cache_type, overlay_path) in &[
+            (CacheType::Writeback, None),
+            (CacheType::Writethrough, None),
+            (CacheType::Writethrough, Some(overlay_path.as_path())),
+        ] {
+            let disk_image = open_disk_image(
+                image.path(),
+                false,
+                DiskImageFormat::Raw,
+                *overlay_path,
+                *cache_type,
+            )
+            .unwrap();
+            assert_eq!(
+                is_dsync(disk_image.file()),
+                *cache_type == CacheType::Writethrough
+            );
+        }
+
+        // The flushes of the guest sync the raw file, except with the `Unsafe` cache type. Syncing
+        // `/dev/null` fails with EINVAL, so only the synced requests complete with an error.
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+        let dev_null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .unwrap();
+        h.update_disk_image(Box::new(dev_null)).unwrap();
+        for i in 0..2 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x1000))
+            .unwrap();
+
+        for (cache_type, status) in &[
+            (CacheType::Writeback, VIRTIO_BLK_S_IOERR),
+            (CacheType::Unsafe, VIRTIO_BLK_S_OK),
+        ] {
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            h.cache_type = *cache_type;
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(GuestAddress(0x2000)).unwrap(),
+                *status
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_topology() {
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let new_block = |topology| {
+            let f: File = tempfile().unwrap();
+            f.set_len(0x10_0000).unwrap();
+            Block::new(
+                Box::new(f),
+                false,
+                IoEngine::Sync,
+                1,
+                CacheType::Writeback,
+                topology,
+                EpollConfig::new(0, epoll_raw_fd, sender.clone()),
+                None,
+                test_metrics(),
+            )
+        };
+
+        // Invalid block sizes are rejected.
+        for (logical_block_size, physical_block_size, optimal_io_size) in &[
+            (256, 512, 0),
+            (8192, 8192, 0),
+            (1000, 4096, 0),
+            (4096, 512, 0),
+            (512, 3072, 0),
+            (512, 4096, 6144),
+        ] {
+            let topology = BlockTopology {
+                logical_block_size: *logical_block_size,
+                physical_block_size: *physical_block_size,
+                optimal_io_size: *optimal_io_size,
+            };
+            assert!(!topology.is_valid());
+            assert!(new_block(Some(topology)).is_err());
+        }
+
+        // Without a topology, the guest sees 512-byte blocks.
+        let b = new_block(None).unwrap();
+        assert_eq!(b.features(0) & (1u32 << VIRTIO_BLK_F_BLK_SIZE), 0);
+        assert_eq!(b.features(0) & (1u32 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        assert_eq!(b.logical_block_size, 512);
+
+        let b = new_block(Some(BlockTopology {
+            logical_block_size: 512,
+            physical_block_size: 4096,
+            optimal_io_size: 0x1_0000,
+        }))
+        .unwrap();
+        assert_ne!(b.features(0) & (1u32 << VIRTIO_BLK_F_BLK_SIZE), 0);
+        assert_ne!(b.features(0) & (1u32 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        let mut config = [0u8; CONFIG_SPACE_SIZE];
+        b.read_config(0, &mut config);
+        assert_eq!(LittleEndian::read_u64(&config[0..8]), 0x800);
+        assert_eq!(LittleEndian::read_u32(&config[20..24]), 512);
+        // 8 logical blocks per physical one.
+        assert_eq!(config[24], 3);
+        assert_eq!(config[25], 0);
+        assert_eq!(LittleEndian::read_u16(&config[26..28]), 8);
+        assert_eq!(LittleEndian::read_u32(&config[28..32]), 128);
+        assert_eq!(LittleEndian::read_u32(&config[44..48]), 8);
+
+        let b = new_block(Some(BlockTopology {
+            logical_block_size: 4096,
+            physical_block_size: 0x4000,
+            optimal_io_size: 0,
+        }))
+        .unwrap();
+        b.read_config(0, &mut config);
+        assert_eq!(LittleEndian::read_u64(&config[0..8]), 0x800);
+        assert_eq!(LittleEndian::read_u32(&config[20..24]), 4096);
+        assert_eq!(config[24], 2);
+        assert_eq!(LittleEndian::read_u16(&config[26..28]), 4);
+        assert_eq!(LittleEndian::read_u32(&config[28..32]), 0);
+        // Discards are aligned on physical blocks.
+        assert_eq!(LittleEndian::read_u32(&config[44..48]), 32);
+        assert_eq!(b.logical_block_size, 4096);
+
+        // The reads and writes not aligned on the logical blocks fail with an I/O error.
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+        h.logical_block_size = 4096;
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[1]
+            .flags
+            .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_IN, GuestAddress(0x1000))
+            .unwrap();
+        for (sector, data_len, status) in &[
+            // The sector is not aligned.
+            (1, 0x1000, VIRTIO_BLK_S_IOERR),
+            // The length is not aligned.
+            (0, 0x200, VIRTIO_BLK_S_IOERR),
+            (0, 0x1000, VIRTIO_BLK_S_OK),
+        ] {
+            m.write_obj_at_addr::<u64>(*sector, GuestAddress(0x1000 + 8))
+                .unwrap();
+            vq.dtable[1].len.set(*data_len);
+            m.write_obj_at_addr::<u32>(0xff, GuestAddress(0x3000))
+                .unwrap();
+            vq.used.idx.set(0);
+            h.set_queue(0, vq.create_queue());
+            invoke_handler_for_queue_event(&mut h);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                m.read_obj_from_addr::<u32>(GuestAddress(0x3000)).unwrap(),
+                *status
+            );
+        }
+
+        unsafe { libc::close(epoll_raw_fd) };
+    }
+}
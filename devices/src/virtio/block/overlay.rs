@@ -0,0 +1,371 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Copy-on-write overlay on top of a read-only base image.
+//!
+//! The overlay file starts with a header, followed by a bitmap with one bit per sector of the
+//! disk. A set bit means that the sector was written, and that its content is in the data area of
+//! the overlay, at the same offset as in the disk. The data area is sparse, so the overlay only
+//! takes space for the sectors written by the guest, and the other sectors are read from the base.
+//!
+//! The bitmap is written after the data it covers, and a flush syncs both to the disk.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::disk_image::{checked_add_signed, DiskImage};
+use super::SECTOR_SIZE;
+
+const OVERLAY_MAGIC: u64 = 0x5941_4c52_4556_4f46; // "FOVERLAY"
+const OVERLAY_VERSION: u32 = 1;
+// The header is padded to a page, and so are the bitmap and the data area.
+const HEADER_SIZE: u64 = 4096;
+const ALIGNMENT: u64 = 4096;
+
+fn invalid_overlay(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid overlay: {}", msg),
+    )
+}
+
+fn round_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+/// A read-only base image seen through a writable overlay file.
+pub struct OverlayImage {
+    base: Box<dyn DiskImage>,
+    overlay: File,
+    size: u64,
+    // One bit per sector, set if the sector is in the overlay.
+    bitmap: Vec<u8>,
+    bitmap_offset: u64,
+    data_offset: u64,
+    // Offset in the disk of the next read or write.
+    position: u64,
+}
+
+impl OverlayImage {
+    /// Opens the overlay stored in `overlay` on top of `base`. An empty overlay file is
+    /// initialized, with all the sectors read from the base. Otherwise, the overlay must have been
+    /// created for a base of the same size.
+    pub fn new(mut base: Box<dyn DiskImage>, mut overlay: File) -> io::Result<OverlayImage> {
+        let size = base.seek(SeekFrom::End(0))?;
+        let bitmap_len = (size + SECTOR_SIZE * 8 - 1) / (SECTOR_SIZE * 8);
+
+        if overlay.metadata()?.len() == 0 {
+            let data_offset = round_up(HEADER_SIZE + bitmap_len, ALIGNMENT);
+            let mut header = vec![0u8; HEADER_SIZE as usize];
+            LittleEndian::write_u64(&mut header[0..8], OVERLAY_MAGIC);
+            LittleEndian::write_u32(&mut header[8..12], OVERLAY_VERSION);
+            LittleEndian::write_u64(&mut header[16..24], size);
+            LittleEndian::write_u64(&mut header[24..32], HEADER_SIZE);
+            LittleEndian::write_u64(&mut header[32..40], data_offset);
+            overlay.write_all(&header)?;
+            // The bitmap starts zeroed, without taking any space.
+            overlay.set_len(data_offset)?;
+            overlay.sync_all()?;
+        }
+
+        let mut header = vec![0u8; HEADER_SIZE as usize];
+        overlay.seek(SeekFrom::Start(0))?;
+        overlay.read_exact(&mut header)?;
+        if LittleEndian::read_u64(&header[0..8]) != OVERLAY_MAGIC {
+            return Err(invalid_overlay("bad magic number"));
+        }
+        if LittleEndian::read_u32(&header[8..12]) != OVERLAY_VERSION {
+            return Err(invalid_overlay("unsupported version"));
+        }
+        if LittleEndian::read_u64(&header[16..24]) != size {
+            return Err(invalid_overlay("the size of the base image changed"));
+        }
+        let bitmap_offset = LittleEndian::read_u64(&header[24..32]);
+        let data_offset = LittleEndian::read_u64(&header[32..40]);
+        if bitmap_offset < HEADER_SIZE
+            || bitmap_offset
+                .checked_add(bitmap_len)
+                .map_or(true, |end| end > data_offset)
+        {
+            return Err(invalid_overlay("bad bitmap offset"));
+        }
+
+        let mut bitmap = vec![0u8; bitmap_len as usize];
+        overlay.seek(SeekFrom::Start(bitmap_offset))?;
+        overlay.read_exact(&mut bitmap)?;
+
+        Ok(OverlayImage {
+            base,
+            overlay,
+            size,
+            bitmap,
+            bitmap_offset,
+            data_offset,
+            position: 0,
+        })
+    }
+
+    fn is_in_overlay(&self, sector: u64) -> bool {
+        self.bitmap[(sector / 8) as usize] & (1 << (sector % 8)) != 0
+    }
+
+    // Marks the sectors from `first` to `last`, excluded, as being in the overlay.
+    fn set_in_overlay(&mut self, first: u64, last: u64) -> io::Result<()> {
+        let mut changed = false;
+        for sector in first..last {
+            if !self.is_in_overlay(sector) {
+                self.bitmap[(sector / 8) as usize] |= 1 << (sector % 8);
+                changed = true;
+            }
+        }
+        if changed {
+            let first_byte = (first / 8) as usize;
+            let last_byte = ((last - 1) / 8) as usize;
+            self.overlay
+                .seek(SeekFrom::Start(self.bitmap_offset + first_byte as u64))?;
+            self.overlay
+                .write_all(&self.bitmap[first_byte..=last_byte])?;
+        }
+        Ok(())
+    }
+
+    // Returns the end of the run of sectors starting at `position` that are all in the overlay,
+    // or all in the base, bounded by `end`.
+    fn run_end(&self, position: u64, end: u64) -> u64 {
+        let in_overlay = self.is_in_overlay(position / SECTOR_SIZE);
+        let mut sector = position / SECTOR_SIZE + 1;
+        while sector * SECTOR_SIZE < end && self.is_in_overlay(sector) == in_overlay {
+            sector += 1;
+        }
+        cmp::min(sector * SECTOR_SIZE, end)
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<()> {
+        if self.is_in_overlay(position / SECTOR_SIZE) {
+            self.overlay
+                .seek(SeekFrom::Start(self.data_offset + position))?;
+            self.overlay.read_exact(buf)
+        } else {
+            self.base.seek(SeekFrom::Start(position))?;
+            self.base.read_exact(buf)
+        }
+    }
+
+    // Writes `buf`, which is inside the sector holding `position` without covering all of it.
+    fn write_partial_sector(&mut self, position: u64, buf: &[u8]) -> io::Result<()> {
+        let sector = position / SECTOR_SIZE;
+        if self.is_in_overlay(sector) {
+            self.overlay
+                .seek(SeekFrom::Start(self.data_offset + position))?;
+            return self.overlay.write_all(buf);
+        }
+
+        // The rest of the sector is copied from the base.
+        let sector_start = sector * SECTOR_SIZE;
+        let sector_len = cmp::min(SECTOR_SIZE, self.size - sector_start) as usize;
+        let mut data = vec![0u8; sector_len];
+        self.base.seek(SeekFrom::Start(sector_start))?;
+        self.base.read_exact(&mut data)?;
+        let in_sector = (position - sector_start) as usize;
+        data[in_sector..in_sector + buf.len()].copy_from_slice(buf);
+
+        self.overlay
+            .seek(SeekFrom::Start(self.data_offset + sector_start))?;
+        self.overlay.write_all(&data)?;
+        self.set_in_overlay(sector, sector + 1)
+    }
+}
+
+impl Read for OverlayImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len() as u64, self.size - self.position) as usize;
+        let end = self.position + len as u64;
+        let mut done = 0;
+        while done < len {
+            let position = self.position;
+            let chunk = (self.run_end(position, end) - position) as usize;
+            self.read_at(position, &mut buf[done..done + chunk])?;
+            self.position += chunk as u64;
+            done += chunk;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for OverlayImage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len() as u64, self.size - self.position) as usize;
+        let end = self.position + len as u64;
+        let mut done = 0;
+        while done < len {
+            let position = self.position;
+            let remaining = (len - done) as u64;
+            let in_sector = position % SECTOR_SIZE;
+            // Whole sectors, the last one possibly ending with the disk, replace the base ones.
+            let whole = if in_sector != 0 {
+                0
+            } else if end == self.size {
+                remaining
+            } else {
+                remaining - remaining % SECTOR_SIZE
+            };
+
+            let chunk = if whole > 0 {
+                self.overlay
+                    .seek(SeekFrom::Start(self.data_offset + position))?;
+                self.overlay.write_all(&buf[done..done + whole as usize])?;
+                let first = position / SECTOR_SIZE;
+                let last = (position + whole + SECTOR_SIZE - 1) / SECTOR_SIZE;
+                self.set_in_overlay(first, last)?;
+                whole as usize
+            } else {
+                let chunk = cmp::min(remaining, SECTOR_SIZE - in_sector) as usize;
+                self.write_partial_sector(position, &buf[done..done + chunk])?;
+                chunk
+            };
+            self.position += chunk as u64;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.overlay.sync_all()
+    }
+}
+
+impl Seek for OverlayImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add_signed(self.size, offset),
+            SeekFrom::Current(offset) => checked_add_signed(self.position, offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl DiskImage for OverlayImage {
+    // The overlay is what identifies the disk of a given microVM.
+    fn file(&self) -> &File {
+        &self.overlay
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.overlay.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::NamedTempFile;
+    use super::*;
+
+    fn base_image(len: usize) -> (NamedTempFile, Vec<u8>) {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let base = NamedTempFile::new().unwrap();
+        base.reopen().unwrap().write_all(&data).unwrap();
+        (base, data)
+    }
+
+    fn open_overlay(base: &NamedTempFile, overlay: &NamedTempFile) -> io::Result<OverlayImage> {
+        OverlayImage::new(
+            Box::new(File::open(base.path()).unwrap()),
+            overlay.reopen().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_read_write() {
+        // The last sector is partial.
+        let (base, mut expected) = base_image(0x10_0000 + 0x100);
+        let overlay = NamedTempFile::new().unwrap();
+        let mut image = open_overlay(&base, &overlay).unwrap();
+
+        // Everything is read from the base at first.
+        let mut buf = vec![0u8; expected.len()];
+        assert_eq!(image.seek(SeekFrom::End(0)).unwrap(), expected.len() as u64);
+        image.seek(SeekFrom::Start(0)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // Whole sectors, partial sectors, and the tail of the disk.
+        let writes: &[(usize, usize)] = &[(0x200, 0x400), (0x1010, 0x20), (0x2100, 0x400)];
+        for (i, &(offset, len)) in writes.iter().enumerate() {
+            let data = vec![i as u8 + 1; len];
+            image.seek(SeekFrom::Start(offset as u64)).unwrap();
+            image.write_all(&data).unwrap();
+            expected[offset..offset + len].copy_from_slice(&data);
+        }
+        let tail = expected.len() - 0x300;
+        image.seek(SeekFrom::Start(tail as u64)).unwrap();
+        assert_eq!(image.write(&[0xffu8; 0x400]).unwrap(), 0x300);
+        assert_eq!(image.write(&[0xffu8; 0x400]).unwrap(), 0);
+        for b in expected[tail..].iter_mut() {
+            *b = 0xff;
+        }
+        image.flush().unwrap();
+
+        image.seek(SeekFrom::Start(0)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // Only the written sectors are redirected.
+        assert_eq!(image.bitmap[0], 0b0000_0110);
+        assert_eq!(image.bitmap[1], 0b0000_0001);
+        assert_eq!(image.bitmap[2], 0b0000_0111);
+
+        // The base is untouched, and the overlay persists the writes.
+        let (_, base_data) = base_image(expected.len());
+        let mut base_after = Vec::new();
+        File::open(base.path())
+            .unwrap()
+            .read_to_end(&mut base_after)
+            .unwrap();
+        assert_eq!(base_after, base_data);
+
+        let mut image = open_overlay(&base, &overlay).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_invalid_overlay() {
+        let (base, _) = base_image(0x1000);
+        let overlay = NamedTempFile::new().unwrap();
+        assert!(open_overlay(&base, &overlay).is_ok());
+
+        // The base image changed size.
+        let (other_base, _) = base_image(0x2000);
+        assert!(open_overlay(&other_base, &overlay).is_err());
+
+        // The overlay is not an overlay.
+        assert!(open_overlay(&base, &other_base).is_err());
+        let mut file = overlay.reopen().unwrap();
+        file.seek(SeekFrom::Start(8)).unwrap();
+        file.write_all(&[2, 0, 0, 0]).unwrap();
+        assert!(open_overlay(&base, &overlay).is_err());
+        file.set_len(0x10).unwrap();
+        assert!(open_overlay(&base, &overlay).is_err());
+    }
+}
@@ -0,0 +1,849 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads and writes QEMU copy-on-write (qcow2) disk images.
+//!
+//! The guest disk is split into clusters. A two level table (L1 and L2 tables) maps each guest
+//! cluster to a host cluster of the image file, and the clusters that were never written are
+//! read from the backing file, if any, or as zeroes. New clusters are appended to the image file,
+//! and their reference counts are kept up to date so that the image stays valid for other tools.
+//! The metadata is written through, so `flush` only has to sync the image file.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+
+use super::disk_image::{checked_add_signed, DiskImage};
+
+const QCOW_MAGIC: u32 = 0x5146_49fb;
+const V2_HEADER_SIZE: usize = 72;
+const V3_HEADER_SIZE: usize = 104;
+
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+// Only 16 bit reference counts, the default ones, are supported.
+const REFCOUNT_ORDER: u32 = 4;
+const REFCOUNT_BYTES: u64 = 2;
+// Bounds the memory used by the L1 and reference count tables.
+const MAX_TABLE_BYTES: u64 = 32 << 20;
+const MAX_BACKING_FILE_NAME: u32 = 1023;
+// Bounds the length of the chains of backing files, which could also be loops.
+const MAX_BACKING_DEPTH: u32 = 16;
+// Number of L2 tables kept in memory.
+const L2_CACHE_SIZE: usize = 64;
+
+// Bits of the L1 and L2 entries holding a host offset.
+const ENTRY_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+// Bits of the reference count table entries holding a host offset.
+const REFCOUNT_TABLE_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+// The reference count of the cluster is 1.
+const COPIED_FLAG: u64 = 1 << 63;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+// The cluster reads as zeroes (qcow2 version 3 only).
+const ZERO_FLAG: u64 = 1;
+
+fn invalid_image(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid qcow2 image: {}", msg),
+    )
+}
+
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("Unsupported qcow2 image: {}", msg),
+    )
+}
+
+fn div_round_up(value: u64, divisor: u64) -> u64 {
+    value / divisor + if value % divisor != 0 { 1 } else { 0 }
+}
+
+// The fields of the qcow2 header used for accessing the image.
+struct QcowHeader {
+    version: u32,
+    backing_file_offset: u64,
+    backing_file_size: u32,
+    cluster_bits: u32,
+    size: u64,
+    crypt_method: u32,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    nb_snapshots: u32,
+    incompatible_features: u64,
+    refcount_order: u32,
+}
+
+impl QcowHeader {
+    fn read(file: &mut File) -> io::Result<QcowHeader> {
+        let mut buf = [0u8; V3_HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buf[..V2_HEADER_SIZE])?;
+
+        let mut header = &buf[..V2_HEADER_SIZE];
+        if header.read_u32::<BigEndian>()? != QCOW_MAGIC {
+            return Err(invalid_image("bad magic number"));
+        }
+        let version = header.read_u32::<BigEndian>()?;
+        let mut qcow_header = QcowHeader {
+            version,
+            backing_file_offset: header.read_u64::<BigEndian>()?,
+            backing_file_size: header.read_u32::<BigEndian>()?,
+            cluster_bits: header.read_u32::<BigEndian>()?,
+            size: header.read_u64::<BigEndian>()?,
+            crypt_method: header.read_u32::<BigEndian>()?,
+            l1_size: header.read_u32::<BigEndian>()?,
+            l1_table_offset: header.read_u64::<BigEndian>()?,
+            refcount_table_offset: header.read_u64::<BigEndian>()?,
+            refcount_table_clusters: header.read_u32::<BigEndian>()?,
+            nb_snapshots: header.read_u32::<BigEndian>()?,
+            incompatible_features: 0,
+            refcount_order: REFCOUNT_ORDER,
+        };
+
+        match version {
+            2 => (),
+            3 => {
+                file.read_exact(&mut buf[V2_HEADER_SIZE..])?;
+                let mut header = &buf[V2_HEADER_SIZE..];
+                qcow_header.incompatible_features = header.read_u64::<BigEndian>()?;
+                // The compatible and autoclear features can be safely ignored.
+                header.read_u64::<BigEndian>()?;
+                header.read_u64::<BigEndian>()?;
+                qcow_header.refcount_order = header.read_u32::<BigEndian>()?;
+            }
+            v => return Err(unsupported(&format!("version {}", v))),
+        }
+        Ok(qcow_header)
+    }
+}
+
+/// A qcow2 image, seen as the guest disk it holds.
+pub struct QcowFile {
+    file: File,
+    backing_file: Option<Box<dyn DiskImage>>,
+    backing_size: u64,
+    version: u32,
+    cluster_bits: u32,
+    cluster_size: u64,
+    size: u64,
+    l1_table: Vec<u64>,
+    l1_table_offset: u64,
+    refcount_table: Vec<u64>,
+    refcount_table_offset: u64,
+    // L2 tables indexed by their offset in the image file.
+    l2_cache: HashMap<u64, Vec<u64>>,
+    // Offset in the image file of the next cluster to be allocated.
+    next_cluster: u64,
+    // Offset in the guest disk of the next read or write.
+    position: u64,
+}
+
+impl QcowFile {
+    /// Opens the qcow2 image stored in `file`, found at `path`. The backing file, if any, is
+    /// looked up relatively to the directory of `path`, and opened read-only.
+    pub fn new(file: File, path: &Path) -> io::Result<QcowFile> {
+        QcowFile::with_depth(file, path, 0)
+    }
+
+    fn with_depth(mut file: File, path: &Path, depth: u32) -> io::Result<QcowFile> {
+        let header = QcowHeader::read(&mut file)?;
+
+        if header.cluster_bits < MIN_CLUSTER_BITS || header.cluster_bits > MAX_CLUSTER_BITS {
+            return Err(invalid_image("bad cluster size"));
+        }
+        if header.crypt_method != 0 {
+            return Err(unsupported("encryption"));
+        }
+        if header.nb_snapshots != 0 {
+            return Err(unsupported("internal snapshots"));
+        }
+        if header.incompatible_features != 0 {
+            return Err(unsupported(&format!(
+                "incompatible features {:#x}",
+                header.incompatible_features
+            )));
+        }
+        if header.refcount_order != REFCOUNT_ORDER {
+            return Err(unsupported("reference counts other than 16 bit"));
+        }
+
+        let cluster_size = 1u64 << header.cluster_bits;
+        let l2_entries = cluster_size / 8;
+        let guest_clusters = div_round_up(header.size, cluster_size);
+        if u64::from(header.l1_size) < div_round_up(guest_clusters, l2_entries) {
+            return Err(invalid_image("L1 table too small for the disk size"));
+        }
+        let refcount_table_size = u64::from(header.refcount_table_clusters) * cluster_size;
+        if u64::from(header.l1_size) * 8 > MAX_TABLE_BYTES || refcount_table_size > MAX_TABLE_BYTES
+        {
+            return Err(unsupported("metadata tables too large"));
+        }
+        if header.l1_table_offset % cluster_size != 0
+            || header.refcount_table_offset % cluster_size != 0
+        {
+            return Err(invalid_image("unaligned metadata tables"));
+        }
+
+        let l1_table = read_table(&mut file, header.l1_table_offset, u64::from(header.l1_size))?;
+        let refcount_table = read_table(
+            &mut file,
+            header.refcount_table_offset,
+            refcount_table_size / 8,
+        )?;
+
+        let (backing_file, backing_size) = if header.backing_file_offset != 0 {
+            if depth >= MAX_BACKING_DEPTH {
+                return Err(unsupported("chain of backing files too long"));
+            }
+            if header.backing_file_size > MAX_BACKING_FILE_NAME {
+                return Err(invalid_image("backing file name too long"));
+            }
+            let mut name = vec![0u8; header.backing_file_size as usize];
+            file.seek(SeekFrom::Start(header.backing_file_offset))?;
+            file.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| invalid_image("backing file name is not UTF-8"))?;
+            let backing_path = path.parent().unwrap_or_else(|| Path::new("")).join(&name);
+            let mut backing_file = open_backing_file(&backing_path, depth)?;
+            let backing_size = backing_file.seek(SeekFrom::End(0))?;
+            (Some(backing_file), backing_size)
+        } else {
+            (None, 0)
+        };
+
+        let file_size = file.seek(SeekFrom::End(0))?;
+        Ok(QcowFile {
+            file,
+            backing_file,
+            backing_size,
+            version: header.version,
+            cluster_bits: header.cluster_bits,
+            cluster_size,
+            size: header.size,
+            l1_table,
+            l1_table_offset: header.l1_table_offset,
+            refcount_table,
+            refcount_table_offset: header.refcount_table_offset,
+            l2_cache: HashMap::new(),
+            next_cluster: div_round_up(file_size, cluster_size) * cluster_size,
+            position: 0,
+        })
+    }
+
+    fn l2_entries(&self) -> u64 {
+        self.cluster_size / 8
+    }
+
+    // Returns the indexes in the L1 and L2 tables of the cluster holding `guest_offset`.
+    fn table_indexes(&self, guest_offset: u64) -> (usize, usize) {
+        let cluster = guest_offset >> self.cluster_bits;
+        (
+            (cluster / self.l2_entries()) as usize,
+            (cluster % self.l2_entries()) as usize,
+        )
+    }
+
+    fn l2_table(&mut self, l2_offset: u64) -> io::Result<&mut Vec<u64>> {
+        if !self.l2_cache.contains_key(&l2_offset) {
+            if l2_offset % self.cluster_size != 0 {
+                return Err(invalid_image("unaligned L2 table"));
+            }
+            let entries = self.l2_entries();
+            let table = read_table(&mut self.file, l2_offset, entries)?;
+            if self.l2_cache.len() >= L2_CACHE_SIZE {
+                // The metadata is written through, so any table can be dropped.
+                let evicted = *self.l2_cache.keys().next().unwrap();
+                self.l2_cache.remove(&evicted);
+            }
+            self.l2_cache.insert(l2_offset, table);
+        }
+        Ok(self.l2_cache.get_mut(&l2_offset).unwrap())
+    }
+
+    // Returns the L2 entry of the cluster holding `guest_offset`, which is 0 if the cluster was
+    // never allocated.
+    fn l2_entry(&mut self, guest_offset: u64) -> io::Result<u64> {
+        let (l1_index, l2_index) = self.table_indexes(guest_offset);
+        let l2_offset = self.l1_table[l1_index] & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(0);
+        }
+        let entry = self.l2_table(l2_offset)?[l2_index];
+        if entry & COMPRESSED_FLAG != 0 {
+            return Err(unsupported("compressed clusters"));
+        }
+        Ok(entry)
+    }
+
+    fn set_l2_entry(&mut self, guest_offset: u64, entry: u64) -> io::Result<()> {
+        let (l1_index, l2_index) = self.table_indexes(guest_offset);
+        let mut l2_offset = self.l1_table[l1_index] & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            // The new table must be on disk before the L1 table points to it.
+            l2_offset = self.allocate_cluster()?;
+            self.write_zero_cluster(l2_offset)?;
+            self.l1_table[l1_index] = l2_offset | COPIED_FLAG;
+            let l1_entry_offset = self.l1_table_offset + l1_index as u64 * 8;
+            write_u64_at(&mut self.file, l1_entry_offset, l2_offset | COPIED_FLAG)?;
+        }
+        self.l2_table(l2_offset)?[l2_index] = entry;
+        write_u64_at(&mut self.file, l2_offset + l2_index as u64 * 8, entry)
+    }
+
+    // Reserves a new cluster at the end of the image file, without writing it.
+    fn allocate_cluster(&mut self) -> io::Result<u64> {
+        let offset = self.next_cluster;
+        self.next_cluster += self.cluster_size;
+        self.set_refcount(offset, 1)?;
+        Ok(offset)
+    }
+
+    fn set_refcount(&mut self, host_offset: u64, refcount: u16) -> io::Result<()> {
+        let block_entries = self.cluster_size / REFCOUNT_BYTES;
+        let cluster = host_offset >> self.cluster_bits;
+        let table_index = (cluster / block_entries) as usize;
+        if table_index >= self.refcount_table.len() {
+            return Err(unsupported("the reference count table is full"));
+        }
+
+        let mut block_offset = self.refcount_table[table_index] & REFCOUNT_TABLE_OFFSET_MASK;
+        if block_offset == 0 {
+            block_offset = self.next_cluster;
+            self.next_cluster += self.cluster_size;
+            self.write_zero_cluster(block_offset)?;
+            self.refcount_table[table_index] = block_offset;
+            let table_entry_offset = self.refcount_table_offset + table_index as u64 * 8;
+            write_u64_at(&mut self.file, table_entry_offset, block_offset)?;
+            // The new block is referenced as well, possibly by itself.
+            self.set_refcount(block_offset, 1)?;
+        }
+
+        let entry_offset = block_offset + (cluster % block_entries) * REFCOUNT_BYTES;
+        self.file.seek(SeekFrom::Start(entry_offset))?;
+        self.file.write_u16::<BigEndian>(refcount)
+    }
+
+    fn write_zero_cluster(&mut self, host_offset: u64) -> io::Result<()> {
+        let zeroes = vec![0u8; self.cluster_size as usize];
+        self.file.seek(SeekFrom::Start(host_offset))?;
+        self.file.write_all(&zeroes)
+    }
+
+    // Reads `buf` from the backing file, or as zeroes past its end.
+    fn read_backing(&mut self, guest_offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        if let Some(ref mut backing_file) = self.backing_file {
+            if guest_offset < self.backing_size {
+                let len = cmp::min(buf.len() as u64, self.backing_size - guest_offset) as usize;
+                backing_file.seek(SeekFrom::Start(guest_offset))?;
+                backing_file.read_exact(&mut buf[..len])?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reads `buf`, which does not cross a cluster boundary, at `guest_offset`.
+    fn read_in_cluster(&mut self, guest_offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let entry = self.l2_entry(guest_offset)?;
+        let host_cluster = entry & ENTRY_OFFSET_MASK;
+        if self.version >= 3 && entry & ZERO_FLAG != 0 {
+            for b in buf.iter_mut() {
+                *b = 0;
+            }
+            Ok(())
+        } else if host_cluster == 0 {
+            self.read_backing(guest_offset, buf)
+        } else {
+            let in_cluster = guest_offset & (self.cluster_size - 1);
+            self.file.seek(SeekFrom::Start(host_cluster + in_cluster))?;
+            self.file.read_exact(buf)
+        }
+    }
+
+    // Writes `buf`, which does not cross a cluster boundary, at `guest_offset`.
+    fn write_in_cluster(&mut self, guest_offset: u64, buf: &[u8]) -> io::Result<()> {
+        let entry = self.l2_entry(guest_offset)?;
+        let host_cluster = entry & ENTRY_OFFSET_MASK;
+        let in_cluster = guest_offset & (self.cluster_size - 1);
+        let is_zero = self.version >= 3 && entry & ZERO_FLAG != 0;
+        if host_cluster != 0 && !is_zero {
+            self.file.seek(SeekFrom::Start(host_cluster + in_cluster))?;
+            return self.file.write_all(buf);
+        }
+
+        // The rest of the cluster keeps reading the same after it is allocated.
+        let mut data = vec![0u8; self.cluster_size as usize];
+        if !is_zero && buf.len() as u64 != self.cluster_size {
+            self.read_backing(guest_offset - in_cluster, &mut data)?;
+        }
+        data[in_cluster as usize..in_cluster as usize + buf.len()].copy_from_slice(buf);
+
+        // Zero clusters may already have a host cluster allocated.
+        let host_cluster = if host_cluster != 0 {
+            host_cluster
+        } else {
+            self.allocate_cluster()?
+        };
+        // The data must be on disk before the L2 table points to it.
+        self.file.seek(SeekFrom::Start(host_cluster))?;
+        self.file.write_all(&data)?;
+        self.set_l2_entry(guest_offset, host_cluster | COPIED_FLAG)
+    }
+}
+
+impl Read for QcowFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len() as u64, self.size - self.position) as usize;
+        let mut done = 0;
+        while done < len {
+            let in_cluster = self.position & (self.cluster_size - 1);
+            let chunk = cmp::min((len - done) as u64, self.cluster_size - in_cluster) as usize;
+            let position = self.position;
+            self.read_in_cluster(position, &mut buf[done..done + chunk])?;
+            self.position += chunk as u64;
+            done += chunk;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for QcowFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len() as u64, self.size - self.position) as usize;
+        let mut done = 0;
+        while done < len {
+            let in_cluster = self.position & (self.cluster_size - 1);
+            let chunk = cmp::min((len - done) as u64, self.cluster_size - in_cluster) as usize;
+            let position = self.position;
+            self.write_in_cluster(position, &buf[done..done + chunk])?;
+            self.position += chunk as u64;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+impl Seek for QcowFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add_signed(self.size, offset),
+            SeekFrom::Current(offset) => checked_add_signed(self.position, offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl DiskImage for QcowFile {
+    fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+fn read_table(file: &mut File, offset: u64, entries: u64) -> io::Result<Vec<u64>> {
+    let mut buf = vec![0u8; entries as usize * 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    let mut table = vec![0u64; entries as usize];
+    BigEndian::read_u64_into(&buf, &mut table);
+    Ok(table)
+}
+
+fn write_u64_at(file: &mut File, offset: u64, value: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_u64::<BigEndian>(value)
+}
+
+// Opens a backing file read-only, as a qcow2 image if it has the qcow2 magic number, and as a raw
+// image otherwise.
+fn open_backing_file(path: &Path, depth: u32) -> io::Result<Box<dyn DiskImage>> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let mut magic = [0u8; 4];
+    let is_qcow = match file.read_exact(&mut magic) {
+        Ok(()) => BigEndian::read_u32(&magic) == QCOW_MAGIC,
+        // Raw images can be shorter than the magic number.
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    if is_qcow {
+        Ok(Box::new(QcowFile::with_depth(file, path, depth + 1)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::{tempdir, NamedTempFile};
+    use super::*;
+
+    const TEST_CLUSTER_BITS: u32 = 12;
+    const TEST_CLUSTER_SIZE: u64 = 1 << TEST_CLUSTER_BITS;
+
+    /// Creates an empty qcow2 version 3 image of `size` bytes with 4 KiB clusters, laid out as
+    /// qemu-img does: the header, the reference count table, a reference count block and the L1
+    /// table, each in its own cluster.
+    pub fn create_qcow_image(file: &mut File, size: u64, backing_file: Option<&str>) {
+        let l2_entries = TEST_CLUSTER_SIZE / 8;
+        let l1_size = div_round_up(div_round_up(size, TEST_CLUSTER_SIZE), l2_entries);
+        assert!(l1_size * 8 <= TEST_CLUSTER_SIZE);
+
+        let mut header = vec![0u8; TEST_CLUSTER_SIZE as usize];
+        {
+            let mut h = &mut header[..];
+            h.write_u32::<BigEndian>(QCOW_MAGIC).unwrap();
+            h.write_u32::<BigEndian>(3).unwrap();
+            // The backing file name follows the header extensions.
+            h.write_u64::<BigEndian>(if backing_file.is_some() { 0x200 } else { 0 })
+                .unwrap();
+            h.write_u32::<BigEndian>(backing_file.map_or(0, |name| name.len() as u32))
+                .unwrap();
+            h.write_u32::<BigEndian>(TEST_CLUSTER_BITS).unwrap();
+            h.write_u64::<BigEndian>(size).unwrap();
+            // No encryption.
+            h.write_u32::<BigEndian>(0).unwrap();
+            h.write_u32::<BigEndian>(l1_size as u32).unwrap();
+            h.write_u64::<BigEndian>(3 * TEST_CLUSTER_SIZE).unwrap();
+            h.write_u64::<BigEndian>(TEST_CLUSTER_SIZE).unwrap();
+            h.write_u32::<BigEndian>(1).unwrap();
+            // No snapshots.
+            h.write_u32::<BigEndian>(0).unwrap();
+            h.write_u64::<BigEndian>(0).unwrap();
+            // No features.
+            h.write_u64::<BigEndian>(0).unwrap();
+            h.write_u64::<BigEndian>(0).unwrap();
+            h.write_u64::<BigEndian>(0).unwrap();
+            h.write_u32::<BigEndian>(REFCOUNT_ORDER).unwrap();
+            h.write_u32::<BigEndian>(V3_HEADER_SIZE as u32).unwrap();
+        }
+        if let Some(name) = backing_file {
+            header[0x200..0x200 + name.len()].copy_from_slice(name.as_bytes());
+        }
+
+        let mut refcount_table = vec![0u8; TEST_CLUSTER_SIZE as usize];
+        BigEndian::write_u64(&mut refcount_table, 2 * TEST_CLUSTER_SIZE);
+        let mut refcount_block = vec![0u8; TEST_CLUSTER_SIZE as usize];
+        for i in 0..4 {
+            BigEndian::write_u16(&mut refcount_block[i * 2..], 1);
+        }
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&header).unwrap();
+        file.write_all(&refcount_table).unwrap();
+        file.write_all(&refcount_block).unwrap();
+        file.write_all(&vec![0u8; TEST_CLUSTER_SIZE as usize])
+            .unwrap();
+    }
+
+    fn open_qcow_image(image: &NamedTempFile) -> QcowFile {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(image.path())
+            .unwrap();
+        QcowFile::new(file, image.path()).unwrap()
+    }
+
+    fn refcount(image: &mut QcowFile, host_offset: u64) -> u16 {
+        let block_entries = image.cluster_size / REFCOUNT_BYTES;
+        let cluster = host_offset >> image.cluster_bits;
+        let block = image.refcount_table[(cluster / block_entries) as usize];
+        image
+            .file
+            .seek(SeekFrom::Start(
+                block + (cluster % block_entries) * REFCOUNT_BYTES,
+            ))
+            .unwrap();
+        image.file.read_u16::<BigEndian>().unwrap()
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let image = NamedTempFile::new().unwrap();
+        let mut file = image.reopen().unwrap();
+        create_qcow_image(&mut file, 0x10_0000, None);
+        assert!(QcowFile::new(image.reopen().unwrap(), image.path()).is_ok());
+
+        // Each of these corrupts a header field at some offset.
+        let cases: &[(u64, &[u8])] = &[
+            // Magic.
+            (0, &[0, 0, 0, 0]),
+            // Version.
+            (4, &[0, 0, 0, 1]),
+            // Cluster bits.
+            (20, &[0, 0, 0, 30]),
+            // Encryption.
+            (32, &[0, 0, 0, 1]),
+            // L1 table size.
+            (36, &[0, 0, 0, 0]),
+            // Snapshots.
+            (60, &[0, 0, 0, 1]),
+            // Incompatible features.
+            (79, &[1]),
+            // Refcount order.
+            (96, &[0, 0, 0, 5]),
+        ];
+        for (offset, bytes) in cases {
+            create_qcow_image(&mut file, 0x10_0000, None);
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(bytes).unwrap();
+            assert!(QcowFile::new(image.reopen().unwrap(), image.path()).is_err());
+        }
+
+        // Truncated image.
+        file.set_len(0x10).unwrap();
+        assert!(QcowFile::new(image.reopen().unwrap(), image.path()).is_err());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let size = 0x40_0000;
+        let image = NamedTempFile::new().unwrap();
+        create_qcow_image(&mut image.reopen().unwrap(), size, None);
+        let mut qcow = open_qcow_image(&image);
+
+        // The disk reads as zeroes, up to its virtual size.
+        assert_eq!(qcow.seek(SeekFrom::End(0)).unwrap(), size);
+        qcow.seek(SeekFrom::Start(size - 0x800)).unwrap();
+        let mut buf = vec![0xffu8; 0x1000];
+        assert_eq!(qcow.read(&mut buf).unwrap(), 0x800);
+        assert!(buf[..0x800].iter().all(|b| *b == 0));
+        assert_eq!(qcow.read(&mut buf).unwrap(), 0);
+        assert_eq!(qcow.write(&buf).unwrap(), 0);
+
+        // A write across clusters, and across L2 tables.
+        let data: Vec<u8> = (0..0x3000).map(|i| i as u8).collect();
+        let offset = 0x20_0000 - 0x1800;
+        qcow.seek(SeekFrom::Start(offset)).unwrap();
+        qcow.write_all(&data).unwrap();
+        qcow.flush().unwrap();
+
+        let mut read_back = vec![0u8; 0x4000];
+        qcow.seek(SeekFrom::Start(offset - 0x800)).unwrap();
+        qcow.read_exact(&mut read_back).unwrap();
+        assert!(read_back[..0x800].iter().all(|b| *b == 0));
+        assert_eq!(&read_back[0x800..0x3800], &data[..]);
+        assert!(read_back[0x3800..].iter().all(|b| *b == 0));
+
+        // Rewriting allocated clusters does not allocate new ones.
+        let file_size = qcow.file.metadata().unwrap().len();
+        qcow.seek(SeekFrom::Start(offset)).unwrap();
+        qcow.write_all(&data).unwrap();
+        assert_eq!(qcow.file.metadata().unwrap().len(), file_size);
+
+        // All the allocated clusters are referenced once: the 4 initial ones, 4 data clusters
+        // and 2 L2 tables.
+        assert_eq!(file_size, 10 * TEST_CLUSTER_SIZE);
+        for cluster in 0..10 {
+            assert_eq!(refcount(&mut qcow, cluster * TEST_CLUSTER_SIZE), 1);
+        }
+        assert_eq!(refcount(&mut qcow, 10 * TEST_CLUSTER_SIZE), 0);
+
+        // The data is still there after reopening the image.
+        let mut qcow = open_qcow_image(&image);
+        let mut reopened = vec![0u8; 0x4000];
+        qcow.seek(SeekFrom::Start(offset - 0x800)).unwrap();
+        qcow.read_exact(&mut reopened).unwrap();
+        assert_eq!(reopened, read_back);
+    }
+
+    #[test]
+    fn test_refcount_blocks() {
+        // Each refcount block covers 2048 clusters, so writing past 8 MiB of image file
+        // allocates a second block.
+        let size = 0x100_0000;
+        let image = NamedTempFile::new().unwrap();
+        create_qcow_image(&mut image.reopen().unwrap(), size, None);
+        let mut qcow = open_qcow_image(&image);
+
+        let data = vec![0xaau8; TEST_CLUSTER_SIZE as usize];
+        for cluster in 0..2100 {
+            qcow.seek(SeekFrom::Start(cluster * TEST_CLUSTER_SIZE))
+                .unwrap();
+            qcow.write_all(&data).unwrap();
+        }
+        let second_block = qcow.refcount_table[1];
+        assert_ne!(second_block, 0);
+        assert_eq!(refcount(&mut qcow, second_block), 1);
+        let file_clusters = qcow.file.metadata().unwrap().len() / TEST_CLUSTER_SIZE;
+        for cluster in 0..file_clusters {
+            assert_eq!(refcount(&mut qcow, cluster * TEST_CLUSTER_SIZE), 1);
+        }
+
+        let mut qcow = open_qcow_image(&image);
+        let mut buf = vec![0u8; TEST_CLUSTER_SIZE as usize];
+        qcow.seek(SeekFrom::Start(2099 * TEST_CLUSTER_SIZE))
+            .unwrap();
+        qcow.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_backing_files() {
+        let dir = tempdir().unwrap();
+
+        // A raw base, shorter than the disk.
+        let base: Vec<u8> = (0..0x3000).map(|i| (i % 251) as u8).collect();
+        File::create(dir.path().join("base.raw"))
+            .unwrap()
+            .write_all(&base)
+            .unwrap();
+        // A qcow2 image on top of it, with one cluster written.
+        let middle_path = dir.path().join("middle.qcow2");
+        let mut middle_file = File::create(&middle_path).unwrap();
+        create_qcow_image(&mut middle_file, 0x10_0000, Some("base.raw"));
+        {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&middle_path)
+                .unwrap();
+            let mut middle = QcowFile::new(file, &middle_path).unwrap();
+            middle.seek(SeekFrom::Start(0x1000)).unwrap();
+            middle.write_all(&[0x11u8; 0x10]).unwrap();
+        }
+        // And a qcow2 image on top of that one.
+        let top_path = dir.path().join("top.qcow2");
+        let mut top_file = File::create(&top_path).unwrap();
+        create_qcow_image(&mut top_file, 0x10_0000, Some("middle.qcow2"));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&top_path)
+            .unwrap();
+        let mut top = QcowFile::new(file, &top_path).unwrap();
+
+        let mut expected = base.clone();
+        expected[0x1000..0x1010].copy_from_slice(&[0x11u8; 0x10]);
+        expected.extend_from_slice(&[0u8; 0x1000]);
+        let mut buf = vec![0u8; 0x4000];
+        top.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // A partial write keeps the rest of the cluster from the backing files.
+        top.seek(SeekFrom::Start(0x1008)).unwrap();
+        top.write_all(&[0x22u8; 0x10]).unwrap();
+        expected[0x1008..0x1018].copy_from_slice(&[0x22u8; 0x10]);
+        top.seek(SeekFrom::Start(0)).unwrap();
+        top.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        // The backing files are left untouched.
+        let mut base_after = Vec::new();
+        File::open(dir.path().join("base.raw"))
+            .unwrap()
+            .read_to_end(&mut base_after)
+            .unwrap();
+        assert_eq!(base_after, base);
+
+        // A missing backing file makes the image unusable.
+        std::fs::remove_file(dir.path().join("base.raw")).unwrap();
+        assert!(QcowFile::new(File::open(&top_path).unwrap(), &top_path).is_err());
+
+        // So does a loop of backing files.
+        let loop_path = dir.path().join("loop.qcow2");
+        let mut loop_file = File::create(&loop_path).unwrap();
+        create_qcow_image(&mut loop_file, 0x10_0000, Some("loop.qcow2"));
+        assert!(QcowFile::new(File::open(&loop_path).unwrap(), &loop_path).is_err());
+    }
+
+    #[test]
+    fn test_zero_clusters() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("base.raw"))
+            .unwrap()
+            .write_all(&[0xffu8; 0x4000])
+            .unwrap();
+        let path = dir.path().join("image.qcow2");
+        create_qcow_image(
+            &mut File::create(&path).unwrap(),
+            0x10_0000,
+            Some("base.raw"),
+        );
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut qcow = QcowFile::new(file, &path).unwrap();
+
+        // Allocate the first cluster, then turn it and the second one into zero clusters.
+        qcow.write_all(&[0x11u8; 0x10]).unwrap();
+        let allocated = qcow.l2_entry(0).unwrap() & ENTRY_OFFSET_MASK;
+        assert_ne!(allocated, 0);
+        qcow.set_l2_entry(0, allocated | ZERO_FLAG).unwrap();
+        qcow.set_l2_entry(TEST_CLUSTER_SIZE, ZERO_FLAG).unwrap();
+
+        let mut buf = vec![0xaau8; 0x3000];
+        qcow.seek(SeekFrom::Start(0)).unwrap();
+        qcow.read_exact(&mut buf).unwrap();
+        assert!(buf[..0x2000].iter().all(|b| *b == 0));
+        assert!(buf[0x2000..].iter().all(|b| *b == 0xff));
+
+        // Partial writes to zero clusters keep the rest of the cluster zeroed, and reuse the
+        // host cluster if there is one.
+        let file_size = qcow.file.metadata().unwrap().len();
+        qcow.seek(SeekFrom::Start(0x10)).unwrap();
+        qcow.write_all(&[0x22u8; 0x10]).unwrap();
+        assert_eq!(qcow.l2_entry(0).unwrap(), allocated | COPIED_FLAG);
+        assert_eq!(qcow.file.metadata().unwrap().len(), file_size);
+        qcow.seek(SeekFrom::Start(0x1010)).unwrap();
+        qcow.write_all(&[0x33u8; 0x10]).unwrap();
+
+        qcow.seek(SeekFrom::Start(0)).unwrap();
+        qcow.read_exact(&mut buf).unwrap();
+        let mut expected = vec![0u8; 0x2000];
+        expected[0x10..0x20].copy_from_slice(&[0x22u8; 0x10]);
+        expected[0x1010..0x1020].copy_from_slice(&[0x33u8; 0x10]);
+        assert_eq!(&buf[..0x2000], &expected[..]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let image = NamedTempFile::new().unwrap();
+        create_qcow_image(&mut image.reopen().unwrap(), 0x10_0000, None);
+        let mut qcow = open_qcow_image(&image);
+
+        assert_eq!(qcow.seek(SeekFrom::Start(0x10)).unwrap(), 0x10);
+        assert_eq!(qcow.seek(SeekFrom::Current(0x10)).unwrap(), 0x20);
+        assert_eq!(qcow.seek(SeekFrom::Current(-0x20)).unwrap(), 0);
+        assert_eq!(qcow.seek(SeekFrom::End(-0x10)).unwrap(), 0x10_0000 - 0x10);
+        assert!(qcow.seek(SeekFrom::Current(-0x10_0000)).is_err());
+        assert_eq!(qcow.position, 0x10_0000 - 0x10);
+    }
+}
@@ -5,6 +5,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::io;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
@@ -70,6 +71,48 @@ pub trait VirtioDevice: Send {
     }
 }
 
+/// Errors encountered while restoring the state of a virtio MMIO device.
+#[derive(Debug)]
+pub enum MmioStateError {
+    /// Activating the restored device failed.
+    Activate(ActivateError),
+    /// The device was already activated by the guest driver.
+    AlreadyActivated,
+    /// The saved state belongs to a different type of device.
+    DeviceTypeMismatch,
+    /// Cannot notify the device about pending queue events.
+    EventFd(io::Error),
+    /// The saved queues are not usable with this device and guest memory.
+    InvalidQueues,
+    /// The saved state does not hold one entry per device queue.
+    QueueCountMismatch,
+}
+
+/// The state of a virtio MMIO device, as saved in a microVM snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MmioDeviceState {
+    /// The virtio device type.
+    pub device_type: u32,
+    /// Whether the guest driver activated the device.
+    pub device_activated: bool,
+    /// Value of the `DeviceFeaturesSel` register.
+    pub features_select: u32,
+    /// Value of the `DriverFeaturesSel` register.
+    pub acked_features_select: u32,
+    /// Value of the `QueueSel` register.
+    pub queue_select: u32,
+    /// The feature bits acknowledged by the guest driver.
+    pub acked_features: u64,
+    /// Value of the `InterruptStatus` register.
+    pub interrupt_status: usize,
+    /// Value of the `Status` register.
+    pub driver_status: u32,
+    /// Value of the `ConfigGeneration` register.
+    pub config_generation: u32,
+    /// The guest-programmed parameters of each queue.
+    pub queues: Vec<QueueState>,
+}
+
 /// Implements the
 /// [MMIO](http://docs.oasis-open.org/virtio/virtio/v1.0/cs04/virtio-v1.0-cs04.html#x1-1090002)
 /// transport for virtio devices.
@@ -91,6 +134,8 @@ pub struct MmioDevice {
     features_select: u32,
     acked_features_select: u32,
     queue_select: u32,
+    // The feature bits written by the guest driver, kept for saving the device state.
+    acked_features: u64,
     interrupt_status: Arc<AtomicUsize>,
     interrupt_evt: Option<EventFd>,
     driver_status: u32,
@@ -118,6 +163,7 @@ impl MmioDevice {
             features_select: 0,
             acked_features_select: 0,
             queue_select: 0,
+            acked_features: 0,
             interrupt_status: Arc::new(AtomicUsize::new(0)),
             interrupt_evt: Some(EventFd::new()?),
             driver_status: DEVICE_INIT,
@@ -145,6 +191,87 @@ impl MmioDevice {
         self.interrupt_evt.as_ref()
     }
 
+    /// Whether a guest driver drives this device: it has set `DRIVER_OK`, and has neither reset
+    /// the device nor set `FAILED` since. The devices which cannot be reset get `FAILED` when
+    /// the guest driver releases them.
+    pub fn is_driver_active(&self) -> bool {
+        self.check_driver_status(DEVICE_DRIVER_OK, DEVICE_FAILED)
+    }
+
+    /// Returns the transport state of this device, as programmed by the guest driver.
+    pub fn save_state(&self) -> MmioDeviceState {
+        MmioDeviceState {
+            device_type: self.device.device_type(),
+            device_activated: self.device_activated,
+            features_select: self.features_select,
+            acked_features_select: self.acked_features_select,
+            queue_select: self.queue_select,
+            acked_features: self.acked_features,
+            interrupt_status: self.interrupt_status.load(Ordering::SeqCst),
+            driver_status: self.driver_status,
+            config_generation: self.config_generation,
+            queues: self.queues.iter().map(Queue::state).collect(),
+        }
+    }
+
+    /// Brings a freshly created device to the state saved by `save_state()`.
+    ///
+    /// The guest memory must already hold the restored contents, since the queue positions are
+    /// recovered from it. If the device was active, it is activated again and notified about
+    /// all its queues, so that requests the guest made right before the snapshot get handled.
+    pub fn restore_state(
+        &mut self,
+        state: &MmioDeviceState,
+    ) -> std::result::Result<(), MmioStateError> {
+        if state.device_type != self.device.device_type() {
+            return Err(MmioStateError::DeviceTypeMismatch);
+        }
+        if state.queues.len() != self.queues.len() {
+            return Err(MmioStateError::QueueCountMismatch);
+        }
+        let mem = self.mem.as_ref().ok_or(MmioStateError::AlreadyActivated)?;
+
+        for page in 0..2 {
+            let value = (state.acked_features >> (32 * page)) as u32;
+            if value != 0 {
+                self.device.ack_features(page, value);
+            }
+        }
+        for (queue, queue_state) in self.queues.iter_mut().zip(state.queues.iter()) {
+            queue.restore_state(queue_state, mem);
+        }
+        self.features_select = state.features_select;
+        self.acked_features_select = state.acked_features_select;
+        self.queue_select = state.queue_select;
+        self.acked_features = state.acked_features;
+        self.interrupt_status
+            .store(state.interrupt_status, Ordering::SeqCst);
+        self.driver_status = state.driver_status;
+        self.config_generation = state.config_generation;
+
+        if state.device_activated {
+            if !self.are_queues_valid() {
+                return Err(MmioStateError::InvalidQueues);
+            }
+            let queue_evts = self
+                .queue_evts
+                .iter()
+                .map(EventFd::try_clone)
+                .collect::<io::Result<Vec<EventFd>>>()
+                .map_err(MmioStateError::EventFd)?;
+            self.activate().map_err(MmioStateError::Activate)?;
+            for queue_evt in queue_evts.iter() {
+                queue_evt.write(1).map_err(MmioStateError::EventFd)?;
+            }
+            if state.interrupt_status != 0 {
+                if let Some(ref interrupt_evt) = self.interrupt_evt {
+                    interrupt_evt.write(1).map_err(MmioStateError::EventFd)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn check_driver_status(&self, set: u32, clr: u32) -> bool {
         self.driver_status & (set | clr) == set
     }
@@ -187,6 +314,23 @@ impl MmioDevice {
         }
     }
 
+    // Moves the guest memory, queues and queue events into the device.
+    fn activate(&mut self) -> ActivateResult {
+        if let Some(ref interrupt_evt) = self.interrupt_evt {
+            if let Some(mem) = self.mem.take() {
+                self.device.activate(
+                    mem,
+                    interrupt_evt.try_clone().expect("Failed to clone eventfd"),
+                    self.interrupt_status.clone(),
+                    self.queues.clone(),
+                    self.queue_evts.split_off(0),
+                )?;
+                self.device_activated = true;
+            }
+        }
+        Ok(())
+    }
+
     fn reset(&mut self) {
         if self.device_activated {
             warn!("reset device while it's still in active state");
@@ -233,20 +377,7 @@ impl MmioDevice {
                 // If the driver incorrectly sets up the queues, the following
                 // check will fail and take the device into an unusable state.
                 if !self.device_activated && self.are_queues_valid() {
-                    if let Some(ref interrupt_evt) = self.interrupt_evt {
-                        if let Some(mem) = self.mem.take() {
-                            self.device
-                                .activate(
-                                    mem,
-                                    interrupt_evt.try_clone().expect("Failed to clone eventfd"),
-                                    self.interrupt_status.clone(),
-                                    self.queues.clone(),
-                                    self.queue_evts.split_off(0),
-                                )
-                                .expect("Failed to activate device");
-                            self.device_activated = true;
-                        }
-                    }
+                    self.activate().expect("Failed to activate device");
                 }
             }
             _ if (v & DEVICE_FAILED) != 0 => {
@@ -338,6 +469,10 @@ impl BusDevice for MmioDevice {
                             .check_driver_status(DEVICE_DRIVER, DEVICE_FEATURES_OK | DEVICE_FAILED)
                         {
                             self.device.ack_features(self.acked_features_select, v);
+                            if self.acked_features_select < 2 {
+                                self.acked_features |=
+                                    u64::from(v) << (32 * self.acked_features_select);
+                            }
                         } else {
                             warn!(
                                 "ack virtio features in invalid state 0x{:x}",
@@ -847,4 +982,74 @@ mod tests {
         assert_eq!(d.driver_status, 0x8f);
         assert!(d.device_activated);
     }
+
+    #[test]
+    fn test_save_restore_state() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut d = MmioDevice::new(m.clone(), Box::new(DummyDevice::new())).unwrap();
+        let mut buf = vec![0; 4];
+
+        // A device which was not touched by the driver can be restored as such.
+        let state = d.save_state();
+        assert_eq!(state.device_type, 123);
+        assert!(!state.device_activated);
+        assert_eq!(state.queues.len(), 2);
+        let mut restored = MmioDevice::new(m.clone(), Box::new(DummyDevice::new())).unwrap();
+        assert!(restored.restore_state(&state).is_ok());
+        assert_eq!(restored.save_state(), state);
+        assert!(!restored.device_activated);
+
+        // Acknowledge features on both pages, then bring the device up.
+        set_driver_status(&mut d, DEVICE_ACKNOWLEDGE);
+        set_driver_status(&mut d, DEVICE_ACKNOWLEDGE | DEVICE_DRIVER);
+        LittleEndian::write_u32(&mut buf[..], 0x2);
+        d.write(0x20, &buf[..]);
+        LittleEndian::write_u32(&mut buf[..], 1);
+        d.write(0x24, &buf[..]);
+        d.write(0x20, &buf[..]);
+        activate_device(&mut d);
+        d.interrupt_status.store(0x1, Ordering::SeqCst);
+
+        let state = d.save_state();
+        assert!(state.device_activated);
+        assert_eq!(state.acked_features, 0x1_0000_0002);
+        assert_eq!(state.interrupt_status, 0x1);
+        assert!(state.queues.iter().all(|q| q.ready && q.size == 16));
+
+        let mut restored = MmioDevice::new(m.clone(), Box::new(DummyDevice::new())).unwrap();
+        assert!(restored.restore_state(&state).is_ok());
+        assert!(restored.device_activated);
+        assert!(restored.mem.is_none());
+        assert!(restored.queue_evts.is_empty());
+        assert_eq!(restored.save_state(), state);
+        // The interrupt is raised again, in case the guest did not get to handle it.
+        assert_eq!(restored.interrupt_evt().unwrap().read().unwrap(), 1);
+
+        // An active device cannot be restored again.
+        match restored.restore_state(&state) {
+            Err(MmioStateError::AlreadyActivated) => (),
+            _ => panic!("Expected an AlreadyActivated error."),
+        }
+
+        // The state must match the device.
+        let mut restored = MmioDevice::new(m.clone(), Box::new(DummyDevice::new())).unwrap();
+        let mut bad_state = state.clone();
+        bad_state.device_type = 1;
+        match restored.restore_state(&bad_state) {
+            Err(MmioStateError::DeviceTypeMismatch) => (),
+            _ => panic!("Expected a DeviceTypeMismatch error."),
+        }
+        let mut bad_state = state.clone();
+        bad_state.queues.pop();
+        match restored.restore_state(&bad_state) {
+            Err(MmioStateError::QueueCountMismatch) => (),
+            _ => panic!("Expected a QueueCountMismatch error."),
+        }
+        let mut bad_state = state.clone();
+        bad_state.queues[0].size = 0;
+        match restored.restore_state(&bad_state) {
+            Err(MmioStateError::InvalidQueues) => (),
+            _ => panic!("Expected an InvalidQueues error."),
+        }
+    }
 }
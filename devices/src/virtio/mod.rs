@@ -12,16 +12,24 @@ use std::io::Error as IOError;
 use std::os::unix::io::RawFd;
 use std::sync::mpsc;
 
+mod anti_spoofing;
+pub mod balloon;
 pub mod block;
 mod mmio;
 pub mod net;
+mod packet_filter;
+mod pcap;
 mod queue;
 #[cfg(feature = "vsock")]
 pub mod vhost;
 
+pub use self::anti_spoofing::*;
+pub use self::balloon::*;
 pub use self::block::*;
 pub use self::mmio::*;
 pub use self::net::*;
+pub use self::packet_filter::*;
+pub use self::pcap::*;
 pub use self::queue::*;
 #[cfg(feature = "vsock")]
 pub use self::vhost::vsock::*;
@@ -39,6 +47,7 @@ const DEVICE_FAILED: u32 = 0x80;
 /// Type 0 is not used by virtio. Use it as wildcard for non-virtio devices
 pub const TYPE_NET: u32 = 1;
 pub const TYPE_BLOCK: u32 = 2;
+pub const TYPE_BALLOON: u32 = 5;
 
 /// Interrupt flags (re: interrupt status & acknowledge registers).
 /// See linux/virtio_mmio.h.
@@ -21,9 +21,13 @@ use std::sync::Arc;
 use std::vec::Vec;
 
 use super::super::Error as DeviceError;
-use super::{ActivateError, ActivateResult, Queue, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_VRING};
+use super::{
+    ActivateError, ActivateResult, AntiSpoofing, DescriptorChain, FilterDirection, PacketFilter,
+    PcapWriter, Queue, SpoofedFrame, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_VRING,
+};
 use dumbo::{ns::MmdsNetworkStack, pdu::ethernet::EthernetFrame};
-use logger::{Metric, METRICS};
+use logger::metrics::NetDeviceMetrics;
+use logger::{DeviceMetrics, Metric, METRICS};
 use memory_model::{GuestAddress, GuestMemory};
 use net_gen;
 use net_util::{MacAddr, Tap, TapError, MAC_ADDR_LEN};
@@ -38,21 +42,50 @@ use {DeviceEventT, EpollHandler};
 /// http://docs.oasis-open.org/virtio/virtio/v1.0/virtio-v1.0.html#x1-1740003
 const MAX_BUFFER_SIZE: usize = 65562;
 const QUEUE_SIZE: u16 = 256;
-const NUM_QUEUES: usize = 2;
-const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
+// Size of the config space with multiple queue pairs, up to the maximum number of queue pairs.
+const MQ_CONFIG_SPACE_SIZE: usize = 10;
+// Offset of the maximum number of queue pairs in the config space.
+const MQ_CONFIG_MAX_PAIRS_OFFSET: usize = 8;
+// Size of the config space with an MTU, up to the MTU.
+const MTU_CONFIG_SPACE_SIZE: usize = 12;
+// Offset of the MTU in the config space.
+const MTU_CONFIG_OFFSET: usize = 10;
+// Offset of the segmentation offload type in the VNET header.
+const VNET_HDR_GSO_TYPE_OFFSET: usize = 1;
+// The largest Ethernet header, with an 802.1Q tag, which comes on top of the MTU.
+const MAX_ETH_HEADER_LEN: usize = 18;
+// Size of the header and of the data of the command which sets the number of queue pairs.
+const CTRL_MQ_REQUEST_SIZE: usize = 4;
+/// The maximum number of queue pairs of a network device.
+pub const NET_MAX_QUEUE_PAIRS: u16 = 16;
+/// The minimum MTU of a network device, which IPv4 requires.
+pub const NET_MIN_MTU: u16 = 68;
+/// The maximum MTU of a network device. Frames of this size fit in the frame buffers, along with
+/// their VNET header and Ethernet header.
+pub const NET_MAX_MTU: u16 = 65532;
 
-// A frame is available for reading from the tap device to receive in the guest.
-const RX_TAP_EVENT: DeviceEventT = 0;
-// The guest has made a buffer available to receive a frame into.
-const RX_QUEUE_EVENT: DeviceEventT = 1;
-// The transmit queue has a frame that is ready to send from the guest.
-const TX_QUEUE_EVENT: DeviceEventT = 2;
 // rx rate limiter budget is now available.
-const RX_RATE_LIMITER_EVENT: DeviceEventT = 3;
+const RX_RATE_LIMITER_EVENT: DeviceEventT = 0;
 // tx rate limiter budget is now available.
-const TX_RATE_LIMITER_EVENT: DeviceEventT = 4;
-// Number of DeviceEventT events supported by this implementation.
-pub const NET_EVENTS_COUNT: usize = 5;
+const TX_RATE_LIMITER_EVENT: DeviceEventT = 1;
+// The driver has sent a command on the control queue.
+const CTRL_QUEUE_EVENT: DeviceEventT = 2;
+// A frame is available for reading from the tap device to receive in the guest, on the first
+// queue pair. The events of each queue pair follow each other, in the order below, and the events
+// of the other queue pairs follow.
+const RX_TAP_EVENT: DeviceEventT = 3;
+// The guest has made a buffer available to receive a frame into.
+const RX_QUEUE_EVENT: DeviceEventT = 4;
+// The transmit queue has a frame that is ready to send from the guest.
+const TX_QUEUE_EVENT: DeviceEventT = 5;
+// Number of DeviceEventT events of each queue pair.
+const QUEUE_PAIR_EVENTS_COUNT: DeviceEventT = 3;
+
+/// Number of DeviceEventT events supported by a network device with `num_queue_pairs` queue
+/// pairs.
+pub fn net_events_count(num_queue_pairs: u16) -> usize {
+    RX_TAP_EVENT as usize + QUEUE_PAIR_EVENTS_COUNT as usize * num_queue_pairs as usize
+}
 
 #[derive(Debug)]
 pub enum Error {
@@ -64,28 +97,32 @@ pub enum Error {
     TapSetNetmask(TapError),
     /// Setting tap interface offload flags failed.
     TapSetOffload(TapError),
+    /// Getting or setting the MTU of the tap interface failed.
+    TapSetMtu(TapError),
     /// Setting vnet header size failed.
     TapSetVnetHdrSize(TapError),
     /// Enabling tap interface failed.
     TapEnable(TapError),
+    /// The number of tap queues is zero or above `NET_MAX_QUEUE_PAIRS`.
+    InvalidQueuePairs,
+    /// The MTU is not between `NET_MIN_MTU` and `NET_MAX_MTU`.
+    InvalidMtu,
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 struct TxVirtio {
     queue_evt: EventFd,
-    rate_limiter: RateLimiter,
     queue: Queue,
     iovec: Vec<(GuestAddress, usize)>,
     frame_buf: [u8; MAX_BUFFER_SIZE],
 }
 
 impl TxVirtio {
-    fn new(queue: Queue, queue_evt: EventFd, rate_limiter: RateLimiter) -> Self {
+    fn new(queue: Queue, queue_evt: EventFd) -> Self {
         let tx_queue_max_size = queue.get_max_size() as usize;
         TxVirtio {
             queue_evt,
-            rate_limiter,
             queue,
             iovec: Vec::with_capacity(tx_queue_max_size),
             frame_buf: [0u8; MAX_BUFFER_SIZE],
@@ -95,7 +132,6 @@ impl TxVirtio {
 
 struct RxVirtio {
     queue_evt: EventFd,
-    rate_limiter: RateLimiter,
     deferred_frame: bool,
     deferred_irqs: bool,
     queue: Queue,
@@ -104,10 +140,9 @@ struct RxVirtio {
 }
 
 impl RxVirtio {
-    fn new(queue: Queue, queue_evt: EventFd, rate_limiter: RateLimiter) -> Self {
+    fn new(queue: Queue, queue_evt: EventFd) -> Self {
         RxVirtio {
             queue_evt,
-            rate_limiter,
             deferred_frame: false,
             deferred_irqs: false,
             queue,
@@ -117,6 +152,22 @@ impl RxVirtio {
     }
 }
 
+// The receive and transmit queues of a queue pair, and the tap queue they exchange frames with.
+struct QueuePair {
+    rx: RxVirtio,
+    tx: TxVirtio,
+    tap: Tap,
+    // Whether the host steers frames to the tap queue.
+    tap_attached: bool,
+    rx_tap_listening: bool,
+    rx_tap_epoll_token: u64,
+}
+
+struct CtrlVirtio {
+    queue_evt: EventFd,
+    queue: Queue,
+}
+
 fn vnet_hdr_len() -> usize {
     mem::size_of::<virtio_net_hdr_v1>()
 }
@@ -131,6 +182,81 @@ fn frame_bytes_from_buf_mut(buf: &mut [u8]) -> &mut [u8] {
     &mut buf[vnet_hdr_len()..]
 }
 
+// Mirrors the frame in `buf`, which starts with a VNET header, to the capture file, if any.
+fn capture_frame(
+    capture: Option<&mut PcapWriter>,
+    buf: &[u8],
+    metrics: &DeviceMetrics<NetDeviceMetrics>,
+) {
+    if let Some(capture) = capture {
+        if buf.len() < vnet_hdr_len() {
+            return;
+        }
+        if let Err(e) = capture.write_frame(frame_bytes_from_buf(buf)) {
+            error!("Failed to write the frame to the capture file: {:?}", e);
+            metrics.update(|m| m.capture_fails.inc());
+        }
+    }
+}
+
+// Returns whether the frame in `buf`, which starts with a VNET header, is larger than allowed by
+// `mtu`. The host splits segmentation offload frames into segments of the size held by their
+// header, so only the frames sent as they are get checked.
+fn frame_exceeds_mtu(buf: &[u8], mtu: Option<u16>) -> bool {
+    match mtu {
+        Some(mtu) if buf.len() > vnet_hdr_len() => {
+            let gso_type = u32::from(buf[VNET_HDR_GSO_TYPE_OFFSET]) & !VIRTIO_NET_HDR_GSO_ECN;
+            gso_type == VIRTIO_NET_HDR_GSO_NONE
+                && frame_bytes_from_buf(buf).len() > usize::from(mtu) + MAX_ETH_HEADER_LEN
+        }
+        _ => false,
+    }
+}
+
+// Returns whether the frame in `buf`, which starts with a VNET header, must be dropped because
+// its source addresses are not the ones of the guest.
+fn frame_is_spoofed(
+    anti_spoofing: Option<&AntiSpoofing>,
+    buf: &[u8],
+    metrics: &DeviceMetrics<NetDeviceMetrics>,
+) -> bool {
+    let anti_spoofing = match anti_spoofing {
+        Some(anti_spoofing) => anti_spoofing,
+        None => return false,
+    };
+    let result = if buf.len() < vnet_hdr_len() {
+        Err(SpoofedFrame::Malformed)
+    } else {
+        anti_spoofing.check(frame_bytes_from_buf(buf))
+    };
+    match result {
+        Ok(()) => false,
+        Err(spoofed) => {
+            metrics.update(|m| {
+                match spoofed {
+                    SpoofedFrame::Mac => m.tx_spoofed_mac_count.inc(),
+                    SpoofedFrame::Ip => m.tx_spoofed_ip_count.inc(),
+                    SpoofedFrame::Tagged | SpoofedFrame::Malformed => (),
+                }
+                m.tx_spoofed_drops.inc();
+            });
+            true
+        }
+    }
+}
+
+// Returns whether the frame in `buf`, which starts with a VNET header, goes through `filter`, if
+// any.
+fn filter_allows(filter: Option<&PacketFilter>, buf: &[u8], direction: FilterDirection) -> bool {
+    match filter {
+        Some(filter) if buf.len() >= vnet_hdr_len() => {
+            filter.allows(frame_bytes_from_buf(buf), direction)
+        }
+        Some(filter) => filter.allows(&[], direction),
+        None => true,
+    }
+}
+
 // This initializes to all 0 the VNET hdr part of a buf.
 fn init_vnet_hdr(buf: &mut [u8]) {
     // The buffer should be larger than vnet_hdr_len.
@@ -142,10 +268,16 @@ fn init_vnet_hdr(buf: &mut [u8]) {
 
 /// Handler that drives the execution of the Net devices
 pub struct NetEpollHandler {
-    rx: RxVirtio,
-    tap: Tap,
+    queue_pairs: Vec<QueuePair>,
+    // The number of queue pairs the driver uses, as set through the control queue.
+    active_queue_pairs: usize,
+    // The queue pairs processed first after the rate limiters get unblocked.
+    next_rx_queue_pair: usize,
+    next_tx_queue_pair: usize,
+    ctrl: Option<CtrlVirtio>,
+    rx_rate_limiter: RateLimiter,
+    tx_rate_limiter: RateLimiter,
     mem: GuestMemory,
-    tx: TxVirtio,
     interrupt_status: Arc<AtomicUsize>,
     interrupt_evt: EventFd,
     // TODO(smbarber): http://crbug.com/753630
@@ -154,9 +286,13 @@ pub struct NetEpollHandler {
     acked_features: u64,
     mmds_ns: Option<MmdsNetworkStack>,
     guest_mac: Option<MacAddr>,
+    mtu: Option<u16>,
+    capture: Option<PcapWriter>,
+    anti_spoofing: Option<AntiSpoofing>,
+    ingress_filter: Option<PacketFilter>,
+    egress_filter: Option<PacketFilter>,
     epoll_fd: RawFd,
-    rx_tap_listening: bool,
-    rx_tap_epoll_token: u64,
+    metrics: DeviceMetrics<NetDeviceMetrics>,
 
     #[cfg(test)]
     test_mutators: tests::TestMutators,
@@ -168,7 +304,7 @@ impl NetEpollHandler {
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
         self.interrupt_evt.write(1).map_err(|e| {
             error!("Failed to signal used queue: {:?}", e);
-            METRICS.net.event_fails.inc();
+            self.metrics.update(|m| m.event_fails.inc());
             DeviceError::FailedSignalingUsedQueue(e)
         })
     }
@@ -176,44 +312,41 @@ impl NetEpollHandler {
     // Attempts to copy a single frame into the guest if there is enough
     // rate limiting budget.
     // Returns true on successful frame delivery.
-    fn rate_limited_rx_single_frame(&mut self) -> bool {
+    fn rate_limited_rx_single_frame(&mut self, queue_index: usize) -> bool {
+        let bytes_read = self.queue_pairs[queue_index].rx.bytes_read as u64;
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
-        if !self.rx.rate_limiter.consume(1, TokenType::Ops) {
+        if !self.rx_rate_limiter.consume(1, TokenType::Ops) {
             return false;
         }
         // If limiter.consume() fails it means there is no more TokenType::Bytes
         // budget and rate limiting is in effect.
-        if !self
-            .rx
-            .rate_limiter
-            .consume(self.rx.bytes_read as u64, TokenType::Bytes)
-        {
+        if !self.rx_rate_limiter.consume(bytes_read, TokenType::Bytes) {
             // revert the OPS consume()
-            self.rx.rate_limiter.manual_replenish(1, TokenType::Ops);
+            self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
             return false;
         }
 
         // Attempt frame delivery.
-        let success = self.rx_single_frame();
+        let success = self.rx_single_frame(queue_index);
 
         // Undo the tokens consumption if guest delivery failed.
         if !success {
             // revert the OPS consume()
-            self.rx.rate_limiter.manual_replenish(1, TokenType::Ops);
+            self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
             // revert the BYTES consume()
-            self.rx
-                .rate_limiter
-                .manual_replenish(self.rx.bytes_read as u64, TokenType::Bytes);
+            self.rx_rate_limiter
+                .manual_replenish(bytes_read, TokenType::Bytes);
         }
         success
     }
 
-    // Copies a single frame from `self.rx.frame_buf` into the guest. Returns true
-    // if a buffer was used, and false if the frame must be deferred until a buffer
-    // is made available by the driver.
-    fn rx_single_frame(&mut self) -> bool {
-        let mut next_desc = self.rx.queue.pop(&self.mem);
+    // Copies a single frame from the `frame_buf` of the receive queue into the guest. Returns
+    // true if a buffer was used, and false if the frame must be deferred until a buffer is made
+    // available by the driver.
+    fn rx_single_frame(&mut self, queue_index: usize) -> bool {
+        let rx = &mut self.queue_pairs[queue_index].rx;
+        let mut next_desc = rx.queue.pop(&self.mem);
 
         if next_desc.is_none() {
             return false;
@@ -230,64 +363,71 @@ impl NetEpollHandler {
                     if !desc.is_write_only() {
                         break;
                     }
-                    let limit = cmp::min(write_count + desc.len as usize, self.rx.bytes_read);
-                    let source_slice = &self.rx.frame_buf[write_count..limit];
+                    let limit = cmp::min(write_count + desc.len as usize, rx.bytes_read);
+                    let source_slice = &rx.frame_buf[write_count..limit];
                     let write_result = self.mem.write_slice_at_addr(source_slice, desc.addr);
 
                     match write_result {
                         Ok(sz) => {
-                            METRICS.net.rx_count.inc();
+                            self.metrics.update(|m| m.rx_count.inc());
                             write_count += sz;
                         }
                         Err(e) => {
                             error!("Failed to write slice: {:?}", e);
-                            METRICS.net.rx_fails.inc();
+                            self.metrics.update(|m| m.rx_fails.inc());
                             break;
                         }
                     };
 
-                    if write_count >= self.rx.bytes_read {
+                    if write_count >= rx.bytes_read {
                         break;
                     }
                     next_desc = desc.next_descriptor();
                 }
                 None => {
                     warn!("Receiving buffer is too small to hold frame of current size");
-                    METRICS.net.rx_fails.inc();
+                    self.metrics.update(|m| m.rx_fails.inc());
                     break;
                 }
             }
         }
 
-        self.rx
-            .queue
-            .add_used(&self.mem, head_index, write_count as u32);
+        rx.queue.add_used(&self.mem, head_index, write_count as u32);
 
         // Mark that we have at least one pending packet and we need to interrupt the guest.
-        self.rx.deferred_irqs = true;
+        rx.deferred_irqs = true;
 
-        if write_count >= self.rx.bytes_read {
-            METRICS.net.rx_bytes_count.add(write_count);
-            METRICS.net.rx_packets_count.inc();
+        if write_count >= rx.bytes_read {
+            self.metrics.update(|m| {
+                m.rx_bytes_count.add(write_count);
+                m.rx_packets_count.inc();
+            });
             true
         } else {
             false
         }
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP,
+    // unless the egress filter drops it. Either way, the frame is captured if a capture is in
+    // progress and the frame is not dropped.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
     // Returns whether MMDS consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
         frame_buf: &[u8],
         tap: &mut Tap,
         guest_mac: Option<MacAddr>,
+        egress_filter: Option<&PacketFilter>,
+        capture: Option<&mut PcapWriter>,
+        metrics: &DeviceMetrics<NetDeviceMetrics>,
     ) -> bool {
         if let Some(ns) = mmds_ns {
             if ns.detour_frame(frame_bytes_from_buf(frame_buf)) {
+                capture_frame(capture, frame_buf, metrics);
                 METRICS.mmds.rx_accepted.inc();
 
                 // MMDS frames are not accounted by the rate limiter.
@@ -301,11 +441,17 @@ impl NetEpollHandler {
 
         // This frame goes to the TAP.
 
+        if !filter_allows(egress_filter, frame_buf, FilterDirection::Egress) {
+            metrics.update(|m| m.tx_filtered_drops.inc());
+            return false;
+        }
+        capture_frame(capture, frame_buf, metrics);
+
         // Check for guest MAC spoofing.
         if let Some(mac) = guest_mac {
             let _ = EthernetFrame::from_bytes(&frame_buf[vnet_hdr_len()..]).and_then(|eth_frame| {
                 if mac != eth_frame.src_mac() {
-                    METRICS.net.tx_spoofed_mac_count.inc();
+                    metrics.update(|m| m.tx_spoofed_mac_count.inc());
                 }
                 Ok(())
             });
@@ -314,42 +460,60 @@ impl NetEpollHandler {
         let write_result = tap.write(frame_buf);
         match write_result {
             Ok(_) => {
-                METRICS.net.tx_bytes_count.add(frame_buf.len());
-                METRICS.net.tx_packets_count.inc();
-                METRICS.net.tx_count.inc();
+                metrics.update(|m| {
+                    m.tx_bytes_count.add(frame_buf.len());
+                    m.tx_packets_count.inc();
+                    m.tx_count.inc();
+                });
             }
             Err(e) => {
                 error!("Failed to write to tap: {:?}", e);
-                METRICS.net.tx_fails.inc();
+                metrics.update(|m| m.tx_fails.inc());
             }
         };
         false
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
-    fn read_from_mmds_or_tap(&mut self) -> io::Result<usize> {
-        if let Some(ns) = self.mmds_ns.as_mut() {
-            if let Some(len) = ns.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx.frame_buf))
-            {
-                let len = len.get();
-                METRICS.mmds.tx_frames.inc();
-                METRICS.mmds.tx_bytes.add(len);
-                init_vnet_hdr(&mut self.rx.frame_buf);
-                return Ok(vnet_hdr_len() + len);
+    // We currently prioritize packets from the MMDS over regular network packets. The MMDS
+    // frames are received on the first queue pair.
+    fn read_from_mmds_or_tap(&mut self, queue_index: usize) -> io::Result<usize> {
+        if queue_index == 0 {
+            if let Some(ns) = self.mmds_ns.as_mut() {
+                let frame_buf = &mut self.queue_pairs[0].rx.frame_buf;
+                if let Some(len) = ns.write_next_frame(frame_bytes_from_buf_mut(frame_buf)) {
+                    let len = len.get();
+                    METRICS.mmds.tx_frames.inc();
+                    METRICS.mmds.tx_bytes.add(len);
+                    init_vnet_hdr(frame_buf);
+                    return Ok(vnet_hdr_len() + len);
+                }
             }
         }
-        self.read_tap()
+        // The frames dropped by the ingress filter are skipped.
+        loop {
+            let len = self.read_tap(queue_index)?;
+            if filter_allows(
+                self.ingress_filter.as_ref(),
+                &self.queue_pairs[queue_index].rx.frame_buf[..len],
+                FilterDirection::Ingress,
+            ) {
+                return Ok(len);
+            }
+            self.metrics.update(|m| m.rx_filtered_drops.inc());
+        }
     }
 
-    fn process_rx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_rx(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
         // Read as many frames as possible.
         loop {
-            match self.read_from_mmds_or_tap() {
+            match self.read_from_mmds_or_tap(queue_index) {
                 Ok(count) => {
-                    self.rx.bytes_read = count;
-                    METRICS.net.rx_count.inc();
-                    if !self.rate_limited_rx_single_frame() {
-                        self.rx.deferred_frame = true;
+                    let rx = &mut self.queue_pairs[queue_index].rx;
+                    rx.bytes_read = count;
+                    capture_frame(self.capture.as_mut(), &rx.frame_buf[..count], &self.metrics);
+                    self.metrics.update(|m| m.rx_count.inc());
+                    if !self.rate_limited_rx_single_frame(queue_index) {
+                        self.queue_pairs[queue_index].rx.deferred_frame = true;
                         break;
                     }
                 }
@@ -360,7 +524,7 @@ impl NetEpollHandler {
                         Some(err) if err == EAGAIN => (),
                         _ => {
                             error!("Failed to read tap: {:?}", e);
-                            METRICS.net.rx_fails.inc();
+                            self.metrics.update(|m| m.rx_fails.inc());
                             return Err(DeviceError::FailedReadTap);
                         }
                     };
@@ -368,46 +532,50 @@ impl NetEpollHandler {
                 }
             }
         }
-        if self.rx.deferred_irqs {
-            self.rx.deferred_irqs = false;
+        self.signal_deferred_irqs(queue_index)
+    }
+
+    // Interrupts the guest if frames were received on the queue since the last interrupt.
+    fn signal_deferred_irqs(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
+        let rx = &mut self.queue_pairs[queue_index].rx;
+        if rx.deferred_irqs {
+            rx.deferred_irqs = false;
             self.signal_used_queue()
         } else {
             Ok(())
         }
     }
 
-    fn resume_rx(&mut self) -> result::Result<(), DeviceError> {
-        if self.rx.deferred_frame {
-            if self.rate_limited_rx_single_frame() {
-                self.rx.deferred_frame = false;
+    fn resume_rx(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
+        if self.queue_pairs[queue_index].rx.deferred_frame {
+            if self.rate_limited_rx_single_frame(queue_index) {
+                self.queue_pairs[queue_index].rx.deferred_frame = false;
                 // process_rx() was interrupted possibly before consuming all
                 // packets in the tap; try continuing now.
-                self.process_rx()
-            } else if self.rx.deferred_irqs {
-                self.rx.deferred_irqs = false;
-                self.signal_used_queue()
+                self.process_rx(queue_index)
             } else {
-                Ok(())
+                self.signal_deferred_irqs(queue_index)
             }
         } else {
             Ok(())
         }
     }
 
-    fn process_tx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_tx(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
         // The MMDS network stack works like a state machine, based on synchronous calls, and
         // without being added to any event loop. If any frame is accepted by the MMDS, we also
         // trigger a process_rx() which checks if there are any new frames to be sent, starting
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
 
-        while let Some(head) = self.tx.queue.pop(&self.mem) {
+        while let Some(head) = self.queue_pairs[queue_index].tx.queue.pop(&self.mem) {
+            let pair = &mut self.queue_pairs[queue_index];
             // If limiter.consume() fails it means there is no more TokenType::Ops
             // budget and rate limiting is in effect.
-            if !self.tx.rate_limiter.consume(1, TokenType::Ops) {
+            if !self.tx_rate_limiter.consume(1, TokenType::Ops) {
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
-                self.tx.queue.undo_pop();
+                pair.tx.queue.undo_pop();
                 break;
             }
 
@@ -415,12 +583,12 @@ impl NetEpollHandler {
             let mut read_count = 0;
             let mut next_desc = Some(head);
 
-            self.tx.iovec.clear();
+            pair.tx.iovec.clear();
             while let Some(desc) = next_desc {
                 if desc.is_write_only() {
                     break;
                 }
-                self.tx.iovec.push((desc.addr, desc.len as usize));
+                pair.tx.iovec.push((desc.addr, desc.len as usize));
                 read_count += desc.len as usize;
                 next_desc = desc.next_descriptor();
             }
@@ -428,15 +596,14 @@ impl NetEpollHandler {
             // If limiter.consume() fails it means there is no more TokenType::Bytes
             // budget and rate limiting is in effect.
             if !self
-                .tx
-                .rate_limiter
+                .tx_rate_limiter
                 .consume(read_count as u64, TokenType::Bytes)
             {
                 // revert the OPS consume()
-                self.tx.rate_limiter.manual_replenish(1, TokenType::Ops);
+                self.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
-                self.tx.queue.undo_pop();
+                pair.tx.queue.undo_pop();
                 break;
             }
 
@@ -444,49 +611,215 @@ impl NetEpollHandler {
             // Copy buffer from across multiple descriptors.
             // TODO(performance - Issue #420): change this to use `writev()` instead of `write()`
             // and get rid of the intermediate buffer.
-            for (desc_addr, desc_len) in self.tx.iovec.drain(..) {
-                let limit = cmp::min((read_count + desc_len) as usize, self.tx.frame_buf.len());
+            for (desc_addr, desc_len) in pair.tx.iovec.drain(..) {
+                let limit = cmp::min((read_count + desc_len) as usize, pair.tx.frame_buf.len());
 
                 let read_result = self.mem.read_slice_at_addr(
-                    &mut self.tx.frame_buf[read_count..limit as usize],
+                    &mut pair.tx.frame_buf[read_count..limit as usize],
                     desc_addr,
                 );
                 match read_result {
                     Ok(sz) => {
                         read_count += sz;
-                        METRICS.net.tx_count.inc();
+                        self.metrics.update(|m| m.tx_count.inc());
                     }
                     Err(e) => {
                         error!("Failed to read slice: {:?}", e);
-                        METRICS.net.tx_fails.inc();
+                        self.metrics.update(|m| m.tx_fails.inc());
                         break;
                     }
                 }
             }
 
-            if Self::write_to_mmds_or_tap(
+            if frame_exceeds_mtu(&pair.tx.frame_buf[..read_count], self.mtu) {
+                self.metrics.update(|m| m.tx_mtu_exceeded_count.inc());
+            } else if frame_is_spoofed(
+                self.anti_spoofing.as_ref(),
+                &pair.tx.frame_buf[..read_count],
+                &self.metrics,
+            ) {
+                // The frame is dropped, as if it was sent.
+            } else if Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
-                &mut self.tx.rate_limiter,
-                &self.tx.frame_buf[..read_count],
-                &mut self.tap,
+                &mut self.tx_rate_limiter,
+                &pair.tx.frame_buf[..read_count],
+                &mut pair.tap,
                 self.guest_mac,
-            ) && !self.rx.deferred_frame
-            {
+                self.egress_filter.as_ref(),
+                self.capture.as_mut(),
+                &self.metrics,
+            ) {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
             }
 
-            self.tx.queue.add_used(&self.mem, head_index, 0);
+            pair.tx.queue.add_used(&self.mem, head_index, 0);
         }
 
-        // An incoming frame for the MMDS may trigger the transmission of a new message.
-        if process_rx_for_mmds {
-            self.process_rx()
+        // An incoming frame for the MMDS may trigger the transmission of a new message, which is
+        // received on the first queue pair.
+        if process_rx_for_mmds && !self.queue_pairs[0].rx.deferred_frame {
+            self.process_rx(0)
         } else {
             Ok(())
         }
     }
 
+    // Handles the commands sent by the driver on the control queue.
+    fn process_ctrl(&mut self) -> result::Result<(), DeviceError> {
+        let mut used_any = false;
+        loop {
+            let (head_index, request, ack_addr) = match self.ctrl.as_mut() {
+                Some(ctrl) => match ctrl.queue.pop(&self.mem) {
+                    Some(head) => {
+                        let head_index = head.index;
+                        let (request, ack_addr) = read_ctrl_request(&self.mem, head);
+                        (head_index, request, ack_addr)
+                    }
+                    None => break,
+                },
+                None => break,
+            };
+
+            let ack = if self.execute_ctrl_command(&request) {
+                VIRTIO_NET_OK
+            } else {
+                VIRTIO_NET_ERR
+            } as u8;
+            let len = match ack_addr {
+                Some(addr) => match self.mem.write_obj_at_addr(ack, addr) {
+                    Ok(()) => 1,
+                    Err(e) => {
+                        error!("Failed to write the control queue acknowledgement: {:?}", e);
+                        self.metrics.update(|m| m.ctrl_fails.inc());
+                        0
+                    }
+                },
+                None => {
+                    error!("The control queue command has no acknowledgement descriptor");
+                    self.metrics.update(|m| m.ctrl_fails.inc());
+                    0
+                }
+            };
+            // The control queue exists, since the command was popped from it.
+            if let Some(ctrl) = self.ctrl.as_mut() {
+                ctrl.queue.add_used(&self.mem, head_index, len);
+            }
+            used_any = true;
+        }
+
+        if used_any {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Executes a command sent on the control queue, and returns whether it succeeded. Only the
+    // command setting the number of queue pairs is supported, since the other features of the
+    // control queue are not offered.
+    fn execute_ctrl_command(&mut self, request: &[u8]) -> bool {
+        if request.len() < CTRL_MQ_REQUEST_SIZE
+            || u32::from(request[0]) != VIRTIO_NET_CTRL_MQ
+            || u32::from(request[1]) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET
+        {
+            warn!("Unsupported control queue command: {:?}", request);
+            self.metrics.update(|m| m.ctrl_fails.inc());
+            return false;
+        }
+        let count = usize::from(u16::from_le_bytes([request[2], request[3]]));
+        if count == 0 || count > self.queue_pairs.len() {
+            warn!("Invalid number of queue pairs: {}", count);
+            self.metrics.update(|m| m.ctrl_fails.inc());
+            return false;
+        }
+        self.set_active_queue_pairs(count)
+    }
+
+    // Makes the first `count` queue pairs the ones in use. The tap queues of the other pairs are
+    // detached, so that the host does not steer frames to receive queues the driver ignores.
+    fn set_active_queue_pairs(&mut self, count: usize) -> bool {
+        for queue_index in 0..self.queue_pairs.len() {
+            let active = queue_index < count;
+            let pair = &mut self.queue_pairs[queue_index];
+            if pair.tap_attached != active {
+                if let Err(e) = pair.tap.set_queue_attached(active) {
+                    error!("Failed to change the tap queue {}: {:?}", queue_index, e);
+                    self.metrics.update(|m| m.ctrl_fails.inc());
+                    return false;
+                }
+                pair.tap_attached = active;
+            }
+
+            let result = if active
+                && queue_index >= self.active_queue_pairs
+                && !self.queue_pairs[queue_index].rx_tap_listening
+            {
+                // The frames waiting in the tap queue get received as soon as the driver made
+                // buffers available.
+                self.register_tap_rx_listener(queue_index)
+            } else if !active && self.queue_pairs[queue_index].rx_tap_listening {
+                self.unregister_tap_rx_listener(queue_index)
+            } else {
+                Ok(())
+            };
+            if let Err(e) = result {
+                error!(
+                    "Failed to change the tap queue {} listener: {:?}",
+                    queue_index, e
+                );
+                self.metrics.update(|m| m.ctrl_fails.inc());
+                return false;
+            }
+        }
+        self.active_queue_pairs = count;
+        true
+    }
+
+    // Resumes the reception on the queue pairs in use, starting with a different one each time,
+    // so that a busy queue pair cannot starve the others.
+    fn resume_rx_queue_pairs(&mut self) -> result::Result<(), DeviceError> {
+        let count = self.active_queue_pairs;
+        let first = self.next_rx_queue_pair % count;
+        self.next_rx_queue_pair = (first + 1) % count;
+        for i in 0..count {
+            self.resume_rx((first + i) % count)?;
+        }
+        Ok(())
+    }
+
+    // Resumes the transmission on all the queue pairs, starting with a different one each time.
+    fn process_tx_queue_pairs(&mut self) -> result::Result<(), DeviceError> {
+        let count = self.queue_pairs.len();
+        let first = self.next_tx_queue_pair % count;
+        self.next_tx_queue_pair = (first + 1) % count;
+        for i in 0..count {
+            self.process_tx((first + i) % count)?;
+        }
+        Ok(())
+    }
+
+    /// Starts mirroring the frames exchanged with the TAP and with MMDS to `capture`, or stops
+    /// capturing if it is `None`.
+    pub fn set_capture(&mut self, capture: Option<PcapWriter>) {
+        self.capture = capture;
+    }
+
+    /// Replaces the filters of the frames received from and sent to the TAP, leaving each one
+    /// unchanged if it is `None`.
+    pub fn patch_packet_filters(
+        &mut self,
+        ingress_filter: Option<PacketFilter>,
+        egress_filter: Option<PacketFilter>,
+    ) {
+        if ingress_filter.is_some() {
+            self.ingress_filter = ingress_filter;
+        }
+        if egress_filter.is_some() {
+            self.egress_filter = egress_filter;
+        }
+    }
+
     /// Updates the parameters for the rate limiters
     pub fn patch_rate_limiters(
         &mut self,
@@ -495,36 +828,138 @@ impl NetEpollHandler {
         tx_bytes: Option<TokenBucket>,
         tx_ops: Option<TokenBucket>,
     ) {
-        self.rx.rate_limiter.update_buckets(rx_bytes, rx_ops);
-        self.tx.rate_limiter.update_buckets(tx_bytes, tx_ops);
+        self.rx_rate_limiter.update_buckets(rx_bytes, rx_ops);
+        self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
     }
 
     #[cfg(not(test))]
-    fn read_tap(&mut self) -> io::Result<usize> {
-        self.tap.read(&mut self.rx.frame_buf)
+    fn read_tap(&mut self, queue_index: usize) -> io::Result<usize> {
+        let pair = &mut self.queue_pairs[queue_index];
+        pair.tap.read(&mut pair.rx.frame_buf)
     }
 
-    fn register_tap_rx_listener(&mut self) -> std::result::Result<(), std::io::Error> {
+    fn register_tap_rx_listener(
+        &mut self,
+        queue_index: usize,
+    ) -> std::result::Result<(), std::io::Error> {
+        let pair = &mut self.queue_pairs[queue_index];
         epoll::ctl(
             self.epoll_fd,
             epoll::ControlOptions::EPOLL_CTL_ADD,
-            self.tap.as_raw_fd(),
-            epoll::Event::new(epoll::Events::EPOLLIN, self.rx_tap_epoll_token),
+            pair.tap.as_raw_fd(),
+            epoll::Event::new(epoll::Events::EPOLLIN, pair.rx_tap_epoll_token),
         )?;
-        self.rx_tap_listening = true;
+        pair.rx_tap_listening = true;
         Ok(())
     }
 
-    fn unregister_tap_rx_listener(&mut self) -> std::result::Result<(), std::io::Error> {
+    fn unregister_tap_rx_listener(
+        &mut self,
+        queue_index: usize,
+    ) -> std::result::Result<(), std::io::Error> {
+        let pair = &mut self.queue_pairs[queue_index];
         epoll::ctl(
             self.epoll_fd,
             epoll::ControlOptions::EPOLL_CTL_DEL,
-            self.tap.as_raw_fd(),
-            epoll::Event::new(epoll::Events::EPOLLIN, self.rx_tap_epoll_token),
+            pair.tap.as_raw_fd(),
+            epoll::Event::new(epoll::Events::EPOLLIN, pair.rx_tap_epoll_token),
         )?;
-        self.rx_tap_listening = false;
+        pair.rx_tap_listening = false;
         Ok(())
     }
+
+    fn handle_rx_queue_event(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.metrics.update(|m| m.rx_queue_event_count.inc());
+        if let Err(e) = self.queue_pairs[queue_index].rx.queue_evt.read() {
+            error!("Failed to get rx queue event: {:?}", e);
+            self.metrics.update(|m| m.event_fails.inc());
+            Err(DeviceError::FailedReadingQueue {
+                event_type: "rx queue event",
+                underlying: e,
+            })
+        } else if queue_index >= self.active_queue_pairs {
+            // The tap queue of this pair is detached.
+            Ok(())
+        } else {
+            if !self.queue_pairs[queue_index].rx_tap_listening {
+                self.register_tap_rx_listener(queue_index)
+                    .map_err(DeviceError::IoError)?;
+            }
+            // If the limiter is not blocked, resume the receiving of bytes.
+            if !self.rx_rate_limiter.is_blocked() {
+                // There should be a buffer available now to receive the frame into.
+                self.resume_rx(queue_index)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_rx_tap_event(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.metrics.update(|m| m.rx_tap_event_count.inc());
+
+        if self.queue_pairs[queue_index].rx.queue.is_empty(&self.mem) {
+            self.unregister_tap_rx_listener(queue_index)
+                .map_err(DeviceError::IoError)?;
+            return Err(DeviceError::NoAvailBuffers);
+        }
+
+        // While limiter is blocked, don't process any more incoming.
+        if self.rx_rate_limiter.is_blocked() {
+            Ok(())
+        } else if self.queue_pairs[queue_index].rx.deferred_frame
+        // Process a deferred frame first if available. Don't read from tap again
+        // until we manage to receive this deferred frame.
+        {
+            if self.rate_limited_rx_single_frame(queue_index) {
+                self.queue_pairs[queue_index].rx.deferred_frame = false;
+                self.process_rx(queue_index)
+            } else {
+                self.signal_deferred_irqs(queue_index)
+            }
+        } else {
+            self.process_rx(queue_index)
+        }
+    }
+
+    fn handle_tx_queue_event(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.metrics.update(|m| m.tx_queue_event_count.inc());
+        if let Err(e) = self.queue_pairs[queue_index].tx.queue_evt.read() {
+            error!("Failed to get tx queue event: {:?}", e);
+            self.metrics.update(|m| m.event_fails.inc());
+            Err(DeviceError::FailedReadingQueue {
+                event_type: "tx queue event",
+                underlying: e,
+            })
+        } else if !self.tx_rate_limiter.is_blocked()
+        // If the limiter is not blocked, continue transmitting bytes.
+        {
+            self.process_tx(queue_index)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Reads the request of a command sent on the control queue, from the read only descriptors of the
+// chain. Returns the first bytes of the request, which are all the supported command needs, and
+// the address of the acknowledgement, held by the first write only descriptor.
+fn read_ctrl_request(mem: &GuestMemory, head: DescriptorChain) -> (Vec<u8>, Option<GuestAddress>) {
+    let mut request = Vec::with_capacity(CTRL_MQ_REQUEST_SIZE);
+    let mut next_desc = Some(head);
+    while let Some(desc) = next_desc {
+        if desc.is_write_only() {
+            return (request, Some(desc.addr));
+        }
+        let mut buf = vec![0u8; cmp::min(desc.len as usize, CTRL_MQ_REQUEST_SIZE - request.len())];
+        if let Err(e) = mem.read_slice_at_addr(&mut buf, desc.addr) {
+            error!("Failed to read the control queue command: {:?}", e);
+            break;
+        }
+        request.extend_from_slice(&buf);
+        next_desc = desc.next_descriptor();
+    }
+    (request, None)
 }
 
 impl EpollHandler for NetEpollHandler {
@@ -534,107 +969,68 @@ impl EpollHandler for NetEpollHandler {
         _evset: epoll::Events,
     ) -> result::Result<(), DeviceError> {
         match device_event {
-            RX_QUEUE_EVENT => {
-                METRICS.net.rx_queue_event_count.inc();
-                if let Err(e) = self.rx.queue_evt.read() {
-                    error!("Failed to get rx queue event: {:?}", e);
-                    METRICS.net.event_fails.inc();
-                    Err(DeviceError::FailedReadingQueue {
-                        event_type: "rx queue event",
-                        underlying: e,
-                    })
-                } else {
-                    if !self.rx_tap_listening {
-                        self.register_tap_rx_listener()
-                            .map_err(DeviceError::IoError)?;
-                    }
-                    // If the limiter is not blocked, resume the receiving of bytes.
-                    if !self.rx.rate_limiter.is_blocked() {
-                        // There should be a buffer available now to receive the frame into.
-                        self.resume_rx()
-                    } else {
-                        Ok(())
-                    }
-                }
-            }
-            RX_TAP_EVENT => {
-                METRICS.net.rx_tap_event_count.inc();
-
-                if self.rx.queue.is_empty(&self.mem) {
-                    self.unregister_tap_rx_listener()
-                        .map_err(DeviceError::IoError)?;
-                    return Err(DeviceError::NoAvailBuffers);
-                }
-
-                // While limiter is blocked, don't process any more incoming.
-                if self.rx.rate_limiter.is_blocked() {
-                    Ok(())
-                } else if self.rx.deferred_frame
-                // Process a deferred frame first if available. Don't read from tap again
-                // until we manage to receive this deferred frame.
-                {
-                    if self.rate_limited_rx_single_frame() {
-                        self.rx.deferred_frame = false;
-                        self.process_rx()
-                    } else if self.rx.deferred_irqs {
-                        self.rx.deferred_irqs = false;
-                        self.signal_used_queue()
-                    } else {
-                        Ok(())
-                    }
-                } else {
-                    self.process_rx()
-                }
-            }
-            TX_QUEUE_EVENT => {
-                METRICS.net.tx_queue_event_count.inc();
-                if let Err(e) = self.tx.queue_evt.read() {
-                    error!("Failed to get tx queue event: {:?}", e);
-                    METRICS.net.event_fails.inc();
-                    Err(DeviceError::FailedReadingQueue {
-                        event_type: "tx queue event",
-                        underlying: e,
-                    })
-                } else if !self.tx.rate_limiter.is_blocked()
-                // If the limiter is not blocked, continue transmitting bytes.
-                {
-                    self.process_tx()
-                } else {
-                    Ok(())
-                }
-            }
             RX_RATE_LIMITER_EVENT => {
-                METRICS.net.rx_event_rate_limiter_count.inc();
+                self.metrics.update(|m| m.rx_event_rate_limiter_count.inc());
                 // Upon rate limiter event, call the rate limiter handler
-                // and restart processing the queue.
-                match self.rx.rate_limiter.event_handler() {
-                    Ok(_) => {
-                        // There might be enough budget now to receive the frame.
-                        self.resume_rx()
+                // and restart processing the queues.
+                match self.rx_rate_limiter.event_handler() {
+                    Ok(throttled) => {
+                        self.metrics
+                            .update(|m| m.rx_rate_limiter_throttled_us.record(throttled));
+                        // There might be enough budget now to receive the frames.
+                        self.resume_rx_queue_pairs()
                     }
                     Err(e) => {
-                        METRICS.net.event_fails.inc();
+                        self.metrics.update(|m| m.event_fails.inc());
                         error!("Failed to get rx rate-limiter event: {:?}", e);
                         Err(DeviceError::RateLimited(e))
                     }
                 }
             }
             TX_RATE_LIMITER_EVENT => {
-                METRICS.net.tx_rate_limiter_event_count.inc();
+                self.metrics.update(|m| m.tx_rate_limiter_event_count.inc());
                 // Upon rate limiter event, call the rate limiter handler
-                // and restart processing the queue.
-                match self.tx.rate_limiter.event_handler() {
-                    Ok(_) => {
-                        // There might be enough budget now to send the frame.
-                        self.process_tx()
+                // and restart processing the queues.
+                match self.tx_rate_limiter.event_handler() {
+                    Ok(throttled) => {
+                        self.metrics
+                            .update(|m| m.tx_rate_limiter_throttled_us.record(throttled));
+                        // There might be enough budget now to send the frames.
+                        self.process_tx_queue_pairs()
                     }
                     Err(e) => {
-                        METRICS.net.event_fails.inc();
+                        self.metrics.update(|m| m.event_fails.inc());
                         error!("Failed to get tx rate-limiter event: {:?}", e);
                         Err(DeviceError::RateLimited(e))
                     }
                 }
             }
+            CTRL_QUEUE_EVENT if self.ctrl.is_some() => {
+                self.metrics.update(|m| m.ctrl_queue_event_count.inc());
+                // The control queue exists, as checked above.
+                if let Err(e) = self.ctrl.as_ref().unwrap().queue_evt.read() {
+                    error!("Failed to get ctrl queue event: {:?}", e);
+                    self.metrics.update(|m| m.event_fails.inc());
+                    Err(DeviceError::FailedReadingQueue {
+                        event_type: "ctrl queue event",
+                        underlying: e,
+                    })
+                } else {
+                    self.process_ctrl()
+                }
+            }
+            other
+                if other >= RX_TAP_EVENT
+                    && usize::from((other - RX_TAP_EVENT) / QUEUE_PAIR_EVENTS_COUNT)
+                        < self.queue_pairs.len() =>
+            {
+                let queue_index = usize::from((other - RX_TAP_EVENT) / QUEUE_PAIR_EVENTS_COUNT);
+                match RX_TAP_EVENT + (other - RX_TAP_EVENT) % QUEUE_PAIR_EVENTS_COUNT {
+                    RX_TAP_EVENT => self.handle_rx_tap_event(queue_index),
+                    RX_QUEUE_EVENT => self.handle_rx_queue_event(queue_index),
+                    _ => self.handle_tx_queue_event(queue_index),
+                }
+            }
             other => Err(DeviceError::UnknownEvent {
                 device: "net",
                 event: other,
@@ -644,23 +1040,32 @@ impl EpollHandler for NetEpollHandler {
 }
 
 pub struct EpollConfig {
-    rx_tap_token: u64,
-    rx_queue_token: u64,
-    tx_queue_token: u64,
     rx_rate_limiter_token: u64,
     tx_rate_limiter_token: u64,
+    ctrl_queue_token: u64,
+    // The token of the first event of the first queue pair. The tokens of the other events
+    // follow.
+    queue_pair_token: u64,
     epoll_raw_fd: RawFd,
     sender: mpsc::Sender<Box<EpollHandler>>,
 }
 
+impl EpollConfig {
+    // Returns the token of the `event` of the queue pair `queue_index`.
+    fn queue_pair_event_token(&self, queue_index: usize, event: DeviceEventT) -> u64 {
+        self.queue_pair_token
+            + (queue_index * QUEUE_PAIR_EVENTS_COUNT as usize) as u64
+            + u64::from(event - RX_TAP_EVENT)
+    }
+}
+
 impl EpollConfigConstructor for EpollConfig {
     fn new(first_token: u64, epoll_raw_fd: RawFd, sender: mpsc::Sender<Box<EpollHandler>>) -> Self {
         EpollConfig {
-            rx_tap_token: first_token + u64::from(RX_TAP_EVENT),
-            rx_queue_token: first_token + u64::from(RX_QUEUE_EVENT),
-            tx_queue_token: first_token + u64::from(TX_QUEUE_EVENT),
             rx_rate_limiter_token: first_token + u64::from(RX_RATE_LIMITER_EVENT),
             tx_rate_limiter_token: first_token + u64::from(TX_RATE_LIMITER_EVENT),
+            ctrl_queue_token: first_token + u64::from(CTRL_QUEUE_EVENT),
+            queue_pair_token: first_token + u64::from(RX_TAP_EVENT),
             epoll_raw_fd,
             sender,
         }
@@ -668,37 +1073,70 @@ impl EpollConfigConstructor for EpollConfig {
 }
 
 pub struct Net {
-    tap: Option<Tap>,
+    // The tap queues, one for each queue pair.
+    taps: Vec<Tap>,
+    queue_sizes: Vec<u16>,
     avail_features: u64,
     acked_features: u64,
     // The config space will only consist of the MAC address specified by the user,
-    // or nothing, if no such address if provided.
+    // or nothing, if no such address if provided. With several queue pairs, it is followed by
+    // the status and by the maximum number of queue pairs.
     config_space: Vec<u8>,
     epoll_config: EpollConfig,
     rx_rate_limiter: Option<RateLimiter>,
     tx_rate_limiter: Option<RateLimiter>,
     allow_mmds_requests: bool,
+    mtu: Option<u16>,
+    capture: Option<PcapWriter>,
+    anti_spoofing: Option<AntiSpoofing>,
+    ingress_filter: Option<PacketFilter>,
+    egress_filter: Option<PacketFilter>,
+    metrics: DeviceMetrics<NetDeviceMetrics>,
 }
 
 impl Net {
-    /// Create a new virtio network device with the given TAP interface.
-    pub fn new_with_tap(
-        tap: Tap,
+    /// Create a new virtio network device with the given queues of a TAP interface, one for each
+    /// queue pair. The device updates `metrics`, which also count towards the totals of the
+    /// network devices. The `mtu`, if any, is set on the TAP interface and advertised to the guest.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_taps(
+        taps: Vec<Tap>,
         guest_mac: Option<&MacAddr>,
+        mtu: Option<u16>,
         epoll_config: EpollConfig,
         rx_rate_limiter: Option<RateLimiter>,
         tx_rate_limiter: Option<RateLimiter>,
         allow_mmds_requests: bool,
+        metrics: DeviceMetrics<NetDeviceMetrics>,
     ) -> Result<Self> {
-        // Set offload flags to match the virtio features below.
-        tap.set_offload(
-            net_gen::TUN_F_CSUM | net_gen::TUN_F_UFO | net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6,
-        )
-        .map_err(Error::TapSetOffload)?;
+        if taps.is_empty() || taps.len() > NET_MAX_QUEUE_PAIRS as usize {
+            return Err(Error::InvalidQueuePairs);
+        }
+        if let Some(mtu) = mtu {
+            if mtu < NET_MIN_MTU || mtu > NET_MAX_MTU {
+                return Err(Error::InvalidMtu);
+            }
+            // The MTU belongs to the interface, which all the tap queues share. Setting it needs
+            // more privileges than reading it, so it is only set when it differs.
+            let tap = &taps[0];
+            if tap.mtu().map_err(Error::TapSetMtu)? != u32::from(mtu) {
+                tap.set_mtu(u32::from(mtu)).map_err(Error::TapSetMtu)?;
+            }
+        }
+        for tap in taps.iter() {
+            // Set offload flags to match the virtio features below.
+            tap.set_offload(
+                net_gen::TUN_F_CSUM
+                    | net_gen::TUN_F_UFO
+                    | net_gen::TUN_F_TSO4
+                    | net_gen::TUN_F_TSO6,
+            )
+            .map_err(Error::TapSetOffload)?;
 
-        let vnet_hdr_size = vnet_hdr_len() as i32;
-        tap.set_vnet_hdr_size(vnet_hdr_size)
-            .map_err(Error::TapSetVnetHdrSize)?;
+            let vnet_hdr_size = vnet_hdr_len() as i32;
+            tap.set_vnet_hdr_size(vnet_hdr_size)
+                .map_err(Error::TapSetVnetHdrSize)?;
+        }
 
         let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_CSUM
@@ -708,21 +1146,36 @@ impl Net {
             | 1 << VIRTIO_NET_F_HOST_UFO
             | 1 << VIRTIO_F_VERSION_1;
 
-        let mut config_space;
+        let mut config_space = Vec::new();
         if let Some(mac) = guest_mac {
-            config_space = Vec::with_capacity(MAC_ADDR_LEN);
-            // This is safe, because we know the capacity is large enough.
-            unsafe { config_space.set_len(MAC_ADDR_LEN) }
-            config_space[..].copy_from_slice(mac.get_bytes());
+            config_space.extend_from_slice(mac.get_bytes());
             // When this feature isn't available, the driver generates a random MAC address.
             // Otherwise, it should attempt to read the device MAC address from the config space.
             avail_features |= 1 << VIRTIO_NET_F_MAC;
-        } else {
-            config_space = Vec::new();
+        }
+
+        // Each queue pair is made of a receive queue followed by a transmit queue.
+        let num_queue_pairs = taps.len();
+        let mut queue_sizes = vec![QUEUE_SIZE; 2 * num_queue_pairs];
+        if num_queue_pairs > 1 {
+            // The driver sets the number of queue pairs it uses through the control queue, which
+            // follows the queue pairs.
+            avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_MQ;
+            queue_sizes.push(QUEUE_SIZE);
+            config_space.resize(MQ_CONFIG_SPACE_SIZE, 0);
+            config_space[MQ_CONFIG_MAX_PAIRS_OFFSET..]
+                .copy_from_slice(&(num_queue_pairs as u16).to_le_bytes());
+        }
+        if let Some(mtu) = mtu {
+            // The driver then uses the MTU of the host, instead of the standard one.
+            avail_features |= 1 << VIRTIO_NET_F_MTU;
+            config_space.resize(MTU_CONFIG_SPACE_SIZE, 0);
+            config_space[MTU_CONFIG_OFFSET..].copy_from_slice(&mtu.to_le_bytes());
         }
 
         Ok(Net {
-            tap: Some(tap),
+            taps,
+            queue_sizes,
             avail_features,
             acked_features: 0u64,
             config_space,
@@ -730,6 +1183,12 @@ impl Net {
             rx_rate_limiter,
             tx_rate_limiter,
             allow_mmds_requests,
+            mtu,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            metrics,
         })
     }
 
@@ -743,24 +1202,48 @@ impl Net {
         rx_rate_limiter: Option<RateLimiter>,
         tx_rate_limiter: Option<RateLimiter>,
         allow_mmds_requests: bool,
+        metrics: DeviceMetrics<NetDeviceMetrics>,
     ) -> Result<Self> {
         let tap = Tap::new().map_err(Error::TapOpen)?;
         tap.set_ip_addr(ip_addr).map_err(Error::TapSetIp)?;
         tap.set_netmask(netmask).map_err(Error::TapSetNetmask)?;
         tap.enable().map_err(Error::TapEnable)?;
 
-        Self::new_with_tap(
-            tap,
+        Self::new_with_taps(
+            vec![tap],
             guest_mac,
+            None,
             epoll_config,
             rx_rate_limiter,
             tx_rate_limiter,
             allow_mmds_requests,
+            metrics,
         )
     }
 
+    /// Sets the capture in progress when the device gets activated. See
+    /// `NetEpollHandler::set_capture`.
+    pub fn set_capture(&mut self, capture: Option<PcapWriter>) {
+        self.capture = capture;
+    }
+
+    /// Sets the filter which drops the frames sent by the guest with spoofed source addresses.
+    pub fn set_anti_spoofing(&mut self, anti_spoofing: Option<AntiSpoofing>) {
+        self.anti_spoofing = anti_spoofing;
+    }
+
+    /// Sets the filters of the frames received from and sent to the TAP.
+    pub fn set_packet_filters(
+        &mut self,
+        ingress_filter: Option<PacketFilter>,
+        egress_filter: Option<PacketFilter>,
+    ) {
+        self.ingress_filter = ingress_filter;
+        self.egress_filter = egress_filter;
+    }
+
     fn guest_mac(&self) -> Option<MacAddr> {
-        if self.config_space.len() < MAC_ADDR_LEN {
+        if self.avail_features & (1 << VIRTIO_NET_F_MAC) == 0 {
             None
         } else {
             Some(MacAddr::from_bytes_unchecked(
@@ -776,7 +1259,7 @@ impl VirtioDevice for Net {
     }
 
     fn queue_max_sizes(&self) -> &[u16] {
-        QUEUE_SIZES
+        &self.queue_sizes
     }
 
     fn features(&self, page: u32) -> u32 {
@@ -814,7 +1297,7 @@ impl VirtioDevice for Net {
         let config_len = self.config_space.len() as u64;
         if offset >= config_len {
             error!("Failed to read config space");
-            METRICS.net.cfg_fails.inc();
+            self.metrics.update(|m| m.cfg_fails.inc());
             return;
         }
         if let Some(end) = offset.checked_add(data.len() as u64) {
@@ -826,14 +1309,15 @@ impl VirtioDevice for Net {
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         let data_len = data.len() as u64;
-        let config_len = self.config_space.len() as u64;
+        // Only the MAC address is writable.
+        let config_len = cmp::min(self.config_space.len(), MAC_ADDR_LEN) as u64;
         if offset + data_len > config_len {
             error!("Failed to write config space");
-            METRICS.net.cfg_fails.inc();
+            self.metrics.update(|m| m.cfg_fails.inc());
             return;
         }
         let (_, right) = self.config_space.split_at_mut(offset as usize);
-        right.copy_from_slice(&data[..]);
+        right[..data.len()].copy_from_slice(&data[..]);
     }
 
     fn activate(
@@ -841,122 +1325,156 @@ impl VirtioDevice for Net {
         mem: GuestMemory,
         interrupt_evt: EventFd,
         status: Arc<AtomicUsize>,
-        mut queues: Vec<Queue>,
-        mut queue_evts: Vec<EventFd>,
+        queues: Vec<Queue>,
+        queue_evts: Vec<EventFd>,
     ) -> ActivateResult {
-        if queues.len() != NUM_QUEUES || queue_evts.len() != NUM_QUEUES {
+        let num_queues = self.queue_sizes.len();
+        if queues.len() != num_queues || queue_evts.len() != num_queues {
             error!(
                 "Cannot perform activate. Expected {} queue(s), got {}",
-                NUM_QUEUES,
+                num_queues,
                 queues.len()
             );
-            METRICS.net.activate_fails.inc();
+            self.metrics.update(|m| m.activate_fails.inc());
 
             return Err(ActivateError::BadActivate);
         }
 
-        if let Some(tap) = self.tap.take() {
-            let rx_queue = queues.remove(0);
-            let tx_queue = queues.remove(0);
-            let rx_queue_evt = queue_evts.remove(0);
-            let tx_queue_evt = queue_evts.remove(0);
-            let mut mmds_ns = if self.allow_mmds_requests {
-                Some(MmdsNetworkStack::new_with_defaults())
-            } else {
-                None
-            };
-            let handler = NetEpollHandler {
-                rx: RxVirtio::new(
-                    rx_queue,
-                    rx_queue_evt,
-                    self.rx_rate_limiter.take().unwrap_or_default(),
-                ),
+        if self.taps.is_empty() {
+            self.metrics.update(|m| m.activate_fails.inc());
+            return Err(ActivateError::BadActivate);
+        }
+
+        let mut queues = queues.into_iter();
+        let mut queue_evts = queue_evts.into_iter();
+        let mut queue_pairs = Vec::with_capacity(self.taps.len());
+        // The queues and the queue events match the queue sizes, checked above.
+        for (queue_index, tap) in self.taps.drain(..).enumerate() {
+            queue_pairs.push(QueuePair {
+                rx: RxVirtio::new(queues.next().unwrap(), queue_evts.next().unwrap()),
+                tx: TxVirtio::new(queues.next().unwrap(), queue_evts.next().unwrap()),
                 tap,
-                mem,
-                tx: TxVirtio::new(
-                    tx_queue,
-                    tx_queue_evt,
-                    self.tx_rate_limiter.take().unwrap_or_default(),
-                ),
-                interrupt_status: status,
-                interrupt_evt,
-                acked_features: self.acked_features,
-                mmds_ns,
-                guest_mac: self.guest_mac(),
-                epoll_fd: self.epoll_config.epoll_raw_fd,
+                tap_attached: true,
                 rx_tap_listening: false,
-                rx_tap_epoll_token: self.epoll_config.rx_tap_token,
+                rx_tap_epoll_token: self
+                    .epoll_config
+                    .queue_pair_event_token(queue_index, RX_TAP_EVENT),
+            });
+        }
+        // The control queue follows the queue pairs, if there are several of them.
+        let ctrl = match (queues.next(), queue_evts.next()) {
+            (Some(queue), Some(queue_evt)) => Some(CtrlVirtio { queue_evt, queue }),
+            _ => None,
+        };
 
-                #[cfg(test)]
-                test_mutators: tests::TestMutators::default(),
-            };
+        let mmds_ns = if self.allow_mmds_requests {
+            Some(MmdsNetworkStack::new_with_defaults())
+        } else {
+            None
+        };
+        let active_queue_pairs = queue_pairs.len();
+        let mut handler = NetEpollHandler {
+            queue_pairs,
+            active_queue_pairs,
+            next_rx_queue_pair: 0,
+            next_tx_queue_pair: 0,
+            ctrl,
+            rx_rate_limiter: self.rx_rate_limiter.take().unwrap_or_default(),
+            tx_rate_limiter: self.tx_rate_limiter.take().unwrap_or_default(),
+            mem,
+            interrupt_status: status,
+            interrupt_evt,
+            acked_features: self.acked_features,
+            mmds_ns,
+            guest_mac: self.guest_mac(),
+            mtu: self.mtu,
+            capture: self.capture.take(),
+            anti_spoofing: self.anti_spoofing.clone(),
+            ingress_filter: self.ingress_filter.clone(),
+            egress_filter: self.egress_filter.clone(),
+            epoll_fd: self.epoll_config.epoll_raw_fd,
+            metrics: self.metrics.clone(),
+
+            #[cfg(test)]
+            test_mutators: tests::TestMutators::default(),
+        };
+        // Until the driver sets the number of queue pairs, only the first one is used.
+        if !handler.set_active_queue_pairs(1) {
+            self.metrics.update(|m| m.activate_fails.inc());
+            return Err(ActivateError::BadActivate);
+        }
 
-            let rx_queue_raw_fd = handler.rx.queue_evt.as_raw_fd();
-            let tx_queue_raw_fd = handler.tx.queue_evt.as_raw_fd();
+        let mut queue_evt_listeners = Vec::with_capacity(num_queues);
+        for (queue_index, pair) in handler.queue_pairs.iter().enumerate() {
+            queue_evt_listeners.push((
+                pair.rx.queue_evt.as_raw_fd(),
+                self.epoll_config
+                    .queue_pair_event_token(queue_index, RX_QUEUE_EVENT),
+            ));
+            queue_evt_listeners.push((
+                pair.tx.queue_evt.as_raw_fd(),
+                self.epoll_config
+                    .queue_pair_event_token(queue_index, TX_QUEUE_EVENT),
+            ));
+        }
+        if let Some(ref ctrl) = handler.ctrl {
+            queue_evt_listeners.push((
+                ctrl.queue_evt.as_raw_fd(),
+                self.epoll_config.ctrl_queue_token,
+            ));
+        }
 
-            let rx_rate_limiter_rawfd = handler.rx.rate_limiter.as_raw_fd();
-            let tx_rate_limiter_rawfd = handler.tx.rate_limiter.as_raw_fd();
+        let rx_rate_limiter_rawfd = handler.rx_rate_limiter.as_raw_fd();
+        let tx_rate_limiter_rawfd = handler.tx_rate_limiter.as_raw_fd();
 
-            //channel should be open and working
-            self.epoll_config
-                .sender
-                .send(Box::new(handler))
-                .expect("Failed to send through the channel");
+        //channel should be open and working
+        self.epoll_config
+            .sender
+            .send(Box::new(handler))
+            .expect("Failed to send through the channel");
 
-            //TODO: barrier needed here maybe?
+        //TODO: barrier needed here maybe?
 
+        for (queue_evt_raw_fd, token) in queue_evt_listeners {
             epoll::ctl(
                 self.epoll_config.epoll_raw_fd,
                 epoll::ControlOptions::EPOLL_CTL_ADD,
-                rx_queue_raw_fd,
-                epoll::Event::new(epoll::Events::EPOLLIN, self.epoll_config.rx_queue_token),
+                queue_evt_raw_fd,
+                epoll::Event::new(epoll::Events::EPOLLIN, token),
             )
             .map_err(|e| {
-                METRICS.net.activate_fails.inc();
+                self.metrics.update(|m| m.activate_fails.inc());
                 ActivateError::EpollCtl(e)
             })?;
+        }
 
+        if rx_rate_limiter_rawfd != -1 {
             epoll::ctl(
                 self.epoll_config.epoll_raw_fd,
                 epoll::ControlOptions::EPOLL_CTL_ADD,
-                tx_queue_raw_fd,
-                epoll::Event::new(epoll::Events::EPOLLIN, self.epoll_config.tx_queue_token),
+                rx_rate_limiter_rawfd,
+                epoll::Event::new(
+                    epoll::Events::EPOLLIN,
+                    self.epoll_config.rx_rate_limiter_token,
+                ),
             )
-            .map_err(|e| {
-                METRICS.net.activate_fails.inc();
-                ActivateError::EpollCtl(e)
-            })?;
-
-            if rx_rate_limiter_rawfd != -1 {
-                epoll::ctl(
-                    self.epoll_config.epoll_raw_fd,
-                    epoll::ControlOptions::EPOLL_CTL_ADD,
-                    rx_rate_limiter_rawfd,
-                    epoll::Event::new(
-                        epoll::Events::EPOLLIN,
-                        self.epoll_config.rx_rate_limiter_token,
-                    ),
-                )
-                .map_err(ActivateError::EpollCtl)?;
-            }
-
-            if tx_rate_limiter_rawfd != -1 {
-                epoll::ctl(
-                    self.epoll_config.epoll_raw_fd,
-                    epoll::ControlOptions::EPOLL_CTL_ADD,
-                    tx_rate_limiter_rawfd,
-                    epoll::Event::new(
-                        epoll::Events::EPOLLIN,
-                        self.epoll_config.tx_rate_limiter_token,
-                    ),
-                )
-                .map_err(ActivateError::EpollCtl)?;
-            }
+            .map_err(ActivateError::EpollCtl)?;
+        }
 
-            return Ok(());
+        if tx_rate_limiter_rawfd != -1 {
+            epoll::ctl(
+                self.epoll_config.epoll_raw_fd,
+                epoll::ControlOptions::EPOLL_CTL_ADD,
+                tx_rate_limiter_rawfd,
+                epoll::Event::new(
+                    epoll::Events::EPOLLIN,
+                    self.epoll_config.tx_rate_limiter_token,
+                ),
+            )
+            .map_err(ActivateError::EpollCtl)?;
         }
-        METRICS.net.activate_fails.inc();
-        Err(ActivateError::BadActivate)
+
+        Ok(())
     }
 }
 
@@ -966,6 +1484,9 @@ impl VirtioDevice for Net {
 // don't implement the trait.
 #[allow(clippy::assertions_on_constants)]
 mod tests {
+    extern crate tempfile;
+
+    use std::fs;
     use std::sync::mpsc::Receiver;
     use std::thread;
     use std::time::Duration;
@@ -976,10 +1497,13 @@ mod tests {
     use super::*;
     use memory_model::GuestAddress;
     use virtio::queue::tests::*;
+    use virtio::FilterAction;
 
     use dumbo::pdu::{arp, ethernet};
     use rate_limiter::TokenBucket;
 
+    use self::tempfile::TempDir;
+
     const EPOLLIN: epoll::Events = epoll::Events::EPOLLIN;
 
     /// Will read $metric, run the code in $block, then assert metric has increased by $delta.
@@ -991,14 +1515,21 @@ mod tests {
         }};
     }
 
+    fn test_metrics() -> DeviceMetrics<NetDeviceMetrics> {
+        DeviceMetrics::new(Arc::new(NetDeviceMetrics::default()), &METRICS.net)
+    }
+
     pub struct TestMutators {
         pub tap_read_fail: bool,
+        // The number of frames left to read from the tap, if limited.
+        pub tap_frames_left: Option<usize>,
     }
 
     impl Default for TestMutators {
         fn default() -> TestMutators {
             TestMutators {
                 tap_read_fail: false,
+                tap_frames_left: None,
             }
         }
     }
@@ -1045,6 +1576,7 @@ mod tests {
                         .unwrap(),
                     ),
                     true,
+                    test_metrics(),
                 )
                 .unwrap(),
                 epoll_raw_fd,
@@ -1065,21 +1597,29 @@ mod tests {
 
     impl NetEpollHandler {
         fn get_rx_rate_limiter(&self) -> &RateLimiter {
-            &self.rx.rate_limiter
+            &self.rx_rate_limiter
         }
 
         fn get_tx_rate_limiter(&self) -> &RateLimiter {
-            &self.tx.rate_limiter
+            &self.tx_rate_limiter
         }
 
         // This needs to be public to be accessible from the non-cfg-test `impl NetEpollHandler`.
-        pub fn read_tap(&mut self) -> io::Result<usize> {
+        pub fn read_tap(&mut self, queue_index: usize) -> io::Result<usize> {
             use std::cmp::min;
 
-            let count = min(1234, self.rx.frame_buf.len());
+            if let Some(ref mut frames_left) = self.test_mutators.tap_frames_left {
+                if *frames_left == 0 {
+                    return Err(io::Error::from_raw_os_error(EAGAIN));
+                }
+                *frames_left -= 1;
+            }
+
+            let frame_buf = &mut self.queue_pairs[queue_index].rx.frame_buf;
+            let count = min(1234, frame_buf.len());
 
             for i in 0..count {
-                self.rx.frame_buf[i] = 5;
+                frame_buf[i] = 5;
             }
 
             if self.test_mutators.tap_read_fail {
@@ -1090,20 +1630,20 @@ mod tests {
         }
 
         fn rx_single_frame_no_irq_coalescing(&mut self) -> bool {
-            let ret = self.rx_single_frame();
-            if self.rx.deferred_irqs {
-                self.rx.deferred_irqs = false;
+            let ret = self.rx_single_frame(0);
+            if self.queue_pairs[0].rx.deferred_irqs {
+                self.queue_pairs[0].rx.deferred_irqs = false;
                 let _ = self.signal_used_queue();
             }
             ret
         }
 
         fn set_rx_rate_limiter(&mut self, rx_rate_limiter: RateLimiter) {
-            self.rx.rate_limiter = rx_rate_limiter;
+            self.rx_rate_limiter = rx_rate_limiter;
         }
 
         fn set_tx_rate_limiter(&mut self, tx_rate_limiter: RateLimiter) {
-            self.tx.rate_limiter = tx_rate_limiter;
+            self.tx_rate_limiter = tx_rate_limiter;
         }
     }
 
@@ -1153,19 +1693,34 @@ mod tests {
 
         (
             NetEpollHandler {
-                rx: RxVirtio::new(rx_queue, rx_queue_evt, RateLimiter::default()),
-                tap: n.tap.take().unwrap(),
+                queue_pairs: vec![QueuePair {
+                    rx: RxVirtio::new(rx_queue, rx_queue_evt),
+                    tx: TxVirtio::new(tx_queue, tx_queue_evt),
+                    tap: n.taps.pop().unwrap(),
+                    tap_attached: true,
+                    rx_tap_listening: false,
+                    rx_tap_epoll_token: 0,
+                }],
+                active_queue_pairs: 1,
+                next_rx_queue_pair: 0,
+                next_tx_queue_pair: 0,
+                ctrl: None,
+                rx_rate_limiter: RateLimiter::default(),
+                tx_rate_limiter: RateLimiter::default(),
                 mem: mem.clone(),
-                tx: TxVirtio::new(tx_queue, tx_queue_evt, RateLimiter::default()),
                 interrupt_status,
                 interrupt_evt,
                 acked_features: n.acked_features,
                 mmds_ns: Some(MmdsNetworkStack::new_with_defaults()),
                 test_mutators,
                 guest_mac: None,
+                mtu: None,
+                capture: None,
+                anti_spoofing: None,
+                ingress_filter: None,
+                egress_filter: None,
                 epoll_fd,
-                rx_tap_epoll_token: 0,
-                rx_tap_listening: false,
+                metrics: test_metrics(),
             },
             txq,
             rxq,
@@ -1210,7 +1765,7 @@ mod tests {
         // Test `queue_max_sizes()`.
         {
             let x = n.queue_max_sizes();
-            assert_eq!(x, QUEUE_SIZES);
+            assert_eq!(x, &[QUEUE_SIZE; 2]);
 
             // power of 2?
             for &y in x {
@@ -1337,6 +1892,7 @@ mod tests {
             None,
             None,
             false,
+            test_metrics(),
         ) {
             Err(Error::TapSetIp(_)) => (),
             _ => assert!(false),
@@ -1357,6 +1913,7 @@ mod tests {
             None,
             None,
             false,
+            test_metrics(),
         ) {
             Err(Error::TapSetNetmask(_)) => (),
             _ => assert!(false),
@@ -1377,7 +1934,7 @@ mod tests {
         {
             // Create an ethernet frame.
             let eth_frame_i = ethernet::EthernetFrame::write_incomplete(
-                frame_bytes_from_buf_mut(&mut h.tx.frame_buf),
+                frame_bytes_from_buf_mut(&mut h.queue_pairs[0].tx.frame_buf),
                 tha,
                 sha,
                 ethernet::ETHERTYPE_ARP,
@@ -1406,23 +1963,25 @@ mod tests {
 
         // Call the code which sends the packet to the host or MMDS.
         // Validate the frame was consumed by MMDS and that the metrics reflect that.
-        check_metric_after_block!(
-            &METRICS.mmds.rx_accepted,
-            1,
+        check_metric_after_block!(&METRICS.mmds.rx_accepted, 1, {
+            let pair = &mut h.queue_pairs[0];
             assert!(NetEpollHandler::write_to_mmds_or_tap(
                 h.mmds_ns.as_mut(),
-                &mut h.tx.rate_limiter,
-                &h.tx.frame_buf[..packet_len],
-                &mut h.tap,
+                &mut h.tx_rate_limiter,
+                &pair.tx.frame_buf[..packet_len],
+                &mut pair.tap,
                 Some(sha),
+                None,
+                None,
+                &h.metrics,
             ))
-        );
+        });
 
         // Validate that MMDS has a response and we can retrieve it.
         check_metric_after_block!(
             &METRICS.mmds.tx_frames,
             1,
-            h.read_from_mmds_or_tap().unwrap()
+            h.read_from_mmds_or_tap(0).unwrap()
         );
     }
 
@@ -1441,7 +2000,7 @@ mod tests {
         {
             // Create an ethernet frame.
             let eth_frame_i = ethernet::EthernetFrame::write_incomplete(
-                frame_bytes_from_buf_mut(&mut h.tx.frame_buf),
+                frame_bytes_from_buf_mut(&mut h.queue_pairs[0].tx.frame_buf),
                 dst_mac,
                 guest_mac,
                 ethernet::ETHERTYPE_ARP,
@@ -1469,30 +2028,36 @@ mod tests {
         }
 
         // Check that a legit MAC doesn't affect the spoofed MAC metric.
-        check_metric_after_block!(
-            &METRICS.net.tx_spoofed_mac_count,
-            0,
+        check_metric_after_block!(&METRICS.net.tx_spoofed_mac_count, 0, {
+            let pair = &mut h.queue_pairs[0];
             NetEpollHandler::write_to_mmds_or_tap(
                 h.mmds_ns.as_mut(),
-                &mut h.tx.rate_limiter,
-                &h.tx.frame_buf[..packet_len],
-                &mut h.tap,
+                &mut h.tx_rate_limiter,
+                &pair.tx.frame_buf[..packet_len],
+                &mut pair.tap,
                 Some(guest_mac),
+                None,
+                None,
+                &h.metrics,
             )
-        );
+        });
 
         // Check that a spoofed MAC increases our spoofed MAC metric.
-        check_metric_after_block!(
-            &METRICS.net.tx_spoofed_mac_count,
-            1,
+        check_metric_after_block!(&METRICS.net.tx_spoofed_mac_count, 1, {
+            let pair = &mut h.queue_pairs[0];
             NetEpollHandler::write_to_mmds_or_tap(
                 h.mmds_ns.as_mut(),
-                &mut h.tx.rate_limiter,
-                &h.tx.frame_buf[..packet_len],
-                &mut h.tap,
+                &mut h.tx_rate_limiter,
+                &pair.tx.frame_buf[..packet_len],
+                &mut pair.tap,
                 Some(not_guest_mac),
+                None,
+                None,
+                &h.metrics,
             )
-        );
+        });
+        // The metric is counted for the device as well as in the totals.
+        assert_eq!(h.metrics.device().tx_spoofed_mac_count.count(), 1);
     }
 
     #[test]
@@ -1542,10 +2107,11 @@ mod tests {
     fn test_read_tap_fail_event_handler() {
         let test_mutators = TestMutators {
             tap_read_fail: true,
+            ..TestMutators::default()
         };
         let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
         let (mut h, _txq, rxq) = default_test_netepollhandler(&mem, test_mutators);
-        h.register_tap_rx_listener().unwrap();
+        h.register_tap_rx_listener(0).unwrap();
 
         // The RX queue is empty.
         match h.handle_event(RX_TAP_EVENT, epoll::Events::EPOLLIN) {
@@ -1553,7 +2119,7 @@ mod tests {
             _ => panic!("invalid"),
         }
         // Since the RX was empty, we shouldn't be listening for tap RX events.
-        assert!(!h.rx_tap_listening);
+        assert!(!h.queue_pairs[0].rx_tap_listening);
 
         // Fake an avail buffer; this time, tap reading should error out.
         rxq.avail.idx.set(1);
@@ -1599,10 +2165,10 @@ mod tests {
 
         // Some corner cases for rx_single_frame().
         {
-            assert_eq!(h.rx.bytes_read, 0);
+            assert_eq!(h.queue_pairs[0].rx.bytes_read, 0);
 
             // Let's imagine we received some data.
-            h.rx.bytes_read = MAX_BUFFER_SIZE;
+            h.queue_pairs[0].rx.bytes_read = MAX_BUFFER_SIZE;
 
             {
                 // a read only descriptor
@@ -1614,7 +2180,7 @@ mod tests {
 
                 // resetting values
                 rxq.used.idx.set(0);
-                h.rx.queue = rxq.create_queue();
+                h.queue_pairs[0].rx.queue = rxq.create_queue();
                 h.interrupt_evt.write(1).unwrap();
                 // The prev rx_single_frame_no_irq_coalescing() call should have written one more.
                 assert_eq!(h.interrupt_evt.read().unwrap(), 2);
@@ -1632,13 +2198,13 @@ mod tests {
                 assert_eq!(rxq.used.idx.get(), 1);
 
                 rxq.used.idx.set(0);
-                h.rx.queue = rxq.create_queue();
+                h.queue_pairs[0].rx.queue = rxq.create_queue();
                 h.interrupt_evt.write(1).unwrap();
                 assert_eq!(h.interrupt_evt.read().unwrap(), 2);
             }
 
             // set rx_count back to 0
-            h.rx.bytes_read = 0;
+            h.queue_pairs[0].rx.bytes_read = 0;
         }
 
         // Now let's move on to the actual device events.
@@ -1649,7 +2215,7 @@ mod tests {
             txq.avail.ring[0].set(0);
             txq.dtable[0].set(daddr, 0x1000, 0, 0);
 
-            h.tx.queue_evt.write(1).unwrap();
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
             h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
             // Make sure the data queue advanced.
             assert_eq!(txq.used.idx.get(), 1);
@@ -1658,7 +2224,7 @@ mod tests {
         {
             // testing RX_TAP_EVENT
 
-            assert!(!h.rx.deferred_frame);
+            assert!(!h.queue_pairs[0].rx.deferred_frame);
 
             // this should work just fine
             rxq.avail.idx.set(1);
@@ -1667,7 +2233,7 @@ mod tests {
 
             h.interrupt_evt.write(1).unwrap();
             h.handle_event(RX_TAP_EVENT, EPOLLIN).unwrap();
-            assert!(h.rx.deferred_frame);
+            assert!(h.queue_pairs[0].rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read().unwrap(), 2);
             // The #cfg(test) enabled version of read_tap always returns 1234 bytes (or the len of
             // the buffer, whichever is smaller).
@@ -1677,20 +2243,20 @@ mod tests {
             // a different execution path.
 
             // reset some parts of the queue first
-            h.rx.queue = rxq.create_queue();
+            h.queue_pairs[0].rx.queue = rxq.create_queue();
             rxq.used.idx.set(0);
 
             // this should also be successful
             h.interrupt_evt.write(1).unwrap();
             h.handle_event(RX_TAP_EVENT, EPOLLIN).unwrap();
-            assert!(h.rx.deferred_frame);
+            assert!(h.queue_pairs[0].rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read().unwrap(), 2);
 
             // ... but the following shouldn't, because we emulate receiving much more data than
             // we can fit inside a single descriptor
 
-            h.rx.bytes_read = MAX_BUFFER_SIZE;
-            h.rx.queue = rxq.create_queue();
+            h.queue_pairs[0].rx.bytes_read = MAX_BUFFER_SIZE;
+            h.queue_pairs[0].rx.queue = rxq.create_queue();
             rxq.used.idx.set(0);
 
             h.interrupt_evt.write(1).unwrap();
@@ -1699,14 +2265,17 @@ mod tests {
                 1,
                 h.handle_event(RX_TAP_EVENT, EPOLLIN)
             );
-            assert!(h.rx.deferred_frame);
+            assert!(h.queue_pairs[0].rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read().unwrap(), 2);
 
             // A mismatch shows the reception was unsuccessful.
-            assert_ne!(rxq.used.ring[0].get().len as usize, h.rx.bytes_read);
+            assert_ne!(
+                rxq.used.ring[0].get().len as usize,
+                h.queue_pairs[0].rx.bytes_read
+            );
 
             // We set this back to a manageable size, for the following test.
-            h.rx.bytes_read = 1234;
+            h.queue_pairs[0].rx.bytes_read = 1234;
         }
 
         {
@@ -1715,7 +2284,7 @@ mod tests {
             rxq.avail.ring[1].set(1);
             rxq.dtable[1].set(daddr + 0x1000, 0x1000, VIRTQ_DESC_F_WRITE, 0);
 
-            h.rx.queue_evt.write(1).unwrap();
+            h.queue_pairs[0].rx.queue_evt.write(1).unwrap();
             h.interrupt_evt.write(1).unwrap();
 
             // rx_count increments 1 from rx_single_frame() and 1 from process_rx()
@@ -1730,11 +2299,12 @@ mod tests {
         {
             let test_mutators = TestMutators {
                 tap_read_fail: true,
+                ..TestMutators::default()
             };
             let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
             let (mut h, _txq, _rxq) = default_test_netepollhandler(&mem, test_mutators);
 
-            check_metric_after_block!(&METRICS.net.rx_fails, 1, h.process_rx());
+            check_metric_after_block!(&METRICS.net.rx_fails, 1, h.process_rx(0));
         }
     }
 
@@ -1764,7 +2334,7 @@ mod tests {
             // following TX procedure should fail because of bandwidth rate limiting
             {
                 // trigger the TX handler
-                h.tx.queue_evt.write(1).unwrap();
+                h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
                 h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
 
                 // assert that limiter is blocked
@@ -1803,7 +2373,7 @@ mod tests {
             h.set_rx_rate_limiter(rl);
 
             // set up RX
-            assert!(!h.rx.deferred_frame);
+            assert!(!h.queue_pairs[0].rx.deferred_frame);
             rxq.avail.idx.set(1);
             rxq.avail.ring[0].set(0);
             rxq.dtable[0].set(daddr, 0x1000, VIRTQ_DESC_F_WRITE, 0);
@@ -1817,7 +2387,7 @@ mod tests {
 
                 // assert that limiter is blocked
                 assert!(h.get_rx_rate_limiter().is_blocked());
-                assert!(h.rx.deferred_frame);
+                assert!(h.queue_pairs[0].rx.deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 assert_eq!(h.interrupt_evt.read().unwrap(), 1);
                 // make sure the data is still queued for processing
@@ -1872,7 +2442,7 @@ mod tests {
             // following TX procedure should fail because of ops rate limiting
             {
                 // trigger the TX handler
-                h.tx.queue_evt.write(1).unwrap();
+                h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
                 h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
 
                 // assert that limiter is blocked
@@ -1906,7 +2476,7 @@ mod tests {
             h.set_rx_rate_limiter(rl);
 
             // set up RX
-            assert!(!h.rx.deferred_frame);
+            assert!(!h.queue_pairs[0].rx.deferred_frame);
             rxq.avail.idx.set(1);
             rxq.avail.ring[0].set(0);
             rxq.dtable[0].set(daddr, 0x1000, VIRTQ_DESC_F_WRITE, 0);
@@ -1920,7 +2490,7 @@ mod tests {
 
                 // assert that limiter is blocked
                 assert!(h.get_rx_rate_limiter().is_blocked());
-                assert!(h.rx.deferred_frame);
+                assert!(h.queue_pairs[0].rx.deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 assert_eq!(h.interrupt_evt.read().unwrap(), 1);
                 // make sure the data is still queued for processing
@@ -1987,4 +2557,351 @@ mod tests {
         compare_buckets(h.get_tx_rate_limiter().bandwidth().unwrap(), &tx_bytes);
         compare_buckets(h.get_tx_rate_limiter().ops().unwrap(), &tx_ops);
     }
+
+    #[test]
+    fn test_mtu() {
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let n = Net::new_with_taps(
+            vec![Tap::new().unwrap()],
+            None,
+            Some(9000),
+            EpollConfig::new(0, epoll_raw_fd, sender.clone()),
+            None,
+            None,
+            false,
+            test_metrics(),
+        )
+        .unwrap();
+        assert_ne!(n.features(0) & (1 << VIRTIO_NET_F_MTU), 0);
+        let mut mtu = [0u8; 2];
+        n.read_config(MTU_CONFIG_OFFSET as u64, &mut mtu);
+        assert_eq!(u16::from_le_bytes(mtu), 9000);
+
+        match Net::new_with_taps(
+            vec![Tap::new().unwrap()],
+            None,
+            Some(NET_MIN_MTU - 1),
+            EpollConfig::new(0, epoll_raw_fd, sender),
+            None,
+            None,
+            false,
+            test_metrics(),
+        ) {
+            Err(Error::InvalidMtu) => (),
+            _ => panic!("invalid"),
+        }
+        unsafe { libc::close(epoll_raw_fd) };
+
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+        h.mtu = Some(1000);
+        let daddr = 0x2000;
+        mem.write_slice_at_addr(&[0u8; 0x1000], GuestAddress(daddr as usize))
+            .unwrap();
+
+        // A frame above the MTU is dropped.
+        check_metric_after_block!(&METRICS.net.tx_mtu_exceeded_count, 1, {
+            txq.avail.idx.set(1);
+            txq.avail.ring[0].set(0);
+            txq.dtable[0].set(daddr, 0x1000, 0, 0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(txq.used.idx.get(), 1);
+
+        // The segmentation offload frames are split by the host.
+        mem.write_obj_at_addr(
+            VIRTIO_NET_HDR_GSO_TCPV4 as u8,
+            GuestAddress(daddr as usize + 1),
+        )
+        .unwrap();
+        check_metric_after_block!(&METRICS.net.tx_mtu_exceeded_count, 0, {
+            txq.avail.idx.set(2);
+            txq.avail.ring[1].set(0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(txq.used.idx.get(), 2);
+
+        assert!(!frame_exceeds_mtu(&[0u8; 0x1000], None));
+        assert!(!frame_exceeds_mtu(&[0u8; 1000], Some(1000)));
+    }
+
+    #[test]
+    fn test_anti_spoofing() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+        let guest_mac = MacAddr::parse_str("11:22:33:44:55:66").unwrap();
+        h.anti_spoofing = Some(AntiSpoofing::new(guest_mac, None));
+        let daddr = 0x2000;
+        let frame_len = vnet_hdr_len() + 60;
+        mem.write_slice_at_addr(&vec![0u8; frame_len], GuestAddress(daddr as usize))
+            .unwrap();
+        txq.dtable[0].set(daddr, frame_len as u32, 0, 0);
+
+        // A frame with another source MAC address is dropped.
+        check_metric_after_block!(&METRICS.net.tx_spoofed_drops, 1, {
+            txq.avail.idx.set(1);
+            txq.avail.ring[0].set(0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(txq.used.idx.get(), 1);
+        assert_eq!(h.metrics.device().tx_spoofed_mac_count.count(), 1);
+        assert_eq!(h.metrics.device().tx_packets_count.count(), 0);
+
+        // A frame from the guest MAC address goes to the tap.
+        mem.write_slice_at_addr(
+            guest_mac.get_bytes(),
+            GuestAddress(daddr as usize + vnet_hdr_len() + MAC_ADDR_LEN),
+        )
+        .unwrap();
+        check_metric_after_block!(&METRICS.net.tx_spoofed_drops, 0, {
+            txq.avail.idx.set(2);
+            txq.avail.ring[1].set(0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(txq.used.idx.get(), 2);
+        assert_eq!(h.metrics.device().tx_packets_count.count(), 1);
+
+        // Frames too short for a VNET header are dropped as well.
+        assert!(frame_is_spoofed(
+            h.anti_spoofing.as_ref(),
+            &[0u8; 4],
+            &h.metrics
+        ));
+        assert!(!frame_is_spoofed(None, &[0u8; 4], &h.metrics));
+    }
+
+    #[test]
+    fn test_packet_filters() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+        let deny_all = PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: Vec::new(),
+        };
+        let allow_all = PacketFilter {
+            default_policy: FilterAction::Allow,
+            rules: Vec::new(),
+        };
+        h.patch_packet_filters(Some(deny_all.clone()), Some(deny_all));
+        let daddr = 0x2000;
+        let frame_len = vnet_hdr_len() + 60;
+        mem.write_slice_at_addr(&vec![0u8; frame_len], GuestAddress(daddr as usize))
+            .unwrap();
+        txq.dtable[0].set(daddr, frame_len as u32, 0, 0);
+
+        // The frames for the TAP are dropped by the egress filter.
+        check_metric_after_block!(&METRICS.net.tx_filtered_drops, 1, {
+            txq.avail.idx.set(1);
+            txq.avail.ring[0].set(0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(txq.used.idx.get(), 1);
+        assert_eq!(h.metrics.device().tx_packets_count.count(), 0);
+
+        // The frames from the TAP are dropped by the ingress filter.
+        h.mmds_ns = None;
+        h.test_mutators.tap_frames_left = Some(2);
+        check_metric_after_block!(&METRICS.net.rx_filtered_drops, 2, {
+            h.process_rx(0).unwrap();
+        });
+        assert!(!h.queue_pairs[0].rx.deferred_frame);
+
+        // Patching the egress filter leaves the ingress one unchanged.
+        h.patch_packet_filters(None, Some(allow_all.clone()));
+        check_metric_after_block!(&METRICS.net.tx_filtered_drops, 0, {
+            txq.avail.idx.set(2);
+            txq.avail.ring[1].set(0);
+            h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+            h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(h.metrics.device().tx_packets_count.count(), 1);
+        h.test_mutators.tap_frames_left = Some(1);
+        check_metric_after_block!(&METRICS.net.rx_filtered_drops, 1, {
+            h.process_rx(0).unwrap();
+        });
+
+        h.patch_packet_filters(Some(allow_all), None);
+        h.test_mutators.tap_frames_left = Some(1);
+        check_metric_after_block!(&METRICS.net.rx_filtered_drops, 0, {
+            h.process_rx(0).unwrap();
+        });
+        assert_eq!(h.queue_pairs[0].rx.bytes_read, 1234);
+        assert!(filter_allows(None, &[], FilterDirection::Ingress));
+    }
+
+    #[test]
+    fn test_capture() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.pcap");
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, txq, rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+        h.set_capture(Some(
+            PcapWriter::new(path.to_str().unwrap(), 1 << 20, 0).unwrap(),
+        ));
+
+        // A frame sent by the guest.
+        txq.avail.idx.set(1);
+        txq.avail.ring[0].set(0);
+        txq.dtable[0].set(0x2000, 0x100, 0, 0);
+        h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+        h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        assert_eq!(txq.used.idx.get(), 1);
+
+        // The test tap always has a frame of 1234 bytes to read. The second one gets deferred,
+        // since the guest made a single buffer available.
+        rxq.avail.idx.set(1);
+        rxq.avail.ring[0].set(0);
+        rxq.dtable[0].set(0x3000, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+        h.process_rx(0).unwrap();
+        assert_eq!(rxq.used.idx.get(), 1);
+        assert!(h.queue_pairs[0].rx.deferred_frame);
+
+        // The frames are captured without their VNET header.
+        let capture_len = 24 + (16 + 0x100 - vnet_hdr_len()) + 2 * (16 + 1234 - vnet_hdr_len());
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, capture_len);
+
+        h.set_capture(None);
+        txq.avail.idx.set(2);
+        txq.avail.ring[1].set(0);
+        h.queue_pairs[0].tx.queue_evt.write(1).unwrap();
+        h.handle_event(TX_QUEUE_EVENT, EPOLLIN).unwrap();
+        assert_eq!(txq.used.idx.get(), 2);
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, capture_len);
+    }
+
+    #[test]
+    fn test_multi_queue() {
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        // A network device needs between 1 and `NET_MAX_QUEUE_PAIRS` tap queues.
+        match Net::new_with_taps(
+            vec![],
+            None,
+            None,
+            EpollConfig::new(0, epoll_raw_fd, sender.clone()),
+            None,
+            None,
+            false,
+            test_metrics(),
+        ) {
+            Err(Error::InvalidQueuePairs) => (),
+            _ => panic!("invalid"),
+        }
+
+        let taps = vec![
+            Tap::open_named_multi_queue("fcnetmq0").unwrap(),
+            Tap::open_named_multi_queue("fcnetmq0").unwrap(),
+        ];
+        let mut n = Net::new_with_taps(
+            taps,
+            None,
+            None,
+            EpollConfig::new(0, epoll_raw_fd, sender),
+            None,
+            None,
+            false,
+            test_metrics(),
+        )
+        .unwrap();
+
+        // Two queue pairs, followed by the control queue.
+        assert_eq!(n.queue_max_sizes(), &[QUEUE_SIZE; 5]);
+        let features = n.features(0);
+        assert_ne!(features & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_ne!(features & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+        assert_eq!(features & (1 << VIRTIO_NET_F_MAC), 0);
+        assert!(n.guest_mac().is_none());
+        let mut max_queue_pairs = [0u8; 2];
+        n.read_config(MQ_CONFIG_MAX_PAIRS_OFFSET as u64, &mut max_queue_pairs);
+        assert_eq!(u16::from_le_bytes(max_queue_pairs), 2);
+        // The maximum number of queue pairs is read only.
+        n.write_config(MQ_CONFIG_MAX_PAIRS_OFFSET as u64, &[1, 0]);
+        n.read_config(MQ_CONFIG_MAX_PAIRS_OFFSET as u64, &mut max_queue_pairs);
+        assert_eq!(u16::from_le_bytes(max_queue_pairs), 2);
+
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vqs: Vec<VirtQueue> = (0..5)
+            .map(|i| VirtQueue::new(GuestAddress(i * 0x1000), &mem, 16))
+            .collect();
+        let queue_evts: Vec<EventFd> = (0..5).map(|_| EventFd::new().unwrap()).collect();
+        let ctrl_queue_evt = queue_evts[4].try_clone().unwrap();
+        // The activation fails without the control queue.
+        assert!(n
+            .activate(
+                mem.clone(),
+                EventFd::new().unwrap(),
+                Arc::new(AtomicUsize::new(0)),
+                vqs[..4].iter().map(|vq| vq.create_queue()).collect(),
+                (0..4).map(|_| EventFd::new().unwrap()).collect(),
+            )
+            .is_err());
+        n.activate(
+            mem.clone(),
+            EventFd::new().unwrap(),
+            Arc::new(AtomicUsize::new(0)),
+            vqs.iter().map(|vq| vq.create_queue()).collect(),
+            queue_evts,
+        )
+        .unwrap();
+        let mut h = receiver.try_recv().unwrap();
+
+        // The command setting the number of queue pairs is made of a header, the number of queue
+        // pairs, and the acknowledgement written by the device.
+        let ctrlq = &vqs[4];
+        ctrlq.dtable[0].set(0x8000, 2, VIRTQ_DESC_F_NEXT, 1);
+        ctrlq.dtable[1].set(0x8010, 2, VIRTQ_DESC_F_NEXT, 2);
+        ctrlq.dtable[2].set(0x8020, 1, VIRTQ_DESC_F_WRITE, 0);
+        mem.write_slice_at_addr(
+            &[
+                VIRTIO_NET_CTRL_MQ as u8,
+                VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET as u8,
+            ],
+            GuestAddress(0x8000),
+        )
+        .unwrap();
+
+        // Both queue pairs can be used.
+        mem.write_obj_at_addr(2u16, GuestAddress(0x8010)).unwrap();
+        ctrlq.avail.ring[0].set(0);
+        ctrlq.avail.idx.set(1);
+        ctrl_queue_evt.write(1).unwrap();
+        h.handle_event(CTRL_QUEUE_EVENT, EPOLLIN).unwrap();
+        assert_eq!(ctrlq.used.idx.get(), 1);
+        assert_eq!(ctrlq.used.ring[0].get().len, 1);
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x8020)).unwrap(),
+            VIRTIO_NET_OK as u8
+        );
+
+        // The device only has two queue pairs.
+        mem.write_obj_at_addr(3u16, GuestAddress(0x8010)).unwrap();
+        check_metric_after_block!(&METRICS.net.ctrl_fails, 1, {
+            ctrlq.avail.ring[1].set(0);
+            ctrlq.avail.idx.set(2);
+            ctrl_queue_evt.write(1).unwrap();
+            h.handle_event(CTRL_QUEUE_EVENT, EPOLLIN).unwrap();
+        });
+        assert_eq!(ctrlq.used.idx.get(), 2);
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x8020)).unwrap(),
+            VIRTIO_NET_ERR as u8
+        );
+
+        // The events of the second queue pair follow the ones of the first queue pair.
+        let bad_event = RX_TAP_EVENT + 2 * QUEUE_PAIR_EVENTS_COUNT;
+        match h.handle_event(bad_event, EPOLLIN) {
+            Err(DeviceError::UnknownEvent { event, .. }) => assert_eq!(event, bad_event),
+            _ => panic!("invalid"),
+        }
+        assert_eq!(net_events_count(2), bad_event as usize);
+
+        unsafe { libc::close(epoll_raw_fd) };
+    }
 }
@@ -0,0 +1,488 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filters the IPv4 packets exchanged by a network device with its tap device, by protocol,
+//! remote address and port, like a stateless firewall.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_TCP};
+
+const PROTOCOL_ICMP: u8 = 0x01;
+const PROTOCOL_UDP: u8 = 0x11;
+// Offset of the destination port in the TCP and UDP headers.
+const DST_PORT_OFFSET: usize = 2;
+
+/// What happens to the packets matched by a rule.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FilterAction {
+    /// The packet goes through.
+    Allow,
+    /// The packet is dropped.
+    Deny,
+}
+
+/// The protocols carried by IPv4 packets which the rules can match.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FilterProtocol {
+    /// Transmission Control Protocol.
+    Tcp,
+    /// User Datagram Protocol.
+    Udp,
+    /// Internet Control Message Protocol.
+    Icmp,
+}
+
+impl FilterProtocol {
+    fn number(self) -> u8 {
+        match self {
+            FilterProtocol::Tcp => PROTOCOL_TCP,
+            FilterProtocol::Udp => PROTOCOL_UDP,
+            FilterProtocol::Icmp => PROTOCOL_ICMP,
+        }
+    }
+}
+
+/// A range of TCP or UDP ports, bounds included.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortRange {
+    /// The first port of the range.
+    pub start: u16,
+    /// The last port of the range.
+    pub end: u16,
+}
+
+/// A block of IPv4 addresses, written as `<address>/<prefix length>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv4Cidr {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Creates the block of the addresses sharing the first `prefix_len` bits of `addr`, which
+    /// must be at most 32.
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Ipv4Cidr> {
+        if prefix_len > 32 {
+            return None;
+        }
+        Some(Ipv4Cidr { addr, prefix_len })
+    }
+
+    fn mask(self) -> u32 {
+        // Shifting by 32 bits overflows, so the empty prefix is handled on its own.
+        if self.prefix_len == 0 {
+            0
+        } else {
+            !0u32 << (32 - u32::from(self.prefix_len))
+        }
+    }
+
+    /// Checks whether `addr` belongs to the block.
+    pub fn contains(self, addr: Ipv4Addr) -> bool {
+        (u32::from(self.addr) ^ u32::from(addr)) & self.mask() == 0
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Ipv4Cidr, ()> {
+        let mut parts = s.splitn(2, '/');
+        let addr = parts.next().and_then(|addr| addr.parse().ok()).ok_or(())?;
+        // A single address is a block of its own.
+        let prefix_len = match parts.next() {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| ())?,
+            None => 32,
+        };
+        Ipv4Cidr::new(addr, prefix_len).ok_or(())
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for Ipv4Cidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Ipv4Cidr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| D::Error::custom("The provided IPv4 CIDR block is invalid."))
+    }
+}
+
+/// A rule of a `PacketFilter`, which matches the IPv4 packets meeting all its conditions.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRule {
+    /// What happens to the matched packets.
+    pub action: FilterAction,
+    /// The protocol of the matched packets. Any protocol if missing.
+    pub protocol: Option<FilterProtocol>,
+    /// The addresses of the remote hosts of the matched packets, which are their source for the
+    /// ingress packets and their destination for the egress ones. Any address if missing.
+    pub cidr: Option<Ipv4Cidr>,
+    /// The destination ports of the matched packets, which requires the TCP or UDP protocol. Any
+    /// port if missing. The packets whose port cannot be read, such as the fragments other than
+    /// the first one, match the rules denying ports, and never the rules allowing them.
+    pub ports: Option<PortRange>,
+}
+
+impl FilterRule {
+    fn matches(&self, protocol: u8, remote_addr: Ipv4Addr, dst_port: Option<u16>) -> bool {
+        if let Some(rule_protocol) = self.protocol {
+            if rule_protocol.number() != protocol {
+                return false;
+            }
+        }
+        if let Some(cidr) = self.cidr {
+            if !cidr.contains(remote_addr) {
+                return false;
+            }
+        }
+        match (self.ports, dst_port) {
+            (Some(ports), Some(port)) => port >= ports.start && port <= ports.end,
+            // Otherwise, a packet could get past a denied port by being fragmented.
+            (Some(_), None) => self.action == FilterAction::Deny,
+            (None, _) => true,
+        }
+    }
+}
+
+/// The direction of the packets going through a `PacketFilter`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterDirection {
+    /// The packets received from the tap device, for the guest.
+    Ingress,
+    /// The packets sent by the guest to the tap device.
+    Egress,
+}
+
+/// A list of rules for the packets going one way, evaluated in order. The first rule matching a
+/// packet decides its fate, and the packets matched by no rule get the default policy.
+///
+/// The ARP frames always go through, while the frames of protocols other than IPv4 get the
+/// default policy.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PacketFilter {
+    /// What happens to the packets matched by no rule.
+    pub default_policy: FilterAction,
+    /// The rules, evaluated in order.
+    #[serde(default)]
+    pub rules: Vec<FilterRule>,
+}
+
+impl PacketFilter {
+    /// Checks that the port ranges are not empty, and that they come with the TCP or UDP
+    /// protocol.
+    pub fn is_valid(&self) -> bool {
+        self.rules.iter().all(|rule| match rule.ports {
+            Some(ports) => {
+                ports.start <= ports.end
+                    && (rule.protocol == Some(FilterProtocol::Tcp)
+                        || rule.protocol == Some(FilterProtocol::Udp))
+            }
+            None => true,
+        })
+    }
+
+    /// Checks whether the Ethernet `frame`, without its VNET header, goes through the filter.
+    pub fn allows(&self, frame: &[u8], direction: FilterDirection) -> bool {
+        let action = match EthernetFrame::from_bytes(frame) {
+            Ok(ref eth_frame) if eth_frame.ethertype() == ETHERTYPE_ARP => FilterAction::Allow,
+            Ok(ref eth_frame) if eth_frame.ethertype() == ETHERTYPE_IPV4 => {
+                self.ipv4_action(eth_frame.payload(), direction)
+            }
+            _ => self.default_policy,
+        };
+        action == FilterAction::Allow
+    }
+
+    fn ipv4_action(&self, payload: &[u8], direction: FilterDirection) -> FilterAction {
+        let packet = match IPv4Packet::from_header_bytes(payload) {
+            Ok(packet) => packet,
+            Err(_) => return self.default_policy,
+        };
+        let header_len = packet.header_len();
+
+        let protocol = packet.protocol();
+        let remote_addr = match direction {
+            FilterDirection::Ingress => packet.source_address(),
+            FilterDirection::Egress => packet.destination_address(),
+        };
+        // Only the first fragment of a packet holds its ports.
+        let (_, fragment_offset) = packet.flags_and_fragment_offset();
+        let dst_port = if (protocol == PROTOCOL_TCP || protocol == PROTOCOL_UDP)
+            && fragment_offset == 0
+            && payload.len() >= header_len + DST_PORT_OFFSET + 2
+        {
+            let offset = header_len + DST_PORT_OFFSET;
+            Some(u16::from_be_bytes([payload[offset], payload[offset + 1]]))
+        } else {
+            None
+        };
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(protocol, remote_addr, dst_port))
+            .map_or(self.default_policy, |rule| rule.action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use dumbo::pdu::ipv4::IPV4_MIN_HEADER_LEN;
+    use net_util::MacAddr;
+
+    // Writes an Ethernet frame holding an IPv4 packet from `src` to `dst`, with the given
+    // protocol, and the given destination port if it is TCP or UDP.
+    fn ipv4_frame(protocol: u8, src: Ipv4Addr, dst: Ipv4Addr, dst_port: u16) -> Vec<u8> {
+        let mut buf = vec![0u8; 14 + IPV4_MIN_HEADER_LEN + 8];
+        {
+            let mut eth_frame = EthernetFrame::write_incomplete(
+                buf.as_mut_slice(),
+                MacAddr::parse_str("12:34:56:78:9a:bc").unwrap(),
+                MacAddr::parse_str("12:34:56:78:9a:bd").unwrap(),
+                ETHERTYPE_IPV4,
+            )
+            .ok()
+            .unwrap();
+            let payload = eth_frame.inner_mut().payload_mut();
+            payload
+                [IPV4_MIN_HEADER_LEN + DST_PORT_OFFSET..IPV4_MIN_HEADER_LEN + DST_PORT_OFFSET + 2]
+                .copy_from_slice(&dst_port.to_be_bytes());
+            IPv4Packet::write_header(payload, protocol, src, dst)
+                .ok()
+                .unwrap()
+                .with_payload_len_unchecked(8, true);
+        }
+        buf
+    }
+
+    fn eth_frame(ethertype: u16) -> Vec<u8> {
+        let mut buf = vec![0u8; 60];
+        EthernetFrame::write_incomplete(
+            buf.as_mut_slice(),
+            MacAddr::parse_str("12:34:56:78:9a:bc").unwrap(),
+            MacAddr::parse_str("12:34:56:78:9a:bd").unwrap(),
+            ethertype,
+        )
+        .ok()
+        .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_ipv4_cidr() {
+        let cidr: Ipv4Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(10, 1, 2, 3)));
+        assert!(!cidr.contains(Ipv4Addr::new(10, 2, 2, 3)));
+        assert_eq!(cidr.to_string(), "10.1.0.0/16");
+
+        let cidr: Ipv4Cidr = "10.1.2.3".parse().unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(10, 1, 2, 3)));
+        assert!(!cidr.contains(Ipv4Addr::new(10, 1, 2, 4)));
+
+        let cidr: Ipv4Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(255, 255, 255, 255)));
+
+        for s in &["10.1.0.0/33", "10.1.0/16", "10.1.0.0/", "/16", ""] {
+            assert!(s.parse::<Ipv4Cidr>().is_err());
+        }
+
+        let cidr: Ipv4Cidr = serde_json::from_str("\"192.168.0.0/24\"").unwrap();
+        assert_eq!(
+            cidr,
+            Ipv4Cidr::new(Ipv4Addr::new(192, 168, 0, 0), 24).unwrap()
+        );
+        assert_eq!(serde_json::to_string(&cidr).unwrap(), "\"192.168.0.0/24\"");
+        assert!(serde_json::from_str::<Ipv4Cidr>("\"192.168.0.0/40\"").is_err());
+    }
+
+    #[test]
+    fn test_packet_filter_validation() {
+        let mut rule = FilterRule {
+            action: FilterAction::Allow,
+            protocol: Some(FilterProtocol::Tcp),
+            cidr: None,
+            ports: Some(PortRange { start: 22, end: 22 }),
+        };
+        let mut filter = PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: vec![rule.clone()],
+        };
+        assert!(filter.is_valid());
+
+        rule.ports = Some(PortRange { start: 23, end: 22 });
+        filter.rules.push(rule.clone());
+        assert!(!filter.is_valid());
+
+        rule.ports = Some(PortRange { start: 22, end: 23 });
+        rule.protocol = Some(FilterProtocol::Icmp);
+        filter.rules[1] = rule.clone();
+        assert!(!filter.is_valid());
+        rule.protocol = None;
+        filter.rules[1] = rule;
+        assert!(!filter.is_valid());
+    }
+
+    #[test]
+    fn test_packet_filter() {
+        let guest = Ipv4Addr::new(172, 16, 0, 2);
+        let admin = Ipv4Addr::new(10, 0, 0, 1);
+        let other = Ipv4Addr::new(192, 168, 0, 1);
+
+        // SSH from the admin network, DNS anywhere, no ICMP and nothing else.
+        let filter: PacketFilter = serde_json::from_str(
+            r#"{
+                "default_policy": "Deny",
+                "rules": [
+                    {
+                        "action": "Allow",
+                        "protocol": "Tcp",
+                        "cidr": "10.0.0.0/8",
+                        "ports": {"start": 22, "end": 22}
+                    },
+                    {
+                        "action": "Allow",
+                        "protocol": "Udp",
+                        "ports": {"start": 53, "end": 53}
+                    },
+                    {
+                        "action": "Deny",
+                        "protocol": "Icmp"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(filter.is_valid());
+
+        let ssh_in = ipv4_frame(PROTOCOL_TCP, admin, guest, 22);
+        assert!(filter.allows(&ssh_in, FilterDirection::Ingress));
+        // The remote address of the egress packets is their destination.
+        assert!(!filter.allows(&ssh_in, FilterDirection::Egress));
+        let ssh_out = ipv4_frame(PROTOCOL_TCP, guest, admin, 22);
+        assert!(filter.allows(&ssh_out, FilterDirection::Egress));
+        assert!(!filter.allows(
+            &ipv4_frame(PROTOCOL_TCP, other, guest, 22),
+            FilterDirection::Ingress
+        ));
+        assert!(!filter.allows(
+            &ipv4_frame(PROTOCOL_TCP, admin, guest, 23),
+            FilterDirection::Ingress
+        ));
+        assert!(!filter.allows(
+            &ipv4_frame(PROTOCOL_UDP, admin, guest, 22),
+            FilterDirection::Ingress
+        ));
+        assert!(filter.allows(
+            &ipv4_frame(PROTOCOL_UDP, other, guest, 53),
+            FilterDirection::Ingress
+        ));
+        assert!(!filter.allows(
+            &ipv4_frame(PROTOCOL_ICMP, admin, guest, 0),
+            FilterDirection::Ingress
+        ));
+
+        // The rules allowing ports do not match the fragments without ports.
+        let mut fragment = ipv4_frame(PROTOCOL_TCP, admin, guest, 22);
+        fragment[14 + 7] = 1;
+        assert!(!filter.allows(&fragment, FilterDirection::Ingress));
+
+        // The ARP frames go through, the other protocols and the malformed packets get the
+        // default policy.
+        assert!(filter.allows(&eth_frame(ETHERTYPE_ARP), FilterDirection::Ingress));
+        assert!(!filter.allows(&eth_frame(0x86dd), FilterDirection::Ingress));
+        assert!(!filter.allows(&eth_frame(ETHERTYPE_IPV4), FilterDirection::Ingress));
+        assert!(!filter.allows(&[0u8; 13], FilterDirection::Ingress));
+        let mut truncated = ipv4_frame(PROTOCOL_TCP, admin, guest, 22);
+        truncated.truncate(14 + IPV4_MIN_HEADER_LEN - 1);
+        assert!(!filter.allows(&truncated, FilterDirection::Ingress));
+
+        // The first matching rule wins.
+        let filter = PacketFilter {
+            default_policy: FilterAction::Allow,
+            rules: vec![
+                FilterRule {
+                    action: FilterAction::Allow,
+                    protocol: None,
+                    cidr: Some("10.0.0.1".parse().unwrap()),
+                    ports: None,
+                },
+                FilterRule {
+                    action: FilterAction::Deny,
+                    protocol: None,
+                    cidr: Some("10.0.0.0/8".parse().unwrap()),
+                    ports: None,
+                },
+            ],
+        };
+        assert!(filter.allows(&ssh_in, FilterDirection::Ingress));
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        assert!(!filter.allows(
+            &ipv4_frame(PROTOCOL_UDP, ip, guest, 53),
+            FilterDirection::Ingress
+        ));
+        assert!(filter.allows(&eth_frame(0x86dd), FilterDirection::Ingress));
+        // Without its ports, the packet is still matched by its address.
+        assert!(filter.allows(
+            &ssh_in[..14 + IPV4_MIN_HEADER_LEN + 1],
+            FilterDirection::Ingress
+        ));
+
+        // The rules denying ports match the packets without ports.
+        let filter = PacketFilter {
+            default_policy: FilterAction::Allow,
+            rules: vec![FilterRule {
+                action: FilterAction::Deny,
+                protocol: Some(FilterProtocol::Tcp),
+                cidr: None,
+                ports: Some(PortRange { start: 22, end: 22 }),
+            }],
+        };
+        assert!(!filter.allows(&ssh_in, FilterDirection::Ingress));
+        let http_in = ipv4_frame(PROTOCOL_TCP, admin, guest, 80);
+        assert!(filter.allows(&http_in, FilterDirection::Ingress));
+        // A fragment other than the first one, even of a packet to an allowed port.
+        let mut fragment = http_in.clone();
+        fragment[14 + 7] = 1;
+        assert!(!filter.allows(&fragment, FilterDirection::Ingress));
+        // A first fragment too short to hold the ports.
+        let mut fragment = ssh_in.clone();
+        fragment[14 + 6] = 0x20;
+        assert!(!filter.allows(
+            &fragment[..14 + IPV4_MIN_HEADER_LEN + 1],
+            FilterDirection::Ingress
+        ));
+        // The fragments of the other protocols are not matched.
+        let mut fragment = ipv4_frame(PROTOCOL_UDP, admin, guest, 22);
+        fragment[14 + 7] = 1;
+        assert!(filter.allows(&fragment, FilterDirection::Ingress));
+    }
+}
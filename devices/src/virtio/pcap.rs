@@ -0,0 +1,173 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writes the frames of a network device to a file in the pcap format, which tools such as
+//! `tcpdump` and `wireshark` read. The file is rotated by size, like the log files.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use logger::rotate_files;
+
+// The magic number of the pcap files with timestamps in microseconds.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+// The largest frame captured, which is larger than any frame of a network device.
+const PCAP_SNAPLEN: u32 = 65535;
+// The link type of Ethernet frames.
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+// Size of the header at the start of the file.
+const PCAP_FILE_HEADER_LEN: u64 = 24;
+// Size of the header before each frame.
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+
+/// Writes Ethernet frames to a pcap file, which is rotated before it grows over `max_size`
+/// bytes. The rotated files are named after it, with the `.1` suffix for the latest one, up to
+/// `.<max_files>`.
+#[derive(Debug)]
+pub struct PcapWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl PcapWriter {
+    /// Creates the pcap file at `path`, replacing any file with the same name.
+    pub fn new(path: &str, max_size: u64, max_files: usize) -> io::Result<PcapWriter> {
+        let path = PathBuf::from(path);
+        let file = File::create(&path)?;
+        let mut writer = PcapWriter {
+            path,
+            file,
+            size: 0,
+            max_size,
+            max_files,
+        };
+        writer.write_file_header()?;
+        Ok(writer)
+    }
+
+    /// Writes `frame`, which starts with its Ethernet header, timestamped with the current time.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let captured_len = frame.len().min(PCAP_SNAPLEN as usize);
+        let record_len = PCAP_RECORD_HEADER_LEN + captured_len as u64;
+        if self.size > PCAP_FILE_HEADER_LEN && self.size + record_len > self.max_size {
+            self.rotate()?;
+        }
+
+        // The clock is not expected to go back before the epoch.
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(captured_len as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame[..captured_len]);
+        self.file.write_all(&record)?;
+        self.size += record_len;
+        Ok(())
+    }
+
+    fn write_file_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(PCAP_FILE_HEADER_LEN as usize);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        // The timestamps are in UTC, and their accuracy is not known.
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        self.file.write_all(&header)?;
+        self.size = PCAP_FILE_HEADER_LEN;
+        Ok(())
+    }
+
+    // Every file starts with its own header, including the file emptied when no rotated file is
+    // kept.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+            self.file.seek(SeekFrom::Start(0))?;
+        } else {
+            rotate_files(&self.path, self.max_files)?;
+            self.file = File::create(&self.path)?;
+        }
+        self.write_file_header()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::TempDir;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_pcap_writer() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.pcap");
+        let read = |suffix: &str| {
+            let mut rotated_path = path.clone().into_os_string();
+            rotated_path.push(suffix);
+            fs::read(rotated_path).ok()
+        };
+        let frame_len = |buf: &[u8], offset: usize| {
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&buf[offset + 8..offset + 12]);
+            u32::from_le_bytes(len) as usize
+        };
+
+        // The file holds the header and two records of 10 bytes frames.
+        let max_size = PCAP_FILE_HEADER_LEN + 2 * (PCAP_RECORD_HEADER_LEN + 10);
+        let mut writer = PcapWriter::new(path.to_str().unwrap(), max_size, 1).unwrap();
+        let header = read("").unwrap();
+        assert_eq!(header.len() as u64, PCAP_FILE_HEADER_LEN);
+        assert_eq!(header[..4], PCAP_MAGIC.to_le_bytes());
+        assert_eq!(header[20..], PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+
+        for len in 1..=4 {
+            writer.write_frame(&[len as u8; 10]).unwrap();
+        }
+        let current = read("").unwrap();
+        let rotated = read(".1").unwrap();
+        assert_eq!(current.len() as u64, max_size);
+        assert_eq!(rotated.len() as u64, max_size);
+        let first_record = PCAP_FILE_HEADER_LEN as usize;
+        assert_eq!(frame_len(&current, first_record), 10);
+        assert_eq!(current[first_record + PCAP_RECORD_HEADER_LEN as usize], 3);
+        assert_eq!(rotated[first_record + PCAP_RECORD_HEADER_LEN as usize], 1);
+        assert!(read(".2").is_none());
+
+        // A frame larger than the maximum size still goes to a file of its own.
+        writer.write_frame(&[5u8; 100]).unwrap();
+        let current = read("").unwrap();
+        assert_eq!(
+            current.len() as u64,
+            PCAP_FILE_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 100
+        );
+        assert_eq!(frame_len(&current, first_record), 100);
+
+        // Without rotated files, the file starts over.
+        let mut writer = PcapWriter::new(path.to_str().unwrap(), max_size, 0).unwrap();
+        for len in 1..=3 {
+            writer.write_frame(&[len as u8; 10]).unwrap();
+        }
+        let current = read("").unwrap();
+        assert_eq!(
+            current.len() as u64,
+            PCAP_FILE_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 10
+        );
+        assert_eq!(current[..4], PCAP_MAGIC.to_le_bytes());
+        assert_eq!(current[first_record + PCAP_RECORD_HEADER_LEN as usize], 3);
+    }
+}
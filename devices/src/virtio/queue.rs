@@ -142,6 +142,25 @@ impl<'a> DescriptorChain<'a> {
     }
 }
 
+/// The guest-programmed parameters of a virtio queue, as saved in a microVM snapshot.
+///
+/// The ring positions are not part of the state: the device model completes every descriptor
+/// chain it pops before going back to the event loop, so they are recovered from the used ring
+/// in guest memory when restoring.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueueState {
+    /// The queue size in elements the driver selected.
+    pub size: u16,
+    /// Whether the driver finished configuring the queue.
+    pub ready: bool,
+    /// Guest physical address of the descriptor table.
+    pub desc_table: u64,
+    /// Guest physical address of the available ring.
+    pub avail_ring: u64,
+    /// Guest physical address of the used ring.
+    pub used_ring: u64,
+}
+
 #[derive(Clone)]
 /// A virtio queue's parameters.
 pub struct Queue {
@@ -182,6 +201,37 @@ impl Queue {
         }
     }
 
+    /// Returns the guest-programmed parameters of this queue.
+    pub fn state(&self) -> QueueState {
+        QueueState {
+            size: self.size,
+            ready: self.ready,
+            desc_table: self.desc_table.offset() as u64,
+            avail_ring: self.avail_ring.offset() as u64,
+            used_ring: self.used_ring.offset() as u64,
+        }
+    }
+
+    /// Restores the parameters saved by `state()` and resumes processing from the used ring
+    /// index found in `mem`.
+    pub fn restore_state(&mut self, state: &QueueState, mem: &GuestMemory) {
+        self.size = state.size;
+        self.ready = state.ready;
+        self.desc_table = GuestAddress(state.desc_table as usize);
+        self.avail_ring = GuestAddress(state.avail_ring as usize);
+        self.used_ring = GuestAddress(state.used_ring as usize);
+
+        let used_idx = if self.ready && self.is_valid(mem) {
+            // Safe to unwrap because `is_valid()` checked that the used ring is in bounds.
+            mem.read_obj_from_addr::<u16>(self.used_ring.unchecked_add(2))
+                .unwrap()
+        } else {
+            0
+        };
+        self.next_avail = Wrapping(used_idx);
+        self.next_used = Wrapping(used_idx);
+    }
+
     pub fn get_max_size(&self) -> u16 {
         self.max_size
     }
@@ -799,4 +849,37 @@ pub mod tests {
         assert_eq!(x.id, 1);
         assert_eq!(x.len, 0x1000);
     }
+
+    #[test]
+    fn test_queue_state() {
+        let m = &GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+
+        // Complete two descriptor chains so that the used ring index moves forward.
+        q.add_used(m, 0, 0x1000);
+        q.add_used(m, 1, 0x1000);
+        assert_eq!(vq.used.idx.get(), 2);
+
+        let state = q.state();
+        assert_eq!(state.size, 16);
+        assert!(state.ready);
+        assert_eq!(state.desc_table, vq.dtable_start().offset() as u64);
+        assert_eq!(state.avail_ring, vq.avail_start().offset() as u64);
+        assert_eq!(state.used_ring, vq.used_start().offset() as u64);
+
+        // The restored queue picks up where the used ring left off.
+        let mut restored = Queue::new(16);
+        restored.restore_state(&state, m);
+        assert_eq!(restored.state(), state);
+        assert_eq!(restored.next_avail, Wrapping(2));
+        assert_eq!(restored.next_used, Wrapping(2));
+
+        // A queue which was never set up by the driver starts from scratch.
+        let mut state = Queue::new(16).state();
+        state.used_ring = vq.used_start().offset() as u64;
+        restored.restore_state(&state, m);
+        assert!(!restored.ready);
+        assert_eq!(restored.next_used, Wrapping(0));
+    }
 }
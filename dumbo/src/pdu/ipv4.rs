@@ -27,6 +27,9 @@ const SOURCE_ADDRESS_OFFSET: usize = 12;
 const DESTINATION_ADDRESS_OFFSET: usize = 16;
 const OPTIONS_OFFSET: usize = 20;
 
+/// The length of an IPv4 header without options.
+pub const IPV4_MIN_HEADER_LEN: usize = OPTIONS_OFFSET;
+
 const IPV4_VERSION: u8 = 0x04;
 const DEFAULT_TTL: u8 = 200;
 
@@ -111,6 +114,29 @@ impl<'a, T: NetworkBytes> IPv4Packet<'a, T> {
         Ok(packet)
     }
 
+    /// Attempts to interpret `bytes` as an IPv4 packet, checking only the `version` and `ihl`
+    /// header fields. The total length is not checked, because segmentation offload packets do
+    /// not hold their final length, so this can be used for reading the header of the packets
+    /// exchanged with a tap device.
+    pub fn from_header_bytes(bytes: T) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let packet = IPv4Packet::from_bytes_unchecked(bytes);
+        let (version, header_len) = packet.version_and_header_len();
+
+        if version != IPV4_VERSION {
+            return Err(Error::Version);
+        }
+
+        if header_len < OPTIONS_OFFSET {
+            return Err(Error::HeaderLen);
+        }
+
+        Ok(packet)
+    }
+
     /// Returns the value of the `version` header field, and the header length.
     ///
     /// This method returns the actual length (in bytes) of the header, and not the value of the
@@ -594,10 +620,18 @@ mod tests {
         // Invalid version.
         p(buf.as_mut()).set_version_and_header_len(IPV4_VERSION + 1, header_len);
         look_for_error(buf.as_ref(), Error::Version);
+        assert_eq!(
+            IPv4Packet::from_header_bytes(buf.as_ref()).unwrap_err(),
+            Error::Version
+        );
 
         // Short header length.
         p(buf.as_mut()).set_version_and_header_len(IPV4_VERSION, OPTIONS_OFFSET - 1);
         look_for_error(buf.as_ref(), Error::HeaderLen);
+        assert_eq!(
+            IPv4Packet::from_header_bytes(buf.as_ref()).unwrap_err(),
+            Error::HeaderLen
+        );
 
         // Header length too large. We have to add at least 4 here, because the setter converts
         // header_len into the ihl field via division by 4, so anything less will lead to a valid
@@ -612,6 +646,8 @@ mod tests {
             .set_version_and_header_len(IPV4_VERSION, OPTIONS_OFFSET)
             .set_total_len(OPTIONS_OFFSET as u16 - 1);
         look_for_error(buf.as_ref(), Error::InvalidTotalLen);
+        // The total length is not checked when only the header is read.
+        assert!(IPv4Packet::from_header_bytes(buf.as_ref()).is_ok());
 
         // Total len not matching slice length.
         p(buf.as_mut()).set_total_len(buf_len as u16 - 1);
@@ -634,6 +670,10 @@ mod tests {
         let mut small_buf = [0u8; 1];
 
         look_for_error(small_buf.as_ref(), Error::SliceTooShort);
+        assert_eq!(
+            IPv4Packet::from_header_bytes(small_buf.as_ref()).unwrap_err(),
+            Error::SliceTooShort
+        );
 
         assert_eq!(
             IPv4Packet::write_header(small_buf.as_mut(), PROTOCOL_TCP, src, dst).unwrap_err(),
@@ -22,6 +22,10 @@ pub enum LoggerError {
     InvalidLogOption(String),
     /// Opening named pipe fails.
     OpenFIFO(std::io::Error),
+    /// Opening the log file fails.
+    OpenFile(std::io::Error),
+    /// Connecting to the syslog socket fails.
+    ConnectSyslog(std::io::Error),
     /// Writing to named pipe fails.
     LogWrite(std::io::Error),
     /// Flushing to disk fails.
@@ -53,6 +57,10 @@ impl fmt::Display for LoggerError {
             LoggerError::OpenFIFO(ref e) => {
                 format!("Failed to open pipe. Error: {}", e.description())
             }
+            LoggerError::OpenFile(ref e) => format!("Failed to open file. Error: {}", e),
+            LoggerError::ConnectSyslog(ref e) => {
+                format!("Failed to connect to the syslog socket. Error: {}", e)
+            }
             LoggerError::LogWrite(ref e) => {
                 format!("Failed to write logs. Error: {}", e.description())
             }
@@ -103,6 +111,21 @@ mod tests {
             "The logger is initializing. Can't perform the requested action right now."
         );
 
+        assert_eq!(
+            format!(
+                "{}",
+                LoggerError::OpenFile(std::io::Error::new(ErrorKind::NotFound, "open"))
+            ),
+            "Failed to open file. Error: open"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                LoggerError::ConnectSyslog(std::io::Error::new(ErrorKind::NotFound, "connect"))
+            ),
+            "Failed to connect to the syslog socket. Error: connect"
+        );
+
         assert!(format!(
             "{:?}",
             LoggerError::LogWrite(std::io::Error::new(ErrorKind::Interrupted, "write"))
@@ -2,9 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 #![deny(missing_docs)]
-//! Utility for sending log related messages and metrics to two different named pipes (FIFO) or
-//! simply to stdout/stderr. The logging destination is specified upon the initialization of the
-//! logging system.
+//! Utility for sending log related messages and metrics to two different destinations, such as
+//! named pipes (FIFO), or simply to stdout/stderr. The logging destination is specified upon the
+//! initialization of the logging system.
 //!
 //! # Enabling logging
 //! There are 2 ways to enable the logging functionality:
@@ -14,10 +14,14 @@
 //! The logger can be preinitialized any number of times before calling `LOGGER.init()`.
 //!
 //! 2) Calling `LOGGER.init()`. This will enable the logger to work in full mode.
-//! In this mode the logger can write both messages and metrics to pipes.
+//! In this mode the logger can write both messages and metrics to their destinations.
 //! The logger can be initialized only once. Any call to the `LOGGER.init()` following that will
 //! fail with an explicit error.
 //!
+//! The messages and the metrics can be written to named pipes, to regular files rotated by size,
+//! to Unix datagram sockets which receive syslog messages or to ring buffers in memory, which are
+//! read through `LOGGER.buffered_logs()` and `LOGGER.buffered_metrics()`.
+//!
 //! ## Example for logging to stdout/stderr
 //!
 //! ```
@@ -49,7 +53,7 @@
 //!
 //! #[macro_use]
 //! extern crate logger;
-//! use logger::{AppInfo, LOGGER};
+//! use logger::{AppInfo, LogDestination, LOGGER};
 //!
 //! fn main() {
 //!     let log_file_temp =
@@ -69,8 +73,8 @@
 //!     assert!(LOGGER.deref().init(
 //!                 &AppInfo::new("Firecracker", "1.0"),
 //!                 "MY-INSTANCE",
-//!                 logs,
-//!                 metrics,
+//!                 &LogDestination::Fifo(logs),
+//!                 &LogDestination::Fifo(metrics),
 //!                 &vec![]
 //!             ).is_ok());
 //!     // The following messages should appear in the `log_file_temp` file.
@@ -133,8 +137,10 @@
 //! In order to not block the instance if nobody is consuming the logs that are flushed to the two
 //! pipes, we are opening them with `O_NONBLOCK` flag. In this case, writing to a pipe will
 //! start failing when reaching 64K of unconsumed content. Simultaneously, the `missed_metrics_count`
-//! metric will get increased.
-//! Metrics are only logged to pipes. Logs can be flushed either to stdout/stderr or to a pipe.
+//! metric will get increased. Likewise, the syslog socket is non-blocking, and the messages which
+//! cannot be written to a file or a socket are dropped and counted as missed.
+//! Metrics are only logged to the destination given upon initialization. Logs can be flushed
+//! either to stdout/stderr or to their destination.
 
 extern crate chrono;
 // workaround to macro_reexport
@@ -150,6 +156,7 @@ extern crate time;
 
 pub mod error;
 pub mod metrics;
+pub mod openmetrics;
 mod writers;
 
 use std::error::Error;
@@ -166,8 +173,8 @@ use error::LoggerError;
 pub use log::Level::*;
 pub use log::*;
 use log::{set_logger, set_max_level, Log, Metadata, Record};
-pub use metrics::{Metric, METRICS};
-use writers::*;
+pub use metrics::{DeviceMetrics, LatencyHistogram, Metric, StoreMetric, METRICS};
+pub use writers::{open_writer, rotate_files, LogDestination, LogWriter};
 
 /// Type for returning functions outcome.
 ///
@@ -208,7 +215,34 @@ lazy_static! {
 enum Destination {
     Stderr,
     Stdout,
-    Pipe,
+    Writer,
+}
+
+/// Enum representing the formats of the log lines.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(usize)]
+pub enum LogFormat {
+    /// Plain text lines, made of the timestamp, a tag and the message.
+    Text,
+    /// One JSON object per line, with the timestamp, the level, the instance ID, the module, the
+    /// file, the line number and the message as separate fields.
+    Json,
+}
+
+// A log line in the JSON format.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: String,
+    instance_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    message: String,
 }
 
 /// Enum representing logging options that can be activated from the API.
@@ -285,11 +319,12 @@ pub struct Logger {
     show_level: AtomicBool,
     show_file_path: AtomicBool,
     show_line_numbers: AtomicBool,
+    format: AtomicUsize,
     level_info: LevelInfo,
-    // Used in case we want to send logs to a FIFO.
-    log_fifo: Mutex<Option<PipeLogWriter>>,
-    // Used in case we want to send metrics to a FIFO.
-    metrics_fifo: Mutex<Option<PipeLogWriter>>,
+    // Used in case we want to send logs to a FIFO or another destination.
+    log_writer: Mutex<Option<Box<dyn LogWriter>>>,
+    // Used in case we want to send metrics to a FIFO or another destination.
+    metrics_writer: Mutex<Option<Box<dyn LogWriter>>>,
     instance_id: RwLock<String>,
     flags: AtomicUsize,
 }
@@ -305,16 +340,6 @@ fn get_default_destination(level: Level) -> Destination {
     }
 }
 
-// Auxiliary function to flush a message to a PipeLogWriter.
-// This is used by the internal logger to either flush human-readable logs or metrics.
-fn log_to_fifo(mut msg: String, fifo_writer: &mut PipeLogWriter) -> Result<()> {
-    msg = format!("{}\n", msg);
-    fifo_writer.write(&msg)?;
-    // No need to call flush here since the write will handle the flush on its own given that
-    // our messages always has a newline.
-    Ok(())
-}
-
 impl Logger {
     // Creates a new instance of the current logger.
     //
@@ -324,13 +349,14 @@ impl Logger {
             show_level: AtomicBool::new(true),
             show_line_numbers: AtomicBool::new(true),
             show_file_path: AtomicBool::new(true),
+            format: AtomicUsize::new(LogFormat::Text as usize),
             level_info: LevelInfo {
                 // DEFAULT_LEVEL is warn so the destination output is stderr.
                 code: AtomicUsize::new(DEFAULT_LEVEL as usize),
                 writer: AtomicUsize::new(Destination::Stderr as usize),
             },
-            log_fifo: Mutex::new(None),
-            metrics_fifo: Mutex::new(None),
+            log_writer: Mutex::new(None),
+            metrics_writer: Mutex::new(None),
             instance_id: RwLock::new(String::new()),
             flags: AtomicUsize::new(0),
         }
@@ -348,6 +374,10 @@ impl Logger {
         self.show_line_numbers.load(Ordering::Relaxed)
     }
 
+    fn json_format(&self) -> bool {
+        self.format.load(Ordering::Relaxed) == LogFormat::Json as usize
+    }
+
     /// Enables or disables including the level in the log message's tag portion.
     ///
     /// # Arguments
@@ -416,6 +446,39 @@ impl Logger {
             .store(file_path && line_numbers, Ordering::Relaxed);
     }
 
+    /// Sets the format of the log lines. The default format is `Text`. With `Json`, each log line
+    /// is a JSON object which always holds the level and the origin of the message, whatever
+    /// `set_include_level` and `set_include_origin` say.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the log lines.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate log;
+    /// extern crate logger;
+    /// use logger::{LogFormat, LOGGER};
+    /// use std::ops::Deref;
+    ///
+    /// fn main() {
+    ///     let l = LOGGER.deref();
+    ///     l.set_format(LogFormat::Json);
+    ///     assert!(l.preinit(Some("MY-INSTANCE".to_string())).is_ok());
+    ///     warn!("A warning log message in [JSON]");
+    /// }
+    /// ```
+    /// The code above will more or less print:
+    /// ```bash
+    /// {"timestamp":"2018-11-07T05:34:25.180751152","level":"WARN","instance_id":"MY-INSTANCE",
+    /// "module":"main","file":"src/main.rs","line":11,"message":"A warning log message in [JSON]"}
+    /// ```
+    pub fn set_format(&self, format: LogFormat) {
+        self.format.store(format as usize, Ordering::Relaxed);
+    }
+
     /// Explicitly sets the log level for the Logger.
     /// User needs to say the level code(error, warn...) and the output destination will be
     /// updated if and only if the logger was not initialized to log to a FIFO.
@@ -449,7 +512,7 @@ impl Logger {
     /// ```
     pub fn set_level(&self, level: Level) {
         self.level_info.set_code(level);
-        if self.level_info.writer() != Destination::Pipe as usize {
+        if self.level_info.writer() != Destination::Writer as usize {
             self.level_info.set_writer(get_default_destination(level));
         }
     }
@@ -460,6 +523,21 @@ impl Logger {
         self.flags.load(Ordering::Relaxed)
     }
 
+    /// Turns a logging option on or off, after the logger was initialized.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The option to turn on or off.
+    /// * `enabled` - Whether the option is on.
+    ///
+    pub fn set_option(&self, option: LogOption, enabled: bool) {
+        if enabled {
+            self.flags.fetch_or(option as usize, Ordering::SeqCst);
+        } else {
+            self.flags.fetch_and(!(option as usize), Ordering::SeqCst);
+        }
+    }
+
     /// Creates the first portion (to the left of the separator)
     /// of the log statement based on the logger settings.
     ///
@@ -499,8 +577,31 @@ impl Logger {
         res
     }
 
-    fn log_fifo_guard(&self) -> MutexGuard<Option<PipeLogWriter>> {
-        match self.log_fifo.lock() {
+    /// Formats a log statement as a JSON object, with the origin taken from `record` if any.
+    ///
+    fn format_json(&self, level: Level, record: Option<&Record>, message: String) -> String {
+        // It's safe to unwrap here, because `instance_id` is only written to
+        // during log initialization, so there aren't any writers that could
+        // poison the lock.
+        let id_guard = self
+            .instance_id
+            .read()
+            .expect("Failed to read instance ID due to poisoned lock");
+        let json_record = JsonRecord {
+            timestamp: Local::now().format(TIME_FMT).to_string(),
+            level: level.to_string(),
+            instance_id: id_guard.as_ref(),
+            module: record.and_then(|r| r.module_path()),
+            file: record.and_then(|r| r.file()),
+            line: record.and_then(|r| r.line()),
+            message,
+        };
+        // A struct of strings and numbers always serializes.
+        serde_json::to_string(&json_record).expect("Failed to serialize log record")
+    }
+
+    fn log_writer_guard(&self) -> MutexGuard<Option<Box<dyn LogWriter>>> {
+        match self.log_writer.lock() {
             Ok(guard) => guard,
             // If a thread panics while holding this lock, the writer within should still be usable.
             // (we might get an incomplete log line or something like that).
@@ -508,8 +609,8 @@ impl Logger {
         }
     }
 
-    fn metrics_fifo_guard(&self) -> MutexGuard<Option<PipeLogWriter>> {
-        match self.metrics_fifo.lock() {
+    fn metrics_writer_guard(&self) -> MutexGuard<Option<Box<dyn LogWriter>>> {
+        match self.metrics_writer.lock() {
             Ok(guard) => guard,
             // If a thread panics while holding this lock, the writer within should still be usable.
             // (we might get an incomplete log line or something like that).
@@ -609,23 +710,23 @@ impl Logger {
     ///
     /// * `app_info` - Info about the app that uses the logger.
     /// * `instance_id` - Unique string identifying this logger session.
-    /// * `log_pipe` - Path to a FIFO used for logging plain text.
-    /// * `metrics_pipe` - Path to a FIFO used for logging JSON formatted metrics.
+    /// * `log_destination` - Destination of the plain text logs.
+    /// * `metrics_destination` - Destination of the JSON formatted metrics.
     /// * `options` - Logger options
     ///
     /// # Example
     ///
     /// ```
     /// extern crate logger;
-    /// use logger::{AppInfo, LOGGER};
+    /// use logger::{AppInfo, LogDestination, LOGGER};
     /// use std::ops::Deref;
     ///
     /// fn main() {
     ///     LOGGER.deref().init(
     ///         &AppInfo::new("Firecracker", "1.0"),
     ///         "MY-INSTANCE",
-    ///         "/tmp/log".to_string(),
-    ///         "/tmp/metrics".to_string(),
+    ///         &LogDestination::Fifo("/tmp/log".to_string()),
+    ///         &LogDestination::RingBuffer(100),
     ///         &vec![]
     ///     );
     /// }
@@ -634,8 +735,8 @@ impl Logger {
         &self,
         app_info: &AppInfo,
         instance_id: &str,
-        log_pipe: String,
-        metrics_pipe: String,
+        log_destination: &LogDestination,
+        metrics_destination: &LogDestination,
         options: &[Value],
     ) -> Result<()> {
         self.try_lock(INITIALIZING)?;
@@ -649,31 +750,31 @@ impl Logger {
             *id_guard = instance_id.to_string();
         }
 
-        match PipeLogWriter::new(&log_pipe) {
+        match open_writer(log_destination, &app_info.name) {
             Ok(t) => {
                 // The mutex shouldn't be poisoned before init otherwise panic!.
-                let mut g = LOGGER.log_fifo_guard();
+                let mut g = LOGGER.log_writer_guard();
                 *g = Some(t);
             }
             Err(ref e) => {
                 STATE.store(UNINITIALIZED, Ordering::SeqCst);
                 return Err(LoggerError::NeverInitialized(format!(
-                    "Could not open logging fifo: {}",
+                    "Could not open logging destination: {}",
                     e
                 )));
             }
         };
 
-        match PipeLogWriter::new(&metrics_pipe) {
+        match open_writer(metrics_destination, &app_info.name) {
             Ok(t) => {
                 // The mutex shouldn't be poisoned before init otherwise panic!.
-                let mut g = LOGGER.metrics_fifo_guard();
+                let mut g = LOGGER.metrics_writer_guard();
                 *g = Some(t);
             }
             Err(ref e) => {
                 STATE.store(UNINITIALIZED, Ordering::SeqCst);
                 return Err(LoggerError::NeverInitialized(format!(
-                    "Could not open metrics fifo: {}",
+                    "Could not open metrics destination: {}",
                     e
                 )));
             }
@@ -689,11 +790,12 @@ impl Logger {
 
         set_max_level(Level::Trace.to_level_filter());
 
-        self.log_helper(
-            format!("Running {} v{}", app_info.name, app_info.version),
-            Some(Destination::Pipe),
-        );
-        LOGGER.level_info.set_writer(Destination::Pipe);
+        let mut msg = format!("Running {} v{}", app_info.name, app_info.version);
+        if self.json_format() {
+            msg = self.format_json(Level::Info, None, msg);
+        }
+        self.log_helper(msg, Level::Info, Some(Destination::Writer));
+        LOGGER.level_info.set_writer(Destination::Writer);
         STATE.store(INITIALIZED, Ordering::SeqCst);
 
         Ok(())
@@ -702,7 +804,7 @@ impl Logger {
     // In a future PR we'll update the way things are written to the selected destination to avoid
     // the creation and allocation of unnecessary intermediate Strings. The log_helper method takes
     // care of the common logic involved in both writing regular log messages, and dumping metrics.
-    fn log_helper(&self, msg: String, maybe_forced_destination: Option<Destination>) {
+    fn log_helper(&self, msg: String, level: Level, maybe_forced_destination: Option<Destination>) {
         let destination = maybe_forced_destination
             .map(|forced_destination| forced_destination as usize)
             .unwrap_or_else(|| self.level_info.writer());
@@ -710,15 +812,14 @@ impl Logger {
         // We have the awkward IF's for now because we can't use just "<enum_variant> as usize
         // on the left side of a match arm for some reason.
         match destination {
-            x if x == Destination::Pipe as usize => {
-                // Unwrap is safe cause the Destination is a Pipe.
-                if log_to_fifo(
-                    msg,
-                    self.log_fifo_guard()
-                        .as_mut()
-                        .expect("Failed to write to fifo due to poisoned lock"),
-                )
-                .is_err()
+            x if x == Destination::Writer as usize => {
+                // Unwrap is safe cause the writer is set along with the Destination.
+                if self
+                    .log_writer_guard()
+                    .as_mut()
+                    .expect("Failed to write logs due to missing writer")
+                    .write(level, &msg)
+                    .is_err()
                 {
                     // No reason to log the error to stderr here, just increment the metric.
                     METRICS.logger.missed_log_count.inc();
@@ -735,27 +836,41 @@ impl Logger {
         }
     }
 
-    /// Flushes metrics to the FIFO provided as argument upon initialization of the logger.
+    /// Returns the latest log lines, if the logs are kept in a ring buffer.
+    ///
+    pub fn buffered_logs(&self) -> Option<Vec<String>> {
+        self.log_writer_guard()
+            .as_ref()
+            .and_then(|writer| writer.lines())
+    }
+
+    /// Returns the latest flushes of the metrics, if they are kept in a ring buffer.
+    ///
+    pub fn buffered_metrics(&self) -> Option<Vec<String>> {
+        self.metrics_writer_guard()
+            .as_ref()
+            .and_then(|writer| writer.lines())
+    }
+
+    /// Flushes metrics to the destination provided as argument upon initialization of the logger.
     ///
     pub fn log_metrics(&self) -> Result<()> {
         // Check that the logger is initialized.
         if STATE.load(Ordering::Relaxed) == INITIALIZED {
             match serde_json::to_string(METRICS.deref()) {
                 Ok(msg) => {
-                    // Check that the destination is indeed a FIFO.
-                    if self.level_info.writer() == Destination::Pipe as usize {
-                        log_to_fifo(
-                            msg,
-                            self.metrics_fifo_guard()
-                                .as_mut()
-                                .expect("Failed to write to fifo due to poisoned lock"),
-                        )
-                        .map_err(|e| {
-                            METRICS.logger.missed_metrics_count.inc();
-                            e
-                        })?;
+                    // Check that the destination is indeed the writer.
+                    if self.level_info.writer() == Destination::Writer as usize {
+                        self.metrics_writer_guard()
+                            .as_mut()
+                            .expect("Failed to write metrics due to missing writer")
+                            .write(Level::Info, &msg)
+                            .map_err(|e| {
+                                METRICS.logger.missed_metrics_count.inc();
+                                e
+                            })?;
                     }
-                    // We are not logging metrics if the Destination is not a PIPE.
+                    // We are not logging metrics if the Destination is not the writer.
                     Ok(())
                 }
                 Err(e) => {
@@ -784,15 +899,19 @@ impl Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let msg = format!(
-                "{}{}{}{}",
-                Local::now().format(TIME_FMT),
-                self.create_prefix(&record),
-                MSG_SEPARATOR,
-                record.args()
-            );
+            let msg = if self.json_format() {
+                self.format_json(record.level(), Some(record), record.args().to_string())
+            } else {
+                format!(
+                    "{}{}{}{}",
+                    Local::now().format(TIME_FMT),
+                    self.create_prefix(&record),
+                    MSG_SEPARATOR,
+                    record.args()
+                )
+            };
 
-            self.log_helper(msg, None);
+            self.log_helper(msg, record.level(), None);
         }
     }
 
@@ -845,9 +964,21 @@ mod tests {
         assert_eq!(l.level_info.writer(), Destination::Stderr as usize);
         assert_eq!(l.show_line_numbers(), true);
         assert_eq!(l.show_level(), true);
+        assert!(!l.json_format());
         assert_eq!(l.flags.load(Ordering::Relaxed), 0);
     }
 
+    #[test]
+    fn test_set_option() {
+        let l = Logger::new();
+        l.set_option(LogOption::LogDirtyPages, true);
+        assert_eq!(l.flags(), LogOption::LogDirtyPages as usize);
+        l.set_option(LogOption::LogDirtyPages, true);
+        assert_eq!(l.flags(), LogOption::LogDirtyPages as usize);
+        l.set_option(LogOption::LogDirtyPages, false);
+        assert_eq!(l.flags(), 0);
+    }
+
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn test_init() {
@@ -879,7 +1010,7 @@ mod tests {
         assert_eq!(
             format!(
                 "{:?}",
-                l.init(&app_info, TEST_INSTANCE_ID, log_file.clone(), metrics_file.clone(), &[Value::Bool(true)])
+                l.init(&app_info, TEST_INSTANCE_ID, &LogDestination::Fifo(log_file.clone()), &LogDestination::Fifo(metrics_file.clone()), &[Value::Bool(true)])
                     .err()
             ),
             "Some(NeverInitialized(\"Could not set option flags: Invalid log option: Bool(true)\"))"
@@ -890,8 +1021,8 @@ mod tests {
                 l.init(
                     &app_info,
                     TEST_INSTANCE_ID,
-                    log_file.clone(),
-                    metrics_file.clone(),
+                    &LogDestination::Fifo(log_file.clone()),
+                    &LogDestination::Fifo(metrics_file.clone()),
                     &[Value::String("foobar".to_string())]
                 )
                 .err()
@@ -916,8 +1047,8 @@ mod tests {
             .init(
                 &app_info,
                 TEST_INSTANCE_ID,
-                log_file.clone(),
-                metrics_file.clone(),
+                &LogDestination::Fifo(log_file.clone()),
+                &LogDestination::Fifo(metrics_file.clone()),
                 &[Value::String("LogDirtyPages".to_string())]
             )
             .is_ok());
@@ -929,8 +1060,8 @@ mod tests {
             .init(
                 &app_info,
                 TEST_INSTANCE_ID,
-                log_file.clone(),
-                metrics_file.clone(),
+                &LogDestination::Fifo(log_file.clone()),
+                &LogDestination::Fifo(metrics_file.clone()),
                 &[]
             )
             .is_err());
@@ -967,8 +1098,8 @@ mod tests {
             .init(
                 &app_info,
                 TEST_INSTANCE_ID,
-                String::from(""),
-                metrics_file.clone(),
+                &LogDestination::Fifo(String::from("")),
+                &LogDestination::Fifo(metrics_file.clone()),
                 &[]
             )
             .is_err());
@@ -976,8 +1107,8 @@ mod tests {
         let res = l.init(
             &app_info,
             TEST_INSTANCE_ID,
-            log_file.clone(),
-            String::from(""),
+            &LogDestination::Fifo(log_file.clone()),
+            &LogDestination::Fifo(String::from("")),
             &[],
         );
         assert!(res.is_err());
@@ -1011,8 +1142,8 @@ mod tests {
                 l.init(
                     &app_info,
                     TEST_INSTANCE_ID,
-                    log_file.clone(),
-                    metrics_file.clone(),
+                    &LogDestination::Fifo(log_file.clone()),
+                    &LogDestination::Fifo(metrics_file.clone()),
                     &[]
                 )
                 .err()
@@ -1021,6 +1152,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_json_format() {
+        let l = Logger::new();
+        l.set_format(LogFormat::Json);
+        assert!(l.json_format());
+        *l.instance_id.write().unwrap() = TEST_INSTANCE_ID.to_string();
+
+        let line = l.format_json(
+            Level::Warn,
+            Some(
+                &log::Record::builder()
+                    .level(Level::Warn)
+                    .module_path(Some("logger::tests"))
+                    .file(Some("logger/src/lib.rs"))
+                    .line(Some(42))
+                    .args(format_args!("[{}] \"quoted\"", "bracketed"))
+                    .build(),
+            ),
+            String::from("[bracketed] \"quoted\""),
+        );
+        let json: Value = serde_json::from_str(&line).unwrap();
+        assert!(json["timestamp"].is_string());
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["instance_id"], TEST_INSTANCE_ID);
+        assert_eq!(json["module"], "logger::tests");
+        assert_eq!(json["file"], "logger/src/lib.rs");
+        assert_eq!(json["line"], 42);
+        assert_eq!(json["message"], "[bracketed] \"quoted\"");
+
+        // The lines which don't come from a log record have no origin.
+        let line = l.format_json(Level::Info, None, String::from("Running"));
+        let json: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert!(json.get("module").is_none());
+        assert!(json.get("file").is_none());
+        assert!(json.get("line").is_none());
+        assert_eq!(json["message"], "Running");
+    }
+
+    #[test]
+    fn test_buffered_logs() {
+        let l = Logger::new();
+        assert!(l.buffered_logs().is_none());
+        assert!(l.buffered_metrics().is_none());
+
+        *l.log_writer.lock().unwrap() =
+            Some(open_writer(&LogDestination::RingBuffer(2), "").unwrap());
+        l.level_info.set_writer(Destination::Writer);
+        for msg in &["one", "two", "three"] {
+            Logger::log(
+                &l,
+                &log::Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            );
+        }
+        let lines = l.buffered_logs().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("] two"));
+        assert!(lines[1].ends_with("] three"));
+        assert!(l.buffered_metrics().is_none());
+    }
+
     #[test]
     fn test_get_default_destination() {
         assert!(get_default_destination(log::Level::Error) == Destination::Stderr);
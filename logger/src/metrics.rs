@@ -23,12 +23,58 @@
 //!   (this could be a concern, I guess).
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
+//!
+//! Durations are counted by `LatencyHistogram`s, in buckets on a log scale, so the percentiles
+//! can be computed from them.
+//!
+//! The block and network devices also have metrics of their own, keyed by the ID of the device.
+//! Each update of the metrics of a device is applied to the totals of its kind as well. The only
+//! lock is taken when devices are added or removed, and when the metrics are flushed. Some of
+//! the metrics of the block devices are also counted per queue, so that an imbalance between the
+//! queues shows.
+//!
+//! The metrics are also rendered in the OpenMetrics text format by the `openmetrics` module,
+//! which serializes their totals instead, without resetting them. The values of the metrics are
+//! serialized as newtype structs named after their types, which tell their kind to it.
 
+use std::cell::Cell;
+use std::cmp;
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use chrono;
+use serde::ser::{SerializeMap, SerializeStruct};
 use serde::{Serialize, Serializer};
 
+thread_local! {
+    // Whether the metrics serialized by this thread are the totals, rather than the values since
+    // the previous flush.
+    static SERIALIZE_TOTALS: Cell<bool> = Cell::new(false);
+}
+
+// Resets the serialization of the totals when dropped, even if it panics.
+struct TotalsGuard;
+
+impl Drop for TotalsGuard {
+    fn drop(&mut self) {
+        SERIALIZE_TOTALS.with(|totals| totals.set(false));
+    }
+}
+
+/// Runs `f`, during which the metrics serialized by the current thread are the totals rather
+/// than the values since the previous flush, and are not reset.
+pub(crate) fn with_totals<T, F: FnOnce() -> T>(f: F) -> T {
+    SERIALIZE_TOTALS.with(|totals| totals.set(true));
+    let _guard = TotalsGuard;
+    f()
+}
+
+fn serialize_totals() -> bool {
+    SERIALIZE_TOTALS.with(|totals| totals.get())
+}
+
 /// Used for defining new types of metrics that can be either incremented with an unit
 /// or an arbitrary amount of units.
 // This trait helps with writing less code. It has to be in scope (via an use directive) in order
@@ -44,6 +90,15 @@ pub trait Metric {
     fn count(&self) -> usize;
 }
 
+/// Used for defining new types of metrics that hold the last value reported for a quantity,
+/// instead of counting events.
+pub trait StoreMetric {
+    /// Returns the stored value.
+    fn fetch(&self) -> usize;
+    /// Replaces the stored value with `value`.
+    fn store(&self, value: usize);
+}
+
 /// Representation of a metric that is expected  to be incremented from a single thread, so it
 /// can use simple loads and stores with no additional synchronization necessities.
 // Loads are currently Relaxed everywhere, because we don't do anything besides
@@ -69,7 +124,8 @@ impl Metric for SimpleMetric {
 impl Serialize for SimpleMetric {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // There's no serializer.serialize_usize().
-        serializer.serialize_u64(self.0.load(Ordering::Relaxed) as u64)
+        let count = self.0.load(Ordering::Relaxed) as u64;
+        serializer.serialize_newtype_struct("SimpleMetric", &count)
     }
 }
 
@@ -107,7 +163,11 @@ impl Serialize for SharedMetric {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // There's no serializer.serialize_usize() for some reason :(
         let snapshot = self.0.load(Ordering::Relaxed);
-        let res = serializer.serialize_u64(snapshot as u64 - self.1.load(Ordering::Relaxed) as u64);
+        if serialize_totals() {
+            return serializer.serialize_newtype_struct("SharedMetric", &(snapshot as u64));
+        }
+        let delta = snapshot as u64 - self.1.load(Ordering::Relaxed) as u64;
+        let res = serializer.serialize_newtype_struct("SharedMetric", &delta);
 
         if res.is_ok() {
             self.1.store(snapshot, Ordering::Relaxed);
@@ -116,6 +176,189 @@ impl Serialize for SharedMetric {
     }
 }
 
+/// Representation of a metric that holds a value which may be stored and read from more than one
+/// thread. Unlike `SharedMetric`, the value is not reset when the metrics are flushed.
+#[derive(Default)]
+pub struct SharedStoreMetric(AtomicUsize);
+
+impl StoreMetric for SharedStoreMetric {
+    fn fetch(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn store(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+impl Serialize for SharedStoreMetric {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = self.0.load(Ordering::Relaxed) as u64;
+        serializer.serialize_newtype_struct("SharedStoreMetric", &value)
+    }
+}
+
+/// The number of buckets of a `LatencyHistogram`.
+pub const LATENCY_BUCKETS: usize = 25;
+
+/// Representation of a metric that counts durations in buckets on a log scale. Bucket `i` counts
+/// the durations shorter than 2^i microseconds that do not fit in the previous buckets, and the
+/// last bucket counts all the longer ones, from about 8 seconds. Like `SharedMetric`, it can be
+/// updated from any thread without locking, and it is reset each time it is flushed.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    buckets: [SharedMetric; LATENCY_BUCKETS],
+    count: SharedMetric,
+    sum_us: SharedMetric,
+}
+
+impl LatencyHistogram {
+    /// Counts `latency` in its bucket.
+    pub fn record(&self, latency: Duration) {
+        let us = latency
+            .as_secs()
+            .saturating_mul(1_000_000)
+            .saturating_add(u64::from(latency.subsec_micros()));
+        // The bucket of a duration is the number of bits it takes in microseconds.
+        let index = cmp::min(64 - us.leading_zeros() as usize, LATENCY_BUCKETS - 1);
+        self.buckets[index].inc();
+        self.count.inc();
+        self.sum_us.add(us as usize);
+    }
+
+    /// Returns the number of durations recorded.
+    pub fn count(&self) -> usize {
+        self.count.count()
+    }
+
+    /// Returns the exclusive upper bound of bucket `index` in microseconds, or `None` for the
+    /// last bucket, which has no bound.
+    pub fn bucket_bound_us(index: usize) -> Option<u64> {
+        if index < LATENCY_BUCKETS - 1 {
+            Some(1 << index)
+        } else {
+            None
+        }
+    }
+}
+
+// The buckets of a `LatencyHistogram`, keyed by their upper bound.
+struct LatencyBuckets<'a>(&'a [SharedMetric]);
+
+impl<'a> Serialize for LatencyBuckets<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (index, bucket) in self.0.iter().enumerate() {
+            match LatencyHistogram::bucket_bound_us(index) {
+                Some(bound) => map.serialize_entry(&bound.to_string(), bucket)?,
+                None => map.serialize_entry("inf", bucket)?,
+            }
+        }
+        map.end()
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    /// Like for `SharedMetric`, the counts are reset once serialized.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut histogram = serializer.serialize_struct("LatencyHistogram", 3)?;
+        histogram.serialize_field("count", &self.count)?;
+        histogram.serialize_field("sum_us", &self.sum_us)?;
+        histogram.serialize_field("buckets", &LatencyBuckets(&self.buckets))?;
+        histogram.end()
+    }
+}
+
+/// The metrics of a single device, which also count towards the totals of all the devices of its
+/// kind.
+pub struct DeviceMetrics<T: 'static> {
+    device: Arc<T>,
+    total: &'static T,
+}
+
+impl<T> DeviceMetrics<T> {
+    /// Creates the metrics of a device, given the totals they count towards.
+    pub fn new(device: Arc<T>, total: &'static T) -> Self {
+        DeviceMetrics { device, total }
+    }
+
+    /// Applies `update` to the metrics of the device, and to the totals.
+    pub fn update<F: Fn(&T)>(&self, update: F) {
+        update(&self.device);
+        update(self.total);
+    }
+
+    /// Returns the metrics of the device alone.
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+}
+
+impl<T> Clone for DeviceMetrics<T> {
+    fn clone(&self) -> Self {
+        DeviceMetrics {
+            device: self.device.clone(),
+            total: self.total,
+        }
+    }
+}
+
+/// The metrics of the devices of one kind, keyed by device ID. They are serialized as an object
+/// holding the metrics of each device.
+#[derive(Default)]
+pub struct DeviceMetricsMap<T>(RwLock<BTreeMap<String, Arc<T>>>);
+
+impl<T: Default> DeviceMetricsMap<T> {
+    /// Creates the metrics of the device `id`, replacing any previous ones.
+    pub fn register(&self, id: &str) -> Arc<T> {
+        let metrics = Arc::new(T::default());
+        // The map stays consistent even if a thread panicked while holding the lock.
+        let mut map = self.0.write().unwrap_or_else(|e| e.into_inner());
+        map.insert(id.to_string(), metrics.clone());
+        metrics
+    }
+
+    /// Removes the metrics of the device `id`. Their last values are not flushed.
+    pub fn unregister(&self, id: &str) {
+        let mut map = self.0.write().unwrap_or_else(|e| e.into_inner());
+        map.remove(id);
+    }
+}
+
+impl<T: Serialize> Serialize for DeviceMetricsMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let map = self.0.read().unwrap_or_else(|e| e.into_inner());
+        serializer.collect_map(map.iter().map(|(id, metrics)| (id, &**metrics)))
+    }
+}
+
+/// The metrics of the queues of a device, indexed by queue. They are serialized as a list
+/// holding the metrics of each queue, up to the last queue in use.
+#[derive(Default)]
+pub struct QueueMetricsList<T>(RwLock<Vec<Arc<T>>>);
+
+impl<T: Default> QueueMetricsList<T> {
+    /// Returns the metrics of the queue `index`, creating them if needed.
+    pub fn get(&self, index: usize) -> Arc<T> {
+        // The list stays consistent even if a thread panicked while holding the lock.
+        if let Some(metrics) = self.0.read().unwrap_or_else(|e| e.into_inner()).get(index) {
+            return metrics.clone();
+        }
+        let mut list = self.0.write().unwrap_or_else(|e| e.into_inner());
+        while list.len() <= index {
+            list.push(Arc::new(T::default()));
+        }
+        list[index].clone()
+    }
+}
+
+impl<T: Serialize> Serialize for QueueMetricsList<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let list = self.0.read().unwrap_or_else(|e| e.into_inner());
+        serializer.collect_seq(list.iter().map(|metrics| &**metrics))
+    }
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -131,11 +374,24 @@ pub struct ApiServerMetrics {
     pub sync_outcome_fails: SharedMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedMetric,
+    /// Time taken to handle API requests, from their receipt to their response.
+    pub request_latency_us: LatencyHistogram,
+}
+
+/// Metrics specific to DELETE API Requests for counting user triggered actions and/or failures.
+#[derive(Default, Serialize)]
+pub struct DeleteRequestsMetrics {
+    /// Number of tries to DELETE a block device.
+    pub drive_count: SharedMetric,
+    /// Number of tries to DELETE a net device.
+    pub network_count: SharedMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct GetRequestsMetrics {
+    /// Number of GETs for getting the balloon device configuration.
+    pub balloon_count: SharedMetric,
     /// Number of GETs for getting information on the instance.
     pub instance_info_count: SharedMetric,
     /// Number of failures when obtaining information on the current instance.
@@ -144,6 +400,20 @@ pub struct GetRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures during GETs for getting information on the instance.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of GETs for getting the boot source.
+    pub boot_source_count: SharedMetric,
+    /// Number of GETs for getting the configuration of a block device.
+    pub drive_count: SharedMetric,
+    /// Number of GETs for getting the logger configuration.
+    pub logger_count: SharedMetric,
+    /// Number of GETs for getting the metrics in the OpenMetrics text format.
+    pub metrics_count: SharedMetric,
+    /// Number of GETs for getting the configuration of a network interface.
+    pub network_count: SharedMetric,
+    /// Number of GETs for getting the whole microVM configuration.
+    pub vm_config_count: SharedMetric,
+    /// Number of GETs for getting the configuration of a vsock device.
+    pub vsock_count: SharedMetric,
 }
 
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
@@ -153,6 +423,10 @@ pub struct PutRequestsMetrics {
     pub actions_count: SharedMetric,
     /// Number of failures in triggering an action on the VM.
     pub actions_fails: SharedMetric,
+    /// Number of PUTs for configuring the balloon device.
+    pub balloon_count: SharedMetric,
+    /// Number of failures in configuring the balloon device.
+    pub balloon_fails: SharedMetric,
     /// Number of PUTs for attaching source of boot.
     pub boot_source_count: SharedMetric,
     /// Number of failures during attaching source of boot.
@@ -173,15 +447,27 @@ pub struct PutRequestsMetrics {
     pub network_count: SharedMetric,
     /// Number of failures in creating a new network interface.
     pub network_fails: SharedMetric,
+    /// Number of PUTs for creating or loading a snapshot.
+    pub snapshot_count: SharedMetric,
+    /// Number of failures in creating or loading a snapshot.
+    pub snapshot_fails: SharedMetric,
 }
 
 /// Metrics specific to PATCH API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct PatchRequestsMetrics {
+    /// Number of tries to PATCH the balloon device.
+    pub balloon_count: SharedMetric,
+    /// Number of failures in PATCHing the balloon device.
+    pub balloon_fails: SharedMetric,
     /// Number of tries to PATCH a block device.
     pub drive_count: SharedMetric,
     /// Number of failures in PATCHing a block device.
     pub drive_fails: SharedMetric,
+    /// Number of tries to PATCH the logger.
+    pub logger_count: SharedMetric,
+    /// Number of failures in PATCHing the logger.
+    pub logger_fails: SharedMetric,
     /// Number of tries to PATCH a net device.
     pub network_count: SharedMetric,
     /// Number of failures in PATCHing a net device.
@@ -192,6 +478,55 @@ pub struct PatchRequestsMetrics {
     pub machine_cfg_fails: SharedMetric,
 }
 
+/// Balloon device associated metrics.
+#[derive(Default, Serialize)]
+pub struct BalloonDeviceMetrics {
+    /// Number of times when activate failed on the balloon device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the balloon device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the balloon device failed.
+    pub event_fails: SharedMetric,
+    /// Number of pages given up by the guest.
+    pub inflated_pages_count: SharedMetric,
+    /// Number of failures in releasing the pages given up by the guest.
+    pub inflate_fails: SharedMetric,
+    /// Number of pages taken back by the guest.
+    pub deflated_pages_count: SharedMetric,
+    /// Number of memory statistics updates received from the guest.
+    pub stats_updates_count: SharedMetric,
+    /// Number of failures in reading the memory statistics reported by the guest.
+    pub stats_update_fails: SharedMetric,
+    /// The memory statistics last reported by the guest.
+    pub stats: BalloonStatsMetrics,
+}
+
+/// The memory statistics reported by the guest through the balloon device. The sizes are in
+/// bytes. A statistic the guest never reported is 0.
+#[derive(Default, Serialize)]
+pub struct BalloonStatsMetrics {
+    /// Amount of memory swapped in.
+    pub swap_in: SharedStoreMetric,
+    /// Amount of memory swapped out.
+    pub swap_out: SharedStoreMetric,
+    /// Number of major page faults.
+    pub major_faults: SharedStoreMetric,
+    /// Number of minor page faults.
+    pub minor_faults: SharedStoreMetric,
+    /// Amount of memory not used for any purpose.
+    pub free_memory: SharedStoreMetric,
+    /// Total amount of memory available to the guest.
+    pub total_memory: SharedStoreMetric,
+    /// Estimate of the memory that can be used for new applications without swapping.
+    pub available_memory: SharedStoreMetric,
+    /// Amount of memory used by the disk caches.
+    pub disk_caches: SharedStoreMetric,
+    /// Number of successful huge page allocations.
+    pub hugetlb_allocations: SharedStoreMetric,
+    /// Number of failed huge page allocations.
+    pub hugetlb_failures: SharedStoreMetric,
+}
+
 /// Block Device associated metrics.
 #[derive(Default, Serialize)]
 pub struct BlockDeviceMetrics {
@@ -207,6 +542,10 @@ pub struct BlockDeviceMetrics {
     pub invalid_reqs_count: SharedMetric,
     /// Number of flushes operation triggered on this block device.
     pub flush_count: SharedMetric,
+    /// Number of successful discard operations.
+    pub discard_count: SharedMetric,
+    /// Number of successful write zeroes operations.
+    pub write_zeroes_count: SharedMetric,
     /// Number of events triggerd on the queue of this block device.
     pub queue_event_count: SharedMetric,
     /// Number of events ratelimiter-related.
@@ -223,6 +562,27 @@ pub struct BlockDeviceMetrics {
     pub read_count: SharedMetric,
     /// Number of sucessful write operations.
     pub write_count: SharedMetric,
+    /// Time taken to complete read operations.
+    pub read_latency_us: LatencyHistogram,
+    /// Time taken to complete write operations.
+    pub write_latency_us: LatencyHistogram,
+    /// Time taken to complete flush operations.
+    pub flush_latency_us: LatencyHistogram,
+    /// Time spent blocked by the rate limiter, each time it blocks.
+    pub rate_limiter_throttled_us: LatencyHistogram,
+    /// The metrics of each queue.
+    pub queues: QueueMetricsList<BlockQueueMetrics>,
+}
+
+/// Metrics specific to a queue of a block device.
+#[derive(Default, Serialize)]
+pub struct BlockQueueMetrics {
+    /// Number of events triggered on the queue.
+    pub queue_event_count: SharedMetric,
+    /// Number of successful read operations received on the queue.
+    pub read_count: SharedMetric,
+    /// Number of successful write operations received on the queue.
+    pub write_count: SharedMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -291,6 +651,12 @@ pub struct NetDeviceMetrics {
     pub cfg_fails: SharedMetric,
     /// Number of times when handling events on a network device failed.
     pub event_fails: SharedMetric,
+    /// Number of events associated with the control queue.
+    pub ctrl_queue_event_count: SharedMetric,
+    /// Number of commands sent on the control queue that failed.
+    pub ctrl_fails: SharedMetric,
+    /// Number of frames which could not be written to the capture file.
+    pub capture_fails: SharedMetric,
     /// Number of events associated with the receiving queue.
     pub rx_queue_event_count: SharedMetric,
     /// Number of events associated with the rate limiter installed on the receiving path.
@@ -305,6 +671,8 @@ pub struct NetDeviceMetrics {
     pub rx_fails: SharedMetric,
     /// Number of successful read operations while receiving data.
     pub rx_count: SharedMetric,
+    /// Number of frames received on the associated tap and dropped by the ingress packet filter.
+    pub rx_filtered_drops: SharedMetric,
     /// Number of transmitted bytes.
     pub tx_bytes_count: SharedMetric,
     /// Number of errors while transmitting data.
@@ -319,6 +687,18 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_event_count: SharedMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedMetric,
+    /// Number of packets with a source IPv4 address outside the allowed ones, sent by the guest.
+    pub tx_spoofed_ip_count: SharedMetric,
+    /// Number of frames sent by the guest and dropped by the anti-spoofing filter.
+    pub tx_spoofed_drops: SharedMetric,
+    /// Number of frames sent by the guest and dropped by the egress packet filter.
+    pub tx_filtered_drops: SharedMetric,
+    /// Number of frames larger than the MTU, sent by the guest and dropped.
+    pub tx_mtu_exceeded_count: SharedMetric,
+    /// Time spent blocked by the rate limiter of the receiving path, each time it blocks.
+    pub rx_rate_limiter_throttled_us: LatencyHistogram,
+    /// Time spent blocked by the rate limiter of the transmitting path, each time it blocks.
+    pub tx_rate_limiter_throttled_us: LatencyHistogram,
 }
 
 /// Metrics specific to the i8042 device.
@@ -405,8 +785,14 @@ pub struct FirecrackerMetrics {
     utc_timestamp_ms: SerializeToUtcTimestampMs,
     /// API Server related metrics.
     pub api_server: ApiServerMetrics,
-    /// A block device's related metrics.
+    /// The balloon device's related metrics.
+    pub balloon: BalloonDeviceMetrics,
+    /// The totals of the metrics of the block devices.
     pub block: BlockDeviceMetrics,
+    /// Metrics related to API DELETE requests.
+    pub delete_api_requests: DeleteRequestsMetrics,
+    /// The metrics of each block device, keyed by drive ID.
+    pub drives: DeviceMetricsMap<BlockDeviceMetrics>,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
     /// Metrics relaetd to the i8042 device.
@@ -415,8 +801,10 @@ pub struct FirecrackerMetrics {
     pub logger: LoggerSystemMetrics,
     /// Metrics specific to MMDS functionality.
     pub mmds: MmdsMetrics,
-    /// A network device's related metrics.
+    /// The totals of the metrics of the network devices.
     pub net: NetDeviceMetrics,
+    /// The metrics of each network device, keyed by interface ID.
+    pub network_interfaces: DeviceMetricsMap<NetDeviceMetrics>,
     /// Metrics related to API PATCH requests.
     pub patch_api_requests: PatchRequestsMetrics,
     /// Metrics related to API PUT requests.
@@ -493,6 +881,101 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_store_metric() {
+        let m = SharedStoreMetric::default();
+        assert_eq!(m.fetch(), 0);
+        m.store(42);
+        assert_eq!(m.fetch(), 42);
+
+        // Serializing does not reset the value.
+        assert_eq!(serde_json::to_string(&m).unwrap(), "42");
+        assert_eq!(serde_json::to_string(&m).unwrap(), "42");
+        m.store(7);
+        assert_eq!(serde_json::to_string(&m).unwrap(), "7");
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        for us in &[0, 1, 3, 1000, 1023, 1024, 10_000_000] {
+            histogram.record(Duration::from_micros(*us));
+        }
+        assert_eq!(histogram.count(), 7);
+
+        let json: serde_json::Value = serde_json::to_value(&histogram).unwrap();
+        assert_eq!(json["count"], 7);
+        assert_eq!(json["sum_us"], 10_003_051);
+        let buckets = json["buckets"].as_object().unwrap();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS);
+        assert_eq!(buckets["1"], 1);
+        assert_eq!(buckets["2"], 1);
+        assert_eq!(buckets["4"], 1);
+        assert_eq!(buckets["8"], 0);
+        assert_eq!(buckets["1024"], 2);
+        assert_eq!(buckets["2048"], 1);
+        assert_eq!(buckets["8388608"], 0);
+        assert_eq!(buckets["inf"], 1);
+
+        // The counts are reset on each flush.
+        let json: serde_json::Value = serde_json::to_value(&histogram).unwrap();
+        assert_eq!(json["count"], 0);
+        assert_eq!(json["buckets"]["1024"], 0);
+    }
+
+    #[test]
+    fn test_device_metrics() {
+        lazy_static! {
+            static ref TOTAL: BlockDeviceMetrics = BlockDeviceMetrics::default();
+        }
+        let map = DeviceMetricsMap::<BlockDeviceMetrics>::default();
+        let first = DeviceMetrics::new(map.register("first"), &TOTAL);
+        let second = DeviceMetrics::new(map.register("second"), &TOTAL);
+
+        // The updates of each device go to the totals as well.
+        first.update(|m| m.read_count.inc());
+        second.update(|m| m.read_count.add(2));
+        assert_eq!(first.device().read_count.count(), 1);
+        assert_eq!(second.device().read_count.count(), 2);
+        assert_eq!(TOTAL.read_count.count(), 3);
+
+        let json: serde_json::Value = serde_json::to_value(&map).unwrap();
+        assert_eq!(json["first"]["read_count"], 1);
+        assert_eq!(json["second"]["read_count"], 2);
+        // The values are reset on each flush.
+        let json: serde_json::Value = serde_json::to_value(&map).unwrap();
+        assert_eq!(json["first"]["read_count"], 0);
+
+        // The metrics of a removed device are not flushed anymore, while the metrics of a new
+        // device with the same ID start from scratch.
+        map.unregister("second");
+        let json: serde_json::Value = serde_json::to_value(&map).unwrap();
+        assert!(json.get("second").is_none());
+        let second = DeviceMetrics::new(map.register("second"), &TOTAL);
+        assert_eq!(second.device().read_count.count(), 0);
+        assert_eq!(TOTAL.read_count.count(), 3);
+    }
+
+    #[test]
+    fn test_queue_metrics() {
+        let metrics = BlockDeviceMetrics::default();
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+        assert!(json["queues"].as_array().unwrap().is_empty());
+
+        // The queues before the last one in use are listed too.
+        metrics.queues.get(1).read_count.inc();
+        metrics.queues.get(1).read_count.inc();
+        metrics.queues.get(0).write_count.inc();
+        assert_eq!(metrics.queues.get(1).read_count.count(), 2);
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["queues"][0]["write_count"], 1);
+        assert_eq!(json["queues"][1]["read_count"], 2);
+        assert_eq!(json["queues"][1]["write_count"], 0);
+        // The values are reset on each flush.
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["queues"][1]["read_count"], 0);
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
@@ -0,0 +1,691 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Renders the metrics in the OpenMetrics text format, for the monitoring systems that scrape
+//! them.
+//!
+//! The metrics are walked through their `Serialize` implementation, which tells the kind of each
+//! one: `SharedMetric` and `SimpleMetric` are counters, `SharedStoreMetric` is a gauge and
+//! `LatencyHistogram` is a histogram. The name of a metric is made of the names of the fields
+//! leading to it, the keys of the maps of device metrics become an `id` label, and the indexes of
+//! the lists of queue metrics become a `queue` label. The other values, such as the timestamp of
+//! the metrics, are skipped.
+//!
+//! The totals of the metrics are rendered, so the values flushed to the metrics FIFO are not
+//! affected.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+use serde::ser::{
+    self, Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer,
+};
+
+use metrics::with_totals;
+
+/// Errors encountered while rendering the metrics.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Failed to render the metrics: {}", self.0)
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+struct Family {
+    name: String,
+    kind: Kind,
+    samples: Vec<String>,
+}
+
+/// Metric families in the OpenMetrics text format, whose samples all carry the same labels, such
+/// as the ID of the microVM. They are rendered through `Display`.
+pub struct OpenMetrics {
+    labels: Vec<(String, String)>,
+    families: Vec<Family>,
+}
+
+impl OpenMetrics {
+    /// Creates an empty set of metric families, whose samples will carry `labels`.
+    pub fn new(labels: &[(&str, &str)]) -> Self {
+        OpenMetrics {
+            labels: owned_labels(labels),
+            families: Vec::new(),
+        }
+    }
+
+    /// Adds the totals of `metrics`, whose names start with `prefix`.
+    pub fn add_metrics<T: Serialize>(&mut self, prefix: &str, metrics: &T) -> Result<(), Error> {
+        let mut walker = Walker {
+            exposition: self,
+            path: vec![prefix.to_string()],
+            labels: Vec::new(),
+        };
+        with_totals(|| metrics.serialize(&mut walker))
+    }
+
+    /// Adds a sample of the gauge `name`, which carries `labels` on top of the common ones.
+    pub fn add_gauge(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.add_sample(name, Kind::Gauge, "", &owned_labels(labels), value);
+    }
+
+    fn add_sample(
+        &mut self,
+        family: &str,
+        kind: Kind,
+        suffix: &str,
+        labels: &[(String, String)],
+        value: u64,
+    ) {
+        let index = match self.families.iter().position(|f| f.name == family) {
+            Some(index) => index,
+            None => {
+                self.families.push(Family {
+                    name: family.to_string(),
+                    kind,
+                    samples: Vec::new(),
+                });
+                self.families.len() - 1
+            }
+        };
+
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .chain(labels)
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+            .collect();
+        let sample = if labels.is_empty() {
+            format!("{}{} {}", family, suffix, value)
+        } else {
+            format!("{}{}{{{}}} {}", family, suffix, labels.join(","), value)
+        };
+        self.families[index].samples.push(sample);
+    }
+}
+
+impl Display for OpenMetrics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for family in &self.families {
+            writeln!(f, "# TYPE {} {}", family.name, family.kind.name())?;
+            for sample in &family.samples {
+                writeln!(f, "{}", sample)?;
+            }
+        }
+        writeln!(f, "# EOF")
+    }
+}
+
+fn owned_labels(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+    labels
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Stubs the methods of a `Serializer` for the values it does not expect.
+macro_rules! unsupported {
+    ($($method:ident)*) => { $(unsupported!(@ $method);)* };
+    (@ serialize_bool) => { unsupported!(@ serialize_bool, bool); };
+    (@ serialize_i8) => { unsupported!(@ serialize_i8, i8); };
+    (@ serialize_i16) => { unsupported!(@ serialize_i16, i16); };
+    (@ serialize_i32) => { unsupported!(@ serialize_i32, i32); };
+    (@ serialize_i64) => { unsupported!(@ serialize_i64, i64); };
+    (@ serialize_u8) => { unsupported!(@ serialize_u8, u8); };
+    (@ serialize_u16) => { unsupported!(@ serialize_u16, u16); };
+    (@ serialize_u32) => { unsupported!(@ serialize_u32, u32); };
+    (@ serialize_u64) => { unsupported!(@ serialize_u64, u64); };
+    (@ serialize_f32) => { unsupported!(@ serialize_f32, f32); };
+    (@ serialize_f64) => { unsupported!(@ serialize_f64, f64); };
+    (@ serialize_char) => { unsupported!(@ serialize_char, char); };
+    (@ serialize_str) => { unsupported!(@ serialize_str, &str); };
+    (@ serialize_bytes) => { unsupported!(@ serialize_bytes, &[u8]); };
+    (@ $method:ident, $ty:ty) => {
+        fn $method(self, _: $ty) -> Result<Self::Ok, Error> {
+            Err(Error(format!("unexpected {}", stringify!($ty))))
+        }
+    };
+    (@ serialize_none) => {
+        fn serialize_none(self) -> Result<Self::Ok, Error> {
+            Err(Error("unexpected none".to_string()))
+        }
+    };
+    (@ serialize_some) => {
+        fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Self::Ok, Error> {
+            Err(Error("unexpected option".to_string()))
+        }
+    };
+    (@ serialize_unit) => {
+        fn serialize_unit(self) -> Result<Self::Ok, Error> {
+            Err(Error("unexpected unit".to_string()))
+        }
+    };
+    (@ serialize_unit_struct) => {
+        fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_unit_variant) => {
+        fn serialize_unit_variant(
+            self,
+            name: &'static str,
+            _: u32,
+            _: &'static str,
+        ) -> Result<Self::Ok, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_newtype_struct) => {
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(
+            self,
+            name: &'static str,
+            _: &T,
+        ) -> Result<Self::Ok, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_newtype_variant) => {
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(
+            self,
+            name: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Self::Ok, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_seq) => {
+        fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+            Err(Error("unexpected sequence".to_string()))
+        }
+    };
+    (@ serialize_tuple) => {
+        fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+            Err(Error("unexpected tuple".to_string()))
+        }
+    };
+    (@ serialize_tuple_struct) => {
+        fn serialize_tuple_struct(
+            self,
+            name: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleStruct, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_tuple_variant) => {
+        fn serialize_tuple_variant(
+            self,
+            name: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleVariant, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_map) => {
+        fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+            Err(Error("unexpected map".to_string()))
+        }
+    };
+    (@ serialize_struct) => {
+        fn serialize_struct(
+            self,
+            name: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStruct, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+    (@ serialize_struct_variant) => {
+        fn serialize_struct_variant(
+            self,
+            name: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStructVariant, Error> {
+            Err(Error(format!("unexpected {}", name)))
+        }
+    };
+}
+
+// Walks the metrics, adding each one to the exposition.
+struct Walker<'a> {
+    exposition: &'a mut OpenMetrics,
+    // The names of the fields leading to the current value.
+    path: Vec<String>,
+    // The labels of the maps holding the current value.
+    labels: Vec<(String, String)>,
+}
+
+impl<'a> Walker<'a> {
+    fn name(&self) -> String {
+        self.path.join("_")
+    }
+}
+
+impl<'a, 'b> Serializer for &'b mut Walker<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SeqWalker<'a, 'b>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = MapWalker<'a, 'b>;
+    type SerializeStruct = StructWalker<'a, 'b>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    // The numbers that are not metrics are skipped.
+    fn serialize_i64(self, _: i64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let (kind, suffix) = match name {
+            "SharedMetric" | "SimpleMetric" => (Kind::Counter, "_total"),
+            "SharedStoreMetric" => (Kind::Gauge, ""),
+            _ => return value.serialize(self),
+        };
+        let value = value.serialize(U64Extractor)?;
+        let name = self.name();
+        self.exposition
+            .add_sample(&name, kind, suffix, &self.labels, value);
+        Ok(())
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<SeqWalker<'a, 'b>, Error> {
+        Ok(SeqWalker(self, 0))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapWalker<'a, 'b>, Error> {
+        Ok(MapWalker(self))
+    }
+
+    fn serialize_struct(self, name: &'static str, _: usize) -> Result<StructWalker<'a, 'b>, Error> {
+        if name == "LatencyHistogram" {
+            Ok(StructWalker::Histogram(self, Histogram::default()))
+        } else {
+            Ok(StructWalker::Metrics(self))
+        }
+    }
+
+    unsupported!(
+        serialize_bool serialize_i8 serialize_i16 serialize_i32 serialize_u8 serialize_u16
+        serialize_u32 serialize_f32 serialize_f64 serialize_char serialize_str serialize_bytes
+        serialize_none serialize_some serialize_unit serialize_unit_struct serialize_unit_variant
+        serialize_newtype_variant serialize_tuple serialize_tuple_struct serialize_tuple_variant
+        serialize_struct_variant
+    );
+}
+
+// Walks the lists of queue metrics, whose indexes become a label of the metrics of each queue.
+struct SeqWalker<'a: 'b, 'b>(&'b mut Walker<'a>, usize);
+
+impl<'a, 'b> SerializeSeq for SeqWalker<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.0
+            .labels
+            .push(("queue".to_string(), self.1.to_string()));
+        self.1 += 1;
+        let result = value.serialize(&mut *self.0);
+        self.0.labels.pop();
+        result
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Walks the maps of device metrics, whose keys become a label of the metrics of each device.
+struct MapWalker<'a: 'b, 'b>(&'b mut Walker<'a>);
+
+impl<'a, 'b> SerializeMap for MapWalker<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        let id = key.serialize(StringExtractor)?;
+        self.0.labels.push(("id".to_string(), id));
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let result = value.serialize(&mut *self.0);
+        self.0.labels.pop();
+        result
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    count: u64,
+    sum: u64,
+    buckets: Vec<(String, u64)>,
+}
+
+// Walks the structs of metrics, whose field names are added to the names of the metrics, and
+// collects the values of the histograms.
+enum StructWalker<'a: 'b, 'b> {
+    Metrics(&'b mut Walker<'a>),
+    Histogram(&'b mut Walker<'a>, Histogram),
+}
+
+impl<'a, 'b> SerializeStruct for StructWalker<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        match self {
+            StructWalker::Metrics(walker) => {
+                walker.path.push(key.to_string());
+                let result = value.serialize(&mut **walker);
+                walker.path.pop();
+                result
+            }
+            StructWalker::Histogram(_, histogram) => {
+                match key {
+                    "count" => histogram.count = value.serialize(U64Extractor)?,
+                    "sum_us" => histogram.sum = value.serialize(U64Extractor)?,
+                    "buckets" => histogram.buckets = value.serialize(BucketsExtractor)?,
+                    _ => (),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn end(self) -> Result<(), Error> {
+        if let StructWalker::Histogram(walker, histogram) = self {
+            let name = walker.name();
+            // The buckets of OpenMetrics histograms count all the values up to their bound, while
+            // a bucket of a `LatencyHistogram` counts the whole microseconds below its bound.
+            let mut count = 0;
+            for (bound, bucket_count) in histogram.buckets {
+                count += bucket_count;
+                let le = match bound.parse::<u64>() {
+                    Ok(bound) => (bound - 1).to_string(),
+                    Err(_) => "+Inf".to_string(),
+                };
+                let mut labels = walker.labels.clone();
+                labels.push(("le".to_string(), le));
+                walker
+                    .exposition
+                    .add_sample(&name, Kind::Histogram, "_bucket", &labels, count);
+            }
+            walker.exposition.add_sample(
+                &name,
+                Kind::Histogram,
+                "_count",
+                &walker.labels,
+                histogram.count,
+            );
+            walker.exposition.add_sample(
+                &name,
+                Kind::Histogram,
+                "_sum",
+                &walker.labels,
+                histogram.sum,
+            );
+        }
+        Ok(())
+    }
+}
+
+// Extracts the value of a metric.
+struct U64Extractor;
+
+impl Serializer for U64Extractor {
+    type Ok = u64;
+    type Error = Error;
+    type SerializeSeq = Impossible<u64, Error>;
+    type SerializeTuple = Impossible<u64, Error>;
+    type SerializeTupleStruct = Impossible<u64, Error>;
+    type SerializeTupleVariant = Impossible<u64, Error>;
+    type SerializeMap = Impossible<u64, Error>;
+    type SerializeStruct = Impossible<u64, Error>;
+    type SerializeStructVariant = Impossible<u64, Error>;
+
+    fn serialize_u64(self, value: u64) -> Result<u64, Error> {
+        Ok(value)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<u64, Error> {
+        value.serialize(self)
+    }
+
+    unsupported!(
+        serialize_bool serialize_i8 serialize_i16 serialize_i32 serialize_i64 serialize_u8
+        serialize_u16 serialize_u32 serialize_f32 serialize_f64 serialize_char serialize_str
+        serialize_bytes serialize_none serialize_some serialize_unit serialize_unit_struct
+        serialize_unit_variant serialize_newtype_variant serialize_seq serialize_tuple
+        serialize_tuple_struct serialize_tuple_variant serialize_map serialize_struct
+        serialize_struct_variant
+    );
+}
+
+// Extracts the keys of the maps.
+struct StringExtractor;
+
+impl Serializer for StringExtractor {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    fn serialize_str(self, value: &str) -> Result<String, Error> {
+        Ok(value.to_string())
+    }
+
+    unsupported!(
+        serialize_bool serialize_i8 serialize_i16 serialize_i32 serialize_i64 serialize_u8
+        serialize_u16 serialize_u32 serialize_u64 serialize_f32 serialize_f64 serialize_char
+        serialize_bytes serialize_none serialize_some serialize_unit serialize_unit_struct
+        serialize_unit_variant serialize_newtype_struct serialize_newtype_variant serialize_seq
+        serialize_tuple serialize_tuple_struct serialize_tuple_variant serialize_map
+        serialize_struct serialize_struct_variant
+    );
+}
+
+// Extracts the buckets of a histogram, keyed by their bound.
+struct BucketsExtractor;
+
+impl Serializer for BucketsExtractor {
+    type Ok = Vec<(String, u64)>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Self::Ok, Error>;
+    type SerializeTuple = Impossible<Self::Ok, Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Error>;
+    type SerializeMap = BucketsCollector;
+    type SerializeStruct = Impossible<Self::Ok, Error>;
+    type SerializeStructVariant = Impossible<Self::Ok, Error>;
+
+    fn serialize_map(self, len: Option<usize>) -> Result<BucketsCollector, Error> {
+        Ok(BucketsCollector {
+            buckets: Vec::with_capacity(len.unwrap_or(0)),
+            bound: None,
+        })
+    }
+
+    unsupported!(
+        serialize_bool serialize_i8 serialize_i16 serialize_i32 serialize_i64 serialize_u8
+        serialize_u16 serialize_u32 serialize_u64 serialize_f32 serialize_f64 serialize_char
+        serialize_str serialize_bytes serialize_none serialize_some serialize_unit
+        serialize_unit_struct serialize_unit_variant serialize_newtype_struct
+        serialize_newtype_variant serialize_seq serialize_tuple serialize_tuple_struct
+        serialize_tuple_variant serialize_struct serialize_struct_variant
+    );
+}
+
+struct BucketsCollector {
+    buckets: Vec<(String, u64)>,
+    bound: Option<String>,
+}
+
+impl SerializeMap for BucketsCollector {
+    type Ok = Vec<(String, u64)>;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        self.bound = Some(key.serialize(StringExtractor)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let bound = self
+            .bound
+            .take()
+            .ok_or_else(|| Error("bucket without a bound".to_string()))?;
+        self.buckets.push((bound, value.serialize(U64Extractor)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        Ok(self.buckets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use metrics::{
+        BlockDeviceMetrics, DeviceMetricsMap, LatencyHistogram, SharedMetric, SharedStoreMetric,
+    };
+    use Metric;
+    use StoreMetric;
+
+    #[derive(Default, Serialize)]
+    struct TestMetrics {
+        utc_timestamp_ms: u64,
+        requests: SharedMetric,
+        memory_pages: SharedStoreMetric,
+        latency_us: LatencyHistogram,
+        drives: DeviceMetricsMap<BlockDeviceMetrics>,
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = TestMetrics::default();
+        metrics.requests.add(5);
+        metrics.memory_pages.store(42);
+        metrics.latency_us.record(Duration::from_micros(3));
+        metrics.latency_us.record(Duration::from_micros(100));
+        let drive = metrics.drives.register("root\"fs");
+        drive.read_count.add(2);
+        drive.queues.get(1).write_count.add(3);
+
+        let mut exposition = OpenMetrics::new(&[("instance_id", "vm0")]);
+        exposition.add_metrics("test", &metrics).unwrap();
+        exposition.add_gauge("test_state", &[("state", "Running")], 1);
+        let text = exposition.to_string();
+
+        assert!(!text.contains("utc_timestamp_ms"));
+        assert!(text.contains(
+            "# TYPE test_requests counter\ntest_requests_total{instance_id=\"vm0\"} 5\n"
+        ));
+        assert!(text.contains(
+            "# TYPE test_memory_pages gauge\ntest_memory_pages{instance_id=\"vm0\"} 42\n"
+        ));
+        assert!(text.contains("# TYPE test_latency_us histogram\n"));
+        assert!(text.contains("test_latency_us_bucket{instance_id=\"vm0\",le=\"0\"} 0\n"));
+        assert!(text.contains("test_latency_us_bucket{instance_id=\"vm0\",le=\"1\"} 0\n"));
+        assert!(text.contains("test_latency_us_bucket{instance_id=\"vm0\",le=\"3\"} 1\n"));
+        assert!(text.contains("test_latency_us_bucket{instance_id=\"vm0\",le=\"127\"} 2\n"));
+        assert!(text.contains("test_latency_us_bucket{instance_id=\"vm0\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("test_latency_us_count{instance_id=\"vm0\"} 2\n"));
+        assert!(text.contains("test_latency_us_sum{instance_id=\"vm0\"} 103\n"));
+        assert!(text
+            .contains("test_drives_read_count_total{instance_id=\"vm0\",id=\"root\\\"fs\"} 2\n"));
+        assert!(text.contains(
+            "test_drives_queues_write_count_total{instance_id=\"vm0\",id=\"root\\\"fs\",queue=\"0\"} 0\n"
+        ));
+        assert!(text.contains(
+            "test_drives_queues_write_count_total{instance_id=\"vm0\",id=\"root\\\"fs\",queue=\"1\"} 3\n"
+        ));
+        assert!(text.contains(
+            "# TYPE test_state gauge\ntest_state{instance_id=\"vm0\",state=\"Running\"} 1\n"
+        ));
+        assert!(text.ends_with("# EOF\n"));
+
+        // The totals are rendered each time, while the values flushed to the metrics FIFO are
+        // still the ones since the previous flush.
+        metrics.requests.add(1);
+        let mut exposition = OpenMetrics::new(&[]);
+        exposition.add_metrics("test", &metrics).unwrap();
+        assert!(exposition.to_string().contains("test_requests_total 6\n"));
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["requests"], 6);
+        assert_eq!(json["latency_us"]["count"], 2);
+        metrics.requests.add(1);
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["requests"], 1);
+    }
+}
@@ -1,24 +1,81 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Auxiliary module for flushing some input to a named PIPE (FIFO).
+//! Auxiliary module for flushing logs and metrics to their destination: a named PIPE (FIFO), a
+//! regular file rotated by size, a Unix datagram socket which receives syslog messages or a ring
+//! buffer in memory.
+//!
+//! None of the writers waits for the destination to become available: a message which cannot be
+//! written right away is dropped, and the error is returned for the caller to count it.
 
 use libc::O_NONBLOCK;
-use std::fs::{File, OpenOptions};
-use std::io::{LineWriter, Write};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
 use std::os::unix::fs::OpenOptionsExt;
-use std::path::PathBuf;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::result;
-use std::sync::{Mutex, MutexGuard};
 
 use error::LoggerError;
+use log::Level;
 
 type Result<T> = result::Result<T, LoggerError>;
 
-/// Structure `PipeLogWriter` used for writing to a file in a thread-safe way.
+// The syslog facility of the messages, which is "user-level messages".
+const SYSLOG_FACILITY_USER: u8 = 1;
+
+/// The destinations which the logs and the metrics can be written to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogDestination {
+    /// A named pipe, opened with `O_NONBLOCK`.
+    Fifo(String),
+    /// A regular file, which is rotated before it grows over `max_size` bytes. The rotated files
+    /// are named after it, with the `.1` suffix for the latest one, up to `.<max_files>`.
+    File {
+        /// The path of the file.
+        path: String,
+        /// The size of the file which triggers its rotation, in bytes.
+        max_size: u64,
+        /// The number of rotated files to keep.
+        max_files: usize,
+    },
+    /// A Unix datagram socket which receives syslog messages, such as `/dev/log`.
+    Syslog(String),
+    /// A ring buffer in memory, holding the latest lines up to the given number.
+    RingBuffer(usize),
+}
+
+/// Writes single log lines or metrics to a destination.
+pub trait LogWriter: Send {
+    /// Writes `msg`, which has no trailing newline, logged at `level`.
+    fn write(&mut self, level: Level, msg: &str) -> Result<()>;
+
+    /// Returns the lines kept in memory, for the writers which keep them.
+    fn lines(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+/// Opens a writer for `destination`. `tag` names the application in the syslog messages.
+pub fn open_writer(destination: &LogDestination, tag: &str) -> Result<Box<dyn LogWriter>> {
+    Ok(match destination {
+        LogDestination::Fifo(path) => Box::new(PipeLogWriter::new(path)?),
+        LogDestination::File {
+            path,
+            max_size,
+            max_files,
+        } => Box::new(FileLogWriter::new(path, *max_size, *max_files)?),
+        LogDestination::Syslog(path) => Box::new(SyslogLogWriter::new(path, tag)?),
+        LogDestination::RingBuffer(capacity) => Box::new(RingBufferLogWriter::new(*capacity)),
+    })
+}
+
+/// Structure `PipeLogWriter` used for writing to a named pipe.
 #[derive(Debug)]
 pub struct PipeLogWriter {
-    line_writer: Mutex<LineWriter<File>>,
+    line_writer: LineWriter<File>,
 }
 
 impl PipeLogWriter {
@@ -31,23 +88,183 @@ impl PipeLogWriter {
             .open(&fifo)
         {
             Ok(t) => Ok(PipeLogWriter {
-                line_writer: Mutex::new(LineWriter::new(t)),
+                line_writer: LineWriter::new(t),
             }),
             Err(e) => Err(LoggerError::OpenFIFO(e)),
         }
     }
+}
 
-    pub fn write(&self, msg: &str) -> Result<()> {
-        let mut line_writer = self.get_line_writer()?;
-        line_writer
-            .write_all(msg.as_bytes())
+impl LogWriter for PipeLogWriter {
+    fn write(&mut self, _: Level, msg: &str) -> Result<()> {
+        // No need to call flush here since the write will handle the flush on its own given that
+        // our messages always end with a newline.
+        self.line_writer
+            .write_all(format!("{}\n", msg).as_bytes())
             .map_err(LoggerError::LogWrite)
     }
+}
 
-    fn get_line_writer(&self) -> Result<(MutexGuard<LineWriter<File>>)> {
-        self.line_writer
-            .lock()
-            .map_err(|e| LoggerError::MutexLockFailure(format!("{}", e)))
+/// Structure `FileLogWriter` used for writing to a regular file rotated by size.
+#[derive(Debug)]
+pub struct FileLogWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+fn open_for_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl FileLogWriter {
+    pub fn new(path: &str, max_size: u64, max_files: usize) -> Result<FileLogWriter> {
+        let path = PathBuf::from(path);
+        let file = open_for_append(&path).map_err(LoggerError::OpenFile)?;
+        let size = file.metadata().map_err(LoggerError::OpenFile)?.len();
+        Ok(FileLogWriter {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            // The file is opened for appending, so the next writes start over from its beginning.
+            self.file.set_len(0)?;
+        } else {
+            rotate_files(&self.path, self.max_files)?;
+            self.file = open_for_append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+/// Renames the file at `path` with the `.1` suffix, after shifting the files already rotated up
+/// to `.<max_files>` and dropping the oldest one. The caller then creates a new file at `path`.
+///
+/// This runs after the seccomp filters are installed, so it only calls `rename`: the rotated
+/// files which are missing are skipped without checking for them first.
+pub fn rotate_files(path: &Path, max_files: usize) -> io::Result<()> {
+    for index in (1..max_files).rev() {
+        match fs::rename(rotated_path(path, index), rotated_path(path, index + 1)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            result => result?,
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+impl LogWriter for FileLogWriter {
+    fn write(&mut self, _: Level, msg: &str) -> Result<()> {
+        let line = format!("{}\n", msg);
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate().map_err(LoggerError::LogWrite)?;
+        }
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(LoggerError::LogWrite)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Structure `SyslogLogWriter` used for sending syslog messages to a Unix datagram socket.
+#[derive(Debug)]
+pub struct SyslogLogWriter {
+    socket: UnixDatagram,
+    tag: String,
+    // The ID of the process is read once, since `getpid` is not allowed by the seccomp filters.
+    pid: u32,
+}
+
+impl SyslogLogWriter {
+    pub fn new(socket_path: &str, tag: &str) -> Result<SyslogLogWriter> {
+        let socket = UnixDatagram::unbound().map_err(LoggerError::ConnectSyslog)?;
+        socket
+            .connect(socket_path)
+            .map_err(LoggerError::ConnectSyslog)?;
+        socket
+            .set_nonblocking(true)
+            .map_err(LoggerError::ConnectSyslog)?;
+        Ok(SyslogLogWriter {
+            socket,
+            tag: tag.to_string(),
+            pid: process::id(),
+        })
+    }
+}
+
+// Returns the syslog severity of the messages logged at `level`.
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+impl LogWriter for SyslogLogWriter {
+    fn write(&mut self, level: Level, msg: &str) -> Result<()> {
+        // The format of the messages read from /dev/log by syslog daemons and journald:
+        // <priority>tag[pid]: message.
+        let datagram = format!(
+            "<{}>{}[{}]: {}",
+            SYSLOG_FACILITY_USER * 8 + syslog_severity(level),
+            self.tag,
+            self.pid,
+            msg
+        );
+        self.socket
+            .send(datagram.as_bytes())
+            .map(|_| ())
+            .map_err(LoggerError::LogWrite)
+    }
+}
+
+/// Structure `RingBufferLogWriter` used for keeping the latest lines in memory.
+#[derive(Debug)]
+pub struct RingBufferLogWriter {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RingBufferLogWriter {
+    pub fn new(capacity: usize) -> RingBufferLogWriter {
+        RingBufferLogWriter {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+}
+
+impl LogWriter for RingBufferLogWriter {
+    fn write(&mut self, _: Level, msg: &str) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(msg.to_string());
+        Ok(())
+    }
+
+    fn lines(&self) -> Option<Vec<String>> {
+        Some(self.lines.iter().cloned().collect())
     }
 }
 
@@ -55,7 +272,7 @@ impl PipeLogWriter {
 mod tests {
     extern crate tempfile;
 
-    use self::tempfile::NamedTempFile;
+    use self::tempfile::{NamedTempFile, TempDir};
     use super::*;
 
     #[test]
@@ -73,9 +290,93 @@ mod tests {
             NamedTempFile::new().expect("Failed to create temporary output logging file.");
         let file = String::from(log_file_temp.path().to_path_buf().to_str().unwrap());
 
-        let fw = PipeLogWriter::new(&file).unwrap();
+        let mut fw = PipeLogWriter::new(&file).unwrap();
         let msg = String::from("some message");
-        let res = fw.write(&msg);
+        let res = fw.write(Level::Info, &msg);
         assert!(res.is_ok())
     }
+
+    #[test]
+    fn test_file_rotation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("log");
+        let read = |suffix: &str| {
+            let mut rotated_path = path.clone().into_os_string();
+            rotated_path.push(suffix);
+            fs::read_to_string(rotated_path).ok()
+        };
+
+        let mut fw = FileLogWriter::new(path.to_str().unwrap(), 8, 2).unwrap();
+        for msg in &["one", "two", "three", "four", "five"] {
+            fw.write(Level::Info, msg).unwrap();
+        }
+        assert_eq!(read("").unwrap(), "five\n");
+        assert_eq!(read(".1").unwrap(), "four\n");
+        assert_eq!(read(".2").unwrap(), "three\n");
+        assert!(read(".3").is_none());
+
+        // A line which is longer than the maximum size still goes to a file of its own.
+        fw.write(Level::Info, "a long message").unwrap();
+        assert_eq!(read("").unwrap(), "a long message\n");
+        assert_eq!(read(".1").unwrap(), "five\n");
+
+        // Without rotated files, the file starts over.
+        let mut fw = FileLogWriter::new(path.to_str().unwrap(), 8, 0).unwrap();
+        fw.write(Level::Info, "six").unwrap();
+        assert_eq!(read("").unwrap(), "six\n");
+        assert_eq!(read(".1").unwrap(), "five\n");
+    }
+
+    #[test]
+    fn test_syslog() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("log.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let mut writer = SyslogLogWriter::new(path.to_str().unwrap(), "Firecracker").unwrap();
+        writer.write(Level::Warn, "a warning").unwrap();
+
+        let mut buf = [0u8; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!("<12>Firecracker[{}]: a warning", process::id())
+        );
+
+        // The messages are dropped rather than blocking when the socket is full.
+        let mut result = Ok(());
+        for _ in 0..100_000 {
+            result = writer.write(Level::Info, "a message");
+            if result.is_err() {
+                break;
+            }
+        }
+        match result {
+            Err(LoggerError::LogWrite(ref e)) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+            _ => panic!("Writing to a full socket should fail."),
+        }
+
+        assert!(SyslogLogWriter::new(dir.path().join("none").to_str().unwrap(), "").is_err());
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut writer = RingBufferLogWriter::new(2);
+        assert_eq!(writer.lines(), Some(vec![]));
+        for msg in &["one", "two", "three"] {
+            writer.write(Level::Info, msg).unwrap();
+        }
+        assert_eq!(
+            writer.lines(),
+            Some(vec!["two".to_string(), "three".to_string()])
+        );
+
+        let mut writer = RingBufferLogWriter::new(0);
+        writer.write(Level::Info, "one").unwrap();
+        assert_eq!(writer.lines(), Some(vec![]));
+
+        let mut writer = open_writer(&LogDestination::RingBuffer(1), "").unwrap();
+        writer.write(Level::Info, "one").unwrap();
+        assert_eq!(writer.lines(), Some(vec!["one".to_string()]));
+    }
 }
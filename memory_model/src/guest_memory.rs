@@ -63,6 +63,20 @@ impl GuestMemory {
     /// Creates a container for guest memory regions.
     /// Valid memory regions are specified as a Vec of (Address, Size) tuples sorted by Address.
     pub fn new(ranges: &[(GuestAddress, usize)]) -> Result<GuestMemory> {
+        GuestMemory::with_mappings(ranges, MemoryMapping::new)
+    }
+
+    /// Creates a container for guest memory regions backed by private mappings, whose memory
+    /// can be given back to the host with `remove_range`.
+    /// Valid memory regions are specified as a Vec of (Address, Size) tuples sorted by Address.
+    pub fn new_private(ranges: &[(GuestAddress, usize)]) -> Result<GuestMemory> {
+        GuestMemory::with_mappings(ranges, MemoryMapping::new_private)
+    }
+
+    fn with_mappings(
+        ranges: &[(GuestAddress, usize)],
+        new_mapping: fn(usize) -> result::Result<MemoryMapping, mmap::Error>,
+    ) -> Result<GuestMemory> {
         if ranges.is_empty() {
             return Err(Error::NoMemoryRegions);
         }
@@ -79,7 +93,7 @@ impl GuestMemory {
                 }
             }
 
-            let mapping = MemoryMapping::new(range.1).map_err(Error::MemoryMappingFailed)?;
+            let mapping = new_mapping(range.1).map_err(Error::MemoryMappingFailed)?;
             regions.push(MemoryRegion {
                 mapping,
                 guest_base: range.0,
@@ -366,6 +380,34 @@ impl GuestMemory {
         })
     }
 
+    /// Releases the host memory backing `count` bytes of guest memory starting at `guest_addr`.
+    /// The range must be inside a single memory region. The memory is only given back to the
+    /// host if the guest memory was created with `new_private`, in which case the range reads as
+    /// zeroes until the guest writes it again.
+    ///
+    /// # Arguments
+    /// * `guest_addr` - Page aligned guest address of the start of the range.
+    /// * `count` - Size of the range in bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use memory_model::{GuestAddress, GuestMemory};
+    /// # fn test_remove_range() -> Result<(), ()> {
+    ///     let start_addr = GuestAddress(0x1000);
+    ///     let gm = GuestMemory::new_private(&vec![(start_addr, 0x2000)]).map_err(|_| ())?;
+    ///     gm.remove_range(GuestAddress(0x2000), 0x1000).map_err(|_| ())?;
+    ///     Ok(())
+    /// # }
+    /// ```
+    pub fn remove_range(&self, guest_addr: GuestAddress, count: usize) -> Result<()> {
+        self.do_in_region(guest_addr, count, |mapping, offset| {
+            mapping
+                .remove_range(offset, count)
+                .map_err(|e| Error::MemoryAccess(guest_addr, e))
+        })
+    }
+
     /// Converts a GuestAddress into a pointer in the address space of this
     /// process. This should only be necessary for giving addresses to the
     /// kernel, as with vhost ioctls. Normal reads/writes to guest memory should
@@ -394,6 +436,37 @@ impl GuestMemory {
         })
     }
 
+    /// Converts the range of `count` bytes starting at `guest_addr` into a pointer in the address
+    /// space of this process. The range must be inside a single memory region. Like
+    /// `get_host_address`, this should only be used for giving buffers to the kernel.
+    ///
+    /// # Arguments
+    /// * `guest_addr` - Guest address of the start of the range.
+    /// * `count` - Size of the range in bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use memory_model::{GuestAddress, GuestMemory};
+    /// # fn test_host_addr_range() -> Result<(), ()> {
+    ///     let start_addr = GuestAddress(0x1000);
+    ///     let gm = GuestMemory::new(&vec![(start_addr, 0x500)]).map_err(|_| ())?;
+    ///     assert!(gm.get_host_address_range(GuestAddress(0x1200), 0x100).is_ok());
+    ///     assert!(gm.get_host_address_range(GuestAddress(0x1200), 0x400).is_err());
+    ///     Ok(())
+    /// # }
+    /// ```
+    pub fn get_host_address_range(
+        &self,
+        guest_addr: GuestAddress,
+        count: usize,
+    ) -> Result<*mut u8> {
+        self.do_in_region(guest_addr, count, |mapping, offset| {
+            // This is safe; `do_in_region` already checks that the range is in bounds.
+            Ok(unsafe { mapping.as_ptr().add(offset) })
+        })
+    }
+
     /// Applies two functions, specified as callbacks, on the inner memory regions.
     ///
     /// # Arguments
@@ -645,4 +718,32 @@ mod tests {
             3
         );
     }
+
+    #[test]
+    fn test_remove_range() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x2000);
+        let gm =
+            GuestMemory::new_private(&vec![(start_addr1, 0x2000), (start_addr2, 0x2000)]).unwrap();
+        assert!(gm
+            .write_obj_at_addr(0xdead_beefu32, GuestAddress(0x1000))
+            .is_ok());
+        assert!(gm
+            .write_obj_at_addr(0xdead_beefu32, GuestAddress(0x2000))
+            .is_ok());
+
+        assert!(gm.remove_range(GuestAddress(0x1000), 0x1000).is_ok());
+        assert_eq!(
+            gm.read_obj_from_addr::<u32>(GuestAddress(0x1000)).unwrap(),
+            0
+        );
+        assert_eq!(
+            gm.read_obj_from_addr::<u32>(GuestAddress(0x2000)).unwrap(),
+            0xdead_beef
+        );
+
+        // The range cannot span more than one region, or be outside the guest memory.
+        assert!(gm.remove_range(GuestAddress(0x1000), 0x2000).is_err());
+        assert!(gm.remove_range(GuestAddress(0x4000), 0x1000).is_err());
+    }
 }
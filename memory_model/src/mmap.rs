@@ -25,6 +25,8 @@ pub enum Error {
     InvalidRange(usize, usize),
     /// Couldn't read from the given source.
     ReadFromSource(io::Error),
+    /// `madvise` returned the given error.
+    RemoveRange(io::Error),
     /// `mmap` returned the given error.
     SystemCallFailed(io::Error),
     /// Writing to memory failed
@@ -34,7 +36,7 @@ pub enum Error {
 }
 type Result<T> = std::result::Result<T, Error>;
 
-/// Wraps an anonymous shared memory mapping in the current process.
+/// Wraps an anonymous memory mapping in the current process.
 pub struct MemoryMapping {
     addr: *mut u8,
     size: usize,
@@ -53,6 +55,19 @@ impl MemoryMapping {
     /// # Arguments
     /// * `size` - Size of memory region in bytes.
     pub fn new(size: usize) -> Result<MemoryMapping> {
+        MemoryMapping::with_flags(size, libc::MAP_SHARED)
+    }
+
+    /// Creates an anonymous private mapping of `size` bytes. Unlike with a shared mapping, the
+    /// pages released with `remove_range` are given back to the host.
+    ///
+    /// # Arguments
+    /// * `size` - Size of memory region in bytes.
+    pub fn new_private(size: usize) -> Result<MemoryMapping> {
+        MemoryMapping::with_flags(size, libc::MAP_PRIVATE)
+    }
+
+    fn with_flags(size: usize, flags: libc::c_int) -> Result<MemoryMapping> {
         // This is safe because we are creating an anonymous mapping in a place not already used by
         // any other area in this process.
         let addr = unsafe {
@@ -60,7 +75,7 @@ impl MemoryMapping {
                 null_mut(),
                 size,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_ANONYMOUS | libc::MAP_SHARED | libc::MAP_NORESERVE,
+                libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | flags,
                 -1,
                 0,
             )
@@ -279,6 +294,39 @@ impl MemoryMapping {
         Ok(())
     }
 
+    /// Releases the physical memory backing `count` bytes starting at `mem_offset`. The range
+    /// stays mapped. For a private mapping, it reads as zeroes until it is written again, while
+    /// a shared mapping keeps its content and the memory backing it.
+    ///
+    /// # Examples
+    /// * Release the second page of the mapping.
+    ///
+    /// ```
+    /// #   use memory_model::MemoryMapping;
+    /// #   let mut mem_map = MemoryMapping::new_private(0x2000).unwrap();
+    ///     let res = mem_map.remove_range(0x1000, 0x1000);
+    ///     assert!(res.is_ok());
+    /// ```
+    pub fn remove_range(&self, mem_offset: usize, count: usize) -> Result<()> {
+        let (mem_end, fail) = mem_offset.overflowing_add(count);
+        if fail || mem_end > self.size() {
+            return Err(Error::InvalidRange(mem_offset, count));
+        }
+        // This is safe because the range was checked against the bounds of the mapping, and
+        // the memory is only accessed through volatile reads and writes.
+        let ret = unsafe {
+            libc::madvise(
+                self.addr.add(mem_offset) as *mut libc::c_void,
+                count,
+                libc::MADV_DONTNEED,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::RemoveRange(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
     unsafe fn as_slice(&self) -> &[u8] {
         // This is safe because we mapped the area at addr ourselves, so this slice will not
         // overflow. However, it is possible to alias.
@@ -408,4 +456,33 @@ mod tests {
         );
         assert_eq!(sink, vec![0; mem::size_of::<u32>()]);
     }
+
+    #[test]
+    fn test_remove_range() {
+        // The content of a shared mapping is kept.
+        let mem_map = MemoryMapping::new(0x1000).unwrap();
+        assert!(mem_map.write_obj(0xdead_beefu32, 0).is_ok());
+        assert!(mem_map.remove_range(0, 0x1000).is_ok());
+        assert_eq!(mem_map.read_obj::<u32>(0).unwrap(), 0xdead_beef);
+
+        let mem_map = MemoryMapping::new_private(0x3000).unwrap();
+        assert!(mem_map.write_obj(0xdead_beefu32, 0x1000).is_ok());
+        assert!(mem_map.write_obj(0xdead_beefu32, 0x2000).is_ok());
+
+        assert!(mem_map.remove_range(0x1000, 0x1000).is_ok());
+        assert_eq!(mem_map.read_obj::<u32>(0x1000).unwrap(), 0);
+        assert_eq!(mem_map.read_obj::<u32>(0x2000).unwrap(), 0xdead_beef);
+
+        // The range must be inside the mapping.
+        match mem_map.remove_range(0x2000, 0x2000) {
+            Err(Error::InvalidRange(0x2000, 0x2000)) => (),
+            _ => panic!("Expected an invalid range error."),
+        }
+        assert!(mem_map.remove_range(core::usize::MAX, 0x1000).is_err());
+        // The start of the range must be page aligned.
+        match mem_map.remove_range(0x10, 0x1000) {
+            Err(Error::RemoveRange(_)) => (),
+            _ => panic!("Expected a remove range error."),
+        }
+    }
 }
@@ -70,6 +70,16 @@ fn build_terminated_if_name(if_name: &str) -> Result<Vec<u8>> {
 
 impl Tap {
     pub fn open_named(if_name: &str) -> Result<Tap> {
+        Self::open_named_with_flags(if_name, 0)
+    }
+
+    /// Open a queue of the multi-queue tap interface `if_name`. Each call opens another queue of
+    /// the same interface.
+    pub fn open_named_multi_queue(if_name: &str) -> Result<Tap> {
+        Self::open_named_with_flags(if_name, net_gen::IFF_MULTI_QUEUE)
+    }
+
+    fn open_named_with_flags(if_name: &str, flags: u32) -> Result<Tap> {
         let terminated_if_name = build_terminated_if_name(if_name)?;
 
         let fd = unsafe {
@@ -97,7 +107,7 @@ impl Tap {
             let name_slice = &mut ifrn_name[..terminated_if_name.len()];
             name_slice.copy_from_slice(terminated_if_name.as_slice());
             *ifru_flags =
-                (net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR) as c_short;
+                (net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR | flags) as c_short;
         }
 
         // ioctl is safe since we call it with a valid tap fd and check the return
@@ -168,6 +178,47 @@ impl Tap {
         Ok(())
     }
 
+    /// Get the MTU of the tap interface.
+    pub fn mtu(&self) -> Result<u32> {
+        let sock = create_socket().map_err(Error::NetUtil)?;
+
+        let mut ifreq = self.get_ifreq();
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        #[allow(clippy::cast_lossless)]
+        let ret = unsafe {
+            ioctl_with_mut_ref(&sock, net_gen::sockios::SIOCGIFMTU as c_ulong, &mut ifreq)
+        };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        // We only access one field of the ifru union, hence this is safe.
+        Ok(unsafe { *ifreq.ifr_ifru.ifru_mtu.as_ref() } as u32)
+    }
+
+    /// Set the MTU of the tap interface.
+    pub fn set_mtu(&self, mtu: u32) -> Result<()> {
+        let sock = create_socket().map_err(Error::NetUtil)?;
+
+        let mut ifreq = self.get_ifreq();
+
+        // We only access one field of the ifru union, hence this is safe.
+        unsafe {
+            let ifru_mtu = ifreq.ifr_ifru.ifru_mtu.as_mut();
+            *ifru_mtu = mtu as c_int;
+        }
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        #[allow(clippy::cast_lossless)]
+        let ret = unsafe { ioctl_with_ref(&sock, net_gen::sockios::SIOCSIFMTU as c_ulong, &ifreq) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     /// Set the offload flags for the tap interface.
     pub fn set_offload(&self, flags: c_uint) -> Result<()> {
         // ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -216,6 +267,29 @@ impl Tap {
         Ok(())
     }
 
+    /// Attach or detach this queue of a multi-queue tap interface. The host only steers frames
+    /// to the attached queues.
+    pub fn set_queue_attached(&self, attached: bool) -> Result<()> {
+        let mut ifreq: net_gen::ifreq = Default::default();
+        // We only access one field of the ifru union, hence this is safe.
+        unsafe {
+            let ifru_flags = ifreq.ifr_ifru.ifru_flags.as_mut();
+            *ifru_flags = if attached {
+                net_gen::IFF_ATTACH_QUEUE
+            } else {
+                net_gen::IFF_DETACH_QUEUE
+            } as c_short;
+        }
+
+        // ioctl is safe. Called with a valid tap fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.tap_file, net_gen::TUNSETQUEUE(), &ifreq) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     fn get_ifreq(&self) -> net_gen::ifreq {
         let mut ifreq: net_gen::ifreq = Default::default();
 
@@ -442,6 +516,13 @@ mod tests {
         tap.set_offload(0).unwrap();
     }
 
+    #[test]
+    fn test_tap_mtu() {
+        let tap = Tap::new().unwrap();
+        tap.set_mtu(9000).unwrap();
+        assert_eq!(tap.mtu().unwrap(), 9000);
+    }
+
     #[test]
     fn test_tap_enable() {
         let tap = Tap::new().unwrap();
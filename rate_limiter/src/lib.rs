@@ -263,6 +263,8 @@ pub struct RateLimiter {
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
     timer_active: bool,
+    // When the timer was last set, in nanoseconds.
+    blocked_at_ns: u64,
 }
 
 impl PartialEq for RateLimiter {
@@ -351,6 +353,7 @@ impl RateLimiter {
             ops: ops_token_bucket,
             timer_fd,
             timer_active: false,
+            blocked_at_ns: 0,
         })
     }
 
@@ -379,6 +382,7 @@ impl RateLimiter {
             self.timer_fd
                 .set_state(TIMER_REFILL_STATE, SetTimeFlags::Default);
             self.timer_active = true;
+            self.blocked_at_ns = time::precise_time_ns();
         }
         success
     }
@@ -411,17 +415,21 @@ impl RateLimiter {
     /// This function needs to be called every time there is an event on the
     /// FD provided by this object's `AsRawFd` trait implementation.
     ///
+    /// Returns how long the limiter was blocked, from the failed `consume()` operation until now.
+    ///
     /// # Errors
     ///
     /// If the rate limiter is disabled or is not blocked, an error is returned.
-    pub fn event_handler(&mut self) -> Result<(), Error> {
+    pub fn event_handler(&mut self) -> Result<Duration, Error> {
         match self.timer_fd.read() {
             0 => Err(Error::SpuriousRateLimiterEvent(
                 "Rate limiter event handler called without a present timer",
             )),
             _ => {
                 self.timer_active = false;
-                Ok(())
+                Ok(Duration::from_nanos(
+                    time::precise_time_ns().saturating_sub(self.blocked_at_ns),
+                ))
             }
         }
     }
@@ -658,8 +666,9 @@ mod tests {
         assert!(l.is_blocked());
         // wait the other half of the timer period
         thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS / 2));
-        // the timer_fd should have an event on it by now
-        assert!(l.event_handler().is_ok());
+        // the timer_fd should have an event on it by now, and the limiter was blocked
+        // for at least the timer period
+        assert!(l.event_handler().unwrap() >= Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
         // limiter should now be unblocked
         assert!(!l.is_blocked());
         // try and succeed on another 100 bytes this time
@@ -4,6 +4,7 @@
 extern crate backtrace;
 #[macro_use(crate_version, crate_authors)]
 extern crate clap;
+extern crate futures;
 
 extern crate api_server;
 extern crate fc_util;
@@ -12,24 +13,31 @@ extern crate jailer;
 extern crate logger;
 extern crate mmds;
 extern crate seccomp;
+extern crate sys_util;
 extern crate vmm;
 
 use backtrace::Backtrace;
 use clap::{App, Arg};
+use futures::Future;
 
 use std::io::ErrorKind;
 use std::panic;
 use std::path::PathBuf;
 use std::process;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 
 use api_server::{ApiServer, Error};
 use fc_util::validators::validate_instance_id;
 use logger::{Metric, LOGGER, METRICS};
+use mmds::data_store::Mmds;
 use mmds::MMDS;
+use sys_util::EventFd;
+use vmm::default_syscalls;
 use vmm::signal_handler::register_signal_handlers;
+use vmm::vmm_config::config_file::ConfigFile;
 use vmm::vmm_config::instance_info::{InstanceInfo, InstanceState};
+use vmm::VmmAction;
 
 const DEFAULT_API_SOCK_PATH: &str = "/tmp/firecracker.socket";
 const DEFAULT_INSTANCE_ID: &str = "anonymous-instance";
@@ -96,6 +104,18 @@ fn main() {
                 .default_value("2")
                 .possible_values(&["0", "1", "2"]),
         )
+        .arg(
+            Arg::with_name("config-file")
+                .long("config-file")
+                .help("Path to a JSON file describing the microVM, which is booted right away")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-api")
+                .long("no-api")
+                .help("Do not open the API socket; the microVM is configured from --config-file")
+                .requires("config-file"),
+        )
         .arg(
             Arg::with_name("start-time-us")
                 .long("start-time-us")
@@ -142,6 +162,15 @@ fn main() {
             .expect("'start-time-cpu_us' parameter expected to be of 'u64' type.")
     });
 
+    // The logger is not initialized yet, so the errors are printed to stderr.
+    let config_file = cmd_arguments.value_of("config-file").map(|path| {
+        ConfigFile::from_file(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+        })
+    });
+    let no_api = cmd_arguments.is_present("no-api");
+
     let shared_info = Arc::new(RwLock::new(InstanceInfo {
         state: InstanceState::Uninitialized,
         id: instance_id,
@@ -149,15 +178,47 @@ fn main() {
     }));
     let mmds_info = MMDS.clone();
     let (to_vmm, from_api) = channel();
-    let server =
-        ApiServer::new(mmds_info, shared_info.clone(), to_vmm).expect("Cannot create API server");
-
-    let api_event_fd = server
-        .get_event_fd_clone()
-        .expect("Cannot clone API eventFD.");
+    let server = if no_api {
+        None
+    } else {
+        Some(
+            ApiServer::new(mmds_info.clone(), shared_info.clone(), to_vmm.clone())
+                .expect("Cannot create API server"),
+        )
+    };
+
+    let vmm_event_fd = match server {
+        Some(ref server) => server
+            .get_event_fd_clone()
+            .expect("Cannot clone API eventFD."),
+        None => EventFd::new().expect("Cannot create VMM eventFD."),
+    };
+
+    let vmm_thread_handle = vmm::start_vmm_thread(
+        shared_info,
+        vmm_event_fd.try_clone().expect("Cannot clone VMM eventFD."),
+        from_api,
+        seccomp_level,
+    );
+
+    if let Some(config) = config_file {
+        configure_from_file(config, &mmds_info, &to_vmm, &vmm_event_fd);
+    }
 
-    let _vmm_thread_handle =
-        vmm::start_vmm_thread(shared_info, api_event_fd, from_api, seccomp_level);
+    let server = match server {
+        Some(server) => server,
+        None => {
+            // Without the API, this thread only waits for the VMM to exit the process.
+            if let Err(e) = default_syscalls::set_seccomp_level(seccomp_level) {
+                panic!(
+                    "Failed to set the requested seccomp filters on the main thread: Error: {:?}",
+                    e
+                );
+            }
+            vmm_thread_handle.join().expect("VMM thread panicked.");
+            process::exit(i32::from(vmm::FC_EXIT_CODE_UNEXPECTED_ERROR));
+        }
+    };
 
     match server.bind_and_run(bind_path, start_time_us, start_time_cpu_us, seccomp_level) {
         Ok(_) => (),
@@ -174,6 +235,48 @@ fn main() {
     }
 }
 
+// Configures and starts the microVM described by the configuration file, by sending the
+// equivalent requests to the VMM thread one at a time, just like the API server does.
+// Exits the process if any of them fails.
+fn configure_from_file(
+    mut config: ConfigFile,
+    mmds_info: &Mutex<Mmds>,
+    to_vmm: &Sender<Box<VmmAction>>,
+    vmm_event_fd: &EventFd,
+) {
+    // The logger is only configured by the actions below, so this error is printed to stderr.
+    if let Some(data) = config.mmds.take() {
+        if let Err(e) = mmds_info
+            .lock()
+            .expect("Failed to acquire lock on MMDS info")
+            .put_data(data)
+        {
+            eprintln!("Cannot set the MMDS contents: {}", e.to_string());
+            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+        }
+    }
+
+    for (action, outcome_receiver) in config.into_actions() {
+        to_vmm
+            .send(Box::new(action))
+            .expect("Cannot send request to the VMM thread.");
+        vmm_event_fd
+            .write(1)
+            .expect("Cannot notify the VMM thread.");
+        match outcome_receiver.wait() {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => {
+                error!(
+                    "Cannot configure the microVM from the configuration file: {}",
+                    e
+                );
+                process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+            }
+            Err(_) => panic!("The VMM thread dropped the request."),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tempfile;
@@ -181,7 +284,7 @@ mod tests {
     use self::tempfile::NamedTempFile;
     use super::*;
 
-    use logger::AppInfo;
+    use logger::{AppInfo, LogDestination};
     use std::fs::File;
     use std::io::BufRead;
     use std::io::BufReader;
@@ -252,8 +355,8 @@ mod tests {
             .init(
                 &AppInfo::new("Firecracker", "1.0"),
                 "TEST-ID",
-                log_file_temp.path().to_str().unwrap().to_string(),
-                metrics_file_temp.path().to_str().unwrap().to_string(),
+                &LogDestination::Fifo(log_file_temp.path().to_str().unwrap().to_string()),
+                &LogDestination::Fifo(metrics_file_temp.path().to_str().unwrap().to_string()),
                 &[],
             )
             .expect("Could not initialize logger.");
@@ -0,0 +1,574 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{io, mem, ptr, result};
+
+use libc::{c_long, c_void, syscall};
+
+use EventFd;
+
+// The io_uring syscalls have the same numbers on x86_64 and aarch64, and they are missing from
+// rust libc.
+/// Number of the io_uring_setup syscall.
+pub const SYS_IO_URING_SETUP: c_long = 425;
+/// Number of the io_uring_enter syscall.
+pub const SYS_IO_URING_ENTER: c_long = 426;
+/// Number of the io_uring_register syscall.
+pub const SYS_IO_URING_REGISTER: c_long = 427;
+
+// Offsets used for mapping the rings, from linux/io_uring.h.
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_REGISTER_EVENTFD: u32 = 4;
+const IORING_REGISTER_PROBE: u32 = 8;
+
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IO_URING_OP_SUPPORTED: u16 = 1;
+// The probe only needs to cover the operations in use.
+const PROBE_OPS_LEN: usize = IORING_OP_WRITE as usize + 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoUringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SubmissionEntry {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ProbeOp {
+    op: u8,
+    resv: u8,
+    flags: u16,
+    resv2: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Probe {
+    last_op: u8,
+    ops_len: u8,
+    resv: u16,
+    resv2: [u32; 3],
+    ops: [ProbeOp; PROBE_OPS_LEN],
+}
+
+#[repr(C)]
+struct CompletionEntry {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// An I/O operation that can be submitted to an `IoUring`.
+pub struct Operation {
+    opcode: u8,
+    fd: RawFd,
+    addr: u64,
+    len: u32,
+    offset: u64,
+    user_data: u64,
+}
+
+impl Operation {
+    /// Reads `len` bytes at `offset` in the file `fd` into the buffer at `buf`. The `user_data`
+    /// is handed back in the completion of the operation.
+    pub fn read(fd: RawFd, buf: *mut u8, len: u32, offset: u64, user_data: u64) -> Operation {
+        Operation {
+            opcode: IORING_OP_READ,
+            fd,
+            addr: buf as u64,
+            len,
+            offset,
+            user_data,
+        }
+    }
+
+    /// Writes `len` bytes from the buffer at `buf` at `offset` in the file `fd`. The `user_data`
+    /// is handed back in the completion of the operation.
+    pub fn write(fd: RawFd, buf: *const u8, len: u32, offset: u64, user_data: u64) -> Operation {
+        Operation {
+            opcode: IORING_OP_WRITE,
+            fd,
+            addr: buf as u64,
+            len,
+            offset,
+            user_data,
+        }
+    }
+
+    /// Flushes the data and the metadata of the file `fd` to the disk. The `user_data` is handed
+    /// back in the completion of the operation.
+    pub fn fsync(fd: RawFd, user_data: u64) -> Operation {
+        Operation {
+            opcode: IORING_OP_FSYNC,
+            fd,
+            addr: 0,
+            len: 0,
+            offset: 0,
+            user_data,
+        }
+    }
+}
+
+/// The outcome of an operation submitted to an `IoUring`.
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    /// The `user_data` of the operation.
+    pub user_data: u64,
+    result: i32,
+}
+
+impl Completion {
+    /// Returns the number of bytes transferred by the operation, or the error it failed with.
+    pub fn result(&self) -> result::Result<u32, io::Error> {
+        if self.result < 0 {
+            Err(io::Error::from_raw_os_error(-self.result))
+        } else {
+            Ok(self.result as u32)
+        }
+    }
+}
+
+// A shared mapping of one of the memory areas of an io_uring.
+struct RingMapping {
+    addr: *mut u8,
+    size: usize,
+}
+
+impl RingMapping {
+    fn new(fd: RawFd, size: usize, offset: i64) -> result::Result<RingMapping, io::Error> {
+        // This is safe because we are creating a new mapping, and we check the return value.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RingMapping {
+            addr: addr as *mut u8,
+            size,
+        })
+    }
+
+    // The caller must make sure that `offset` is inside the mapping and properly aligned for `T`.
+    unsafe fn ptr_at<T>(&self, offset: u32) -> *mut T {
+        self.addr.add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for RingMapping {
+    fn drop(&mut self) {
+        // This is safe because we own the mapping.
+        unsafe {
+            libc::munmap(self.addr as *mut c_void, self.size);
+        }
+    }
+}
+
+/// A safe wrapper around a Linux io_uring (man 7 io_uring), for submitting file I/O operations
+/// and collecting their completions asynchronously.
+pub struct IoUring {
+    fd: File,
+    sq_ring: RingMapping,
+    cq_ring: RingMapping,
+    sqes: RingMapping,
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+    sq_entries: u32,
+    cq_entries: u32,
+    // Operations pushed to the submission queue but not yet submitted.
+    to_submit: u32,
+    // Operations pushed to the submission queue whose completion was not yet popped.
+    in_flight: u32,
+}
+
+// The rings are only accessed through `&mut self`, or atomically.
+unsafe impl Send for IoUring {}
+
+impl IoUring {
+    /// Creates a new io_uring with room for `entries` operations in the submission queue.
+    ///
+    /// Fails if the kernel does not support all the operations of `Operation`. The read and write
+    /// operations are only available since Linux 5.6, while io_uring itself dates from 5.1.
+    pub fn new(entries: u32) -> result::Result<IoUring, io::Error> {
+        let mut params = IoUringParams::default();
+        // This is safe because the kernel only writes inside `params`, and we check the return
+        // value.
+        let ret = unsafe {
+            syscall(
+                SYS_IO_URING_SETUP,
+                entries,
+                &mut params as *mut IoUringParams,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // This is safe because we checked ret for success and know the kernel gave us an fd that
+        // we own.
+        let fd = unsafe { File::from_raw_fd(ret as RawFd) };
+
+        let sq_ring = RingMapping::new(
+            fd.as_raw_fd(),
+            params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>(),
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq_ring = RingMapping::new(
+            fd.as_raw_fd(),
+            params.cq_off.cqes as usize
+                + params.cq_entries as usize * mem::size_of::<CompletionEntry>(),
+            IORING_OFF_CQ_RING,
+        )?;
+        let sqes = RingMapping::new(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * mem::size_of::<SubmissionEntry>(),
+            IORING_OFF_SQES,
+        )?;
+
+        let ring = IoUring {
+            fd,
+            sq_ring,
+            cq_ring,
+            sqes,
+            sq_entries: params.sq_entries,
+            cq_entries: params.cq_entries,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            to_submit: 0,
+            in_flight: 0,
+        };
+        ring.check_ops()?;
+        Ok(ring)
+    }
+
+    // Checks that the kernel supports the operations which can be pushed. The probe itself is
+    // only available since Linux 5.6, so it fails with EINVAL on older kernels.
+    fn check_ops(&self) -> result::Result<(), io::Error> {
+        let mut probe = Probe::default();
+        // This is safe because the kernel only writes inside `probe`, whose length is passed, and
+        // we check the return value.
+        let ret = unsafe {
+            syscall(
+                SYS_IO_URING_REGISTER,
+                self.fd.as_raw_fd(),
+                IORING_REGISTER_PROBE,
+                &mut probe as *mut Probe,
+                PROBE_OPS_LEN,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for op in &[IORING_OP_FSYNC, IORING_OP_READ, IORING_OP_WRITE] {
+            if *op >= probe.ops_len || probe.ops[*op as usize].flags & IO_URING_OP_SUPPORTED == 0 {
+                return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the kernel write to `evt` whenever an operation completes.
+    pub fn register_eventfd(&self, evt: &EventFd) -> result::Result<(), io::Error> {
+        let evt_fd: RawFd = evt.as_raw_fd();
+        // This is safe because the kernel only reads the fd, and we check the return value.
+        let ret = unsafe {
+            syscall(
+                SYS_IO_URING_REGISTER,
+                self.fd.as_raw_fd(),
+                IORING_REGISTER_EVENTFD,
+                &evt_fd as *const RawFd,
+                1,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the number of operations pushed whose completion was not yet popped.
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight
+    }
+
+    /// Adds `op` to the submission queue. It only reaches the kernel on the next `submit`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer of `op` must stay valid until its completion is popped.
+    pub unsafe fn push(&mut self, op: Operation) -> result::Result<(), io::Error> {
+        // Never have more operations in flight than the completion queue can hold.
+        if self.in_flight >= self.cq_entries || self.to_submit >= self.sq_entries {
+            return Err(io::Error::from_raw_os_error(libc::EBUSY));
+        }
+
+        let mask = *self.sq_ring.ptr_at::<u32>(self.sq_off.ring_mask);
+        let tail = &*self.sq_ring.ptr_at::<AtomicU32>(self.sq_off.tail);
+        let tail_value = tail.load(Ordering::Acquire);
+        let index = tail_value & mask;
+
+        let sqe = &mut *self
+            .sqes
+            .ptr_at::<SubmissionEntry>(index * mem::size_of::<SubmissionEntry>() as u32);
+        *sqe = SubmissionEntry {
+            opcode: op.opcode,
+            fd: op.fd,
+            off: op.offset,
+            addr: op.addr,
+            len: op.len,
+            user_data: op.user_data,
+            ..Default::default()
+        };
+        *self
+            .sq_ring
+            .ptr_at::<u32>(self.sq_off.array + index * mem::size_of::<u32>() as u32) = index;
+
+        // The entry must be fully written before the kernel sees the new tail.
+        tail.store(tail_value.wrapping_add(1), Ordering::Release);
+        self.to_submit += 1;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Submits the pushed operations to the kernel, and returns their number.
+    pub fn submit(&mut self) -> result::Result<u32, io::Error> {
+        self.enter(0)
+    }
+
+    /// Submits the pushed operations to the kernel, and waits until at least `min_complete`
+    /// operations have completed.
+    pub fn submit_and_wait(&mut self, min_complete: u32) -> result::Result<u32, io::Error> {
+        self.enter(min_complete)
+    }
+
+    fn enter(&mut self, min_complete: u32) -> result::Result<u32, io::Error> {
+        if self.to_submit == 0 && min_complete == 0 {
+            return Ok(0);
+        }
+        let flags = if min_complete > 0 {
+            IORING_ENTER_GETEVENTS
+        } else {
+            0
+        };
+        // This is safe because the kernel only accesses the rings we mapped, and we check the
+        // return value.
+        let ret = unsafe {
+            syscall(
+                SYS_IO_URING_ENTER,
+                self.fd.as_raw_fd(),
+                self.to_submit,
+                min_complete,
+                flags,
+                ptr::null::<c_void>(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let submitted = ret as u32;
+        self.to_submit -= submitted;
+        Ok(submitted)
+    }
+
+    /// Removes the oldest completion from the completion queue, if any.
+    pub fn pop(&mut self) -> Option<Completion> {
+        // This is safe because the offsets were given by the kernel and are inside the mapping.
+        unsafe {
+            let mask = *self.cq_ring.ptr_at::<u32>(self.cq_off.ring_mask);
+            let head = &*self.cq_ring.ptr_at::<AtomicU32>(self.cq_off.head);
+            let tail = &*self.cq_ring.ptr_at::<AtomicU32>(self.cq_off.tail);
+            let head_value = head.load(Ordering::Acquire);
+            if head_value == tail.load(Ordering::Acquire) {
+                return None;
+            }
+
+            let cqe = &*self.cq_ring.ptr_at::<CompletionEntry>(
+                self.cq_off.cqes + (head_value & mask) * mem::size_of::<CompletionEntry>() as u32,
+            );
+            let completion = Completion {
+                user_data: cqe.user_data,
+                result: cqe.res,
+            };
+            // The entry must be fully read before the kernel can reuse it.
+            head.store(head_value.wrapping_add(1), Ordering::Release);
+            self.in_flight -= 1;
+            Some(completion)
+        }
+    }
+}
+
+impl AsRawFd for IoUring {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::tempfile;
+    use super::*;
+
+    #[test]
+    fn test_abi_sizes() {
+        assert_eq!(mem::size_of::<IoUringParams>(), 120);
+        assert_eq!(mem::size_of::<SubmissionEntry>(), 64);
+        assert_eq!(mem::size_of::<CompletionEntry>(), 16);
+        assert_eq!(mem::size_of::<ProbeOp>(), 8);
+        assert_eq!(mem::size_of::<Probe>(), 16 + 8 * PROBE_OPS_LEN);
+    }
+
+    #[test]
+    fn test_read_write_fsync() {
+        let file = tempfile().unwrap();
+        let mut ring = IoUring::new(4).unwrap();
+        let evt = EventFd::new().unwrap();
+        ring.register_eventfd(&evt).unwrap();
+        assert_eq!(ring.pop(), None);
+
+        let data = [0xabu8; 512];
+        unsafe {
+            ring.push(Operation::write(
+                file.as_raw_fd(),
+                data.as_ptr(),
+                512,
+                512,
+                1,
+            ))
+            .unwrap();
+            ring.push(Operation::fsync(file.as_raw_fd(), 2)).unwrap();
+        }
+        assert_eq!(ring.in_flight(), 2);
+        assert_eq!(ring.submit_and_wait(2).unwrap(), 2);
+        assert!(evt.read().unwrap() >= 1);
+
+        let mut completions = vec![ring.pop().unwrap(), ring.pop().unwrap()];
+        completions.sort_by_key(|c| c.user_data);
+        assert_eq!(completions[0].user_data, 1);
+        assert_eq!(completions[0].result().unwrap(), 512);
+        assert_eq!(completions[1].user_data, 2);
+        assert_eq!(completions[1].result().unwrap(), 0);
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.in_flight(), 0);
+        assert_eq!(file.metadata().unwrap().len(), 1024);
+
+        let mut buf = [0u8; 512];
+        unsafe {
+            ring.push(Operation::read(
+                file.as_raw_fd(),
+                buf.as_mut_ptr(),
+                512,
+                512,
+                3,
+            ))
+            .unwrap();
+        }
+        ring.submit_and_wait(1).unwrap();
+        let completion = ring.pop().unwrap();
+        assert_eq!(completion.user_data, 3);
+        assert_eq!(completion.result().unwrap(), 512);
+        assert_eq!(&buf[..], &data[..]);
+
+        // Operations on an invalid file descriptor fail in their completion.
+        unsafe {
+            ring.push(Operation::fsync(-1, 4)).unwrap();
+        }
+        ring.submit_and_wait(1).unwrap();
+        let completion = ring.pop().unwrap();
+        assert_eq!(
+            completion.result().unwrap_err().raw_os_error(),
+            Some(libc::EBADF)
+        );
+    }
+
+    #[test]
+    fn test_full_ring() {
+        let file = tempfile().unwrap();
+        let mut ring = IoUring::new(1).unwrap();
+        unsafe {
+            ring.push(Operation::fsync(file.as_raw_fd(), 0)).unwrap();
+            assert_eq!(
+                ring.push(Operation::fsync(file.as_raw_fd(), 1))
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EBUSY)
+            );
+        }
+        ring.submit_and_wait(1).unwrap();
+        assert!(ring.pop().unwrap().result().is_ok());
+    }
+}
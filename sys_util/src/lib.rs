@@ -14,11 +14,13 @@ use libc::c_int;
 pub mod ioctl;
 
 mod eventfd;
+mod io_uring;
 mod signal;
 mod struct_util;
 mod terminal;
 
 pub use eventfd::*;
+pub use io_uring::*;
 pub use ioctl::*;
 pub use signal::*;
 pub use struct_util::{read_struct, read_struct_slice};
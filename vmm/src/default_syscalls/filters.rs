@@ -25,6 +25,7 @@ mod libc_patch {
 use self::libc_patch::{SYS_fcntl, SYS_fstat, SYS_lseek, SYS_mmap, SYS_newfstatat};
 #[cfg(target_arch = "x86_64")]
 use libc::{SYS_fcntl, SYS_fstat, SYS_lseek, SYS_mmap};
+use sys_util::{SYS_IO_URING_ENTER, SYS_IO_URING_REGISTER, SYS_IO_URING_SETUP};
 
 /// The default filter containing the white listed syscall rules required by `Firecracker` to
 /// function.
@@ -47,8 +48,12 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_epoll_pwait),
             #[cfg(all(target_env = "gnu", target_arch = "x86_64"))]
             allow_syscall(libc::SYS_epoll_wait),
+            // Used for creating the events of the devices attached after boot.
+            allow_syscall(libc::SYS_eventfd2),
             allow_syscall(libc::SYS_exit),
             allow_syscall(libc::SYS_exit_group),
+            // Used for the discard and write zeroes requests of the block devices.
+            allow_syscall(libc::SYS_fallocate),
             allow_syscall_if(
                 SYS_fcntl,
                 or![and![
@@ -57,6 +62,10 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                 ]],
             ),
             allow_syscall(SYS_fstat),
+            // Used for flushing the disk images of the block devices.
+            allow_syscall(libc::SYS_fsync),
+            // Used for creating the overlays of the block devices.
+            allow_syscall(libc::SYS_ftruncate),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(SYS_newfstatat),
             allow_syscall_if(
@@ -69,10 +78,24 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     and![Cond::new(1, Eq, super::FUTEX_CMP_REQUEUE_PRIVATE)?],
                 ],
             ),
+            // Used by glibc's `pthread_kill` when kicking vCPUs out of KVM_RUN.
+            #[cfg(target_env = "gnu")]
+            allow_syscall(libc::SYS_getpid),
             allow_syscall(libc::SYS_getrandom),
             allow_syscall_if(libc::SYS_ioctl, super::create_ioctl_seccomp_rule()?),
+            // Used by the block devices performing asynchronous I/O. Only the registration of an
+            // eventfd and the probing of the supported operations are allowed.
+            allow_syscall(SYS_IO_URING_ENTER),
+            allow_syscall_if(
+                SYS_IO_URING_REGISTER,
+                or![
+                    and![Cond::new(1, Eq, super::IORING_REGISTER_EVENTFD)?],
+                    and![Cond::new(1, Eq, super::IORING_REGISTER_PROBE)?],
+                ],
+            ),
+            allow_syscall(SYS_IO_URING_SETUP),
             allow_syscall(SYS_lseek),
-            #[cfg(target_env = "musl")]
+            // Used by the balloon device for releasing guest memory, and by the musl allocator.
             allow_syscall_if(
                 libc::SYS_madvise,
                 or![and![Cond::new(2, Eq, libc::MADV_DONTNEED as u64)?],],
@@ -86,12 +109,33 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_pipe),
             allow_syscall(libc::SYS_read),
             allow_syscall(libc::SYS_readv),
+            // Used for rotating the log and metrics files.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_rename),
+            #[cfg(target_arch = "aarch64")]
+            allow_syscall(libc::SYS_renameat),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
+            // Used for sending the logs and metrics to a syslog socket.
+            allow_syscall(libc::SYS_sendto),
             allow_syscall(libc::SYS_sigaltstack),
+            // Used for setting the MTU of the tap devices of the network interfaces attached
+            // after boot.
+            allow_syscall_if(
+                libc::SYS_socket,
+                or![and![
+                    Cond::new(0, Eq, libc::AF_INET as u64)?,
+                    Cond::new(1, Eq, libc::SOCK_DGRAM as u64)?,
+                ]],
+            ),
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_stat),
+            // `pthread_kill` is used for kicking vCPUs out of KVM_RUN on pause/resume.
+            #[cfg(target_env = "gnu")]
+            allow_syscall(libc::SYS_tgkill),
+            #[cfg(target_env = "musl")]
+            allow_syscall(libc::SYS_tkill),
             allow_syscall(libc::SYS_timerfd_create),
             allow_syscall(libc::SYS_timerfd_settime),
             allow_syscall(libc::SYS_write),
@@ -34,6 +34,10 @@ const EPOLL_CTL_DEL: u64 = 2;
 const FCNTL_FD_CLOEXEC: u64 = 1;
 const FCNTL_F_SETFD: u64 = 2;
 
+// See include/uapi/linux/io_uring.h in the kernel code.
+const IORING_REGISTER_EVENTFD: u64 = 4;
+const IORING_REGISTER_PROBE: u64 = 8;
+
 // See include/uapi/linux/futex.h in the kernel code.
 const FUTEX_WAIT: u64 = 0;
 const FUTEX_WAKE: u64 = 1;
@@ -77,11 +81,31 @@ const KVM_SET_LAPIC: u64 = 0x4400_ae8f;
 const KVM_GET_SREGS: u64 = 0x8138_ae83;
 const KVM_GET_LAPIC: u64 = 0x8400_ae8e;
 const KVM_GET_SUPPORTED_CPUID: u64 = 0xc008_ae05;
+// Used for saving the vCPU and VM states when creating a snapshot.
+const KVM_GET_REGS: u64 = 0x8090_ae81;
+const KVM_GET_FPU: u64 = 0x81a0_ae8c;
+const KVM_GET_MSRS: u64 = 0xc008_ae88;
+const KVM_GET_MP_STATE: u64 = 0x8004_ae98;
+const KVM_GET_VCPU_EVENTS: u64 = 0x8040_ae9f;
+const KVM_GET_DEBUGREGS: u64 = 0x8080_aea1;
+const KVM_GET_XSAVE: u64 = 0x9000_aea4;
+const KVM_GET_XCRS: u64 = 0x8188_aea6;
+const KVM_GET_IRQCHIP: u64 = 0xc208_ae62;
+const KVM_GET_PIT2: u64 = 0x8070_ae9f;
+const KVM_GET_CLOCK: u64 = 0x8030_ae7c;
 
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNSETIFF: u64 = 0x4004_54ca;
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 const TUNSETVNETHDRSZ: u64 = 0x4004_54d8;
+// Used for attaching and detaching the queues of a multi-queue tap device, when the guest changes
+// the number of queue pairs of a network device.
+const TUNSETQUEUE: u64 = 0x4004_54d9;
+
+// See include/uapi/linux/sockios.h in the kernel code. Used for setting the MTU of the tap
+// devices of the network interfaces attached after boot.
+const SIOCGIFMTU: u64 = 0x8921;
+const SIOCSIFMTU: u64 = 0x8922;
 
 #[cfg(feature = "vsock")]
 mod vsock_ioctls {
@@ -122,6 +146,9 @@ fn create_common_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, Eq, TUNSETIFF)?],
         and![Cond::new(1, Eq, TUNSETOFFLOAD)?],
         and![Cond::new(1, Eq, TUNSETVNETHDRSZ)?],
+        and![Cond::new(1, Eq, TUNSETQUEUE)?],
+        and![Cond::new(1, Eq, SIOCGIFMTU)?],
+        and![Cond::new(1, Eq, SIOCSIFMTU)?],
         and![Cond::new(1, Eq, KVM_GET_LAPIC)?],
         and![Cond::new(1, Eq, KVM_GET_SREGS)?],
         and![Cond::new(1, Eq, KVM_RUN)?],
@@ -131,6 +158,17 @@ fn create_common_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, Eq, KVM_SET_MSRS)?],
         and![Cond::new(1, Eq, KVM_SET_REGS)?],
         and![Cond::new(1, Eq, KVM_SET_SREGS)?],
+        and![Cond::new(1, Eq, KVM_GET_REGS)?],
+        and![Cond::new(1, Eq, KVM_GET_FPU)?],
+        and![Cond::new(1, Eq, KVM_GET_MSRS)?],
+        and![Cond::new(1, Eq, KVM_GET_MP_STATE)?],
+        and![Cond::new(1, Eq, KVM_GET_VCPU_EVENTS)?],
+        and![Cond::new(1, Eq, KVM_GET_DEBUGREGS)?],
+        and![Cond::new(1, Eq, KVM_GET_XSAVE)?],
+        and![Cond::new(1, Eq, KVM_GET_XCRS)?],
+        and![Cond::new(1, Eq, KVM_GET_IRQCHIP)?],
+        and![Cond::new(1, Eq, KVM_GET_PIT2)?],
+        and![Cond::new(1, Eq, KVM_GET_CLOCK)?],
     ])
 }
 
@@ -164,11 +202,19 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
 }
 
 #[cfg(test)]
-#[cfg(target_env = "musl")]
 mod tests {
+    extern crate tempfile;
+
     use super::*;
+    use devices::virtio::PcapWriter;
+    use logger::{open_writer, Level, LogDestination};
     use seccomp::SeccompFilter;
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
     use std::thread;
+    use sys_util::{EventFd, IoUring};
+
+    use self::tempfile::TempDir;
 
     const EXTRA_SYSCALLS: [i64; 5] = [
         libc::SYS_clone,
@@ -194,6 +240,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(target_env = "musl")]
     fn test_basic_seccomp() {
         // Spawn a new thread before running the tests because all tests run
         // in the same thread. Otherwise other tests will fail because of the
@@ -207,6 +254,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(target_env = "musl")]
     fn test_advanced_seccomp() {
         // Spawn a new thread before running the tests because all tests run
         // in the same thread. Otherwise other tests will fail because of the
@@ -218,4 +266,103 @@ mod tests {
         .join()
         .unwrap();
     }
+
+    #[test]
+    fn test_log_writers_seccomp() {
+        let dir = TempDir::new().unwrap();
+        let log_path = dir.path().join("log");
+        let socket_path = dir.path().join("log.sock");
+        let receiver = UnixDatagram::bind(&socket_path).unwrap();
+        let mut file_writer = open_writer(
+            &LogDestination::File {
+                path: log_path.to_str().unwrap().to_string(),
+                max_size: 8,
+                max_files: 2,
+            },
+            "Firecracker",
+        )
+        .unwrap();
+        let mut syslog_writer = open_writer(
+            &LogDestination::Syslog(socket_path.to_str().unwrap().to_string()),
+            "Firecracker",
+        )
+        .unwrap();
+
+        // The writers are used after boot, with the filter installed.
+        thread::spawn(move || {
+            add_syscalls_install_filter(default_filter().unwrap());
+            for msg in &["one", "two", "three"] {
+                file_writer.write(Level::Info, msg).unwrap();
+            }
+            syslog_writer.write(Level::Warn, "a warning").unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let mut rotated_path = log_path.clone().into_os_string();
+        rotated_path.push(".1");
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "three\n");
+        assert_eq!(fs::read_to_string(rotated_path).unwrap(), "one\ntwo\n");
+        let mut buf = [0u8; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).ends_with("]: a warning"));
+    }
+
+    #[test]
+    fn test_pcap_writer_seccomp() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.pcap");
+        // The file holds the pcap header and a single frame.
+        let mut writer = PcapWriter::new(path.to_str().unwrap(), 64, 2).unwrap();
+
+        // The capture is started and rotated after boot, with the filter installed.
+        thread::spawn(move || {
+            add_syscalls_install_filter(default_filter().unwrap());
+            for _ in 0..3 {
+                writer.write_frame(&[0u8; 20]).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+
+        let mut rotated_path = path.clone().into_os_string();
+        rotated_path.push(".2");
+        assert_eq!(fs::metadata(&path).unwrap().len(), 60);
+        assert_eq!(fs::metadata(rotated_path).unwrap().len(), 60);
+    }
+
+    #[test]
+    fn test_tap_mtu_seccomp() {
+        // The MTU of a network interface attached after boot is set on its tap device with the
+        // filter installed. Without a tap device, the ioctls fail, but are not trapped.
+        thread::spawn(move || {
+            add_syscalls_install_filter(default_filter().unwrap());
+            let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+            assert!(sock >= 0);
+            let mut ifreq = [0u8; 40];
+            ifreq[..12].copy_from_slice(b"fc_no_such_0");
+            for request in &[SIOCGIFMTU, SIOCSIFMTU] {
+                let ret = unsafe { libc::ioctl(sock, *request as _, ifreq.as_mut_ptr()) };
+                assert_eq!(ret, -1);
+            }
+            unsafe { libc::close(sock) };
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_io_uring_seccomp() {
+        let evt = EventFd::new().unwrap();
+
+        // The io_uring of a drive is created when the drive is attached, after the filter is
+        // installed.
+        thread::spawn(move || {
+            add_syscalls_install_filter(default_filter().unwrap());
+            let ring = IoUring::new(4).unwrap();
+            ring.register_eventfd(&evt).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
 }
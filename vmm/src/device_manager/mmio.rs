@@ -8,17 +8,23 @@
 use std::collections::HashMap;
 use std::os::unix::io::AsRawFd;
 use std::sync::{Arc, Mutex};
-use std::{fmt, io};
+use std::{fmt, io, mem};
 
 #[cfg(target_arch = "aarch64")]
 use arch::aarch64::DeviceInfoForFDT;
 use arch::DeviceType;
 use devices;
-use devices::virtio::TYPE_BLOCK;
+use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK};
 use devices::BusDevice;
 use kernel_cmdline;
+use kvm_bindings::{
+    kvm_ioeventfd, kvm_ioeventfd_flag_nr_datamatch, kvm_ioeventfd_flag_nr_deassign, kvm_irqfd,
+    KVM_IRQFD_FLAG_DEASSIGN,
+};
 use kvm_ioctls::{IoEventAddress, VmFd};
 use memory_model::GuestMemory;
+use sys_util::{ioctl_with_ref, EventFd};
+use vmm_config::microvm::MmioConfig;
 
 /// Errors for MMIO device manager.
 #[derive(Debug)]
@@ -41,6 +47,14 @@ pub enum Error {
     DeviceNotFound,
     /// Failed to update the mmio device.
     UpdateFailed,
+    /// All the hot-plug slots are in use.
+    NoFreeSlot,
+    /// The device to unplug is still driven by the guest driver.
+    DeviceInUse,
+    /// Deregistering an IO Event failed.
+    UnregisterIoEvent(io::Error),
+    /// Deregistering an IRQ FD failed.
+    UnregisterIrqFd(io::Error),
 }
 
 impl fmt::Display for Error {
@@ -57,6 +71,10 @@ impl fmt::Display for Error {
             Error::RegisterIrqFd(ref e) => write!(f, "failed to register irqfd: {}", e),
             Error::DeviceNotFound => write!(f, "the device couldn't be found"),
             Error::UpdateFailed => write!(f, "failed to update the mmio device"),
+            Error::NoFreeSlot => write!(f, "no more hot-plug slots are available"),
+            Error::DeviceInUse => write!(f, "the device is in use by the guest driver"),
+            Error::UnregisterIoEvent(ref e) => write!(f, "failed to unregister IO event: {}", e),
+            Error::UnregisterIrqFd(ref e) => write!(f, "failed to unregister irqfd: {}", e),
         }
     }
 }
@@ -73,6 +91,155 @@ const MMIO_LEN: u64 = 0x1000;
 /// to its configuration space.
 const MMIO_CFG_SPACE_OFF: u64 = 0x100;
 
+// The values of the `MagicValue` and `Version` registers of an empty hot-plug slot. Together
+// with a device ID of 0, they make the guest driver skip the slot without complaining.
+const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
+const MMIO_VERSION: u32 = 2;
+
+// The KVM ioctls used for deregistering the IO events and IRQ FDs of a device, which are not
+// wrapped by `kvm_ioctls`.
+mod kvm_deassign_ioctls {
+    use kvm_bindings::*;
+
+    const KVMIO: ::std::os::raw::c_uint = 0xAE;
+
+    ioctl_iow_nr!(KVM_IRQFD, KVMIO, 0x76, kvm_irqfd);
+    ioctl_iow_nr!(KVM_IOEVENTFD, KVMIO, 0x79, kvm_ioeventfd);
+}
+
+// Deregisters the IO event that `register_virtio_device` set up for the `index`th queue of the
+// device mapped at `addr`.
+fn unregister_ioevent(vm: &VmFd, evt: &EventFd, addr: u64, index: u32) -> io::Result<()> {
+    let ioeventfd = kvm_ioeventfd {
+        datamatch: u64::from(index),
+        len: mem::size_of::<u32>() as u32,
+        addr: addr + u64::from(devices::virtio::NOTIFY_REG_OFFSET),
+        fd: evt.as_raw_fd(),
+        flags: (1 << kvm_ioeventfd_flag_nr_datamatch) | (1 << kvm_ioeventfd_flag_nr_deassign),
+        ..Default::default()
+    };
+    // Safe because we know that our file is a VM fd, we know the kernel will only read the
+    // correct amount of memory from our pointer, and we verify the return result.
+    let ret = unsafe { ioctl_with_ref(vm, kvm_deassign_ioctls::KVM_IOEVENTFD(), &ioeventfd) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Deregisters the IRQ FD that signals the interrupt line `irq`.
+fn unregister_irqfd(vm: &VmFd, evt: &EventFd, irq: u32) -> io::Result<()> {
+    let irqfd = kvm_irqfd {
+        fd: evt.as_raw_fd() as u32,
+        gsi: irq,
+        flags: KVM_IRQFD_FLAG_DEASSIGN,
+        ..Default::default()
+    };
+    // Safe because we know that our file is a VM fd, we know the kernel will only read the
+    // correct amount of memory from our pointer, and we verify the return result.
+    let ret = unsafe { ioctl_with_ref(vm, kvm_deassign_ioctls::KVM_IRQFD(), &irqfd) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// The bus device of a hot-plug slot, holding the virtio device plugged into the slot, if any.
+// An empty slot looks like a virtio device with ID 0, which the guest driver ignores.
+#[derive(Default)]
+struct SlotDevice(Option<devices::virtio::MmioDevice>);
+
+impl BusDevice for SlotDevice {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if let Some(ref mut device) = self.0 {
+            return device.read(offset, data);
+        }
+        for byte in data.iter_mut() {
+            *byte = 0;
+        }
+        let value = match offset {
+            0x00 => MMIO_MAGIC_VALUE,
+            0x04 => MMIO_VERSION,
+            _ => return,
+        };
+        if data.len() == mem::size_of::<u32>() {
+            data.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if let Some(ref mut device) = self.0 {
+            device.write(offset, data);
+        }
+    }
+
+    fn interrupt(&self, irq_mask: u32) {
+        if let Some(ref device) = self.0 {
+            device.interrupt(irq_mask);
+        }
+    }
+}
+
+// Duplicates of the events that a plugged device registered with KVM, kept for deregistering
+// them once the device is unplugged.
+#[derive(Default)]
+struct PluggedEvents {
+    queue_evts: Vec<EventFd>,
+    interrupt_evt: Option<EventFd>,
+}
+
+// A range of the MMIO bus, together with an interrupt line, reserved at boot time for attaching
+// a virtio device later on. The vCPUs work on their own copies of the bus, so devices are
+// plugged into and out of the slot, instead of being inserted on or removed from the bus.
+struct HotplugSlot {
+    addr: u64,
+    irq: u32,
+    bus_device: Arc<Mutex<SlotDevice>>,
+    // The type and ID of the plugged device, along with its registered events.
+    plugged: Option<((DeviceType, String), PluggedEvents)>,
+}
+
+impl HotplugSlot {
+    // Registers the queue and interrupt events of `device` with KVM, the same way
+    // `register_virtio_device` does, and keeps duplicates of them in `events`.
+    fn register_events(
+        &self,
+        vm: &VmFd,
+        device: &devices::virtio::MmioDevice,
+        events: &mut PluggedEvents,
+    ) -> Result<()> {
+        let io_addr =
+            IoEventAddress::Mmio(self.addr + u64::from(devices::virtio::NOTIFY_REG_OFFSET));
+        for (i, queue_evt) in device.queue_evts().iter().enumerate() {
+            let queue_evt = queue_evt.try_clone().map_err(Error::EventFd)?;
+            vm.register_ioevent(queue_evt.as_raw_fd(), &io_addr, i as u32)
+                .map_err(Error::RegisterIoEvent)?;
+            events.queue_evts.push(queue_evt);
+        }
+        if let Some(interrupt_evt) = device.interrupt_evt() {
+            let interrupt_evt = interrupt_evt.try_clone().map_err(Error::EventFd)?;
+            vm.register_irqfd(interrupt_evt.as_raw_fd(), self.irq)
+                .map_err(Error::RegisterIrqFd)?;
+            events.interrupt_evt = Some(interrupt_evt);
+        }
+        Ok(())
+    }
+
+    // Deregisters from KVM the events registered by `register_events`.
+    fn unregister_events(&self, vm: &VmFd, events: &PluggedEvents) -> Result<()> {
+        for (i, queue_evt) in events.queue_evts.iter().enumerate() {
+            unregister_ioevent(vm, queue_evt, self.addr, i as u32)
+                .map_err(Error::UnregisterIoEvent)?;
+        }
+        if let Some(ref interrupt_evt) = events.interrupt_evt {
+            unregister_irqfd(vm, interrupt_evt, self.irq).map_err(Error::UnregisterIrqFd)?;
+        }
+        Ok(())
+    }
+}
+
 /// Manages the complexities of registering a MMIO device.
 pub struct MMIODeviceManager {
     pub bus: devices::Bus,
@@ -81,6 +248,7 @@ pub struct MMIODeviceManager {
     irq: u32,
     last_irq: u32,
     id_to_dev_info: HashMap<(DeviceType, String), MMIODeviceInfo>,
+    hotplug_slots: Vec<HotplugSlot>,
 }
 
 impl MMIODeviceManager {
@@ -100,6 +268,7 @@ impl MMIODeviceManager {
             last_irq: irq_interval.1,
             bus: devices::Bus::new(),
             id_to_dev_info: HashMap::new(),
+            hotplug_slots: Vec::new(),
         }
     }
 
@@ -165,6 +334,130 @@ impl MMIODeviceManager {
         Ok(ret)
     }
 
+    /// Reserves `count` slots on the MMIO bus for attaching virtio devices after boot. The slots
+    /// are appended to the kernel command line, so that the guest learns about them at boot time.
+    pub fn reserve_hotplug_slots(
+        &mut self,
+        cmdline: &mut kernel_cmdline::Cmdline,
+        count: u8,
+    ) -> Result<()> {
+        for _ in 0..count {
+            if self.irq > self.last_irq {
+                return Err(Error::IrqsExhausted);
+            }
+            let bus_device = Arc::new(Mutex::new(SlotDevice::default()));
+            self.bus
+                .insert(bus_device.clone(), self.mmio_base, MMIO_LEN)
+                .map_err(Error::BusError)?;
+
+            #[cfg(target_arch = "x86_64")]
+            cmdline
+                .insert(
+                    "virtio_mmio.device",
+                    &format!("{}K@0x{:08x}:{}", MMIO_LEN / 1024, self.mmio_base, self.irq),
+                )
+                .map_err(Error::Cmdline)?;
+
+            self.hotplug_slots.push(HotplugSlot {
+                addr: self.mmio_base,
+                irq: self.irq,
+                bus_device,
+                plugged: None,
+            });
+
+            self.mmio_base += MMIO_LEN;
+            self.irq += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the number of reserved hot-plug slots.
+    pub fn hotplug_slot_count(&self) -> usize {
+        self.hotplug_slots.len()
+    }
+
+    /// Plugs a virtio device into the first free hot-plug slot and returns the slot address.
+    /// The device becomes usable once the guest binds its driver to the slot.
+    pub fn hotplug_virtio_device(
+        &mut self,
+        vm: &VmFd,
+        device: Box<devices::virtio::VirtioDevice>,
+        type_id: u32,
+        device_id: &str,
+    ) -> Result<u64> {
+        let slot = self
+            .hotplug_slots
+            .iter_mut()
+            .find(|slot| slot.plugged.is_none())
+            .ok_or(Error::NoFreeSlot)?;
+        let mmio_device = devices::virtio::MmioDevice::new(self.guest_mem.clone(), device)
+            .map_err(Error::CreateMmioDevice)?;
+
+        let mut events = PluggedEvents::default();
+        if let Err(e) = slot.register_events(vm, &mmio_device, &mut events) {
+            // Roll back whatever was registered before the failure.
+            let _ = slot.unregister_events(vm, &events);
+            return Err(e);
+        }
+
+        slot.bus_device
+            .lock()
+            .expect("Failed to plug the device due to poisoned lock")
+            .0 = Some(mmio_device);
+        let key = (DeviceType::Virtio(type_id), device_id.to_string());
+        slot.plugged = Some((key.clone(), events));
+        self.id_to_dev_info.insert(
+            key,
+            MMIODeviceInfo {
+                addr: slot.addr,
+                len: MMIO_LEN,
+                irq: slot.irq,
+            },
+        );
+
+        Ok(slot.addr)
+    }
+
+    /// Unplugs a virtio device from its hot-plug slot, leaving the slot free. Only the devices
+    /// plugged with `hotplug_virtio_device` can be unplugged, once the guest has unbound its
+    /// driver from them: the guest is not notified of the removal.
+    pub fn unplug_virtio_device(&mut self, vm: &VmFd, type_id: u32, device_id: &str) -> Result<()> {
+        let key = (DeviceType::Virtio(type_id), device_id.to_string());
+        let slot = self
+            .hotplug_slots
+            .iter_mut()
+            .find(|slot| match slot.plugged {
+                Some((ref plugged_key, _)) => *plugged_key == key,
+                None => false,
+            })
+            .ok_or(Error::DeviceNotFound)?;
+
+        if let Some(ref device) = slot
+            .bus_device
+            .lock()
+            .expect("Failed to unplug the device due to poisoned lock")
+            .0
+        {
+            if device.is_driver_active() {
+                return Err(Error::DeviceInUse);
+            }
+        }
+
+        if let Some((_, ref events)) = slot.plugged {
+            slot.unregister_events(vm, events)?;
+        }
+        slot.plugged = None;
+        // Dropping the device also drops its remaining references to the events.
+        slot.bus_device
+            .lock()
+            .expect("Failed to unplug the device due to poisoned lock")
+            .0 = None;
+        self.id_to_dev_info.remove(&key);
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
     /// Register an early console at some MMIO address.
     pub fn register_mmio_serial(
@@ -243,7 +536,6 @@ impl MMIODeviceManager {
         Ok(())
     }
 
-    #[cfg(target_arch = "aarch64")]
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -266,14 +558,34 @@ impl MMIODeviceManager {
         None
     }
 
-    /// Update a drive by rebuilding its config space and rewriting it on the bus.
+    /// Update a drive by rewriting the capacity in its config space on the bus.
     pub fn update_drive(&self, device_id: &str, new_size: u64) -> Result<()> {
         match self.get_device(DeviceType::Virtio(TYPE_BLOCK), device_id) {
             Some(device) => {
-                let data = devices::virtio::build_config_space(new_size);
                 let mut busdev = device.lock().map_err(|_| Error::UpdateFailed)?;
 
-                busdev.write(MMIO_CFG_SPACE_OFF, &data[..]);
+                // The capacity, in sectors, is the first field of the config space. The other
+                // fields do not depend on the disk image.
+                let capacity = new_size / devices::virtio::SECTOR_SIZE;
+                busdev.write(MMIO_CFG_SPACE_OFF, &capacity.to_le_bytes());
+                busdev.interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG);
+
+                Ok(())
+            }
+            None => Err(Error::DeviceNotFound),
+        }
+    }
+
+    /// Ask the balloon device to hold `num_pages` pages by rewriting the target size in its
+    /// config space on the bus.
+    pub fn update_balloon(&self, device_id: &str, num_pages: u32) -> Result<()> {
+        match self.get_device(DeviceType::Virtio(TYPE_BALLOON), device_id) {
+            Some(device) => {
+                let mut busdev = device.lock().map_err(|_| Error::UpdateFailed)?;
+
+                // The target size is the first field of the config space, and the only one
+                // written by the host.
+                busdev.write(MMIO_CFG_SPACE_OFF, &num_pages.to_le_bytes());
                 busdev.interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG);
 
                 Ok(())
@@ -284,9 +596,9 @@ impl MMIODeviceManager {
 }
 
 /// Private structure for storing information about the MMIO device registered at some address on the bus.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MMIODeviceInfo {
-    addr: u64,
+    pub addr: u64,
     irq: u32,
     len: u64,
 }
@@ -304,6 +616,16 @@ impl DeviceInfoForFDT for MMIODeviceInfo {
     }
 }
 
+impl<'a> From<&'a MMIODeviceInfo> for MmioConfig {
+    fn from(info: &MMIODeviceInfo) -> Self {
+        MmioConfig {
+            addr: info.addr,
+            len: info.len,
+            irq: info.irq,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::super::vmm_config::instance_info::{InstanceInfo, InstanceState};
@@ -497,6 +819,30 @@ mod tests {
             format!("{}", Error::UpdateFailed),
             "failed to update the mmio device"
         );
+        assert_eq!(
+            format!("{}", Error::NoFreeSlot),
+            "no more hot-plug slots are available"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::UnregisterIoEvent(io::Error::from_raw_os_error(0))
+            ),
+            format!(
+                "failed to unregister IO event: {}",
+                io::Error::from_raw_os_error(0)
+            )
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::UnregisterIrqFd(io::Error::from_raw_os_error(0))
+            ),
+            format!(
+                "failed to unregister irqfd: {}",
+                io::Error::from_raw_os_error(0)
+            )
+        );
         assert_eq!(
             format!("{}", Error::BusError(devices::BusError::Overlap)),
             format!(
@@ -596,4 +942,97 @@ mod tests {
             .get_device(DeviceType::Virtio(type_id), &id)
             .is_none());
     }
+
+    #[test]
+    fn test_hotplug_slots() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let guest_mem = GuestMemory::new(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(guest_mem, &mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        let mut vmm = create_vmm_object();
+        assert!(vmm.setup_interrupt_controller().is_ok());
+
+        assert!(device_manager
+            .reserve_hotplug_slots(&mut cmdline, 2)
+            .is_ok());
+        assert_eq!(device_manager.hotplug_slot_count(), 2);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            cmdline.as_str(),
+            "virtio_mmio.device=4K@0xd0000000:5 virtio_mmio.device=4K@0xd0001000:6"
+        );
+
+        // An empty slot reports the magic value and version, but no device.
+        let mut data = [0u8; 4];
+        assert!(device_manager.bus.read(0xd000_0000, &mut data));
+        assert_eq!(u32::from_le_bytes(data), MMIO_MAGIC_VALUE);
+        assert!(device_manager.bus.read(0xd000_0004, &mut data));
+        assert_eq!(u32::from_le_bytes(data), MMIO_VERSION);
+        assert!(device_manager.bus.read(0xd000_0008, &mut data));
+        assert_eq!(u32::from_le_bytes(data), 0);
+
+        // Plug devices until the slots are exhausted.
+        let dummy_box = Box::new(DummyDevice { dummy: 0 });
+        assert_eq!(
+            device_manager
+                .hotplug_virtio_device(vmm.vm.get_fd(), dummy_box.clone(), TYPE_BLOCK, "foo")
+                .unwrap(),
+            0xd000_0000
+        );
+        assert_eq!(
+            device_manager
+                .hotplug_virtio_device(vmm.vm.get_fd(), dummy_box.clone(), TYPE_BLOCK, "bar")
+                .unwrap(),
+            0xd000_1000
+        );
+        match device_manager.hotplug_virtio_device(
+            vmm.vm.get_fd(),
+            dummy_box.clone(),
+            TYPE_BLOCK,
+            "baz",
+        ) {
+            Err(Error::NoFreeSlot) => (),
+            _ => panic!("Expected a no free slot error."),
+        }
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), "foo")
+            .is_some());
+        // The plugged device is reachable through the bus.
+        assert!(device_manager.bus.read(0xd000_0000, &mut data));
+        assert_eq!(u32::from_le_bytes(data), MMIO_MAGIC_VALUE);
+        assert!(device_manager.bus.read(0xd000_0008, &mut data));
+        assert_eq!(u32::from_le_bytes(data), dummy_box.device_type());
+
+        // Unplugging frees the slot, which can be reused.
+        match device_manager.unplug_virtio_device(vmm.vm.get_fd(), TYPE_BLOCK, "baz") {
+            Err(Error::DeviceNotFound) => (),
+            _ => panic!("Expected a device not found error."),
+        }
+
+        // A device driven by the guest cannot be unplugged, until the guest driver releases it.
+        for status in &[1u32, 3, 11, 15] {
+            device_manager.bus.write(0xd000_0070, &status.to_le_bytes());
+        }
+        match device_manager.unplug_virtio_device(vmm.vm.get_fd(), TYPE_BLOCK, "foo") {
+            Err(Error::DeviceInUse) => (),
+            _ => panic!("Expected a device in use error."),
+        }
+        device_manager.bus.write(0xd000_0070, &0u32.to_le_bytes());
+        assert!(device_manager
+            .unplug_virtio_device(vmm.vm.get_fd(), TYPE_BLOCK, "foo")
+            .is_ok());
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), "foo")
+            .is_none());
+        assert!(device_manager.bus.read(0xd000_0008, &mut data));
+        assert_eq!(u32::from_le_bytes(data), 0);
+        assert_eq!(
+            device_manager
+                .hotplug_virtio_device(vmm.vm.get_fd(), dummy_box, TYPE_BLOCK, "baz")
+                .unwrap(),
+            0xd000_0000
+        );
+    }
 }
@@ -34,6 +34,7 @@ extern crate memory_model;
 extern crate net_util;
 extern crate rate_limiter;
 extern crate seccomp;
+#[macro_use]
 extern crate sys_util;
 
 /// Syscalls allowed through the seccomp filter.
@@ -41,6 +42,8 @@ pub mod default_syscalls;
 mod device_manager;
 /// Signal handling utilities.
 pub mod signal_handler;
+#[cfg(target_arch = "x86_64")]
+mod snapshot;
 /// Wrappers over structures used to configure the VMM.
 pub mod vmm_config;
 mod vstate;
@@ -48,8 +51,8 @@ mod vstate;
 use futures::sync::oneshot;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::fs::{metadata, File, OpenOptions};
-use std::io;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
 use std::result;
@@ -62,7 +65,6 @@ use kvm_bindings::KVM_API_VERSION;
 use kvm_ioctls::{Cap, Kvm};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
-#[cfg(target_arch = "aarch64")]
 use arch::DeviceType;
 use device_manager::legacy::LegacyDeviceManager;
 #[cfg(target_arch = "aarch64")]
@@ -73,31 +75,39 @@ use devices::virtio;
 #[cfg(feature = "vsock")]
 use devices::virtio::vhost::{handle::VHOST_EVENTS_COUNT, TYPE_VSOCK};
 use devices::virtio::EpollConfigConstructor;
-use devices::virtio::{BLOCK_EVENTS_COUNT, TYPE_BLOCK};
-use devices::virtio::{NET_EVENTS_COUNT, TYPE_NET};
+use devices::virtio::{block_events_count, TYPE_BLOCK};
+use devices::virtio::{net_events_count, TYPE_NET};
+use devices::virtio::{BALLOON_EVENTS_COUNT, BALLOON_PAGE_SIZE, TYPE_BALLOON};
 use devices::{DeviceEventT, EpollHandler};
 use fc_util::now_cputime_us;
 use kernel::cmdline as kernel_cmdline;
 use kernel::loader as kernel_loader;
 use logger::error::LoggerError;
-use logger::{AppInfo, Level, LogOption, Metric, LOGGER, METRICS};
+use logger::{
+    AppInfo, DeviceMetrics, Level, LogDestination, LogFormat, LogOption, Metric, LOGGER, METRICS,
+};
 use memory_model::{GuestAddress, GuestMemory};
 use net_util::TapError;
 #[cfg(target_arch = "aarch64")]
 use serde_json::Value;
 use sys_util::{EventFd, Terminal};
+use vmm_config::balloon::{BalloonConfig, BalloonError, BalloonUpdateConfig};
 use vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
 use vmm_config::drive::{BlockDeviceConfig, BlockDeviceConfigs, DriveError};
-use vmm_config::instance_info::{InstanceInfo, InstanceState, StartMicrovmError};
-use vmm_config::logger::{LoggerConfig, LoggerConfigError, LoggerLevel};
+use vmm_config::instance_info::{InstanceInfo, InstanceState, PauseResumeError, StartMicrovmError};
+use vmm_config::logger::{
+    LoggerConfig, LoggerConfigError, LoggerFormat, LoggerLevel, LoggerUpdateConfig,
+};
 use vmm_config::machine_config::{VmConfig, VmConfigError};
+use vmm_config::microvm::{AttachedDevice, MicrovmConfig, MmioConfig};
 use vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceConfigs, NetworkInterfaceError,
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceConfigs, NetworkInterfaceError,
     NetworkInterfaceUpdateConfig,
 };
+use vmm_config::snapshot::{SnapshotConfig, SnapshotError};
 #[cfg(feature = "vsock")]
 use vmm_config::vsock::{VsockDeviceConfig, VsockDeviceConfigs, VsockError};
-use vstate::{Vcpu, Vm};
+use vstate::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse, Vm};
 
 /// Default guest kernel command line:
 /// - `reboot=k` shut down the guest on reboot, instead of well... rebooting;
@@ -112,6 +122,8 @@ use vstate::{Vcpu, Vm};
 const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0 \
                                       i8042.noaux i8042.nomux i8042.nopnp i8042.dumbkbd";
 const WRITE_METRICS_PERIOD_SECONDS: u64 = 60;
+// There is at most one balloon device, so it always has the same ID.
+const BALLOON_DEVICE_ID: &str = "balloon";
 
 /// Success exit code.
 pub const FC_EXIT_CODE_OK: u8 = 0;
@@ -125,6 +137,8 @@ pub const FC_EXIT_CODE_BAD_SYSCALL: u8 = 148;
 pub const FC_EXIT_CODE_SIGBUS: u8 = 149;
 /// Firecracker was shut down after intercepting `SIGSEGV`.
 pub const FC_EXIT_CODE_SIGSEGV: u8 = 150;
+/// The microVM could not be configured from the configuration file.
+pub const FC_EXIT_CODE_BAD_CONFIGURATION: u8 = 152;
 
 /// Errors associated with the VMM internal logic. These errors cannot be generated by direct user
 /// input, but can result from bad configuration of the host (for example if Firecracker doesn't
@@ -212,22 +226,34 @@ impl PartialEq for ErrorKind {
 /// Wrapper for all errors associated with VMM actions.
 #[derive(Debug)]
 pub enum VmmActionError {
-    /// The action `ConfigureBootSource` failed either because of bad user input (`ErrorKind::User`)
-    /// or an internal error (`ErrorKind::Internal`).
+    /// One of the actions `GetBalloonConfig`, `InsertBalloonDevice` or `UpdateBalloon` failed
+    /// either because of bad user input (`ErrorKind::User`) or an internal error
+    /// (`ErrorKind::Internal`).
+    Balloon(ErrorKind, BalloonError),
+    /// One of the actions `ConfigureBootSource` or `GetBootSource` failed either because of bad
+    /// user input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     BootSource(ErrorKind, BootSourceConfigError),
-    /// One of the actions `InsertBlockDevice`, `RescanBlockDevice` or `UpdateBlockDevicePath`
-    /// failed either because of bad user input (`ErrorKind::User`) or an
-    /// internal error (`ErrorKind::Internal`).
+    /// One of the actions `GetBlockDevice`, `InsertBlockDevice`, `RemoveBlockDevice`,
+    /// `RescanBlockDevice` or `UpdateBlockDevicePath` failed either because of bad user input
+    /// (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     DriveConfig(ErrorKind, DriveError),
-    /// The action `ConfigureLogger` failed either because of bad user input (`ErrorKind::User`) or
-    /// an internal error (`ErrorKind::Internal`).
+    /// One of the actions `ConfigureLogger`, `GetLoggerConfiguration` or `UpdateLogger` failed
+    /// either because of bad user input (`ErrorKind::User`) or an internal error
+    /// (`ErrorKind::Internal`).
     Logger(ErrorKind, LoggerConfigError),
     /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed either because of bad
     /// input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     MachineConfig(ErrorKind, VmConfigError),
-    /// The action `InsertNetworkDevice` failed either because of bad user input (`ErrorKind::User`)
-    /// or an internal error (`ErrorKind::Internal`).
+    /// One of the actions `GetNetworkInterface`, `InsertNetworkDevice` or
+    /// `RemoveNetworkInterface` failed either because of bad user input (`ErrorKind::User`) or an
+    /// internal error (`ErrorKind::Internal`).
     NetworkConfig(ErrorKind, NetworkInterfaceError),
+    /// One of the actions `PauseVcpus` or `ResumeVcpus` failed either because of bad user input
+    /// (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
+    PauseResume(ErrorKind, PauseResumeError),
+    /// One of the actions `CreateSnapshot` or `LoadSnapshot` failed either because of bad user
+    /// input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
+    Snapshot(ErrorKind, SnapshotError),
     /// The action `StartMicroVm` failed either because of bad user input (`ErrorKind::User`) or
     /// an internal error (`ErrorKind::Internal`).
     StartMicrovm(ErrorKind, StartMicrovmError),
@@ -235,11 +261,26 @@ pub enum VmmActionError {
     /// `I8042DeviceError`.
     SendCtrlAltDel(ErrorKind, I8042DeviceError),
     #[cfg(feature = "vsock")]
-    /// The action `insert_vsock_device` failed either because of bad user input (`ErrorKind::User`)
-    /// or an internal error (`ErrorKind::Internal`).
+    /// One of the actions `GetVsockDevice` or `InsertVsockDevice` failed either because of bad user
+    /// input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     VsockConfig(ErrorKind, VsockError),
 }
 
+// It's convenient to turn BalloonErrors into VmmActionErrors directly.
+impl std::convert::From<BalloonError> for VmmActionError {
+    fn from(e: BalloonError) -> Self {
+        let kind = match e {
+            // User errors.
+            BalloonError::DeviceNotFound
+            | BalloonError::TooManyPagesRequested
+            | BalloonError::UpdateNotAllowedPostBoot => ErrorKind::User,
+            // Internal errors.
+            BalloonError::UpdateFailed(_) => ErrorKind::Internal,
+        };
+        VmmActionError::Balloon(kind, e)
+    }
+}
+
 // It's convenient to turn DriveErrors into VmmActionErrors directly.
 impl std::convert::From<DriveError> for VmmActionError {
     fn from(e: DriveError) -> Self {
@@ -253,7 +294,16 @@ impl std::convert::From<DriveError> for VmmActionError {
             | DriveError::BlockDeviceUpdateFailed
             | DriveError::OperationNotAllowedPreBoot
             | DriveError::UpdateNotAllowedPostBoot
-            | DriveError::RootBlockDeviceAlreadyAdded => ErrorKind::User,
+            | DriveError::RootBlockDeviceAlreadyAdded
+            | DriveError::RootBlockDeviceHotplug
+            | DriveError::NoHotplugSlot
+            | DriveError::NotHotplugged
+            | DriveError::DeviceInUse
+            | DriveError::AsyncEngineNonRawImage
+            | DriveError::InvalidNumQueues
+            | DriveError::InvalidBlockTopology => ErrorKind::User,
+            // Internal errors.
+            DriveError::HotplugFailed(_) | DriveError::UnplugFailed(_) => ErrorKind::Internal,
         };
         VmmActionError::DriveConfig(kind, e)
     }
@@ -282,10 +332,20 @@ impl std::convert::From<NetworkInterfaceError> for VmmActionError {
             NetworkInterfaceError::GuestMacAddressInUse(_)
             | NetworkInterfaceError::HostDeviceNameInUse(_)
             | NetworkInterfaceError::DeviceIdNotFound
-            | NetworkInterfaceError::UpdateNotAllowedPostBoot => ErrorKind::User,
+            | NetworkInterfaceError::UpdateNotAllowedPostBoot
+            | NetworkInterfaceError::InvalidNumQueuePairs
+            | NetworkInterfaceError::InvalidMtu
+            | NetworkInterfaceError::OpenCapture(_)
+            | NetworkInterfaceError::AntiSpoofingWithoutGuestMac
+            | NetworkInterfaceError::InvalidPacketFilter
+            | NetworkInterfaceError::NoHotplugSlot
+            | NetworkInterfaceError::NotHotplugged
+            | NetworkInterfaceError::DeviceInUse => ErrorKind::User,
             // Internal errors.
             NetworkInterfaceError::EpollHandlerNotFound(_)
-            | NetworkInterfaceError::RateLimiterUpdateFailed(_) => ErrorKind::Internal,
+            | NetworkInterfaceError::RateLimiterUpdateFailed(_)
+            | NetworkInterfaceError::HotplugFailed(_)
+            | NetworkInterfaceError::UnplugFailed(_) => ErrorKind::Internal,
             NetworkInterfaceError::OpenTap(ref te) => match te {
                 // User errors.
                 TapError::OpenTun(_) | TapError::CreateTap(_) | TapError::InvalidIfname => {
@@ -299,6 +359,52 @@ impl std::convert::From<NetworkInterfaceError> for VmmActionError {
     }
 }
 
+// It's convenient to turn PauseResumeErrors into VmmActionErrors directly.
+impl std::convert::From<PauseResumeError> for VmmActionError {
+    fn from(e: PauseResumeError) -> Self {
+        let kind = match e {
+            // User errors.
+            PauseResumeError::MicroVMNotRunning | PauseResumeError::MicroVMNotPaused => {
+                ErrorKind::User
+            }
+            // Internal errors.
+            PauseResumeError::Vcpu(_) => ErrorKind::Internal,
+        };
+        VmmActionError::PauseResume(kind, e)
+    }
+}
+
+// It's convenient to turn SnapshotErrors into VmmActionErrors directly.
+impl std::convert::From<SnapshotError> for VmmActionError {
+    fn from(e: SnapshotError) -> Self {
+        let kind = match e {
+            // User errors.
+            SnapshotError::CreateSnapshotFile(_)
+            | SnapshotError::CreateMemoryFile(_)
+            | SnapshotError::DevicesMismatch
+            | SnapshotError::HotplugSlots
+            | SnapshotError::InvalidSnapshot(_)
+            | SnapshotError::MemoryFileSize
+            | SnapshotError::MicroVMAlreadyRunning
+            | SnapshotError::MicroVMNotPaused
+            | SnapshotError::OpenSnapshotFile(_)
+            | SnapshotError::OpenMemoryFile(_)
+            | SnapshotError::UnsupportedArchitecture
+            | SnapshotError::UnsupportedDevice(_)
+            | SnapshotError::UnsupportedVersion(_) => ErrorKind::User,
+            // Internal errors.
+            SnapshotError::DeviceState(_)
+            | SnapshotError::ReadMemory(_)
+            | SnapshotError::SaveVcpuState(_)
+            | SnapshotError::Vcpu(_)
+            | SnapshotError::VmState(_)
+            | SnapshotError::WriteMemory(_)
+            | SnapshotError::WriteSnapshot(_) => ErrorKind::Internal,
+        };
+        VmmActionError::Snapshot(kind, e)
+    }
+}
+
 // It's convenient to turn StartMicrovmErrors into VmmActionErrors directly.
 impl std::convert::From<StartMicrovmError> for VmmActionError {
     fn from(e: StartMicrovmError) -> Self {
@@ -314,12 +420,14 @@ impl std::convert::From<StartMicrovmError> for VmmActionError {
             | StartMicrovmError::MissingKernelConfig
             | StartMicrovmError::NetDeviceNotConfigured
             | StartMicrovmError::OpenBlockDevice(_)
+            | StartMicrovmError::OpenNetCapture(_)
             | StartMicrovmError::VcpusNotConfigured => ErrorKind::User,
             // Internal errors.
             #[cfg(feature = "vsock")]
             StartMicrovmError::RegisterVsockDevice(_) => ErrorKind::Internal,
             StartMicrovmError::ConfigureSystem(_)
             | StartMicrovmError::ConfigureVm(_)
+            | StartMicrovmError::CreateBalloonDevice(_)
             | StartMicrovmError::CreateRateLimiter(_)
             | StartMicrovmError::DeviceManager
             | StartMicrovmError::EventFd
@@ -349,11 +457,14 @@ impl VmmActionError {
         use self::VmmActionError::*;
 
         match *self {
+            Balloon(ref kind, _) => kind,
             BootSource(ref kind, _) => kind,
             DriveConfig(ref kind, _) => kind,
             Logger(ref kind, _) => kind,
             MachineConfig(ref kind, _) => kind,
             NetworkConfig(ref kind, _) => kind,
+            PauseResume(ref kind, _) => kind,
+            Snapshot(ref kind, _) => kind,
             StartMicrovm(ref kind, _) => kind,
             SendCtrlAltDel(ref kind, _) => kind,
             #[cfg(feature = "vsock")]
@@ -367,11 +478,14 @@ impl Display for VmmActionError {
         use self::VmmActionError::*;
 
         match *self {
+            Balloon(_, ref err) => write!(f, "{}", err.to_string()),
             BootSource(_, ref err) => write!(f, "{}", err.to_string()),
             DriveConfig(_, ref err) => write!(f, "{}", err.to_string()),
             Logger(_, ref err) => write!(f, "{}", err.to_string()),
             MachineConfig(_, ref err) => write!(f, "{}", err.to_string()),
             NetworkConfig(_, ref err) => write!(f, "{}", err.to_string()),
+            PauseResume(_, ref err) => write!(f, "{}", err.to_string()),
+            Snapshot(_, ref err) => write!(f, "{}", err.to_string()),
             StartMicrovm(_, ref err) => write!(f, "{}", err.to_string()),
             SendCtrlAltDel(_, ref err) => write!(f, "{}", err.to_string()),
             #[cfg(feature = "vsock")]
@@ -392,24 +506,72 @@ pub enum VmmAction {
     /// Configure the logger using as input the `LoggerConfig`. This action can only be called
     /// before the microVM has booted. The response is sent using the `OutcomeSender`.
     ConfigureLogger(LoggerConfig, OutcomeSender),
+    /// Save the state of the paused microVM to the files given by `SnapshotConfig`. This action
+    /// can only be called while the microVM is paused. The response is sent using the
+    /// `OutcomeSender`.
+    CreateSnapshot(SnapshotConfig, OutcomeSender),
+    /// Get the configuration of the balloon device. The response is sent using the
+    /// `OutcomeSender`.
+    GetBalloonConfig(OutcomeSender),
+    /// Get the configuration of the block device specified by an ID. The response is sent using the
+    /// `OutcomeSender`.
+    GetBlockDevice(String, OutcomeSender),
+    /// Get the boot source of the microVM. The response is sent using the `OutcomeSender`.
+    GetBootSource(OutcomeSender),
+    /// Get the logger configuration. The response is sent using the `OutcomeSender`.
+    GetLoggerConfiguration(OutcomeSender),
+    /// Get the whole configuration of the microVM, including where its devices are mapped on the
+    /// MMIO bus. The response is sent using the `OutcomeSender`.
+    GetMicrovmConfiguration(OutcomeSender),
+    /// Get the configuration of the network interface specified by an ID. The response is sent
+    /// using the `OutcomeSender`.
+    GetNetworkInterface(String, OutcomeSender),
     /// Get the configuration of the microVM. The action response is sent using the `OutcomeSender`.
     GetVmConfiguration(OutcomeSender),
+    #[cfg(feature = "vsock")]
+    /// Get the configuration of the vsock device specified by an ID. The response is sent using
+    /// the `OutcomeSender`.
+    GetVsockDevice(String, OutcomeSender),
     /// Flush the metrics. This action can only be called after the logger has been configured.
     /// The response is sent using the `OutcomeSender`.
     FlushMetrics(OutcomeSender),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
-    /// input. This action can only be called before the microVM has booted. The response
-    /// is sent using the `OutcomeSender`.
+    /// input. After the microVM has booted, this action can only add new, non-root block
+    /// devices, which are attached to the running microVM. The response is sent using the
+    /// `OutcomeSender`.
     InsertBlockDevice(BlockDeviceConfig, OutcomeSender),
+    /// Add the balloon device or update its configuration using the `BalloonConfig` as input.
+    /// This action can only be called before the microVM has booted. The response is sent using
+    /// the `OutcomeSender`.
+    InsertBalloonDevice(BalloonConfig, OutcomeSender),
     /// Add a new network interface config or update one that already exists using the
-    /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
-    /// booted. The response is sent using the `OutcomeSender`.
+    /// `NetworkInterfaceConfig` as input. After the microVM has booted, this action can only add
+    /// new network interfaces, which are attached to the running microVM. The response is sent
+    /// using the `OutcomeSender`.
     InsertNetworkDevice(NetworkInterfaceConfig, OutcomeSender),
     #[cfg(feature = "vsock")]
     /// Add a new vsock device or update one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted. The response is sent using the `OutcomeSender`.
     InsertVsockDevice(VsockDeviceConfig, OutcomeSender),
+    /// Resume a microVM from the files given by `SnapshotConfig`, instead of booting it. This
+    /// action can only be called before the microVM has booted. The response is sent using the
+    /// `OutcomeSender`.
+    LoadSnapshot(SnapshotConfig, OutcomeSender),
+    /// Pause the vCPUs of the microVM. This action can only be called while the microVM is
+    /// running. The response is sent using the `OutcomeSender`.
+    PauseVcpus(OutcomeSender),
+    /// Resume the vCPUs of the microVM. This action can only be called while the microVM is
+    /// paused. The response is sent using the `OutcomeSender`.
+    ResumeVcpus(OutcomeSender),
+    /// Remove the block device specified by an ID. After the microVM has booted, only the block
+    /// devices added after boot can be removed, and they are detached from the running microVM.
+    /// The response is sent using the `OutcomeSender`.
+    RemoveBlockDevice(String, OutcomeSender),
+    /// Remove the network interface specified by an ID. After the microVM has booted, only the
+    /// network interfaces added after boot can be removed, and they are detached from the running
+    /// microVM. The response is sent using the `OutcomeSender`.
+    RemoveNetworkInterface(String, OutcomeSender),
     /// Update the size of an existing block device specified by an ID. The ID is the first data
     /// associated with this enum variant. This action can only be called after the microVM is
     /// started. The response is sent using the `OutcomeSender`.
@@ -421,6 +583,13 @@ pub enum VmmAction {
     /// Launch the microVM. This action can only be called before the microVM has booted.
     /// The response is sent using the `OutcomeSender`.
     StartMicroVm(OutcomeSender),
+    /// Start writing the frames of the network interface specified by the ID to a pcap file,
+    /// described by `NetworkCaptureConfig`. Any capture already in progress is replaced. The
+    /// response is sent using the `OutcomeSender`.
+    StartNetworkCapture(String, NetworkCaptureConfig, OutcomeSender),
+    /// Stop the capture of the frames of the network interface specified by the ID. The response
+    /// is sent using the `OutcomeSender`.
+    StopNetworkCapture(String, OutcomeSender),
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     SendCtrlAltDel(OutcomeSender),
@@ -428,6 +597,13 @@ pub enum VmmAction {
     /// represents the `drive_id` and the `path_on_host`. The response is sent using
     /// the `OutcomeSender`.
     UpdateBlockDevicePath(String, String, OutcomeSender),
+    /// Change the amount of guest memory the balloon device should hold, using the
+    /// `BalloonUpdateConfig` as input. The response is sent using the `OutcomeSender`.
+    UpdateBalloon(BalloonUpdateConfig, OutcomeSender),
+    /// Change the level and the options of the configured logger, using the `LoggerUpdateConfig`
+    /// as input. This action can be called before or after the microVM has booted. The response
+    /// is sent using the `OutcomeSender`.
+    UpdateLogger(LoggerUpdateConfig, OutcomeSender),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig, OutcomeSender),
@@ -439,8 +615,23 @@ pub enum VmmAction {
 pub enum VmmData {
     /// No data is sent on the channel.
     Empty,
+    /// The configuration of the balloon device.
+    BalloonConfig(BalloonConfig),
+    /// The configuration of a block device.
+    BlockDevice(BlockDeviceConfig),
+    /// The boot source of the microVM.
+    BootSource(BootSourceConfig),
+    /// The logger configuration.
+    Logger(LoggerConfig),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The whole configuration of the microVM.
+    MicrovmConfiguration(MicrovmConfig),
+    /// The configuration of a network interface.
+    NetworkInterface(NetworkInterfaceConfig),
+    #[cfg(feature = "vsock")]
+    /// The configuration of a vsock device.
+    Vsock(VsockDeviceConfig),
 }
 
 /// Data type used to communicate between the API and the VMM.
@@ -467,6 +658,21 @@ fn get_time_us() -> u64 {
     (chrono::Utc::now().timestamp_nanos() / 1000) as u64
 }
 
+// Converts an amount of memory in MiB to a number of balloon pages.
+fn mib_to_balloon_pages(amount_mib: u32) -> u32 {
+    amount_mib * (1 << 20) / BALLOON_PAGE_SIZE as u32
+}
+
+// Maps the level given through the API to the level of the logger.
+fn log_level(level: &LoggerLevel) -> Level {
+    match *level {
+        LoggerLevel::Error => Level::Error,
+        LoggerLevel::Warning => Level::Warn,
+        LoggerLevel::Info => Level::Info,
+        LoggerLevel::Debug => Level::Debug,
+    }
+}
+
 /// Describes a KVM context that gets attached to the micro vm instance.
 /// It gives access to the functionality of the KVM wrapper as long as every required
 /// KVM capability is present on the host.
@@ -548,10 +754,12 @@ struct EpollEvent<T: AsRawFd> {
 struct EpollContext {
     epoll_raw_fd: RawFd,
     stdin_index: u64,
-    // FIXME: find a different design as this does not scale. This Vec can only grow.
+    // The entries left by the detached devices are reused by the devices attached afterwards.
     dispatch_table: Vec<Option<EpollDispatch>>,
     device_handlers: Vec<MaybeHandler>,
     device_id_to_handler_id: HashMap<(u32, String), usize>,
+    // The slots of `device_handlers` left by the detached devices.
+    free_handler_ids: Vec<usize>,
 }
 
 impl EpollContext {
@@ -573,6 +781,7 @@ impl EpollContext {
             dispatch_table,
             device_handlers: Vec::with_capacity(6),
             device_id_to_handler_id: HashMap::new(),
+            free_handler_ids: Vec::new(),
         })
     }
 
@@ -631,21 +840,51 @@ impl EpollContext {
         Ok(EpollEvent { fd })
     }
 
-    fn allocate_tokens(&mut self, count: usize) -> (u64, Sender<Box<EpollHandler>>) {
-        let dispatch_base = self.dispatch_table.len() as u64;
-        let device_idx = self.device_handlers.len();
+    // Returns the index of the first of `count` consecutive free entries of the dispatch table,
+    // which may extend past its end. The stdin entry is never handed out, even when stdin is
+    // disabled.
+    fn free_dispatch_range(&self, count: usize) -> usize {
+        let mut start = self.stdin_index as usize + 1;
+        while start < self.dispatch_table.len() {
+            let free_count = self.dispatch_table[start..]
+                .iter()
+                .take(count)
+                .take_while(|dispatch| dispatch.is_none())
+                .count();
+            if free_count == count || start + free_count == self.dispatch_table.len() {
+                return start;
+            }
+            start += free_count + 1;
+        }
+        self.dispatch_table.len()
+    }
+
+    // Returns the index of the handler in `device_handlers`, the base of its entries in the
+    // dispatch table, and the sender of the handler.
+    fn allocate_tokens(&mut self, count: usize) -> (usize, u64, Sender<Box<EpollHandler>>) {
         let (sender, receiver) = channel();
+        let device_idx = match self.free_handler_ids.pop() {
+            Some(device_idx) => {
+                self.device_handlers[device_idx] = MaybeHandler::new(receiver);
+                device_idx
+            }
+            None => {
+                self.device_handlers.push(MaybeHandler::new(receiver));
+                self.device_handlers.len() - 1
+            }
+        };
 
+        let dispatch_base = self.free_dispatch_range(count);
         for x in 0..count {
-            self.dispatch_table.push(Some(EpollDispatch::DeviceHandler(
-                device_idx,
-                x as DeviceEventT,
-            )));
+            let dispatch = Some(EpollDispatch::DeviceHandler(device_idx, x as DeviceEventT));
+            if dispatch_base + x < self.dispatch_table.len() {
+                self.dispatch_table[dispatch_base + x] = dispatch;
+            } else {
+                self.dispatch_table.push(dispatch);
+            }
         }
 
-        self.device_handlers.push(MaybeHandler::new(receiver));
-
-        (dispatch_base, sender)
+        (device_idx, dispatch_base as u64, sender)
     }
 
     fn allocate_virtio_tokens<T: EpollConfigConstructor>(
@@ -654,14 +893,36 @@ impl EpollContext {
         device_id: &str,
         count: usize,
     ) -> T {
-        let (dispatch_base, sender) = self.allocate_tokens(count);
-        self.device_id_to_handler_id.insert(
-            (type_id, device_id.to_string()),
-            self.device_handlers.len() - 1,
-        );
+        let (device_idx, dispatch_base, sender) = self.allocate_tokens(count);
+        self.device_id_to_handler_id
+            .insert((type_id, device_id.to_string()), device_idx);
         T::new(dispatch_base, self.epoll_raw_fd, sender)
     }
 
+    // Drops the handler of a detached device, together with its entries in the dispatch table.
+    // The file descriptors of the handler leave the epoll set when they get closed.
+    fn remove_device_handler(&mut self, type_id: u32, device_id: &str) {
+        let handler_id = match self
+            .device_id_to_handler_id
+            .remove(&(type_id, device_id.to_string()))
+        {
+            Some(handler_id) => handler_id,
+            None => return,
+        };
+        for dispatch in self.dispatch_table.iter_mut() {
+            if let Some(EpollDispatch::DeviceHandler(device_idx, _)) = *dispatch {
+                if device_idx == handler_id {
+                    *dispatch = None;
+                }
+            }
+        }
+        // The slot of the handler is kept, so that the indices of the other handlers stay valid,
+        // and is reused by the next device.
+        let (_, receiver) = channel();
+        self.device_handlers[handler_id] = MaybeHandler::new(receiver);
+        self.free_handler_ids.push(handler_id);
+    }
+
     fn get_device_handler_by_handler_id(&mut self, id: usize) -> Result<&mut EpollHandler> {
         let maybe = &mut self.device_handlers[id];
         match maybe.handler {
@@ -709,7 +970,8 @@ impl Drop for EpollContext {
 
 struct KernelConfig {
     cmdline: kernel_cmdline::Cmdline,
-    kernel_file: File,
+    // There is no kernel image to load when resuming from a snapshot.
+    kernel_file: Option<File>,
     #[cfg(target_arch = "x86_64")]
     cmdline_addr: GuestAddress,
 }
@@ -723,7 +985,9 @@ struct Vmm {
     // Guest VM core resources.
     guest_memory: Option<GuestMemory>,
     kernel_config: Option<KernelConfig>,
-    vcpus_handles: Vec<thread::JoinHandle<()>>,
+    // The boot source as provided by the user, which `kernel_config` was built from.
+    boot_source_config: Option<BootSourceConfig>,
+    vcpus_handles: Vec<VcpuHandle>,
     exit_evt: Option<EpollEvent<EventFd>>,
     vm: Vm,
 
@@ -736,8 +1000,10 @@ struct Vmm {
     // This is necessary because we want the root to always be mounted on /dev/vda.
     block_device_configs: BlockDeviceConfigs,
     network_interface_configs: NetworkInterfaceConfigs,
+    balloon_config: Option<BalloonConfig>,
     #[cfg(feature = "vsock")]
     vsock_device_configs: VsockDeviceConfigs,
+    logger_config: Option<LoggerConfig>,
 
     epoll_context: EpollContext,
 
@@ -782,6 +1048,7 @@ impl Vmm {
             shared_info: api_shared_info,
             guest_memory: None,
             kernel_config: None,
+            boot_source_config: None,
             vcpus_handles: vec![],
             exit_evt: None,
             vm,
@@ -789,8 +1056,10 @@ impl Vmm {
             legacy_device_manager: LegacyDeviceManager::new().map_err(Error::CreateLegacyDevice)?,
             block_device_configs,
             network_interface_configs: NetworkInterfaceConfigs::new(),
+            balloon_config: None,
             #[cfg(feature = "vsock")]
             vsock_device_configs: VsockDeviceConfigs::new(),
+            logger_config: None,
             epoll_context,
             api_event,
             from_api,
@@ -802,7 +1071,7 @@ impl Vmm {
     fn update_drive_handler(
         &mut self,
         drive_id: &str,
-        disk_image: File,
+        disk_image: Box<dyn virtio::DiskImage>,
     ) -> result::Result<(), DriveError> {
         let handler = self
             .epoll_context
@@ -849,13 +1118,8 @@ impl Vmm {
         // device manager has been initialized.
         let device_manager = self.mmio_device_manager.as_mut().unwrap();
 
-        for drive_config in self.block_device_configs.config_list.iter_mut() {
-            // Add the block device from file.
-            let block_file = OpenOptions::new()
-                .read(true)
-                .write(!drive_config.is_read_only)
-                .open(&drive_config.path_on_host)
-                .map_err(StartMicrovmError::OpenBlockDevice)?;
+        for drive_config in self.block_device_configs.config_list.iter() {
+            let block_box = Vmm::create_block_device(epoll_context, drive_config)?;
 
             if drive_config.is_root_device && drive_config.get_partuuid().is_some() {
                 kernel_config
@@ -879,29 +1143,6 @@ impl Vmm {
                 }
             }
 
-            let epoll_config = epoll_context.allocate_virtio_tokens(
-                TYPE_BLOCK,
-                &drive_config.drive_id,
-                BLOCK_EVENTS_COUNT,
-            );
-            let rate_limiter = match drive_config.rate_limiter {
-                Some(rlim_cfg) => Some(
-                    rlim_cfg
-                        .into_rate_limiter()
-                        .map_err(StartMicrovmError::CreateRateLimiter)?,
-                ),
-                None => None,
-            };
-
-            let block_box = Box::new(
-                devices::virtio::Block::new(
-                    block_file,
-                    drive_config.is_read_only,
-                    epoll_config,
-                    rate_limiter,
-                )
-                .map_err(StartMicrovmError::CreateBlockDevice)?,
-            );
             device_manager
                 .register_virtio_device(
                     self.vm.get_fd(),
@@ -916,6 +1157,53 @@ impl Vmm {
         Ok(())
     }
 
+    // Creates the virtio block device backed by the file of `drive_config`.
+    fn create_block_device(
+        epoll_context: &mut EpollContext,
+        drive_config: &BlockDeviceConfig,
+    ) -> std::result::Result<Box<devices::virtio::Block>, StartMicrovmError> {
+        let disk_image = virtio::open_disk_image(
+            &drive_config.path_on_host,
+            drive_config.is_read_only,
+            drive_config.format,
+            drive_config.overlay_path(),
+            drive_config.cache_type,
+        )
+        .map_err(StartMicrovmError::OpenBlockDevice)?;
+
+        let epoll_config = epoll_context.allocate_virtio_tokens(
+            TYPE_BLOCK,
+            &drive_config.drive_id,
+            block_events_count(drive_config.num_queues),
+        );
+        let rate_limiter = match drive_config.rate_limiter {
+            Some(rlim_cfg) => Some(
+                rlim_cfg
+                    .into_rate_limiter()
+                    .map_err(StartMicrovmError::CreateRateLimiter)?,
+            ),
+            None => None,
+        };
+
+        Ok(Box::new(
+            devices::virtio::Block::new(
+                disk_image,
+                drive_config.is_read_only,
+                drive_config.io_engine,
+                drive_config.num_queues,
+                drive_config.cache_type,
+                drive_config.topology,
+                epoll_config,
+                rate_limiter,
+                DeviceMetrics::new(
+                    METRICS.drives.register(&drive_config.drive_id),
+                    &METRICS.block,
+                ),
+            )
+            .map_err(StartMicrovmError::CreateBlockDevice)?,
+        ))
+    }
+
     fn attach_net_devices(&mut self) -> std::result::Result<(), StartMicrovmError> {
         // We rely on check_health function for making sure kernel_config is not None.
         let kernel_config = self
@@ -928,55 +1216,77 @@ impl Vmm {
         let device_manager = self.mmio_device_manager.as_mut().unwrap();
 
         for cfg in self.network_interface_configs.iter_mut() {
-            let epoll_config = self.epoll_context.allocate_virtio_tokens(
-                TYPE_NET,
-                &cfg.iface_id,
-                NET_EVENTS_COUNT,
-            );
+            let net_box = Vmm::create_net_device(&mut self.epoll_context, cfg)?;
 
-            let allow_mmds_requests = cfg.allow_mmds_requests();
-            let rx_rate_limiter = match cfg.rx_rate_limiter {
-                Some(rlim) => Some(
-                    rlim.into_rate_limiter()
-                        .map_err(StartMicrovmError::CreateRateLimiter)?,
-                ),
-                None => None,
-            };
-            let tx_rate_limiter = match cfg.tx_rate_limiter {
-                Some(rlim) => Some(
-                    rlim.into_rate_limiter()
-                        .map_err(StartMicrovmError::CreateRateLimiter)?,
-                ),
-                None => None,
-            };
+            device_manager
+                .register_virtio_device(
+                    self.vm.get_fd(),
+                    net_box,
+                    &mut kernel_config.cmdline,
+                    TYPE_NET,
+                    &cfg.iface_id,
+                )
+                .map_err(StartMicrovmError::RegisterNetDevice)?;
+        }
+        Ok(())
+    }
 
-            if let Some(tap) = cfg.take_tap() {
-                let net_box = Box::new(
-                    devices::virtio::Net::new_with_tap(
-                        tap,
-                        cfg.guest_mac(),
-                        epoll_config,
-                        rx_rate_limiter,
-                        tx_rate_limiter,
-                        allow_mmds_requests,
-                    )
-                    .map_err(StartMicrovmError::CreateNetDevice)?,
-                );
+    // Creates the virtio net device backed by the tap of `cfg`, which is taken from it.
+    fn create_net_device(
+        epoll_context: &mut EpollContext,
+        cfg: &mut NetworkInterfaceConfig,
+    ) -> std::result::Result<Box<devices::virtio::Net>, StartMicrovmError> {
+        let taps = cfg.take_taps();
+        if taps.is_empty() {
+            return Err(StartMicrovmError::NetDeviceNotConfigured);
+        }
 
-                device_manager
-                    .register_virtio_device(
-                        self.vm.get_fd(),
-                        net_box,
-                        &mut kernel_config.cmdline,
-                        TYPE_NET,
-                        &cfg.iface_id,
-                    )
-                    .map_err(StartMicrovmError::RegisterNetDevice)?;
-            } else {
-                return Err(StartMicrovmError::NetDeviceNotConfigured)?;
-            }
+        let epoll_config = epoll_context.allocate_virtio_tokens(
+            TYPE_NET,
+            &cfg.iface_id,
+            net_events_count(cfg.num_queue_pairs),
+        );
+
+        let allow_mmds_requests = cfg.allow_mmds_requests();
+        let rx_rate_limiter = match cfg.rx_rate_limiter {
+            Some(rlim) => Some(
+                rlim.into_rate_limiter()
+                    .map_err(StartMicrovmError::CreateRateLimiter)?,
+            ),
+            None => None,
+        };
+        let tx_rate_limiter = match cfg.tx_rate_limiter {
+            Some(rlim) => Some(
+                rlim.into_rate_limiter()
+                    .map_err(StartMicrovmError::CreateRateLimiter)?,
+            ),
+            None => None,
+        };
+
+        let mut net = devices::virtio::Net::new_with_taps(
+            taps,
+            cfg.guest_mac(),
+            cfg.mtu,
+            epoll_config,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            allow_mmds_requests,
+            DeviceMetrics::new(
+                METRICS.network_interfaces.register(&cfg.iface_id),
+                &METRICS.net,
+            ),
+        )
+        .map_err(StartMicrovmError::CreateNetDevice)?;
+        if let Some(ref capture_cfg) = cfg.capture {
+            net.set_capture(Some(
+                capture_cfg
+                    .open()
+                    .map_err(StartMicrovmError::OpenNetCapture)?,
+            ));
         }
-        Ok(())
+        net.set_anti_spoofing(cfg.anti_spoofing());
+        net.set_packet_filters(cfg.ingress_filter.clone(), cfg.egress_filter.clone());
+        Ok(Box::new(net))
     }
 
     #[cfg(feature = "vsock")]
@@ -1038,7 +1348,7 @@ impl Vmm {
     #[cfg(target_arch = "x86_64")]
     fn log_dirty_pages(&mut self) {
         // If we're logging dirty pages, post the metrics on how many dirty pages there are.
-        if LOGGER.flags() | LogOption::LogDirtyPages as usize > 0 {
+        if LOGGER.flags() & LogOption::LogDirtyPages as usize > 0 {
             METRICS.memory.dirty_pages.add(self.get_dirty_page_count());
         }
     }
@@ -1059,8 +1369,14 @@ impl Vmm {
             ))?
             << 20;
         let arch_mem_regions = arch::arch_memory_regions(mem_size);
-        self.guest_memory =
-            Some(GuestMemory::new(&arch_mem_regions).map_err(StartMicrovmError::GuestMemory)?);
+        // The memory released by the balloon device is only given back to the host by private
+        // mappings.
+        let guest_memory = if self.balloon_config.is_some() {
+            GuestMemory::new_private(&arch_mem_regions)
+        } else {
+            GuestMemory::new(&arch_mem_regions)
+        };
+        self.guest_memory = Some(guest_memory.map_err(StartMicrovmError::GuestMemory)?);
         self.vm
             .memory_init(
                 self.guest_memory
@@ -1106,11 +1422,55 @@ impl Vmm {
         Ok(())
     }
 
+    fn attach_balloon_device(&mut self) -> std::result::Result<(), StartMicrovmError> {
+        let balloon_config = match self.balloon_config {
+            Some(ref cfg) => cfg,
+            None => return Ok(()),
+        };
+        // We rely on check_health function for making sure kernel_config is not None.
+        let kernel_config = self
+            .kernel_config
+            .as_mut()
+            .ok_or(StartMicrovmError::MissingKernelConfig)?;
+
+        let epoll_config = self.epoll_context.allocate_virtio_tokens(
+            TYPE_BALLOON,
+            BALLOON_DEVICE_ID,
+            BALLOON_EVENTS_COUNT,
+        );
+        let balloon_box = Box::new(
+            devices::virtio::Balloon::new(
+                mib_to_balloon_pages(balloon_config.amount_mib),
+                balloon_config.deflate_on_oom,
+                balloon_config.stats_polling_interval_s,
+                epoll_config,
+            )
+            .map_err(StartMicrovmError::CreateBalloonDevice)?,
+        );
+
+        // `unwrap` is suitable for this context since this should be called only after the
+        // device manager has been initialized.
+        self.mmio_device_manager
+            .as_mut()
+            .unwrap()
+            .register_virtio_device(
+                self.vm.get_fd(),
+                balloon_box,
+                &mut kernel_config.cmdline,
+                TYPE_BALLOON,
+                BALLOON_DEVICE_ID,
+            )
+            .map_err(StartMicrovmError::RegisterMMIODevice)?;
+
+        Ok(())
+    }
+
     fn attach_virtio_devices(&mut self) -> std::result::Result<(), StartMicrovmError> {
         self.init_mmio_device_manager()?;
 
         self.attach_block_devices()?;
         self.attach_net_devices()?;
+        self.attach_balloon_device()?;
         #[cfg(feature = "vsock")]
         {
             let guest_mem = self
@@ -1121,10 +1481,32 @@ impl Vmm {
                 ))?;
             self.attach_vsock_devices(&guest_mem)?;
         }
+        #[cfg(target_arch = "x86_64")]
+        self.reserve_hotplug_slots()?;
 
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    // Reserves the MMIO slots for the devices attached after boot, right after the devices
+    // attached at boot time.
+    fn reserve_hotplug_slots(&mut self) -> std::result::Result<(), StartMicrovmError> {
+        let kernel_config = self
+            .kernel_config
+            .as_mut()
+            .ok_or(StartMicrovmError::MissingKernelConfig)?;
+        // `unwrap` is suitable for this context since this should be called only after the
+        // device manager has been initialized.
+        let device_manager = self.mmio_device_manager.as_mut().unwrap();
+
+        device_manager
+            .reserve_hotplug_slots(
+                &mut kernel_config.cmdline,
+                self.vm_config.hotplug_slots.unwrap_or(0),
+            )
+            .map_err(StartMicrovmError::RegisterMMIODevice)
+    }
+
     #[cfg(target_arch = "aarch64")]
     fn get_mmio_device_info(&self) -> Option<&HashMap<(DeviceType, String), MMIODeviceInfo>> {
         if let Some(ref device_manager) = self.mmio_device_manager {
@@ -1242,6 +1624,10 @@ impl Vmm {
 
         let vcpus_thread_barrier = Arc::new(Barrier::new((vcpu_count + 1) as usize));
 
+        // The signal used for kicking vCPUs out of KVM_RUN needs a handler, otherwise it would
+        // terminate the process.
+        vstate::register_vcpu_kick_handler().map_err(StartMicrovmError::Vcpu)?;
+
         // We're going in reverse so we can `.pop()` on the vec and still maintain order.
         for cpu_id in (0..vcpu_count).rev() {
             let vcpu_thread_barrier = vcpus_thread_barrier.clone();
@@ -1262,14 +1648,25 @@ impl Vmm {
                 vcpu.set_mmio_bus(mmio_device_manager.bus.clone());
             }
             let seccomp_level = self.seccomp_level;
-            self.vcpus_handles.push(
-                thread::Builder::new()
-                    .name(format!("fc_vcpu{}", cpu_id))
-                    .spawn(move || {
-                        vcpu.run(vcpu_thread_barrier, seccomp_level, vcpu_exit_evt);
-                    })
-                    .map_err(StartMicrovmError::VcpuSpawn)?,
-            );
+            let (event_sender, event_receiver) = channel();
+            let (response_sender, response_receiver) = channel();
+            let vcpu_thread = thread::Builder::new()
+                .name(format!("fc_vcpu{}", cpu_id))
+                .spawn(move || {
+                    vcpu.run(
+                        vcpu_thread_barrier,
+                        seccomp_level,
+                        vcpu_exit_evt,
+                        event_receiver,
+                        response_sender,
+                    );
+                })
+                .map_err(StartMicrovmError::VcpuSpawn)?;
+            self.vcpus_handles.push(VcpuHandle::new(
+                event_sender,
+                response_receiver,
+                vcpu_thread,
+            ));
         }
 
         // Load seccomp filters for the VMM thread.
@@ -1294,12 +1691,13 @@ impl Vmm {
         let vm_memory = self.vm.get_memory().ok_or(StartMicrovmError::GuestMemory(
             memory_model::GuestMemoryError::MemoryNotInitialized,
         ))?;
-        let entry_addr = kernel_loader::load_kernel(
-            vm_memory,
-            &mut kernel_config.kernel_file,
-            arch::get_kernel_start(),
-        )
-        .map_err(StartMicrovmError::KernelLoader)?;
+        let kernel_file = kernel_config
+            .kernel_file
+            .as_mut()
+            .ok_or(StartMicrovmError::MissingKernelConfig)?;
+        let entry_addr =
+            kernel_loader::load_kernel(vm_memory, kernel_file, arch::get_kernel_start())
+                .map_err(StartMicrovmError::KernelLoader)?;
 
         // This is x86_64 specific since on aarch64 the commandline will be specified through the FDT.
         #[cfg(target_arch = "x86_64")]
@@ -1432,6 +1830,13 @@ impl Vmm {
             .expect("Failed to start microVM because shared info couldn't be written due to poisoned lock")
             .state = InstanceState::Running;
 
+        self.start_metrics_timer();
+
+        Ok(VmmData::Empty)
+    }
+
+    // Arms the periodic metrics write timer and logs the metrics straight away.
+    fn start_metrics_timer(&mut self) {
         // Arm the log write timer.
         // TODO: the timer does not stop on InstanceStop.
         let timer_state = TimerState::Periodic {
@@ -1446,8 +1851,6 @@ impl Vmm {
         if LOGGER.log_metrics().is_err() {
             METRICS.logger.missed_metrics_count.inc();
         }
-
-        Ok(VmmData::Empty)
     }
 
     fn send_ctrl_alt_del(&mut self) -> std::result::Result<VmmData, VmmActionError> {
@@ -1460,62 +1863,464 @@ impl Vmm {
         Ok(VmmData::Empty)
     }
 
-    /// Waits for all vCPUs to exit and terminates the Firecracker process.
-    fn stop(&mut self, exit_code: i32) {
-        info!("Vmm is stopping.");
+    fn instance_state(&self) -> InstanceState {
+        // Use expect() to crash if the other thread poisoned this lock.
+        self.shared_info
+            .read()
+            .expect("Failed to read instance state because shared info couldn't be read due to poisoned lock")
+            .state
+            .clone()
+    }
 
-        if let Err(e) = self.epoll_context.disable_stdin_event() {
-            warn!("Cannot disable the STDIN event. {:?}", e);
-        }
+    fn set_instance_state(&mut self, instance_state: InstanceState) {
+        // Use expect() to crash if the other thread poisoned this lock.
+        self.shared_info
+            .write()
+            .expect("Failed to set instance state because shared info couldn't be written due to poisoned lock")
+            .state = instance_state;
+    }
 
-        if let Err(e) = self
-            .legacy_device_manager
-            .stdin_handle
-            .lock()
-            .set_canon_mode()
-        {
-            warn!("Cannot set canonical mode for the terminal. {:?}", e);
+    // Waits until the vCPU answers with `expected`, skipping the answers to earlier events.
+    fn wait_vcpu_response(
+        handle: &VcpuHandle,
+        expected: &VcpuResponse,
+    ) -> std::result::Result<(), vstate::Error> {
+        loop {
+            if handle.wait_response()? == *expected {
+                return Ok(());
+            }
         }
+    }
 
-        // Log the metrics before exiting.
-        if let Err(e) = LOGGER.log_metrics() {
-            error!("Failed to log metrics while stopping: {}", e);
+    // Sends `event` to all vCPUs and waits until each of them answers with `expected`. If a vCPU
+    // fails to, `rollback` is sent to the vCPUs which may have got `event`, so that the microVM
+    // is not left with only some of its vCPUs paused or resumed. The vCPUs handle their events in
+    // order, so the ones which have not handled `event` yet end up in their previous state too.
+    fn signal_vcpus(
+        &self,
+        event: VcpuEvent,
+        expected: VcpuResponse,
+        rollback: VcpuEvent,
+        rollback_expected: VcpuResponse,
+    ) -> std::result::Result<(), PauseResumeError> {
+        // The index of the vCPU which failed, and whether the vCPUs after it got `event`.
+        let mut failure = None;
+        for (index, handle) in self.vcpus_handles.iter().enumerate() {
+            if let Err(e) = handle.send_event(event.clone()) {
+                failure = Some((index, e, false));
+                break;
+            }
+        }
+        if failure.is_none() {
+            for (index, handle) in self.vcpus_handles.iter().enumerate() {
+                if let Err(e) = Vmm::wait_vcpu_response(handle, &expected) {
+                    failure = Some((index, e, true));
+                    break;
+                }
+            }
         }
 
-        // Exit from Firecracker using the provided exit code. Safe because we're terminating
-        // the process anyway.
-        unsafe {
-            libc::_exit(exit_code);
+        let (failed_index, error, all_sent) = match failure {
+            Some(failure) => failure,
+            None => return Ok(()),
+        };
+        let sent_count = if all_sent {
+            self.vcpus_handles.len()
+        } else {
+            failed_index + 1
+        };
+        // Undoing the event is harmless for the vCPUs which did not get it, since pausing a
+        // paused vCPU or resuming a running one does nothing.
+        for handle in &self.vcpus_handles[..sent_count] {
+            if let Err(e) = handle.send_event(rollback.clone()) {
+                error!("Failed to roll back a vCPU: {:?}", e);
+            }
+        }
+        for (index, handle) in self.vcpus_handles[..sent_count].iter().enumerate() {
+            // The failed vCPU would most likely time out again.
+            if index != failed_index {
+                if let Err(e) = Vmm::wait_vcpu_response(handle, &rollback_expected) {
+                    error!("Failed to roll back vCPU {}: {:?}", index, e);
+                }
+            }
         }
+        Err(PauseResumeError::Vcpu(error))
     }
 
-    fn is_instance_initialized(&self) -> bool {
-        let instance_state = {
-            // Use expect() to crash if the other thread poisoned this lock.
-            let shared_info = self.shared_info.read()
-                .expect("Failed to determine if instance is initialized because shared info couldn't be read due to poisoned lock");
-            shared_info.state.clone()
-        };
-        match instance_state {
-            InstanceState::Uninitialized => false,
-            _ => true,
+    fn pause_vcpus(&mut self) -> std::result::Result<VmmData, VmmActionError> {
+        if self.instance_state() != InstanceState::Running {
+            Err(PauseResumeError::MicroVMNotRunning)?;
         }
+        self.signal_vcpus(
+            VcpuEvent::Pause,
+            VcpuResponse::Paused,
+            VcpuEvent::Resume,
+            VcpuResponse::Resumed,
+        )?;
+        self.set_instance_state(InstanceState::Paused);
+        Ok(VmmData::Empty)
     }
 
-    #[allow(clippy::unused_label)]
-    fn run_control(&mut self) -> Result<()> {
-        const EPOLL_EVENTS_LEN: usize = 100;
+    fn resume_vcpus(&mut self) -> std::result::Result<VmmData, VmmActionError> {
+        if self.instance_state() != InstanceState::Paused {
+            Err(PauseResumeError::MicroVMNotPaused)?;
+        }
+        self.signal_vcpus(
+            VcpuEvent::Resume,
+            VcpuResponse::Resumed,
+            VcpuEvent::Pause,
+            VcpuResponse::Paused,
+        )?;
+        self.set_instance_state(InstanceState::Running);
+        Ok(VmmData::Empty)
+    }
 
-        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
+    #[cfg(target_arch = "x86_64")]
+    fn create_snapshot(
+        &mut self,
+        snapshot_config: SnapshotConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        info!("VMM received create snapshot command");
+        if self.instance_state() != InstanceState::Paused {
+            Err(SnapshotError::MicroVMNotPaused)?;
+        }
+        // The state of the hot-plug slots is not saved, so it could not be restored.
+        if self.vm_config.hotplug_slots.unwrap_or(0) > 0 {
+            Err(SnapshotError::HotplugSlots)?;
+        }
 
-        let epoll_raw_fd = self.epoll_context.epoll_raw_fd;
+        let vcpus = self.save_vcpus()?;
+        let vm = self.vm.save_state().map_err(SnapshotError::VmState)?;
+        let devices = self.save_devices()?;
+        let serial = self
+            .legacy_device_manager
+            .stdio_serial
+            .lock()
+            .expect("Failed to save the serial state due to poisoned lock")
+            .save_state();
+        let i8042 = self
+            .legacy_device_manager
+            .i8042
+            .lock()
+            .expect("Failed to save the i8042 state due to poisoned lock")
+            .save_state();
 
-        // TODO: try handling of errors/failures without breaking this main loop.
-        'poll: loop {
-            let num_events = epoll::wait(epoll_raw_fd, -1, &mut events[..]).map_err(Error::Poll)?;
+        snapshot::MicrovmState {
+            version: snapshot::SNAPSHOT_VERSION,
+            vm_config: self.vm_config.clone(),
+            vm,
+            vcpus,
+            devices,
+            serial,
+            i8042,
+        }
+        .save(&snapshot_config.snapshot_path)?;
 
-            for event in events.iter().take(num_events) {
-                let dispatch_idx = event.data as usize;
+        // The microVM is paused, so its memory is not changing under our feet.
+        let guest_memory = self
+            .guest_memory
+            .as_ref()
+            .ok_or(StartMicrovmError::GuestMemory(
+                memory_model::GuestMemoryError::MemoryNotInitialized,
+            ))?;
+        snapshot::save_memory(guest_memory, &snapshot_config.mem_file_path)?;
+
+        Ok(VmmData::Empty)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Asks each (paused) vCPU thread for the state of its vCPU.
+    fn save_vcpus(&self) -> std::result::Result<Vec<vstate::VcpuState>, SnapshotError> {
+        for handle in self.vcpus_handles.iter() {
+            handle
+                .send_event(VcpuEvent::SaveState)
+                .map_err(SnapshotError::Vcpu)?;
+        }
+        let mut vcpus = Vec::with_capacity(self.vcpus_handles.len());
+        for (cpu_id, handle) in self.vcpus_handles.iter().enumerate() {
+            loop {
+                match handle.wait_response().map_err(SnapshotError::Vcpu)? {
+                    VcpuResponse::SavedState(state) => {
+                        vcpus.push(*state);
+                        break;
+                    }
+                    VcpuResponse::SaveStateFailed => {
+                        return Err(SnapshotError::SaveVcpuState(cpu_id as u8));
+                    }
+                    // Leftover acknowledgements of previous events.
+                    _ => (),
+                }
+            }
+        }
+        Ok(vcpus)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Saves the state of all virtio devices, ordered by MMIO address.
+    fn save_devices(&self) -> std::result::Result<Vec<snapshot::DeviceState>, SnapshotError> {
+        let device_manager = match self.mmio_device_manager {
+            Some(ref device_manager) => device_manager,
+            None => return Ok(vec![]),
+        };
+
+        let mut devices = Vec::new();
+        for ((device_type, id), info) in device_manager.get_device_info().iter() {
+            match *device_type {
+                DeviceType::Virtio(TYPE_BLOCK) => {
+                    // The requests in flight on an io_uring cannot be saved.
+                    let is_async =
+                        self.block_device_configs.config_list.iter().any(|cfg| {
+                            cfg.drive_id == *id && cfg.io_engine == virtio::IoEngine::Async
+                        });
+                    if is_async {
+                        return Err(SnapshotError::UnsupportedDevice(id.clone()));
+                    }
+                }
+                DeviceType::Virtio(TYPE_NET) => (),
+                _ => return Err(SnapshotError::UnsupportedDevice(id.clone())),
+            }
+            let bus_device = device_manager
+                .get_device(device_type.clone(), id)
+                .ok_or_else(|| SnapshotError::DeviceState(format!("Device {} not found.", id)))?;
+            let mut bus_device = bus_device
+                .lock()
+                .expect("Failed to save the device state due to poisoned lock");
+            let mmio_device = bus_device
+                .as_mut_any()
+                .downcast_mut::<virtio::MmioDevice>()
+                .ok_or_else(|| {
+                    SnapshotError::DeviceState(format!("Device {} is not a virtio device.", id))
+                })?;
+            devices.push(snapshot::DeviceState {
+                id: id.clone(),
+                info: info.clone(),
+                state: mmio_device.save_state(),
+            });
+        }
+        devices.sort_by_key(|device| device.info.addr);
+        Ok(devices)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn load_snapshot(
+        &mut self,
+        snapshot_config: SnapshotConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        info!("VMM received load snapshot command");
+        if self.is_instance_initialized() {
+            Err(SnapshotError::MicroVMAlreadyRunning)?;
+        }
+        let request_ts = TimestampUs {
+            time_us: get_time_us(),
+            cputime_us: now_cputime_us(),
+        };
+
+        let state = snapshot::MicrovmState::load(&snapshot_config.snapshot_path)?;
+        if state.vm_config.vcpu_count != Some(state.vcpus.len() as u8) {
+            Err(SnapshotError::InvalidSnapshot(
+                "The vCPU count does not match the saved vCPU states.".to_string(),
+            ))?;
+        }
+        self.check_snapshot_devices(&state)?;
+
+        // Like a failed `InstanceStart`, a failure past this point leaves the microVM partly set
+        // up, in the `Starting` state, which refuses any further configuration, start or load.
+        self.set_instance_state(InstanceState::Starting);
+        self.vm_config = state.vm_config.clone();
+        // The guest kernel is already in memory. The device manager still appends the
+        // device parameters to this command line, but it is never passed to the guest.
+        self.configure_kernel(KernelConfig {
+            cmdline: kernel_cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE),
+            kernel_file: None,
+            cmdline_addr: GuestAddress(arch::x86_64::layout::CMDLINE_START),
+        });
+
+        self.init_guest_memory()?;
+        // `init_guest_memory` succeeded, so the guest memory is initialized.
+        snapshot::load_memory(
+            self.guest_memory.as_ref().unwrap(),
+            &snapshot_config.mem_file_path,
+        )?;
+
+        self.setup_interrupt_controller()?;
+        self.attach_virtio_devices()?;
+        self.attach_legacy_devices()?;
+        self.restore_devices(&state)?;
+
+        let mut vcpus = Vec::with_capacity(state.vcpus.len());
+        for (cpu_id, vcpu_state) in state.vcpus.iter().enumerate() {
+            let io_bus = self.legacy_device_manager.io_bus.clone();
+            let mut vcpu = Vcpu::new(cpu_id as u8, &self.vm, io_bus, request_ts.clone())
+                .map_err(StartMicrovmError::Vcpu)?;
+            vcpu.restore_state(&self.vm_config, vcpu_state)
+                .map_err(SnapshotError::Vcpu)?;
+            vcpus.push(vcpu);
+        }
+        self.vm
+            .restore_state(&state.vm)
+            .map_err(SnapshotError::VmState)?;
+
+        self.register_events()?;
+        self.start_vcpus(vcpus)?;
+        self.set_instance_state(InstanceState::Running);
+
+        self.start_metrics_timer();
+
+        Ok(VmmData::Empty)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Checks that the configured devices are the ones in the snapshot, in the same order, so
+    // that they end up at the same MMIO addresses.
+    fn check_snapshot_devices(
+        &mut self,
+        state: &snapshot::MicrovmState,
+    ) -> std::result::Result<(), SnapshotError> {
+        #[cfg(feature = "vsock")]
+        {
+            if let Some(cfg) = self.vsock_device_configs.iter().next() {
+                return Err(SnapshotError::UnsupportedDevice(cfg.id.clone()));
+            }
+        }
+
+        // Devices are attached in this order, at increasing MMIO addresses.
+        let configured = self
+            .block_device_configs
+            .config_list
+            .iter()
+            .map(|cfg| (TYPE_BLOCK, cfg.drive_id.as_str()))
+            .chain(
+                self.network_interface_configs
+                    .iter()
+                    .map(|cfg| (TYPE_NET, cfg.iface_id.as_str())),
+            );
+        let saved = state
+            .devices
+            .iter()
+            .map(|device| (device.state.device_type, device.id.as_str()));
+        if !configured.eq(saved) {
+            return Err(SnapshotError::DevicesMismatch);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Restores the state of the freshly attached virtio and legacy devices.
+    fn restore_devices(
+        &mut self,
+        state: &snapshot::MicrovmState,
+    ) -> std::result::Result<(), SnapshotError> {
+        // `unwrap` is suitable for this context since this should be called only after the
+        // device manager has been initialized.
+        let device_manager = self.mmio_device_manager.as_ref().unwrap();
+        for device in state.devices.iter() {
+            let device_type = DeviceType::Virtio(device.state.device_type);
+            if device_manager
+                .get_device_info()
+                .get(&(device_type.clone(), device.id.clone()))
+                != Some(&device.info)
+            {
+                return Err(SnapshotError::DevicesMismatch);
+            }
+            let bus_device = device_manager
+                .get_device(device_type, &device.id)
+                .ok_or(SnapshotError::DevicesMismatch)?;
+            let mut bus_device = bus_device
+                .lock()
+                .expect("Failed to restore the device state due to poisoned lock");
+            bus_device
+                .as_mut_any()
+                .downcast_mut::<virtio::MmioDevice>()
+                .ok_or(SnapshotError::DevicesMismatch)?
+                .restore_state(&device.state)
+                .map_err(|e| SnapshotError::DeviceState(format!("{}: {:?}", device.id, e)))?;
+        }
+
+        self.legacy_device_manager
+            .stdio_serial
+            .lock()
+            .expect("Failed to restore the serial state due to poisoned lock")
+            .restore_state(&state.serial);
+        self.legacy_device_manager
+            .i8042
+            .lock()
+            .expect("Failed to restore the i8042 state due to poisoned lock")
+            .restore_state(&state.i8042)
+            .map_err(|e| SnapshotError::DeviceState(e.to_string()))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn create_snapshot(
+        &mut self,
+        _snapshot_config: SnapshotConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        Err(SnapshotError::UnsupportedArchitecture)?
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn load_snapshot(
+        &mut self,
+        _snapshot_config: SnapshotConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        Err(SnapshotError::UnsupportedArchitecture)?
+    }
+
+    /// Waits for all vCPUs to exit and terminates the Firecracker process.
+    fn stop(&mut self, exit_code: i32) {
+        info!("Vmm is stopping.");
+
+        if let Err(e) = self.epoll_context.disable_stdin_event() {
+            warn!("Cannot disable the STDIN event. {:?}", e);
+        }
+
+        if let Err(e) = self
+            .legacy_device_manager
+            .stdin_handle
+            .lock()
+            .set_canon_mode()
+        {
+            warn!("Cannot set canonical mode for the terminal. {:?}", e);
+        }
+
+        // Log the metrics before exiting.
+        if let Err(e) = LOGGER.log_metrics() {
+            error!("Failed to log metrics while stopping: {}", e);
+        }
+
+        // Exit from Firecracker using the provided exit code. Safe because we're terminating
+        // the process anyway.
+        unsafe {
+            libc::_exit(exit_code);
+        }
+    }
+
+    fn is_instance_initialized(&self) -> bool {
+        let instance_state = {
+            // Use expect() to crash if the other thread poisoned this lock.
+            let shared_info = self.shared_info.read()
+                .expect("Failed to determine if instance is initialized because shared info couldn't be read due to poisoned lock");
+            shared_info.state.clone()
+        };
+        match instance_state {
+            InstanceState::Uninitialized => false,
+            _ => true,
+        }
+    }
+
+    #[allow(clippy::unused_label)]
+    fn run_control(&mut self) -> Result<()> {
+        const EPOLL_EVENTS_LEN: usize = 100;
+
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
+
+        let epoll_raw_fd = self.epoll_context.epoll_raw_fd;
+
+        // TODO: try handling of errors/failures without breaking this main loop.
+        'poll: loop {
+            let num_events = epoll::wait(epoll_raw_fd, -1, &mut events[..]).map_err(Error::Poll)?;
+
+            for event in events.iter().take(num_events) {
+                let dispatch_idx = event.data as usize;
                 let evset = match epoll::Events::from_bits(event.events) {
                     Some(evset) => evset,
                     None => {
@@ -1641,12 +2446,16 @@ impl Vmm {
             ));
         }
 
-        let kernel_file = File::open(kernel_image_path).map_err(|_| {
+        let kernel_file = File::open(&kernel_image_path).map_err(|_| {
             VmmActionError::BootSource(ErrorKind::User, BootSourceConfigError::InvalidKernelPath)
         })?;
         let mut cmdline = kernel_cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
         cmdline
-            .insert_str(kernel_cmdline.unwrap_or_else(|| String::from(DEFAULT_KERNEL_CMDLINE)))
+            .insert_str(
+                kernel_cmdline
+                    .clone()
+                    .unwrap_or_else(|| String::from(DEFAULT_KERNEL_CMDLINE)),
+            )
             .map_err(|_| {
                 VmmActionError::BootSource(
                     ErrorKind::User,
@@ -1655,16 +2464,117 @@ impl Vmm {
             })?;
 
         let kernel_config = KernelConfig {
-            kernel_file,
+            kernel_file: Some(kernel_file),
             cmdline,
             #[cfg(target_arch = "x86_64")]
             cmdline_addr: GuestAddress(arch::x86_64::layout::CMDLINE_START),
         };
         self.configure_kernel(kernel_config);
+        self.boot_source_config = Some(BootSourceConfig {
+            kernel_image_path,
+            boot_args: kernel_cmdline,
+        });
 
         Ok(VmmData::Empty)
     }
 
+    fn get_boot_source(&self) -> std::result::Result<VmmData, VmmActionError> {
+        self.boot_source_config
+            .clone()
+            .map(VmmData::BootSource)
+            .ok_or_else(|| {
+                VmmActionError::BootSource(ErrorKind::User, BootSourceConfigError::NotConfigured)
+            })
+    }
+
+    fn get_block_device(&self, drive_id: &str) -> std::result::Result<VmmData, VmmActionError> {
+        self.block_device_configs
+            .config_list
+            .iter()
+            .find(|cfg| cfg.drive_id == drive_id)
+            .map(|cfg| VmmData::BlockDevice(cfg.clone()))
+            .ok_or_else(|| VmmActionError::from(DriveError::InvalidBlockDeviceID))
+    }
+
+    fn get_net_device(&self, iface_id: &str) -> std::result::Result<VmmData, VmmActionError> {
+        self.network_interface_configs
+            .iter()
+            .find(|cfg| cfg.iface_id == iface_id)
+            .map(|cfg| VmmData::NetworkInterface(cfg.clone()))
+            .ok_or_else(|| VmmActionError::from(NetworkInterfaceError::DeviceIdNotFound))
+    }
+
+    #[cfg(feature = "vsock")]
+    fn get_vsock_device(&self, id: &str) -> std::result::Result<VmmData, VmmActionError> {
+        self.vsock_device_configs
+            .iter()
+            .find(|cfg| cfg.id == id)
+            .map(|cfg| VmmData::Vsock(cfg.clone()))
+            .ok_or_else(|| {
+                VmmActionError::VsockConfig(ErrorKind::User, VsockError::DeviceIdNotFound)
+            })
+    }
+
+    fn get_logger_config(&self) -> std::result::Result<VmmData, VmmActionError> {
+        self.logger_config
+            .clone()
+            .map(VmmData::Logger)
+            .ok_or_else(|| {
+                VmmActionError::Logger(ErrorKind::User, LoggerConfigError::NotConfigured)
+            })
+    }
+
+    // Looks up where the device of the given type and ID is mapped on the MMIO bus, if the
+    // microVM was started.
+    fn get_mmio_config(&self, type_id: u32, device_id: &str) -> Option<MmioConfig> {
+        self.mmio_device_manager
+            .as_ref()
+            .and_then(|device_manager| {
+                device_manager
+                    .get_device_info()
+                    .get(&(DeviceType::Virtio(type_id), device_id.to_string()))
+                    .map(MmioConfig::from)
+            })
+    }
+
+    fn get_microvm_config(&self) -> MicrovmConfig {
+        MicrovmConfig {
+            boot_source: self.boot_source_config.clone(),
+            machine_config: self.vm_config.clone(),
+            drives: self
+                .block_device_configs
+                .config_list
+                .iter()
+                .map(|cfg| AttachedDevice {
+                    config: cfg.clone(),
+                    mmio: self.get_mmio_config(TYPE_BLOCK, &cfg.drive_id),
+                })
+                .collect(),
+            network_interfaces: self
+                .network_interface_configs
+                .iter()
+                .map(|cfg| AttachedDevice {
+                    config: cfg.clone(),
+                    mmio: self.get_mmio_config(TYPE_NET, &cfg.iface_id),
+                })
+                .collect(),
+            #[cfg(feature = "vsock")]
+            vsocks: self
+                .vsock_device_configs
+                .iter()
+                .map(|cfg| AttachedDevice {
+                    config: cfg.clone(),
+                    mmio: self.get_mmio_config(TYPE_VSOCK, &cfg.id),
+                })
+                .collect(),
+            balloon: self.balloon_config.as_ref().map(|cfg| AttachedDevice {
+                config: cfg.clone(),
+                mmio: self.get_mmio_config(TYPE_BALLOON, BALLOON_DEVICE_ID),
+            }),
+            logger: self.logger_config.clone(),
+        }
+    }
+
     fn set_vm_configuration(
         &mut self,
         machine_config: VmConfig,
@@ -1715,6 +2625,67 @@ impl Vmm {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.hotplug_slots.is_some() {
+            self.vm_config.hotplug_slots = machine_config.hotplug_slots;
+        }
+
+        Ok(VmmData::Empty)
+    }
+
+    fn get_balloon_config(&self) -> std::result::Result<VmmData, VmmActionError> {
+        self.balloon_config
+            .clone()
+            .map(VmmData::BalloonConfig)
+            .ok_or_else(|| VmmActionError::from(BalloonError::DeviceNotFound))
+    }
+
+    // Checks that the balloon can hold `amount_mib` of guest memory.
+    fn check_balloon_size(&self, amount_mib: u32) -> std::result::Result<(), BalloonError> {
+        match self.vm_config.mem_size_mib {
+            Some(mem_size_mib) if amount_mib as usize > mem_size_mib => {
+                Err(BalloonError::TooManyPagesRequested)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn insert_balloon_device(
+        &mut self,
+        balloon_config: BalloonConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.is_instance_initialized() {
+            Err(BalloonError::UpdateNotAllowedPostBoot)?;
+        }
+        self.check_balloon_size(balloon_config.amount_mib)?;
+
+        self.balloon_config = Some(balloon_config);
+        Ok(VmmData::Empty)
+    }
+
+    fn update_balloon(
+        &mut self,
+        balloon_update: BalloonUpdateConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.balloon_config.is_none() {
+            Err(BalloonError::DeviceNotFound)?;
+        }
+        self.check_balloon_size(balloon_update.amount_mib)?;
+
+        if self.is_instance_initialized() {
+            // Safe to unwrap() because mmio_device_manager is initialized before the guest
+            // boots.
+            self.mmio_device_manager
+                .as_ref()
+                .unwrap()
+                .update_balloon(
+                    BALLOON_DEVICE_ID,
+                    mib_to_balloon_pages(balloon_update.amount_mib),
+                )
+                .map_err(|e| BalloonError::UpdateFailed(e.to_string()))?;
+        }
+        // Safe to unwrap() because the balloon configuration was checked above.
+        self.balloon_config.as_mut().unwrap().amount_mib = balloon_update.amount_mib;
+
         Ok(VmmData::Empty)
     }
 
@@ -1723,7 +2694,7 @@ impl Vmm {
         body: NetworkInterfaceConfig,
     ) -> std::result::Result<VmmData, VmmActionError> {
         if self.is_instance_initialized() {
-            Err(NetworkInterfaceError::UpdateNotAllowedPostBoot)?;
+            return self.hotplug_net_device(body);
         }
         self.network_interface_configs
             .insert(body)
@@ -1731,10 +2702,81 @@ impl Vmm {
             .map_err(|e| VmmActionError::NetworkConfig(ErrorKind::User, e))
     }
 
+    // Attaches a new network interface to the running microVM, in one of the hot-plug slots.
+    fn hotplug_net_device(
+        &mut self,
+        body: NetworkInterfaceConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        let iface_id = body.iface_id.clone();
+        self.network_interface_configs.insert_hotplugged(body)?;
+
+        // Safe to unwrap() because the configuration was inserted above.
+        let cfg = self.network_interface_configs.get_mut(&iface_id).unwrap();
+        let result = Vmm::create_net_device(&mut self.epoll_context, cfg)
+            .map_err(|e| NetworkInterfaceError::HotplugFailed(e.to_string()))
+            .and_then(|net_box| {
+                // Safe to unwrap() because mmio_device_manager is initialized before the guest
+                // boots, and this function is called after boot.
+                self.mmio_device_manager
+                    .as_mut()
+                    .unwrap()
+                    .hotplug_virtio_device(self.vm.get_fd(), net_box, TYPE_NET, &iface_id)
+                    .map_err(|e| match e {
+                        device_manager::mmio::Error::NoFreeSlot => {
+                            NetworkInterfaceError::NoHotplugSlot
+                        }
+                        e => NetworkInterfaceError::HotplugFailed(e.to_string()),
+                    })
+            });
+        if let Err(e) = result {
+            self.epoll_context
+                .remove_device_handler(TYPE_NET, &iface_id);
+            METRICS.network_interfaces.unregister(&iface_id);
+            self.network_interface_configs.remove(&iface_id);
+            Err(e)?;
+        }
+
+        Ok(VmmData::Empty)
+    }
+
+    // Removes a network interface. When the microVM is running, the device is also detached from
+    // it.
+    fn remove_net_device(
+        &mut self,
+        iface_id: &str,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.network_interface_configs.get_mut(iface_id).is_none() {
+            Err(NetworkInterfaceError::DeviceIdNotFound)?;
+        }
+
+        if self.is_instance_initialized() {
+            // Safe to unwrap() because mmio_device_manager is initialized before the guest
+            // boots, and this branch is only taken after boot.
+            self.mmio_device_manager
+                .as_mut()
+                .unwrap()
+                .unplug_virtio_device(self.vm.get_fd(), TYPE_NET, iface_id)
+                .map_err(|e| match e {
+                    device_manager::mmio::Error::DeviceNotFound => {
+                        NetworkInterfaceError::NotHotplugged
+                    }
+                    device_manager::mmio::Error::DeviceInUse => NetworkInterfaceError::DeviceInUse,
+                    e => NetworkInterfaceError::UnplugFailed(e.to_string()),
+                })?;
+            self.epoll_context.remove_device_handler(TYPE_NET, iface_id);
+            METRICS.network_interfaces.unregister(iface_id);
+        }
+        self.network_interface_configs.remove(iface_id);
+
+        Ok(VmmData::Empty)
+    }
+
     fn update_net_device(
         &mut self,
         new_cfg: NetworkInterfaceUpdateConfig,
     ) -> std::result::Result<VmmData, VmmActionError> {
+        new_cfg.validate()?;
+
         if !self.is_instance_initialized() {
             // VM not started yet, so we only need to update the device configs, not the actual
             // live device.
@@ -1766,6 +2808,14 @@ impl Vmm {
                 }
             }
 
+            // The packet filters are replaced as a whole.
+            if new_cfg.ingress_filter.is_some() {
+                old_cfg.ingress_filter = new_cfg.ingress_filter;
+            }
+            if new_cfg.egress_filter.is_some() {
+                old_cfg.egress_filter = new_cfg.egress_filter;
+            }
+
             return Ok(VmmData::Empty);
         }
 
@@ -1801,7 +2851,72 @@ impl Vmm {
                 .map(|rl| rl.ops.map(vmm_config::TokenBucketConfig::into_token_bucket))
                 .unwrap_or(None),
         );
+        handler.patch_packet_filters(
+            new_cfg.ingress_filter.clone(),
+            new_cfg.egress_filter.clone(),
+        );
+
+        // Unlike the rate limiters, which keep no configuration once built, the packet filters
+        // in use are reported by the configuration.
+        if let Some(cfg) = self.network_interface_configs.get_mut(&new_cfg.iface_id) {
+            if new_cfg.ingress_filter.is_some() {
+                cfg.ingress_filter = new_cfg.ingress_filter;
+            }
+            if new_cfg.egress_filter.is_some() {
+                cfg.egress_filter = new_cfg.egress_filter;
+            }
+        }
+
+        Ok(VmmData::Empty)
+    }
+
+    fn start_network_capture(
+        &mut self,
+        iface_id: &str,
+        capture_cfg: NetworkCaptureConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if !self.is_instance_initialized() {
+            let config = self
+                .network_interface_configs
+                .get_mut(iface_id)
+                .ok_or(NetworkInterfaceError::DeviceIdNotFound)?;
+            // The file is created right away, so that an invalid path is reported by this
+            // request rather than by the start of the microVM, which creates it again.
+            capture_cfg
+                .open()
+                .map_err(NetworkInterfaceError::OpenCapture)?;
+            config.capture = Some(capture_cfg);
+            return Ok(VmmData::Empty);
+        }
 
+        let handler = self
+            .epoll_context
+            .get_device_handler_by_device_id::<virtio::NetEpollHandler>(TYPE_NET, iface_id)
+            .map_err(NetworkInterfaceError::EpollHandlerNotFound)?;
+        handler.set_capture(Some(
+            capture_cfg
+                .open()
+                .map_err(NetworkInterfaceError::OpenCapture)?,
+        ));
+        Ok(VmmData::Empty)
+    }
+
+    fn stop_network_capture(
+        &mut self,
+        iface_id: &str,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if !self.is_instance_initialized() {
+            self.network_interface_configs
+                .get_mut(iface_id)
+                .ok_or(NetworkInterfaceError::DeviceIdNotFound)?
+                .capture = None;
+            return Ok(VmmData::Empty);
+        }
+
+        self.epoll_context
+            .get_device_handler_by_device_id::<virtio::NetEpollHandler>(TYPE_NET, iface_id)
+            .map_err(NetworkInterfaceError::EpollHandlerNotFound)?
+            .set_capture(None);
         Ok(VmmData::Empty)
     }
 
@@ -1834,12 +2949,17 @@ impl Vmm {
             .ok_or(DriveError::InvalidBlockDeviceID)?;
 
         let file_path = PathBuf::from(path_on_host);
-        // Try to open the file specified by path_on_host using the permissions of the block_device.
-        let disk_file = OpenOptions::new()
-            .read(true)
-            .write(!self.block_device_configs.config_list[block_device_index].is_read_only())
-            .open(&file_path)
-            .map_err(|_| DriveError::CannotOpenBlockDevice)?;
+        // Try to open the image specified by path_on_host using the permissions and the format of
+        // the block_device.
+        let drive_config = &self.block_device_configs.config_list[block_device_index];
+        let disk_image = virtio::open_disk_image(
+            &file_path,
+            drive_config.is_read_only(),
+            drive_config.format,
+            drive_config.overlay_path(),
+            drive_config.cache_type,
+        )
+        .map_err(|_| DriveError::CannotOpenBlockDevice)?;
 
         // Update the path of the block device with the specified path_on_host.
         self.block_device_configs.config_list[block_device_index].path_on_host = file_path;
@@ -1847,7 +2967,7 @@ impl Vmm {
         // When the microvm is running, we also need to update the drive handler and send a
         // rescan command to the drive.
         if self.is_instance_initialized() {
-            self.update_drive_handler(&drive_id, disk_file)?;
+            self.update_drive_handler(&drive_id, disk_image)?;
             self.rescan_block_device(&drive_id)?;
         }
         Ok(VmmData::Empty)
@@ -1867,9 +2987,17 @@ impl Vmm {
         let device_manager = self.mmio_device_manager.as_ref().unwrap();
         for drive_config in self.block_device_configs.config_list.iter() {
             if drive_config.drive_id == *drive_id {
-                let metadata = metadata(&drive_config.path_on_host)
-                    .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
-                let new_size = metadata.len();
+                // The size of the guest disk is not the size of the file for all the formats. An
+                // overlay has the size of its base.
+                let new_size = virtio::open_disk_image(
+                    &drive_config.path_on_host,
+                    true,
+                    drive_config.format,
+                    None,
+                    drive_config.cache_type,
+                )
+                .and_then(|mut disk_image| disk_image.seek(SeekFrom::End(0)))
+                .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
                 if new_size % virtio::block::SECTOR_SIZE != 0 {
                     warn!(
                         "Disk size {} is not a multiple of sector size {}; \
@@ -1894,7 +3022,7 @@ impl Vmm {
         block_device_config: BlockDeviceConfig,
     ) -> std::result::Result<VmmData, VmmActionError> {
         if self.is_instance_initialized() {
-            Err(DriveError::UpdateNotAllowedPostBoot)?;
+            return self.hotplug_block_device(block_device_config);
         }
 
         self.block_device_configs
@@ -1903,8 +3031,79 @@ impl Vmm {
             .map_err(VmmActionError::from)
     }
 
+    // Attaches a new block device to the running microVM, in one of the hot-plug slots.
+    fn hotplug_block_device(
+        &mut self,
+        block_device_config: BlockDeviceConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        self.block_device_configs
+            .insert_hotplugged(block_device_config.clone())?;
+
+        let drive_id = &block_device_config.drive_id;
+        let result = Vmm::create_block_device(&mut self.epoll_context, &block_device_config)
+            .map_err(|e| match e {
+                StartMicrovmError::OpenBlockDevice(_) => DriveError::CannotOpenBlockDevice,
+                e => DriveError::HotplugFailed(e.to_string()),
+            })
+            .and_then(|block_box| {
+                // Safe to unwrap() because mmio_device_manager is initialized before the guest
+                // boots, and this function is called after boot.
+                self.mmio_device_manager
+                    .as_mut()
+                    .unwrap()
+                    .hotplug_virtio_device(self.vm.get_fd(), block_box, TYPE_BLOCK, drive_id)
+                    .map_err(|e| match e {
+                        device_manager::mmio::Error::NoFreeSlot => DriveError::NoHotplugSlot,
+                        e => DriveError::HotplugFailed(e.to_string()),
+                    })
+            });
+        if let Err(e) = result {
+            self.epoll_context
+                .remove_device_handler(TYPE_BLOCK, drive_id);
+            METRICS.drives.unregister(drive_id);
+            self.block_device_configs.remove(drive_id);
+            Err(e)?;
+        }
+
+        Ok(VmmData::Empty)
+    }
+
+    // Removes a block device. When the microVM is running, the device is also detached from it.
+    fn remove_block_device(
+        &mut self,
+        drive_id: &str,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self
+            .block_device_configs
+            .get_index_of_drive_id(drive_id)
+            .is_none()
+        {
+            Err(DriveError::InvalidBlockDeviceID)?;
+        }
+
+        if self.is_instance_initialized() {
+            // Safe to unwrap() because mmio_device_manager is initialized before the guest
+            // boots, and this branch is only taken after boot.
+            self.mmio_device_manager
+                .as_mut()
+                .unwrap()
+                .unplug_virtio_device(self.vm.get_fd(), TYPE_BLOCK, drive_id)
+                .map_err(|e| match e {
+                    device_manager::mmio::Error::DeviceNotFound => DriveError::NotHotplugged,
+                    device_manager::mmio::Error::DeviceInUse => DriveError::DeviceInUse,
+                    e => DriveError::UnplugFailed(e.to_string()),
+                })?;
+            self.epoll_context
+                .remove_device_handler(TYPE_BLOCK, drive_id);
+            METRICS.drives.unregister(drive_id);
+        }
+        self.block_device_configs.remove(drive_id);
+
+        Ok(VmmData::Empty)
+    }
+
     fn init_logger(
-        &self,
+        &mut self,
         api_logger: LoggerConfig,
     ) -> std::result::Result<VmmData, VmmActionError> {
         if self.is_instance_initialized() {
@@ -1916,6 +3115,13 @@ impl Vmm {
             ));
         }
 
+        let log_output = api_logger
+            .log_output()
+            .map_err(|e| VmmActionError::Logger(ErrorKind::User, e))?;
+        let metrics_output = api_logger
+            .metrics_output()
+            .map_err(|e| VmmActionError::Logger(ErrorKind::User, e))?;
+
         let instance_id;
         let firecracker_version;
         {
@@ -1924,15 +3130,13 @@ impl Vmm {
             firecracker_version = guard.vmm_version.clone();
         }
 
-        match api_logger.level {
-            LoggerLevel::Error => LOGGER.set_level(Level::Error),
-            LoggerLevel::Warning => LOGGER.set_level(Level::Warn),
-            LoggerLevel::Info => LOGGER.set_level(Level::Info),
-            LoggerLevel::Debug => LOGGER.set_level(Level::Debug),
-        }
-
+        LOGGER.set_level(log_level(&api_logger.level));
         LOGGER.set_include_origin(api_logger.show_log_origin, api_logger.show_log_origin);
         LOGGER.set_include_level(api_logger.show_level);
+        match api_logger.format {
+            LoggerFormat::Text => LOGGER.set_format(LogFormat::Text),
+            LoggerFormat::Json => LOGGER.set_format(LogFormat::Json),
+        }
 
         #[cfg(target_arch = "aarch64")]
         let options: &Vec<Value> = &vec![];
@@ -1943,17 +3147,63 @@ impl Vmm {
             .init(
                 &AppInfo::new("Firecracker", &firecracker_version),
                 &instance_id,
-                api_logger.log_fifo,
-                api_logger.metrics_fifo,
+                &LogDestination::from(log_output),
+                &LogDestination::from(metrics_output),
                 options,
             )
-            .map(|_| VmmData::Empty)
             .map_err(|e| {
                 VmmActionError::Logger(
                     ErrorKind::User,
                     LoggerConfigError::InitializationFailure(e.to_string()),
                 )
-            })
+            })?;
+        self.logger_config = Some(api_logger);
+
+        Ok(VmmData::Empty)
+    }
+
+    fn update_logger(
+        &mut self,
+        logger_update: LoggerUpdateConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        let logger_config = self.logger_config.as_mut().ok_or_else(|| {
+            VmmActionError::Logger(ErrorKind::User, LoggerConfigError::NotConfigured)
+        })?;
+
+        // Change the dirty page tracking first, since it is the only change which can fail.
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(enabled) = logger_update.log_dirty_pages {
+                self.vm.set_dirty_page_logging(enabled).map_err(|e| {
+                    VmmActionError::Logger(
+                        ErrorKind::Internal,
+                        LoggerConfigError::UpdateFailed(format!("{:?}", e)),
+                    )
+                })?;
+                LOGGER.set_option(LogOption::LogDirtyPages, enabled);
+                if let Some(options) = logger_config.options.as_array_mut() {
+                    options.retain(|option| option != "LogDirtyPages");
+                    if enabled {
+                        options.push("LogDirtyPages".into());
+                    }
+                }
+            }
+        }
+
+        if let Some(level) = logger_update.level {
+            LOGGER.set_level(log_level(&level));
+            logger_config.level = level;
+        }
+        if let Some(show_level) = logger_update.show_level {
+            LOGGER.set_include_level(show_level);
+            logger_config.show_level = show_level;
+        }
+        if let Some(show_log_origin) = logger_update.show_log_origin {
+            LOGGER.set_include_origin(show_log_origin, show_log_origin);
+            logger_config.show_log_origin = show_log_origin;
+        }
+
+        Ok(VmmData::Empty)
     }
 
     fn send_response(outcome: VmmRequestOutcome, sender: OutcomeSender) {
@@ -1990,12 +3240,43 @@ impl Vmm {
             VmmAction::FlushMetrics(sender) => {
                 Vmm::send_response(self.flush_metrics(), sender);
             }
+            VmmAction::CreateSnapshot(snapshot_config, sender) => {
+                Vmm::send_response(self.create_snapshot(snapshot_config), sender);
+            }
+            VmmAction::GetBalloonConfig(sender) => {
+                Vmm::send_response(self.get_balloon_config(), sender);
+            }
+            VmmAction::GetBlockDevice(drive_id, sender) => {
+                Vmm::send_response(self.get_block_device(&drive_id), sender);
+            }
+            VmmAction::GetBootSource(sender) => {
+                Vmm::send_response(self.get_boot_source(), sender);
+            }
+            VmmAction::GetLoggerConfiguration(sender) => {
+                Vmm::send_response(self.get_logger_config(), sender);
+            }
+            VmmAction::GetMicrovmConfiguration(sender) => {
+                Vmm::send_response(
+                    Ok(VmmData::MicrovmConfiguration(self.get_microvm_config())),
+                    sender,
+                );
+            }
+            VmmAction::GetNetworkInterface(iface_id, sender) => {
+                Vmm::send_response(self.get_net_device(&iface_id), sender);
+            }
             VmmAction::GetVmConfiguration(sender) => {
                 Vmm::send_response(
                     Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
                     sender,
                 );
             }
+            #[cfg(feature = "vsock")]
+            VmmAction::GetVsockDevice(id, sender) => {
+                Vmm::send_response(self.get_vsock_device(&id), sender);
+            }
+            VmmAction::InsertBalloonDevice(balloon_cfg, sender) => {
+                Vmm::send_response(self.insert_balloon_device(balloon_cfg), sender);
+            }
             VmmAction::InsertBlockDevice(block_device_config, sender) => {
                 Vmm::send_response(self.insert_block_device(block_device_config), sender);
             }
@@ -2006,12 +3287,33 @@ impl Vmm {
             VmmAction::InsertVsockDevice(vsock_cfg, sender) => {
                 Vmm::send_response(self.insert_vsock_device(vsock_cfg), sender);
             }
+            VmmAction::LoadSnapshot(snapshot_config, sender) => {
+                Vmm::send_response(self.load_snapshot(snapshot_config), sender);
+            }
+            VmmAction::PauseVcpus(sender) => {
+                Vmm::send_response(self.pause_vcpus(), sender);
+            }
+            VmmAction::RemoveBlockDevice(drive_id, sender) => {
+                Vmm::send_response(self.remove_block_device(&drive_id), sender);
+            }
+            VmmAction::RemoveNetworkInterface(iface_id, sender) => {
+                Vmm::send_response(self.remove_net_device(&iface_id), sender);
+            }
             VmmAction::RescanBlockDevice(drive_id, sender) => {
                 Vmm::send_response(self.rescan_block_device(&drive_id), sender);
             }
+            VmmAction::ResumeVcpus(sender) => {
+                Vmm::send_response(self.resume_vcpus(), sender);
+            }
             VmmAction::StartMicroVm(sender) => {
                 Vmm::send_response(self.start_microvm(), sender);
             }
+            VmmAction::StartNetworkCapture(iface_id, capture_cfg, sender) => {
+                Vmm::send_response(self.start_network_capture(&iface_id, capture_cfg), sender);
+            }
+            VmmAction::StopNetworkCapture(iface_id, sender) => {
+                Vmm::send_response(self.stop_network_capture(&iface_id), sender);
+            }
             VmmAction::SendCtrlAltDel(sender) => {
                 Vmm::send_response(self.send_ctrl_alt_del(), sender);
             }
@@ -2021,6 +3323,12 @@ impl Vmm {
             VmmAction::UpdateBlockDevicePath(drive_id, path_on_host, sender) => {
                 Vmm::send_response(self.set_block_device_path(drive_id, path_on_host), sender);
             }
+            VmmAction::UpdateBalloon(balloon_update, sender) => {
+                Vmm::send_response(self.update_balloon(balloon_update), sender);
+            }
+            VmmAction::UpdateLogger(logger_update, sender) => {
+                Vmm::send_response(self.update_logger(logger_update), sender);
+            }
             VmmAction::UpdateNetworkInterface(netif_update, sender) => {
                 Vmm::send_response(self.update_net_device(netif_update), sender);
             }
@@ -2066,6 +3374,10 @@ impl PartialEq for VmmAction {
                 &VmmAction::ConfigureLogger(ref log, _),
                 &VmmAction::ConfigureLogger(ref other_log, _),
             ) => log == other_log,
+            (
+                &VmmAction::UpdateLogger(ref log_update, _),
+                &VmmAction::UpdateLogger(ref other_log_update, _),
+            ) => log_update == other_log_update,
             (
                 &VmmAction::SetVmConfiguration(ref vm_config, _),
                 &VmmAction::SetVmConfiguration(ref other_vm_config, _),
@@ -2078,13 +3390,66 @@ impl PartialEq for VmmAction {
                 &VmmAction::UpdateNetworkInterface(ref net_dev, _),
                 &VmmAction::UpdateNetworkInterface(ref other_net_dev, _),
             ) => net_dev == other_net_dev,
+            (
+                &VmmAction::InsertBalloonDevice(ref balloon_cfg, _),
+                &VmmAction::InsertBalloonDevice(ref other_balloon_cfg, _),
+            ) => balloon_cfg == other_balloon_cfg,
+            (
+                &VmmAction::UpdateBalloon(ref balloon_update, _),
+                &VmmAction::UpdateBalloon(ref other_balloon_update, _),
+            ) => balloon_update == other_balloon_update,
+            (
+                &VmmAction::RemoveBlockDevice(ref drive_id, _),
+                &VmmAction::RemoveBlockDevice(ref other_drive_id, _),
+            ) => drive_id == other_drive_id,
+            (
+                &VmmAction::RemoveNetworkInterface(ref iface_id, _),
+                &VmmAction::RemoveNetworkInterface(ref other_iface_id, _),
+            ) => iface_id == other_iface_id,
             (
                 &VmmAction::RescanBlockDevice(ref req, _),
                 &VmmAction::RescanBlockDevice(ref other_req, _),
             ) => req == other_req,
+            (
+                &VmmAction::CreateSnapshot(ref snapshot_config, _),
+                &VmmAction::CreateSnapshot(ref other_snapshot_config, _),
+            ) => snapshot_config == other_snapshot_config,
+            (
+                &VmmAction::LoadSnapshot(ref snapshot_config, _),
+                &VmmAction::LoadSnapshot(ref other_snapshot_config, _),
+            ) => snapshot_config == other_snapshot_config,
+            (
+                &VmmAction::GetBlockDevice(ref drive_id, _),
+                &VmmAction::GetBlockDevice(ref other_drive_id, _),
+            ) => drive_id == other_drive_id,
+            (
+                &VmmAction::GetNetworkInterface(ref iface_id, _),
+                &VmmAction::GetNetworkInterface(ref other_iface_id, _),
+            ) => iface_id == other_iface_id,
+            #[cfg(feature = "vsock")]
+            (
+                &VmmAction::GetVsockDevice(ref id, _),
+                &VmmAction::GetVsockDevice(ref other_id, _),
+            ) => id == other_id,
+            (&VmmAction::GetBalloonConfig(_), &VmmAction::GetBalloonConfig(_)) => true,
+            (&VmmAction::GetBootSource(_), &VmmAction::GetBootSource(_)) => true,
+            (&VmmAction::GetLoggerConfiguration(_), &VmmAction::GetLoggerConfiguration(_)) => true,
+            (&VmmAction::GetMicrovmConfiguration(_), &VmmAction::GetMicrovmConfiguration(_)) => {
+                true
+            }
             (&VmmAction::StartMicroVm(_), &VmmAction::StartMicroVm(_)) => true,
+            (
+                &VmmAction::StartNetworkCapture(ref iface_id, ref capture_cfg, _),
+                &VmmAction::StartNetworkCapture(ref other_iface_id, ref other_capture_cfg, _),
+            ) => iface_id == other_iface_id && capture_cfg == other_capture_cfg,
+            (
+                &VmmAction::StopNetworkCapture(ref iface_id, _),
+                &VmmAction::StopNetworkCapture(ref other_iface_id, _),
+            ) => iface_id == other_iface_id,
             (&VmmAction::SendCtrlAltDel(_), &VmmAction::SendCtrlAltDel(_)) => true,
             (&VmmAction::FlushMetrics(_), &VmmAction::FlushMetrics(_)) => true,
+            (&VmmAction::PauseVcpus(_), &VmmAction::PauseVcpus(_)) => true,
+            (&VmmAction::ResumeVcpus(_), &VmmAction::ResumeVcpus(_)) => true,
             _ => false,
         }
     }
@@ -2138,14 +3503,18 @@ mod tests {
     use std::fs::File;
     use std::io::BufRead;
     use std::io::BufReader;
-    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     use self::tempfile::NamedTempFile;
     use arch::DeviceType;
-    use devices::virtio::{ActivateResult, MmioDevice, Queue};
+    use devices::virtio::{
+        ActivateResult, CacheType, DiskImageFormat, IoEngine, MmioDevice, Queue,
+    };
     use net_util::MacAddr;
     use vmm_config::drive::DriveError;
+    use vmm_config::logger::LoggerDestination;
     use vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm_config::net::{FilterAction, FilterRule, PacketFilter, PortRange};
     use vmm_config::{RateLimiterConfig, TokenBucketConfig};
 
     fn good_kernel_file() -> PathBuf {
@@ -2191,17 +3560,13 @@ mod tests {
             assert!(cmdline.insert_str(DEFAULT_KERNEL_CMDLINE).is_ok());
             let kernel_cfg = KernelConfig {
                 cmdline,
-                kernel_file,
+                kernel_file: Some(kernel_file),
                 #[cfg(target_arch = "x86_64")]
                 cmdline_addr: GuestAddress(arch::x86_64::layout::CMDLINE_START),
             };
             self.configure_kernel(kernel_cfg);
         }
 
-        fn set_instance_state(&mut self, instance_state: InstanceState) {
-            self.shared_info.write().unwrap().state = instance_state;
-        }
-
         fn update_block_device_path(&mut self, block_device_id: &str, new_path: PathBuf) {
             for config in self.block_device_configs.config_list.iter_mut() {
                 if config.drive_id == block_device_id {
@@ -2300,8 +3665,9 @@ mod tests {
     #[test]
     fn test_device_handler() {
         let mut ep = EpollContext::new().unwrap();
-        let (base, sender) = ep.allocate_tokens(1);
+        let (device_idx, base, sender) = ep.allocate_tokens(1);
         assert_eq!(ep.device_handlers.len(), 1);
+        assert_eq!(device_idx, 0);
         assert_eq!(base, 1);
 
         let handler = DummyEpollHandler { evt: None };
@@ -2309,6 +3675,38 @@ mod tests {
         assert!(ep.get_device_handler_by_handler_id(0).is_ok());
     }
 
+    #[test]
+    fn test_reuse_device_handler() {
+        let mut ep = EpollContext::new().unwrap();
+        for (device_id, count) in &[("0", 2), ("1", 3), ("2", 1)] {
+            let (device_idx, _, _) = ep.allocate_tokens(*count);
+            ep.device_id_to_handler_id
+                .insert((TYPE_NET, device_id.to_string()), device_idx);
+        }
+        assert_eq!(ep.dispatch_table.len(), 7);
+
+        // The entries of a detached device are reused by a device needing as many of them.
+        ep.remove_device_handler(TYPE_NET, "0");
+        assert!(ep.dispatch_table[1..3].iter().all(Option::is_none));
+        let (device_idx, base, _) = ep.allocate_tokens(1);
+        assert_eq!((device_idx, base), (0, 1));
+        assert_eq!(
+            ep.dispatch_table[1],
+            Some(EpollDispatch::DeviceHandler(0, 0))
+        );
+
+        // A device needing more entries than the free ones goes to the end of the table, while
+        // the free entries at the end of the table are extended.
+        ep.remove_device_handler(TYPE_NET, "2");
+        let (device_idx, base, _) = ep.allocate_tokens(2);
+        assert_eq!((device_idx, base), (2, 6));
+        assert_eq!(ep.dispatch_table.len(), 8);
+        let (device_idx, base, _) = ep.allocate_tokens(1);
+        assert_eq!((device_idx, base), (3, 2));
+        assert_eq!(ep.device_handlers.len(), 4);
+        assert_eq!(ep.dispatch_table.len(), 8);
+    }
+
     #[test]
     fn test_insert_block_device() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2321,6 +3719,12 @@ mod tests {
             partuuid: None,
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
         assert!(vmm
@@ -2336,6 +3740,12 @@ mod tests {
             partuuid: None,
             is_read_only: true,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
         assert!(vmm
@@ -2351,6 +3761,12 @@ mod tests {
             partuuid: None,
             is_read_only: true,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(root_block_device.clone()).is_err());
 
@@ -2364,6 +3780,12 @@ mod tests {
             partuuid: None,
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(non_root).is_ok());
 
@@ -2376,6 +3798,12 @@ mod tests {
             partuuid: None,
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(non_root).is_err());
 
@@ -2388,6 +3816,12 @@ mod tests {
             partuuid: None,
             is_read_only: true,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         assert!(vmm.insert_block_device(root_block_device).is_err())
     }
@@ -2404,7 +3838,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
         assert!(vmm.insert_net_device(network_interface).is_ok());
 
@@ -2417,7 +3857,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
         assert!(vmm.insert_net_device(network_interface).is_ok());
 
@@ -2429,7 +3875,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
         assert!(vmm.insert_net_device(network_interface).is_err());
 
@@ -2442,7 +3894,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
         assert!(vmm.insert_net_device(network_interface).is_err());
     }
@@ -2472,7 +3930,13 @@ mod tests {
             }),
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         })
         .unwrap();
 
@@ -2486,6 +3950,8 @@ mod tests {
                 bandwidth: None,
                 ops: Some(tbc_2mtps),
             }),
+            ingress_filter: None,
+            egress_filter: None,
         })
         .unwrap();
 
@@ -2502,6 +3968,47 @@ mod tests {
             assert_eq!(nic_1.tx_rate_limiter.unwrap().ops.unwrap(), tbc_2mtps);
         }
 
+        // The packet filters are replaced, if valid.
+        let deny_all = PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: Vec::new(),
+        };
+        vmm.update_net_device(NetworkInterfaceUpdateConfig {
+            iface_id: "1".to_string(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            ingress_filter: Some(deny_all.clone()),
+            egress_filter: None,
+        })
+        .unwrap();
+        let invalid_filter = PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: vec![FilterRule {
+                action: FilterAction::Allow,
+                protocol: None,
+                cidr: None,
+                ports: Some(PortRange { start: 1, end: 2 }),
+            }],
+        };
+        match vmm.update_net_device(NetworkInterfaceUpdateConfig {
+            iface_id: "1".to_string(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            ingress_filter: Some(invalid_filter.clone()),
+            egress_filter: None,
+        }) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::InvalidPacketFilter,
+            )) => (),
+            _ => panic!("Expected an invalid packet filter error."),
+        }
+        {
+            let nic_1 = vmm.network_interface_configs.iter().next().unwrap();
+            assert_eq!(nic_1.ingress_filter, Some(deny_all.clone()));
+            assert_eq!(nic_1.egress_filter, None);
+        }
+
         assert!(vmm.init_guest_memory().is_ok());
         assert!(vmm.setup_interrupt_controller().is_ok());
         vmm.default_kernel_config(None);
@@ -2517,6 +4024,8 @@ mod tests {
                 iface_id: "1".to_string(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                ingress_filter: None,
+                egress_filter: None,
             })
             .is_err());
 
@@ -2555,8 +4064,137 @@ mod tests {
                 bandwidth: Some(tbc_1mtps),
                 ops: None,
             }),
+            ingress_filter: None,
+            egress_filter: Some(deny_all.clone()),
+        })
+        .unwrap();
+
+        // The configuration reports the packet filters in use.
+        let nic_1 = vmm.network_interface_configs.iter().next().unwrap();
+        assert_eq!(nic_1.ingress_filter, Some(deny_all.clone()));
+        assert_eq!(nic_1.egress_filter, Some(deny_all));
+    }
+
+    #[test]
+    fn test_network_capture() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let dir = tempfile::TempDir::new().unwrap();
+        let capture_cfg = NetworkCaptureConfig {
+            path: dir.path().join("1.pcap").to_str().unwrap().to_string(),
+            max_size: 1024 * 1024,
+            max_files: 1,
+        };
+
+        // The network interface must exist.
+        match vmm.start_network_capture("1", capture_cfg.clone()) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::DeviceIdNotFound,
+            )) => (),
+            _ => panic!("Expected a device not found error."),
+        }
+
+        vmm.insert_net_device(NetworkInterfaceConfig {
+            iface_id: String::from("1"),
+            host_dev_name: String::from("hostname11"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         })
         .unwrap();
+
+        // The file must be writable.
+        let bad_capture_cfg = NetworkCaptureConfig {
+            path: dir
+                .path()
+                .join("missing/1.pcap")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ..capture_cfg.clone()
+        };
+        match vmm.start_network_capture("1", bad_capture_cfg) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::OpenCapture(_),
+            )) => (),
+            _ => panic!("Expected an open capture error."),
+        }
+
+        // Before boot, the capture is part of the configuration.
+        assert!(vmm.start_network_capture("1", capture_cfg.clone()).is_ok());
+        assert!(PathBuf::from(&capture_cfg.path).exists());
+        assert_eq!(
+            vmm.network_interface_configs
+                .iter()
+                .next()
+                .unwrap()
+                .capture
+                .as_ref(),
+            Some(&capture_cfg)
+        );
+        assert!(vmm.stop_network_capture("1").is_ok());
+        assert!(vmm
+            .network_interface_configs
+            .iter()
+            .next()
+            .unwrap()
+            .capture
+            .is_none());
+        assert!(vmm.start_network_capture("1", capture_cfg.clone()).is_ok());
+
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        vmm.init_mmio_device_manager()
+            .expect("Cannot initialize mmio device manager");
+
+        std::fs::remove_file(&capture_cfg.path).unwrap();
+        vmm.attach_net_devices().unwrap();
+        vmm.set_instance_state(InstanceState::Running);
+        // The capture file is created along with the device.
+        assert!(PathBuf::from(&capture_cfg.path).exists());
+
+        // The capture cannot change before device activation.
+        assert!(vmm.stop_network_capture("1").is_err());
+
+        // Activate the device
+        {
+            let device_manager = vmm.mmio_device_manager.as_ref().unwrap();
+            let bus_device_mutex = device_manager
+                .get_device(DeviceType::Virtio(TYPE_NET), "1")
+                .unwrap();
+            let bus_device = &mut *bus_device_mutex.lock().unwrap();
+            let mmio_device: &mut MmioDevice = bus_device
+                .as_mut_any()
+                .downcast_mut::<MmioDevice>()
+                .unwrap();
+
+            assert!(mmio_device
+                .device_mut()
+                .activate(
+                    vmm.guest_memory.as_ref().unwrap().clone(),
+                    EventFd::new().unwrap(),
+                    Arc::new(AtomicUsize::new(0)),
+                    vec![Queue::new(0), Queue::new(0)],
+                    vec![EventFd::new().unwrap(), EventFd::new().unwrap()],
+                )
+                .is_ok());
+        }
+
+        assert!(vmm.stop_network_capture("1").is_ok());
+        std::fs::remove_file(&capture_cfg.path).unwrap();
+        assert!(vmm.start_network_capture("1", capture_cfg.clone()).is_ok());
+        assert!(PathBuf::from(&capture_cfg.path).exists());
+        assert!(vmm.stop_network_capture("2").is_err());
     }
 
     #[test]
@@ -2581,6 +4219,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2593,6 +4232,7 @@ mod tests {
             mem_size_mib: Some(256),
             ht_enabled: None,
             cpu_template: None,
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2606,6 +4246,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2617,6 +4258,7 @@ mod tests {
             mem_size_mib: Some(0),
             ht_enabled: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2632,6 +4274,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: None,
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.ht_enabled, Some(false));
@@ -2642,6 +4285,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(2));
@@ -2655,6 +4299,7 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            hotplug_slots: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
     }
@@ -2757,7 +4402,7 @@ mod tests {
             #[cfg(target_arch = "x86_64")]
             cmdline_addr: dummy_addr,
             cmdline: kernel_cmdline::Cmdline::new(10),
-            kernel_file: tempfile::tempfile().unwrap(),
+            kernel_file: Some(tempfile::tempfile().unwrap()),
         });
         assert!(vmm.check_health().is_ok());
     }
@@ -2778,33 +4423,351 @@ mod tests {
 
         let vmm = create_vmm_object(InstanceState::Running);
         assert_eq!(vmm.is_instance_initialized(), true);
+
+        let vmm = create_vmm_object(InstanceState::Paused);
+        assert_eq!(vmm.is_instance_initialized(), true);
     }
 
     #[test]
-    fn test_attach_block_devices() {
+    fn test_pause_resume_vcpus() {
+        // Test pause and resume before boot.
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
-        let block_file = NamedTempFile::new().unwrap();
+        match vmm.pause_vcpus() {
+            Err(VmmActionError::PauseResume(
+                ErrorKind::User,
+                PauseResumeError::MicroVMNotRunning,
+            )) => (),
+            _ => unreachable!(),
+        }
+        match vmm.resume_vcpus() {
+            Err(VmmActionError::PauseResume(
+                ErrorKind::User,
+                PauseResumeError::MicroVMNotPaused,
+            )) => (),
+            _ => unreachable!(),
+        }
 
-        // Use Case 1: Root Block Device is not specified through PARTUUID.
-        let root_block_device = BlockDeviceConfig {
-            drive_id: String::from("root"),
-            path_on_host: block_file.path().to_path_buf(),
-            is_root_device: true,
-            partuuid: None,
-            is_read_only: false,
-            rate_limiter: None,
-        };
-        // Test that creating a new block device returns the correct output.
-        assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
-        assert!(vmm.init_guest_memory().is_ok());
-        assert!(vmm.guest_memory.is_some());
-        assert!(vmm.setup_interrupt_controller().is_ok());
+        // Resuming a running microVM is not allowed.
+        vmm.set_instance_state(InstanceState::Running);
+        assert!(vmm.resume_vcpus().is_err());
+
+        // Without vCPU threads, the state transitions are the only effect.
+        assert!(vmm.pause_vcpus().is_ok());
+        assert_eq!(vmm.instance_state(), InstanceState::Paused);
+        assert!(vmm.pause_vcpus().is_err());
+        assert!(vmm.resume_vcpus().is_ok());
+        assert_eq!(vmm.instance_state(), InstanceState::Running);
+    }
 
-        vmm.default_kernel_config(None);
-        vmm.init_mmio_device_manager()
-            .expect("Cannot initialize mmio device manager");
+    // Creates a handle for a thread which acts like a vCPU, recording whether it is paused.
+    fn fake_vcpu_handle(responsive: bool, paused: Arc<AtomicBool>) -> VcpuHandle {
+        let (event_sender, event_receiver) = channel();
+        let (response_sender, response_receiver) = channel();
+        let thread = thread::spawn(move || {
+            while let Ok(event) = event_receiver.recv() {
+                if !responsive {
+                    continue;
+                }
+                let response = if event == VcpuEvent::Pause {
+                    paused.store(true, Ordering::SeqCst);
+                    VcpuResponse::Paused
+                } else {
+                    paused.store(false, Ordering::SeqCst);
+                    VcpuResponse::Resumed
+                };
+                response_sender.send(response).unwrap();
+            }
+        });
+        VcpuHandle::new(event_sender, response_receiver, thread)
+    }
 
-        assert!(vmm.attach_block_devices().is_ok());
+    // Creates a handle for a vCPU thread which already exited.
+    fn exited_vcpu_handle() -> VcpuHandle {
+        let (event_sender, _) = channel();
+        let (_, response_receiver) = channel();
+        let thread = thread::spawn(|| ());
+        VcpuHandle::new(event_sender, response_receiver, thread)
+    }
+
+    #[test]
+    fn test_pause_resume_vcpus_rollback() {
+        vstate::register_vcpu_kick_handler().unwrap();
+        let paused = [
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        ];
+
+        // The vCPU which paused is resumed when another one does not respond.
+        let mut vmm = create_vmm_object(InstanceState::Running);
+        vmm.vcpus_handles = vec![
+            fake_vcpu_handle(true, paused[0].clone()),
+            fake_vcpu_handle(false, paused[1].clone()),
+        ];
+        match vmm.pause_vcpus() {
+            Err(VmmActionError::PauseResume(
+                ErrorKind::Internal,
+                PauseResumeError::Vcpu(vstate::Error::VcpuResponseTimeout),
+            )) => (),
+            _ => unreachable!(),
+        }
+        assert!(!paused[0].load(Ordering::SeqCst));
+        assert_eq!(vmm.instance_state(), InstanceState::Running);
+
+        // The vCPUs which were sent the event before the failing one are rolled back.
+        vmm.vcpus_handles = vec![
+            fake_vcpu_handle(true, paused[0].clone()),
+            fake_vcpu_handle(true, paused[1].clone()),
+            exited_vcpu_handle(),
+        ];
+        match vmm.pause_vcpus() {
+            Err(VmmActionError::PauseResume(
+                ErrorKind::Internal,
+                PauseResumeError::Vcpu(vstate::Error::VcpuChannel),
+            )) => (),
+            _ => unreachable!(),
+        }
+        assert!(!paused[0].load(Ordering::SeqCst));
+        assert!(!paused[1].load(Ordering::SeqCst));
+        assert_eq!(vmm.instance_state(), InstanceState::Running);
+
+        // The same goes for resuming.
+        vmm.set_instance_state(InstanceState::Paused);
+        paused[0].store(true, Ordering::SeqCst);
+        vmm.vcpus_handles = vec![
+            fake_vcpu_handle(true, paused[0].clone()),
+            exited_vcpu_handle(),
+        ];
+        assert!(vmm.resume_vcpus().is_err());
+        assert!(paused[0].load(Ordering::SeqCst));
+        assert_eq!(vmm.instance_state(), InstanceState::Paused);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_snapshot_preconditions() {
+        let snapshot_config = SnapshotConfig {
+            snapshot_path: String::from("/invalid/snapshot"),
+            mem_file_path: String::from("/invalid/mem"),
+        };
+
+        // Snapshots can only be created while paused.
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.create_snapshot(snapshot_config.clone()) {
+            Err(VmmActionError::Snapshot(ErrorKind::User, SnapshotError::MicroVMNotPaused)) => (),
+            _ => unreachable!(),
+        }
+        vmm.set_instance_state(InstanceState::Running);
+        assert!(vmm.create_snapshot(snapshot_config.clone()).is_err());
+
+        // Snapshots can only be loaded before boot.
+        match vmm.load_snapshot(snapshot_config.clone()) {
+            Err(VmmActionError::Snapshot(
+                ErrorKind::User,
+                SnapshotError::MicroVMAlreadyRunning,
+            )) => (),
+            _ => unreachable!(),
+        }
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.load_snapshot(snapshot_config) {
+            Err(VmmActionError::Snapshot(ErrorKind::User, SnapshotError::OpenSnapshotFile(_))) => {}
+            _ => unreachable!(),
+        }
+        assert_eq!(vmm.instance_state(), InstanceState::Uninitialized);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_load_snapshot_failure() {
+        let mut saved_vmm = create_vmm_object(InstanceState::Uninitialized);
+        saved_vmm.default_kernel_config(None);
+        assert!(saved_vmm.init_guest_memory().is_ok());
+        assert!(saved_vmm.setup_interrupt_controller().is_ok());
+        let mut state = snapshot::MicrovmState {
+            version: snapshot::SNAPSHOT_VERSION,
+            vm_config: VmConfig {
+                vcpu_count: Some(0),
+                ..saved_vmm.vm_config.clone()
+            },
+            vm: saved_vmm.vm.save_state().unwrap(),
+            vcpus: vec![],
+            devices: vec![],
+            serial: saved_vmm
+                .legacy_device_manager
+                .stdio_serial
+                .lock()
+                .unwrap()
+                .save_state(),
+            i8042: saved_vmm
+                .legacy_device_manager
+                .i8042
+                .lock()
+                .unwrap()
+                .save_state(),
+        };
+        // The state of the i8042 device is only restored once the interrupt controller is set
+        // up and the devices are attached.
+        state.i8042.buf.clear();
+        let snapshot_file = NamedTempFile::new().unwrap();
+        assert!(state.save(snapshot_file.path()).is_ok());
+        let mem_file = NamedTempFile::new().unwrap();
+        mem_file
+            .as_file()
+            .set_len((state.vm_config.mem_size_mib.unwrap() << 20) as u64)
+            .unwrap();
+        let snapshot_config = SnapshotConfig {
+            snapshot_path: snapshot_file.path().to_str().unwrap().to_string(),
+            mem_file_path: mem_file.path().to_str().unwrap().to_string(),
+        };
+
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.load_snapshot(snapshot_config.clone()) {
+            Err(VmmActionError::Snapshot(_, SnapshotError::DeviceState(_))) => (),
+            _ => unreachable!(),
+        }
+
+        // The partly set up microVM refuses to be configured, started or loaded again.
+        assert_eq!(vmm.instance_state(), InstanceState::Starting);
+        match vmm.load_snapshot(snapshot_config) {
+            Err(VmmActionError::Snapshot(
+                ErrorKind::User,
+                SnapshotError::MicroVMAlreadyRunning,
+            )) => (),
+            _ => unreachable!(),
+        }
+        match vmm.start_microvm() {
+            Err(VmmActionError::StartMicrovm(
+                ErrorKind::User,
+                StartMicrovmError::MicroVMAlreadyRunning,
+            )) => (),
+            _ => unreachable!(),
+        }
+        assert!(vmm.set_vm_configuration(VmConfig::default()).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_save_restore_devices() {
+        let block_file = NamedTempFile::new().unwrap();
+        let block_device = || BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: block_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        assert!(vmm.insert_block_device(block_device()).is_ok());
+        vmm.default_kernel_config(None);
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        assert!(vmm.attach_virtio_devices().is_ok());
+
+        let devices = vmm.save_devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, "root");
+        assert_eq!(devices[0].state.device_type, TYPE_BLOCK);
+
+        let state = snapshot::MicrovmState {
+            version: snapshot::SNAPSHOT_VERSION,
+            vm_config: vmm.vm_config.clone(),
+            vm: vmm.vm.save_state().unwrap(),
+            vcpus: vec![],
+            devices,
+            serial: vmm
+                .legacy_device_manager
+                .stdio_serial
+                .lock()
+                .unwrap()
+                .save_state(),
+            i8042: vmm.legacy_device_manager.i8042.lock().unwrap().save_state(),
+        };
+
+        // The devices have to be configured the same way as in the snapshot.
+        let mut other_vmm = create_vmm_object(InstanceState::Uninitialized);
+        match other_vmm.check_snapshot_devices(&state) {
+            Err(SnapshotError::DevicesMismatch) => (),
+            _ => unreachable!(),
+        }
+        assert!(other_vmm.insert_block_device(block_device()).is_ok());
+        assert!(other_vmm.check_snapshot_devices(&state).is_ok());
+
+        other_vmm.default_kernel_config(None);
+        assert!(other_vmm.init_guest_memory().is_ok());
+        assert!(other_vmm.setup_interrupt_controller().is_ok());
+        assert!(other_vmm.attach_virtio_devices().is_ok());
+        assert!(other_vmm.restore_devices(&state).is_ok());
+        assert_eq!(other_vmm.save_devices().unwrap(), state.devices);
+
+        // A device mapped elsewhere is reported as a mismatch.
+        let mut state = state.clone();
+        state.devices[0].info.addr += 0x1000;
+        match other_vmm.restore_devices(&state) {
+            Err(SnapshotError::DevicesMismatch) => (),
+            _ => unreachable!(),
+        }
+
+        // The requests in flight on the io_uring of an asynchronous drive cannot be saved.
+        let mut async_vmm = create_vmm_object(InstanceState::Uninitialized);
+        assert!(async_vmm
+            .insert_block_device(BlockDeviceConfig {
+                io_engine: IoEngine::Async,
+                format: DiskImageFormat::Raw,
+                overlay_path: None,
+                num_queues: 1,
+                cache_type: CacheType::Writeback,
+                topology: None,
+                ..block_device()
+            })
+            .is_ok());
+        async_vmm.default_kernel_config(None);
+        assert!(async_vmm.init_guest_memory().is_ok());
+        assert!(async_vmm.setup_interrupt_controller().is_ok());
+        assert!(async_vmm.attach_virtio_devices().is_ok());
+        match async_vmm.save_devices() {
+            Err(SnapshotError::UnsupportedDevice(ref id)) if id == "root" => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_attach_block_devices() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let block_file = NamedTempFile::new().unwrap();
+
+        // Use Case 1: Root Block Device is not specified through PARTUUID.
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: block_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        // Test that creating a new block device returns the correct output.
+        assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.guest_memory.is_some());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+
+        vmm.default_kernel_config(None);
+        vmm.init_mmio_device_manager()
+            .expect("Cannot initialize mmio device manager");
+
+        assert!(vmm.attach_block_devices().is_ok());
         assert!(vmm.get_kernel_cmdline_str().contains("root=/dev/vda rw"));
 
         // Use Case 2: Root Block Device is specified through PARTUUID.
@@ -2816,6 +4779,12 @@ mod tests {
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         // Test that creating a new block device returns the correct output.
@@ -2842,6 +4811,12 @@ mod tests {
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         // Test that creating a new block device returns the correct output.
@@ -2872,61 +4847,477 @@ mod tests {
                 .is_some());
         }
 
-        // Test partial update of block devices.
-        let new_block = NamedTempFile::new().unwrap();
-        let path = String::from(new_block.path().to_path_buf().to_str().unwrap());
-        assert!(vmm
-            .set_block_device_path("not_root".to_string(), path)
-            .is_ok());
+        // Test partial update of block devices.
+        let new_block = NamedTempFile::new().unwrap();
+        let path = String::from(new_block.path().to_path_buf().to_str().unwrap());
+        assert!(vmm
+            .set_block_device_path("not_root".to_string(), path)
+            .is_ok());
+
+        // Test partial update of block device fails due to invalid file.
+        assert!(vmm
+            .set_block_device_path("not_root".to_string(), String::from("dummy_path"))
+            .is_err());
+
+        vmm.set_instance_state(InstanceState::Running);
+
+        // Test updating the block device path, after instance start.
+        let path = String::from(new_block.path().to_path_buf().to_str().unwrap());
+        match vmm.set_block_device_path("not_root".to_string(), path) {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::EpollHandlerNotFound)) => {
+            }
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => {
+                panic!("Updating block device path shouldn't be possible without an epoll handler.")
+            }
+        }
+    }
+
+    #[test]
+    fn test_attach_block_device_with_overlay() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let base_file = NamedTempFile::new().unwrap();
+        base_file.as_file().set_len(0x10_0000).unwrap();
+        let overlay_dir = tempfile::tempdir().unwrap();
+        let overlay_path = overlay_dir.path().join("overlay");
+
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: base_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: Some(overlay_path.clone()),
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        vmm.init_mmio_device_manager()
+            .expect("Cannot initialize mmio device manager");
+
+        // The overlay is created, and the root device is writable.
+        assert!(vmm.attach_block_devices().is_ok());
+        assert!(vmm.get_kernel_cmdline_str().contains("root=/dev/vda rw"));
+        assert!(overlay_path.exists());
+    }
+
+    #[test]
+    fn test_attach_block_device_multi_queue() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let root_file = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: root_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 4,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        vmm.init_mmio_device_manager()
+            .expect("Cannot initialize mmio device manager");
+
+        // Each queue gets its own epoll event.
+        let dispatch_len = vmm.epoll_context.dispatch_table.len();
+        assert!(vmm.attach_block_devices().is_ok());
+        assert_eq!(
+            vmm.epoll_context.dispatch_table.len(),
+            dispatch_len + block_events_count(4)
+        );
+    }
+
+    #[test]
+    fn test_hotplug_block_device() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let root_file = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: root_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
+        vmm.vm_config.hotplug_slots = Some(1);
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        assert!(vmm.attach_virtio_devices().is_ok());
+        // Both the root device and the hot-plug slot are advertised to the guest.
+        assert_eq!(
+            vmm.get_kernel_cmdline_str()
+                .matches("virtio_mmio.device=")
+                .count(),
+            2
+        );
+        vmm.set_instance_state(InstanceState::Running);
+
+        let scratch_file = NamedTempFile::new().unwrap();
+        let scratch = BlockDeviceConfig {
+            drive_id: String::from("scratch"),
+            path_on_host: scratch_file.path().to_path_buf(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(scratch.clone()).is_ok());
+        let slot = vmm.get_mmio_config(TYPE_BLOCK, "scratch").unwrap();
+        assert!(vmm.get_mmio_config(TYPE_BLOCK, "root").unwrap().addr < slot.addr);
+
+        // There is no slot left, and the failed attempt leaves no trace.
+        let other_file = NamedTempFile::new().unwrap();
+        let other = BlockDeviceConfig {
+            drive_id: String::from("other"),
+            path_on_host: other_file.path().to_path_buf(),
+            ..scratch.clone()
+        };
+        match vmm.insert_block_device(other.clone()) {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::NoHotplugSlot)) => (),
+            _ => panic!("Expected a no hot-plug slot error."),
+        }
+        assert!(vmm
+            .block_device_configs
+            .get_index_of_drive_id("other")
+            .is_none());
+        assert!(!vmm
+            .epoll_context
+            .device_id_to_handler_id
+            .contains_key(&(TYPE_BLOCK, String::from("other"))));
+        assert!(!has_drive_metrics("other"));
+
+        // Root devices cannot be attached after boot, and boot time devices cannot be detached.
+        let root = BlockDeviceConfig {
+            drive_id: String::from("other_root"),
+            is_root_device: true,
+            ..other.clone()
+        };
+        match vmm.insert_block_device(root) {
+            Err(VmmActionError::DriveConfig(
+                ErrorKind::User,
+                DriveError::RootBlockDeviceHotplug,
+            )) => (),
+            _ => panic!("Expected a root block device hot-plug error."),
+        }
+        match vmm.remove_block_device("root") {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::NotHotplugged)) => (),
+            _ => panic!("Expected a not hot-plugged error."),
+        }
+
+        // Detaching a device frees its slot.
+        assert!(vmm.remove_block_device("scratch").is_ok());
+        assert!(vmm.get_mmio_config(TYPE_BLOCK, "scratch").is_none());
+        assert!(vmm
+            .block_device_configs
+            .get_index_of_drive_id("scratch")
+            .is_none());
+        assert!(!vmm
+            .epoll_context
+            .device_id_to_handler_id
+            .contains_key(&(TYPE_BLOCK, String::from("scratch"))));
+        match vmm.remove_block_device("scratch") {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::InvalidBlockDeviceID)) => {
+            }
+            _ => panic!("Expected an invalid block device ID error."),
+        }
+        assert!(vmm.insert_block_device(other).is_ok());
+        assert_eq!(vmm.get_mmio_config(TYPE_BLOCK, "other"), Some(slot));
+        assert!(has_drive_metrics("other"));
+        assert!(vmm.remove_block_device("other").is_ok());
+        assert!(!has_drive_metrics("other"));
+    }
+
+    // Returns whether the metrics of the drive `drive_id` are flushed.
+    fn has_drive_metrics(drive_id: &str) -> bool {
+        let drives = serde_json::to_value(&METRICS.drives).unwrap();
+        drives.get(drive_id).is_some()
+    }
+
+    #[test]
+    fn test_remove_block_device_before_boot() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let block_file = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: block_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
+        assert!(vmm.remove_block_device("root").is_ok());
+        assert!(!vmm.block_device_configs.has_root_block_device());
+        assert!(vmm.remove_block_device("root").is_err());
+        // The root device can be added back.
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+    }
+
+    #[test]
+    fn test_attach_net_devices() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.guest_memory.is_some());
+
+        vmm.default_kernel_config(None);
+        vmm.setup_interrupt_controller()
+            .expect("Failed to setup interrupt controller");
+        vmm.init_mmio_device_manager()
+            .expect("Cannot initialize mmio device manager");
+
+        // test create network interface
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname5"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
+        };
+
+        assert!(vmm.insert_net_device(network_interface).is_ok());
+
+        assert!(vmm.attach_net_devices().is_ok());
+        // a second call to attach_net_devices should fail because when
+        // we are creating the virtio::Net object, we are taking the tap.
+        assert!(vmm.attach_net_devices().is_err());
+    }
+
+    #[test]
+    fn test_hotplug_net_device() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname7"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
+        };
+        assert!(vmm.insert_net_device(network_interface).is_ok());
+        vmm.vm_config.hotplug_slots = Some(1);
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        assert!(vmm.attach_virtio_devices().is_ok());
+        vmm.set_instance_state(InstanceState::Running);
+
+        let hotplugged = NetworkInterfaceConfig {
+            iface_id: String::from("hotplugged"),
+            host_dev_name: String::from("hostname8"),
+            guest_mac: Some(MacAddr::parse_str("01:23:45:67:89:0a").unwrap()),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
+        };
+        assert!(vmm.insert_net_device(hotplugged.clone()).is_ok());
+        let slot = vmm.get_mmio_config(TYPE_NET, "hotplugged").unwrap();
+        assert!(vmm.get_mmio_config(TYPE_NET, "netif").unwrap().addr < slot.addr);
+
+        // There is no slot left, and the failed attempt leaves no trace.
+        let other = NetworkInterfaceConfig {
+            iface_id: String::from("other"),
+            host_dev_name: String::from("hostname9"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
+        };
+        match vmm.insert_net_device(other.clone()) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::NoHotplugSlot,
+            )) => (),
+            _ => panic!("Expected a no hot-plug slot error."),
+        }
+        assert!(vmm.network_interface_configs.get_mut("other").is_none());
+        assert!(!vmm
+            .epoll_context
+            .device_id_to_handler_id
+            .contains_key(&(TYPE_NET, String::from("other"))));
+
+        // Boot time interfaces cannot be detached.
+        match vmm.remove_net_device("netif") {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::NotHotplugged,
+            )) => (),
+            _ => panic!("Expected a not hot-plugged error."),
+        }
 
-        // Test partial update of block device fails due to invalid file.
+        // Detaching an interface frees its slot.
+        assert!(vmm.remove_net_device("hotplugged").is_ok());
+        assert!(vmm.get_mmio_config(TYPE_NET, "hotplugged").is_none());
         assert!(vmm
-            .set_block_device_path("not_root".to_string(), String::from("dummy_path"))
-            .is_err());
-
-        vmm.set_instance_state(InstanceState::Running);
-
-        // Test updating the block device path, after instance start.
-        let path = String::from(new_block.path().to_path_buf().to_str().unwrap());
-        match vmm.set_block_device_path("not_root".to_string(), path) {
-            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::EpollHandlerNotFound)) => {
-            }
-            Err(e) => panic!("Unexpected error: {:?}", e),
-            Ok(_) => {
-                panic!("Updating block device path shouldn't be possible without an epoll handler.")
-            }
+            .network_interface_configs
+            .get_mut("hotplugged")
+            .is_none());
+        match vmm.remove_net_device("hotplugged") {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::DeviceIdNotFound,
+            )) => (),
+            _ => panic!("Expected a device ID not found error."),
         }
+        assert!(vmm.insert_net_device(other).is_ok());
+        assert_eq!(vmm.get_mmio_config(TYPE_NET, "other"), Some(slot));
     }
 
     #[test]
-    fn test_attach_net_devices() {
+    fn test_remove_net_device_before_boot() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
-        assert!(vmm.init_guest_memory().is_ok());
-        assert!(vmm.guest_memory.is_some());
-
-        vmm.default_kernel_config(None);
-        vmm.setup_interrupt_controller()
-            .expect("Failed to setup interrupt controller");
-        vmm.init_mmio_device_manager()
-            .expect("Cannot initialize mmio device manager");
-
-        // test create network interface
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif"),
-            host_dev_name: String::from("hostname5"),
+            host_dev_name: String::from("hostname10"),
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
-
+        assert!(vmm.insert_net_device(network_interface.clone()).is_ok());
+        assert!(vmm.remove_net_device("netif").is_ok());
+        assert!(vmm.remove_net_device("netif").is_err());
+        // The host device name can be used again.
         assert!(vmm.insert_net_device(network_interface).is_ok());
+    }
 
-        assert!(vmm.attach_net_devices().is_ok());
-        // a second call to attach_net_devices should fail because when
-        // we are creating the virtio::Net object, we are taking the tap.
-        assert!(vmm.attach_net_devices().is_err());
+    #[test]
+    fn test_balloon_device() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let balloon_update = BalloonUpdateConfig { amount_mib: 32 };
+
+        match vmm.get_balloon_config() {
+            Err(VmmActionError::Balloon(ErrorKind::User, BalloonError::DeviceNotFound)) => (),
+            _ => panic!("Expected a device not found error."),
+        }
+        match vmm.update_balloon(balloon_update.clone()) {
+            Err(VmmActionError::Balloon(ErrorKind::User, BalloonError::DeviceNotFound)) => (),
+            _ => panic!("Expected a device not found error."),
+        }
+
+        // The balloon cannot be larger than the guest memory.
+        let mut balloon_config = BalloonConfig {
+            amount_mib: 256,
+            deflate_on_oom: true,
+            stats_polling_interval_s: 1,
+        };
+        match vmm.insert_balloon_device(balloon_config.clone()) {
+            Err(VmmActionError::Balloon(ErrorKind::User, BalloonError::TooManyPagesRequested)) => {}
+            _ => panic!("Expected a too many pages requested error."),
+        }
+        balloon_config.amount_mib = 64;
+        assert!(vmm.insert_balloon_device(balloon_config.clone()).is_ok());
+        match vmm.get_balloon_config() {
+            Ok(VmmData::BalloonConfig(ref cfg)) => assert_eq!(*cfg, balloon_config),
+            _ => panic!("Expected the balloon configuration."),
+        }
+
+        // Before boot, updates only change the configuration.
+        assert!(vmm.update_balloon(balloon_update).is_ok());
+        assert_eq!(vmm.balloon_config.as_ref().unwrap().amount_mib, 32);
+
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        vmm.default_kernel_config(None);
+        assert!(vmm.attach_virtio_devices().is_ok());
+        assert!(vmm
+            .get_mmio_config(TYPE_BALLOON, BALLOON_DEVICE_ID)
+            .is_some());
+        assert!(vmm.get_microvm_config().balloon.unwrap().mmio.is_some());
+        vmm.set_instance_state(InstanceState::Running);
+
+        match vmm.insert_balloon_device(balloon_config) {
+            Err(VmmActionError::Balloon(
+                ErrorKind::User,
+                BalloonError::UpdateNotAllowedPostBoot,
+            )) => (),
+            _ => panic!("Expected an update not allowed post boot error."),
+        }
+        assert!(vmm
+            .update_balloon(BalloonUpdateConfig { amount_mib: 16 })
+            .is_ok());
+        assert_eq!(vmm.balloon_config.as_ref().unwrap().amount_mib, 16);
+        assert!(vmm
+            .update_balloon(BalloonUpdateConfig { amount_mib: 1024 })
+            .is_err());
+        assert_eq!(vmm.balloon_config.as_ref().unwrap().amount_mib, 16);
+    }
+
+    #[test]
+    fn test_mib_to_balloon_pages() {
+        assert_eq!(mib_to_balloon_pages(0), 0);
+        assert_eq!(mib_to_balloon_pages(1), 256);
+        assert_eq!(mib_to_balloon_pages(128), 32768);
     }
 
     #[test]
@@ -2972,6 +5363,101 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_get_configuration() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+
+        // Nothing was configured yet.
+        match vmm.get_boot_source() {
+            Err(VmmActionError::BootSource(
+                ErrorKind::User,
+                BootSourceConfigError::NotConfigured,
+            )) => {}
+            _ => unreachable!(),
+        }
+        match vmm.get_logger_config() {
+            Err(VmmActionError::Logger(ErrorKind::User, LoggerConfigError::NotConfigured)) => (),
+            _ => unreachable!(),
+        }
+        match vmm.get_block_device("root") {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::InvalidBlockDeviceID)) => {
+            }
+            _ => unreachable!(),
+        }
+        match vmm.get_net_device("eth0") {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::DeviceIdNotFound,
+            )) => (),
+            _ => unreachable!(),
+        }
+
+        let kernel_file = NamedTempFile::new().unwrap();
+        let kernel_path = String::from(kernel_file.path().to_str().unwrap());
+        assert!(vmm
+            .configure_boot_source(kernel_path.clone(), Some(String::from("reboot=k")))
+            .is_ok());
+        match vmm.get_boot_source() {
+            Ok(VmmData::BootSource(boot_source)) => assert_eq!(
+                boot_source,
+                BootSourceConfig {
+                    kernel_image_path: kernel_path.clone(),
+                    boot_args: Some(String::from("reboot=k")),
+                }
+            ),
+            _ => unreachable!(),
+        }
+
+        let block_file = NamedTempFile::new().unwrap();
+        let block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: block_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        assert!(vmm.insert_block_device(block_device.clone()).is_ok());
+        match vmm.get_block_device("root") {
+            Ok(VmmData::BlockDevice(drive)) => assert_eq!(drive, block_device),
+            _ => unreachable!(),
+        }
+
+        // The MMIO location of the devices is only known once they are attached.
+        let microvm_config = vmm.get_microvm_config();
+        assert_eq!(
+            microvm_config.boot_source.unwrap().kernel_image_path,
+            kernel_path
+        );
+        assert_eq!(microvm_config.machine_config, vmm.vm_config);
+        assert_eq!(microvm_config.drives.len(), 1);
+        assert_eq!(microvm_config.drives[0].config, block_device);
+        assert!(microvm_config.drives[0].mmio.is_none());
+        assert!(microvm_config.network_interfaces.is_empty());
+        assert!(microvm_config.logger.is_none());
+
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.setup_interrupt_controller().is_ok());
+        assert!(vmm.attach_virtio_devices().is_ok());
+        let microvm_config = vmm.get_microvm_config();
+        let mmio = microvm_config.drives[0].mmio.as_ref().unwrap();
+        let device_info = vmm
+            .mmio_device_manager
+            .as_ref()
+            .unwrap()
+            .get_device_info()
+            .get(&(DeviceType::Virtio(TYPE_BLOCK), String::from("root")))
+            .unwrap();
+        assert_eq!(*mmio, MmioConfig::from(device_info));
+        assert_eq!(mmio.addr, arch::get_reserved_mem_addr() as u64);
+    }
+
     #[test]
     // Allow assertions on constants is necessary because we cannot implement
     // PartialEq on VmmActionError.
@@ -2991,6 +5477,12 @@ mod tests {
             partuuid: None,
             is_read_only: false,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         let non_root_block_device = BlockDeviceConfig {
             drive_id: scratch_id.clone(),
@@ -2999,6 +5491,12 @@ mod tests {
             partuuid: None,
             is_read_only: true,
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         assert!(vmm.insert_block_device(root_block_device.clone()).is_ok());
@@ -3092,11 +5590,14 @@ mod tests {
         let log_file = NamedTempFile::new().unwrap();
         let metrics_file = NamedTempFile::new().unwrap();
         let desc = LoggerConfig {
-            log_fifo: log_file.path().to_str().unwrap().to_string(),
-            metrics_fifo: metrics_file.path().to_str().unwrap().to_string(),
+            log_fifo: Some(log_file.path().to_str().unwrap().to_string()),
+            metrics_fifo: Some(metrics_file.path().to_str().unwrap().to_string()),
+            log_destination: None,
+            metrics_destination: None,
             level: LoggerLevel::Warning,
             show_level: true,
             show_log_origin: true,
+            format: LoggerFormat::Text,
             #[cfg(target_arch = "x86_64")]
             options: Value::Array(vec![]),
         };
@@ -3104,16 +5605,29 @@ mod tests {
         let mut vmm = create_vmm_object(InstanceState::Running);
         assert!(vmm.init_logger(desc).is_err());
 
+        // Error case: updating the logger before configuring it.
+        assert_eq!(
+            format!(
+                "{:?}",
+                vmm.update_logger(LoggerUpdateConfig::default())
+                    .unwrap_err()
+            ),
+            "Logger(User, NotConfigured)"
+        );
+
         // Reset vmm state to test the other scenarios.
         vmm.set_instance_state(InstanceState::Uninitialized);
 
         // Error case: initializing logger with invalid pipes returns error.
         let desc = LoggerConfig {
-            log_fifo: String::from("not_found_file_log"),
-            metrics_fifo: String::from("not_found_file_metrics"),
+            log_fifo: Some(String::from("not_found_file_log")),
+            metrics_fifo: Some(String::from("not_found_file_metrics")),
+            log_destination: None,
+            metrics_destination: None,
             level: LoggerLevel::Warning,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Text,
             #[cfg(target_arch = "x86_64")]
             options: Value::Array(vec![]),
         };
@@ -3121,25 +5635,50 @@ mod tests {
 
         // Error case: initializing logger with invalid option flags returns error.
         let desc = LoggerConfig {
-            log_fifo: String::from("not_found_file_log"),
-            metrics_fifo: String::from("not_found_file_metrics"),
+            log_fifo: Some(String::from("not_found_file_log")),
+            metrics_fifo: Some(String::from("not_found_file_metrics")),
+            log_destination: None,
+            metrics_destination: None,
             level: LoggerLevel::Warning,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Text,
             #[cfg(target_arch = "x86_64")]
             options: Value::Array(vec![Value::String("foobar".to_string())]),
         };
         assert!(vmm.init_logger(desc).is_err());
 
+        // Error case: the logs have both a named pipe and another destination.
+        let desc = LoggerConfig {
+            log_fifo: Some(String::from("log")),
+            metrics_fifo: None,
+            log_destination: Some(LoggerDestination::RingBuffer { capacity: 10 }),
+            metrics_destination: Some(LoggerDestination::RingBuffer { capacity: 10 }),
+            level: LoggerLevel::Warning,
+            show_level: false,
+            show_log_origin: false,
+            format: LoggerFormat::Text,
+            #[cfg(target_arch = "x86_64")]
+            options: Value::Array(vec![]),
+        };
+        assert_eq!(
+            format!("{:?}", vmm.init_logger(desc).unwrap_err()),
+            "Logger(User, InitializationFailure(\"Exactly one of log_fifo and log_destination \
+             must be given.\"))"
+        );
+
         // Initializing logger with valid pipes is ok.
         let log_file = NamedTempFile::new().unwrap();
         let metrics_file = NamedTempFile::new().unwrap();
         let desc = LoggerConfig {
-            log_fifo: log_file.path().to_str().unwrap().to_string(),
-            metrics_fifo: metrics_file.path().to_str().unwrap().to_string(),
+            log_fifo: Some(log_file.path().to_str().unwrap().to_string()),
+            metrics_fifo: Some(metrics_file.path().to_str().unwrap().to_string()),
+            log_destination: None,
+            metrics_destination: None,
             level: LoggerLevel::Info,
             show_level: true,
             show_log_origin: true,
+            format: LoggerFormat::Text,
             #[cfg(target_arch = "x86_64")]
             options: Value::Array(vec![Value::String("LogDirtyPages".to_string())]),
         };
@@ -3197,6 +5736,25 @@ mod tests {
                 assert!(line.contains("Guest-boot-time ="));
             }
         }
+
+        // Updating the logger after configuring it changes only the given fields.
+        let update = LoggerUpdateConfig {
+            level: Some(LoggerLevel::Debug),
+            show_level: Some(false),
+            show_log_origin: None,
+            #[cfg(target_arch = "x86_64")]
+            log_dirty_pages: Some(false),
+        };
+        assert!(vmm.update_logger(update).is_ok());
+        let logger_config = vmm.logger_config.clone().unwrap();
+        assert_eq!(logger_config.level, LoggerLevel::Debug);
+        assert!(!logger_config.show_level);
+        assert!(logger_config.show_log_origin);
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert_eq!(logger_config.options, Value::Array(vec![]));
+            assert_eq!(LOGGER.flags() & LogOption::LogDirtyPages as usize, 0);
+        }
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -3299,7 +5857,13 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
-            tap: None,
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         };
 
         assert!(vmm.insert_net_device(network_interface).is_ok());
@@ -3396,6 +5960,23 @@ mod tests {
         err.kind().clone()
     }
 
+    #[test]
+    fn test_balloon_error_conversion() {
+        assert_eq!(error_kind(BalloonError::DeviceNotFound), ErrorKind::User);
+        assert_eq!(
+            error_kind(BalloonError::TooManyPagesRequested),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(BalloonError::UpdateNotAllowedPostBoot),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(BalloonError::UpdateFailed(String::new())),
+            ErrorKind::Internal
+        );
+    }
+
     #[test]
     fn test_drive_error_conversion() {
         // Test `DriveError` conversion
@@ -3427,6 +6008,81 @@ mod tests {
             error_kind(DriveError::RootBlockDeviceAlreadyAdded),
             ErrorKind::User
         );
+        assert_eq!(
+            error_kind(DriveError::RootBlockDeviceHotplug),
+            ErrorKind::User
+        );
+        assert_eq!(error_kind(DriveError::NoHotplugSlot), ErrorKind::User);
+        assert_eq!(error_kind(DriveError::NotHotplugged), ErrorKind::User);
+        assert_eq!(error_kind(DriveError::DeviceInUse), ErrorKind::User);
+        assert_eq!(
+            error_kind(DriveError::AsyncEngineNonRawImage),
+            ErrorKind::User
+        );
+        assert_eq!(error_kind(DriveError::InvalidNumQueues), ErrorKind::User);
+        assert_eq!(
+            error_kind(DriveError::InvalidBlockTopology),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(DriveError::HotplugFailed(String::new())),
+            ErrorKind::Internal
+        );
+        assert_eq!(
+            error_kind(DriveError::UnplugFailed(String::new())),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn test_pause_resume_error_conversion() {
+        // Test `PauseResumeError` conversion
+        assert_eq!(
+            error_kind(PauseResumeError::MicroVMNotRunning),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(PauseResumeError::MicroVMNotPaused),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(PauseResumeError::Vcpu(vstate::Error::VcpuResponseTimeout)),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn test_snapshot_error_conversion() {
+        // Test `SnapshotError` conversion
+        assert_eq!(error_kind(SnapshotError::MicroVMNotPaused), ErrorKind::User);
+        assert_eq!(
+            error_kind(SnapshotError::MicroVMAlreadyRunning),
+            ErrorKind::User
+        );
+        assert_eq!(error_kind(SnapshotError::DevicesMismatch), ErrorKind::User);
+        assert_eq!(error_kind(SnapshotError::HotplugSlots), ErrorKind::User);
+        assert_eq!(
+            error_kind(SnapshotError::UnsupportedVersion(0)),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(SnapshotError::OpenMemoryFile(io::Error::from_raw_os_error(
+                0
+            ))),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(SnapshotError::SaveVcpuState(0)),
+            ErrorKind::Internal
+        );
+        assert_eq!(
+            error_kind(SnapshotError::Vcpu(vstate::Error::VcpuResponseTimeout)),
+            ErrorKind::Internal
+        );
+        assert_eq!(
+            error_kind(SnapshotError::WriteMemory(String::new())),
+            ErrorKind::Internal
+        );
     }
 
     #[test]
@@ -3464,6 +6120,48 @@ mod tests {
             error_kind(NetworkInterfaceError::DeviceIdNotFound),
             ErrorKind::User
         );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::InvalidNumQueuePairs),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::InvalidMtu),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::OpenCapture(
+                io::Error::from_raw_os_error(0)
+            )),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::AntiSpoofingWithoutGuestMac),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::InvalidPacketFilter),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::NoHotplugSlot),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::NotHotplugged),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::DeviceInUse),
+            ErrorKind::User
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::HotplugFailed(String::new())),
+            ErrorKind::Internal
+        );
+        assert_eq!(
+            error_kind(NetworkInterfaceError::UnplugFailed(String::new())),
+            ErrorKind::Internal
+        );
         // NetworkInterfaceError::OpenTap can be of multiple kinds.
         {
             assert_eq!(
@@ -3523,6 +6221,12 @@ mod tests {
             )),
             ErrorKind::Internal
         );
+        assert_eq!(
+            error_kind(StartMicrovmError::CreateBalloonDevice(
+                io::Error::from_raw_os_error(0)
+            )),
+            ErrorKind::Internal
+        );
         assert_eq!(
             error_kind(StartMicrovmError::CreateBlockDevice(
                 io::Error::from_raw_os_error(0)
@@ -3611,6 +6315,12 @@ mod tests {
             )),
             ErrorKind::User
         );
+        assert_eq!(
+            error_kind(StartMicrovmError::OpenNetCapture(
+                io::Error::from_raw_os_error(0)
+            )),
+            ErrorKind::User
+        );
         assert_eq!(
             error_kind(StartMicrovmError::RegisterBlockDevice(
                 device_manager::mmio::Error::IrqsExhausted
@@ -3787,6 +6497,33 @@ mod tests {
             ),
             "NetworkConfig(User, DeviceIdNotFound)"
         );
+        assert_eq!(
+            format!(
+                "{:?}",
+                VmmActionError::PauseResume(ErrorKind::User, PauseResumeError::MicroVMNotPaused)
+            ),
+            "PauseResume(User, MicroVMNotPaused)"
+        );
+        assert_eq!(
+            VmmActionError::PauseResume(
+                ErrorKind::Internal,
+                PauseResumeError::Vcpu(vstate::Error::VcpuChannel)
+            )
+            .to_string(),
+            "Cannot pause or resume vCPU. VcpuChannel"
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                VmmActionError::Snapshot(ErrorKind::User, SnapshotError::MicroVMNotPaused)
+            ),
+            "Snapshot(User, MicroVMNotPaused)"
+        );
+        assert_eq!(
+            VmmActionError::Snapshot(ErrorKind::Internal, SnapshotError::SaveVcpuState(1))
+                .to_string(),
+            "Cannot save the state of vCPU 1."
+        );
         assert_eq!(
             format!(
                 "{:?}",
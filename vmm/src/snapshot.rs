@@ -0,0 +1,207 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The on-disk format of microVM snapshots.
+//!
+//! A snapshot is made of two files: the guest memory file, which holds the raw contents of all
+//! the guest memory regions, one after the other, and the snapshot file, which holds everything
+//! else (`MicrovmState`) as JSON.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde_json;
+
+use device_manager::mmio::MMIODeviceInfo;
+use devices::legacy::{I8042State, SerialState};
+use devices::virtio::MmioDeviceState;
+use memory_model::GuestMemory;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::snapshot::SnapshotError;
+use vstate::{VcpuState, VmState};
+
+/// Version of the snapshot file format. Bump it on any incompatible change to `MicrovmState`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+type Result<T> = std::result::Result<T, SnapshotError>;
+
+/// The state of a virtio MMIO device, together with where it is mapped.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeviceState {
+    /// The ID the device was configured with.
+    pub id: String,
+    /// The MMIO address range and IRQ of the device.
+    pub info: MMIODeviceInfo,
+    /// The transport and queue state of the device.
+    pub state: MmioDeviceState,
+}
+
+/// Everything needed to resume a microVM, besides its guest memory.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MicrovmState {
+    /// Version of the snapshot file format.
+    pub version: u32,
+    /// The memory and vCPU configuration of the microVM.
+    pub vm_config: VmConfig,
+    /// The state of the in-kernel interrupt controllers, PIT and clock.
+    pub vm: VmState,
+    /// The state of each vCPU, ordered by vCPU index.
+    pub vcpus: Vec<VcpuState>,
+    /// The state of the virtio devices, ordered by MMIO address.
+    pub devices: Vec<DeviceState>,
+    /// The state of the serial console.
+    pub serial: SerialState,
+    /// The state of the i8042 controller.
+    pub i8042: I8042State,
+}
+
+impl MicrovmState {
+    /// Writes the state to the snapshot file at `path`, replacing any previous contents.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path).map_err(SnapshotError::CreateSnapshotFile)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| SnapshotError::WriteSnapshot(e.to_string()))?;
+        writer
+            .flush()
+            .map_err(|e| SnapshotError::WriteSnapshot(e.to_string()))
+    }
+
+    /// Reads the state from the snapshot file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MicrovmState> {
+        let file = File::open(path).map_err(SnapshotError::OpenSnapshotFile)?;
+        let value: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| SnapshotError::InvalidSnapshot(e.to_string()))?;
+
+        // Check the version first, so that files in other formats are reported as such.
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| SnapshotError::InvalidSnapshot("Missing version.".to_string()))?;
+        if version != u64::from(SNAPSHOT_VERSION) {
+            return Err(SnapshotError::UnsupportedVersion(version as u32));
+        }
+
+        serde_json::from_value(value).map_err(|e| SnapshotError::InvalidSnapshot(e.to_string()))
+    }
+}
+
+/// Writes the contents of all the guest memory regions to the file at `path`.
+pub fn save_memory<P: AsRef<Path>>(guest_memory: &GuestMemory, path: P) -> Result<()> {
+    let mut file = File::create(path).map_err(SnapshotError::CreateMemoryFile)?;
+    guest_memory.with_regions_mut(|_, guest_base, size, _| {
+        guest_memory
+            .write_from_memory(guest_base, &mut file, size)
+            .map_err(|e| SnapshotError::WriteMemory(format!("{:?}", e)))
+    })
+}
+
+/// Fills all the guest memory regions with the contents of the file at `path`.
+///
+/// The file size must match the size of the guest memory.
+pub fn load_memory<P: AsRef<Path>>(guest_memory: &GuestMemory, path: P) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(SnapshotError::OpenMemoryFile)?;
+    // Seek instead of querying the file metadata, so that no extra syscalls are needed.
+    let file_size = file
+        .seek(SeekFrom::End(0))
+        .and_then(|size| file.seek(SeekFrom::Start(0)).map(|_| size))
+        .map_err(SnapshotError::OpenMemoryFile)?;
+    let mut mem_size = 0;
+    guest_memory.with_regions_mut(|_, _, size, _| -> Result<()> {
+        mem_size += size as u64;
+        Ok(())
+    })?;
+    if file_size != mem_size {
+        return Err(SnapshotError::MemoryFileSize);
+    }
+
+    guest_memory.with_regions_mut(|_, guest_base, size, _| {
+        guest_memory
+            .read_to_memory(guest_base, &mut file, size)
+            .map_err(|e| SnapshotError::ReadMemory(format!("{:?}", e)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use self::tempfile::NamedTempFile;
+    use memory_model::GuestAddress;
+
+    #[test]
+    fn test_save_load_memory() {
+        let regions = [(GuestAddress(0), 0x1000), (GuestAddress(0x10000), 0x2000)];
+        let mem = GuestMemory::new(&regions).unwrap();
+        mem.write_obj_at_addr(0xdead_beef_u32, GuestAddress(0x10))
+            .unwrap();
+        mem.write_obj_at_addr(0xcafe_babe_u32, GuestAddress(0x11ff0))
+            .unwrap();
+
+        let mem_file = NamedTempFile::new().unwrap();
+        save_memory(&mem, mem_file.path()).unwrap();
+        assert_eq!(mem_file.as_file().metadata().unwrap().len(), 0x3000);
+
+        let restored = GuestMemory::new(&regions).unwrap();
+        load_memory(&restored, mem_file.path()).unwrap();
+        assert_eq!(
+            restored
+                .read_obj_from_addr::<u32>(GuestAddress(0x10))
+                .unwrap(),
+            0xdead_beef
+        );
+        assert_eq!(
+            restored
+                .read_obj_from_addr::<u32>(GuestAddress(0x11ff0))
+                .unwrap(),
+            0xcafe_babe
+        );
+
+        // The memory file has to match the guest memory size.
+        let smaller = GuestMemory::new(&regions[..1]).unwrap();
+        match load_memory(&smaller, mem_file.path()) {
+            Err(SnapshotError::MemoryFileSize) => (),
+            _ => panic!("Expected a memory file size error."),
+        }
+        match load_memory(&smaller, "/invalid/path") {
+            Err(SnapshotError::OpenMemoryFile(_)) => (),
+            _ => panic!("Expected an open memory file error."),
+        }
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        match MicrovmState::load("/invalid/path") {
+            Err(SnapshotError::OpenSnapshotFile(_)) => (),
+            _ => panic!("Expected an open snapshot file error."),
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"not json").unwrap();
+        match MicrovmState::load(file.path()) {
+            Err(SnapshotError::InvalidSnapshot(_)) => (),
+            _ => panic!("Expected an invalid snapshot error."),
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"{\"version\": 1000}").unwrap();
+        match MicrovmState::load(file.path()) {
+            Err(SnapshotError::UnsupportedVersion(1000)) => (),
+            _ => panic!("Expected an unsupported version error."),
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(format!("{{\"version\": {}}}", SNAPSHOT_VERSION).as_bytes())
+            .unwrap();
+        match MicrovmState::load(file.path()) {
+            Err(SnapshotError::InvalidSnapshot(_)) => (),
+            _ => panic!("Expected an invalid snapshot error."),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+
+/// Strongly typed data structure used for configuring the balloon device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonConfig {
+    /// Amount of guest memory, in MiB, the balloon should hold.
+    pub amount_mib: u32,
+    /// Allows the guest to take memory back from the balloon when it runs out of memory.
+    pub deflate_on_oom: bool,
+    /// Interval in seconds between two memory statistics reports from the guest. The guest
+    /// doesn't report any statistics if the interval is 0.
+    #[serde(default)]
+    pub stats_polling_interval_s: u16,
+}
+
+/// Strongly typed data structure used for changing the target size of the balloon.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonUpdateConfig {
+    /// Amount of guest memory, in MiB, the balloon should hold.
+    pub amount_mib: u32,
+}
+
+/// Errors associated with the balloon device.
+#[derive(Debug, PartialEq)]
+pub enum BalloonError {
+    /// The balloon device was not configured.
+    DeviceNotFound,
+    /// The balloon cannot hold more memory than the guest has.
+    TooManyPagesRequested,
+    /// The balloon device cannot be configured after boot.
+    UpdateNotAllowedPostBoot,
+    /// Cannot change the target size of the balloon device.
+    UpdateFailed(String),
+}
+
+impl Display for BalloonError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::BalloonError::*;
+        match *self {
+            DeviceNotFound => write!(f, "No balloon device is configured."),
+            TooManyPagesRequested => write!(
+                f,
+                "The balloon cannot hold more memory than the microVM has."
+            ),
+            UpdateNotAllowedPostBoot => {
+                write!(f, "The update operation is not allowed after boot.")
+            }
+            UpdateFailed(ref msg) => write!(f, "Cannot update the balloon device. {}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_deserialize_balloon_config() {
+        let config: BalloonConfig =
+            serde_json::from_str(r#"{"amount_mib": 64, "deflate_on_oom": true}"#).unwrap();
+        assert_eq!(
+            config,
+            BalloonConfig {
+                amount_mib: 64,
+                deflate_on_oom: true,
+                stats_polling_interval_s: 0,
+            }
+        );
+
+        assert!(serde_json::from_str::<BalloonConfig>(r#"{"amount_mib": 64}"#).is_err());
+        assert!(serde_json::from_str::<BalloonUpdateConfig>(
+            r#"{"amount_mib": 64, "deflate_on_oom": true}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_display_balloon_error() {
+        assert_eq!(
+            BalloonError::DeviceNotFound.to_string(),
+            "No balloon device is configured."
+        );
+        assert_eq!(
+            BalloonError::TooManyPagesRequested.to_string(),
+            "The balloon cannot hold more memory than the microVM has."
+        );
+        assert_eq!(
+            BalloonError::UpdateNotAllowedPostBoot.to_string(),
+            "The update operation is not allowed after boot."
+        );
+        assert_eq!(
+            BalloonError::UpdateFailed("foo".to_string()).to_string(),
+            "Cannot update the balloon device. foo"
+        );
+    }
+}
@@ -5,7 +5,7 @@ use std::fmt::{Display, Formatter, Result};
 
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
     /// Path of the kernel image.
@@ -23,6 +23,8 @@ pub enum BootSourceConfigError {
     InvalidKernelPath,
     /// The kernel command line is invalid.
     InvalidKernelCommandLine,
+    /// The boot source was not configured.
+    NotConfigured,
     /// The boot source cannot be update post boot.
     UpdateNotAllowedPostBoot,
 }
@@ -37,6 +39,7 @@ impl Display for BootSourceConfigError {
                  invalid permissions.",
             ),
             InvalidKernelCommandLine => write!(f, "The kernel command line is invalid!"),
+            NotConfigured => write!(f, "The boot source has not been configured."),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.")
             }
@@ -0,0 +1,285 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::result;
+
+use futures::sync::oneshot;
+use serde_json::{self, Value};
+
+use vmm_config::balloon::BalloonConfig;
+use vmm_config::boot_source::BootSourceConfig;
+use vmm_config::drive::BlockDeviceConfig;
+use vmm_config::logger::LoggerConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::net::NetworkInterfaceConfig;
+#[cfg(feature = "vsock")]
+use vmm_config::vsock::VsockDeviceConfig;
+use {OutcomeReceiver, OutcomeSender, VmmAction};
+
+/// Strongly typed data structure holding the whole configuration of a microVM, as read from a
+/// JSON configuration file. Each field is the body of the API request on the resource with the
+/// same name.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// The boot source of the microVM.
+    #[serde(rename = "boot-source")]
+    pub boot_source: BootSourceConfig,
+    /// The memory and CPU configuration of the microVM. The defaults are used if missing.
+    #[serde(rename = "machine-config")]
+    pub machine_config: Option<VmConfig>,
+    /// The block devices, attached in the given order.
+    #[serde(default)]
+    pub drives: Vec<BlockDeviceConfig>,
+    /// The network interfaces, attached in the given order.
+    #[serde(rename = "network-interfaces", default)]
+    pub network_interfaces: Vec<NetworkInterfaceConfig>,
+    #[cfg(feature = "vsock")]
+    /// The vsock devices.
+    #[serde(default)]
+    pub vsocks: Vec<VsockDeviceConfig>,
+    /// The balloon device. No balloon device is attached if missing.
+    pub balloon: Option<BalloonConfig>,
+    /// The logger configuration. The logger is not initialized if missing.
+    pub logger: Option<LoggerConfig>,
+    /// The initial contents of the MMDS data store.
+    pub mmds: Option<Value>,
+}
+
+/// Errors associated with reading the microVM configuration file.
+#[derive(Debug)]
+pub enum ConfigFileError {
+    /// The configuration file does not hold a valid microVM configuration.
+    InvalidJson(String),
+    /// The configuration file cannot be opened.
+    OpenFile(io::Error),
+}
+
+impl Display for ConfigFileError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::ConfigFileError::*;
+        match *self {
+            InvalidJson(ref msg) => write!(f, "The configuration file is invalid. {}", msg),
+            OpenFile(ref e) => write!(f, "Cannot open the configuration file. {}", e),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads the microVM configuration from the JSON file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> result::Result<ConfigFile, ConfigFileError> {
+        let file = File::open(path).map_err(ConfigFileError::OpenFile)?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| ConfigFileError::InvalidJson(e.to_string()))
+    }
+
+    /// Turns the configuration into the sequence of VMM actions that configures and starts the
+    /// microVM, each with the receiver of its outcome. The logger is configured first, so that
+    /// any subsequent error gets logged. The MMDS contents are not part of the actions, since
+    /// the data store is not owned by the VMM.
+    pub fn into_actions(self) -> Vec<(VmmAction, OutcomeReceiver)> {
+        let mut actions = Vec::new();
+
+        if let Some(logger) = self.logger {
+            actions.push(action(|sender| VmmAction::ConfigureLogger(logger, sender)));
+        }
+        if let Some(machine_config) = self.machine_config {
+            actions.push(action(|sender| {
+                VmmAction::SetVmConfiguration(machine_config, sender)
+            }));
+        }
+        let boot_source = self.boot_source;
+        actions.push(action(|sender| {
+            VmmAction::ConfigureBootSource(boot_source, sender)
+        }));
+        for drive in self.drives {
+            actions.push(action(|sender| VmmAction::InsertBlockDevice(drive, sender)));
+        }
+        for iface in self.network_interfaces {
+            actions.push(action(|sender| {
+                VmmAction::InsertNetworkDevice(iface, sender)
+            }));
+        }
+        #[cfg(feature = "vsock")]
+        for vsock in self.vsocks {
+            actions.push(action(|sender| VmmAction::InsertVsockDevice(vsock, sender)));
+        }
+        if let Some(balloon) = self.balloon {
+            actions.push(action(|sender| {
+                VmmAction::InsertBalloonDevice(balloon, sender)
+            }));
+        }
+        actions.push(action(VmmAction::StartMicroVm));
+
+        actions
+    }
+}
+
+// Builds a VMM action along with the receiver of its outcome.
+fn action<F: FnOnce(OutcomeSender) -> VmmAction>(build: F) -> (VmmAction, OutcomeReceiver) {
+    let (sender, receiver) = oneshot::channel();
+    (build(sender), receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use std::io::Write;
+
+    use self::tempfile::NamedTempFile;
+
+    fn config_file(json: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(json.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_from_file() {
+        let file = config_file(
+            r#"{
+                "boot-source": {
+                    "kernel_image_path": "/foo/kernel",
+                    "boot_args": "console=ttyS0"
+                },
+                "machine-config": {
+                    "vcpu_count": 2,
+                    "mem_size_mib": 256
+                },
+                "drives": [
+                    {
+                        "drive_id": "rootfs",
+                        "path_on_host": "/foo/rootfs",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    },
+                    {
+                        "drive_id": "scratch",
+                        "path_on_host": "/foo/scratch",
+                        "is_root_device": false,
+                        "is_read_only": true
+                    }
+                ],
+                "network-interfaces": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "tap0"
+                    }
+                ],
+                "balloon": {
+                    "amount_mib": 64,
+                    "deflate_on_oom": true
+                },
+                "logger": {
+                    "log_fifo": "/foo/log",
+                    "metrics_fifo": "/foo/metrics"
+                },
+                "mmds": {
+                    "latest": {
+                        "meta-data": "foo"
+                    }
+                }
+            }"#,
+        );
+        let config = ConfigFile::from_file(file.path()).unwrap();
+        assert_eq!(config.boot_source.kernel_image_path, "/foo/kernel");
+        assert_eq!(config.machine_config.as_ref().unwrap().vcpu_count, Some(2));
+        assert_eq!(config.drives.len(), 2);
+        assert_eq!(config.network_interfaces[0].iface_id, "eth0");
+        assert_eq!(config.balloon.as_ref().unwrap().amount_mib, 64);
+        assert!(config.logger.is_some());
+        assert_eq!(config.mmds.as_ref().unwrap()["latest"]["meta-data"], "foo");
+
+        let actions: Vec<VmmAction> = config
+            .into_actions()
+            .into_iter()
+            .map(|(action, _)| action)
+            .collect();
+        assert_eq!(actions.len(), 8);
+        match actions[0] {
+            VmmAction::ConfigureLogger(..) => (),
+            _ => panic!("The logger should be configured first."),
+        }
+        match actions[1] {
+            VmmAction::SetVmConfiguration(..) => (),
+            _ => panic!("Expected the machine configuration."),
+        }
+        match actions[2] {
+            VmmAction::ConfigureBootSource(..) => (),
+            _ => panic!("Expected the boot source."),
+        }
+        match (&actions[3], &actions[4]) {
+            (
+                VmmAction::InsertBlockDevice(ref first, _),
+                VmmAction::InsertBlockDevice(ref second, _),
+            ) => {
+                assert_eq!(first.drive_id, "rootfs");
+                assert_eq!(second.drive_id, "scratch");
+            }
+            _ => panic!("Expected the block devices, in order."),
+        }
+        match actions[5] {
+            VmmAction::InsertNetworkDevice(..) => (),
+            _ => panic!("Expected the network interface."),
+        }
+        match actions[6] {
+            VmmAction::InsertBalloonDevice(..) => (),
+            _ => panic!("Expected the balloon device."),
+        }
+        match actions[7] {
+            VmmAction::StartMicroVm(_) => (),
+            _ => panic!("The microVM should be started last."),
+        }
+    }
+
+    #[test]
+    fn test_from_file_minimal() {
+        let file = config_file(r#"{"boot-source": {"kernel_image_path": "/foo/kernel"}}"#);
+        let config = ConfigFile::from_file(file.path()).unwrap();
+        assert!(config.machine_config.is_none());
+        assert!(config.drives.is_empty());
+        assert!(config.balloon.is_none());
+        assert!(config.mmds.is_none());
+        assert_eq!(config.into_actions().len(), 2);
+    }
+
+    #[test]
+    fn test_from_file_errors() {
+        match ConfigFile::from_file("/invalid/path") {
+            Err(ConfigFileError::OpenFile(_)) => (),
+            _ => panic!("Expected an open file error."),
+        }
+
+        // The boot source is mandatory.
+        let file = config_file(r#"{"drives": []}"#);
+        match ConfigFile::from_file(file.path()) {
+            Err(ConfigFileError::InvalidJson(_)) => (),
+            _ => panic!("Expected an invalid JSON error."),
+        }
+
+        // Unknown resources are rejected.
+        let file =
+            config_file(r#"{"boot-source": {"kernel_image_path": "/foo/kernel"}, "foo": {}}"#);
+        match ConfigFile::from_file(file.path()) {
+            Err(ConfigFileError::InvalidJson(_)) => (),
+            _ => panic!("Expected an invalid JSON error."),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = ConfigFileError::InvalidJson(String::from("foo"));
+        assert_eq!(err.to_string(), "The configuration file is invalid. foo");
+        let err = ConfigFileError::OpenFile(io::Error::from_raw_os_error(2));
+        assert!(err
+            .to_string()
+            .starts_with("Cannot open the configuration file. "));
+    }
+}
@@ -3,9 +3,11 @@
 
 use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 
+pub use devices::virtio::{BlockTopology, CacheType, DiskImageFormat, IoEngine, BLOCK_MAX_QUEUES};
+
 use super::RateLimiterConfig;
 
 type Result<T> = result::Result<T, DriveError>;
@@ -31,6 +33,24 @@ pub enum DriveError {
     UpdateNotAllowedPostBoot,
     /// A root block device was already added.
     RootBlockDeviceAlreadyAdded,
+    /// A root block device cannot be attached after booting the microVM.
+    RootBlockDeviceHotplug,
+    /// All the slots for attaching block devices after boot are in use.
+    NoHotplugSlot,
+    /// Cannot attach the block device to the running microVM.
+    HotplugFailed(String),
+    /// The block device was attached at boot time, so it cannot be detached.
+    NotHotplugged,
+    /// The guest driver has not released the block device to detach.
+    DeviceInUse,
+    /// Cannot detach the block device from the running microVM.
+    UnplugFailed(String),
+    /// The `Async` I/O engine was requested for a disk image that is not raw, or has an overlay.
+    AsyncEngineNonRawImage,
+    /// The number of queues of the drive is zero or above `BLOCK_MAX_QUEUES`.
+    InvalidNumQueues,
+    /// The block sizes of the drive do not meet their constraints.
+    InvalidBlockTopology,
 }
 
 impl Display for DriveError {
@@ -50,15 +70,46 @@ impl Display for DriveError {
             BlockDeviceUpdateFailed => write!(f, "The update operation failed!"),
             OperationNotAllowedPreBoot => write!(f, "Operation not allowed pre-boot!"),
             RootBlockDeviceAlreadyAdded => write!(f, "A root block device already exists!"),
+            RootBlockDeviceHotplug => {
+                write!(f, "A root block device cannot be attached after boot!")
+            }
+            NoHotplugSlot => write!(f, "No slot is left for attaching the block device!"),
+            HotplugFailed(ref msg) => write!(f, "Cannot attach the block device. {}", msg),
+            NotHotplugged => write!(
+                f,
+                "Only the block devices attached after boot can be detached!"
+            ),
+            DeviceInUse => write!(
+                f,
+                "The block device is in use by the guest, which must unbind its driver first!"
+            ),
+            UnplugFailed(ref msg) => write!(f, "Cannot detach the block device. {}", msg),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.")
             }
+            AsyncEngineNonRawImage => {
+                write!(
+                    f,
+                    "The Async I/O engine can only be used with raw disk images, without overlay!"
+                )
+            }
+            InvalidNumQueues => write!(
+                f,
+                "The number of queues of a block device must be between 1 and {}!",
+                BLOCK_MAX_QUEUES
+            ),
+            InvalidBlockTopology => write!(
+                f,
+                "The logical block size must be a power of two between 512 and 4096, the \
+                 physical block size a power of two at least as large, and the optimal I/O size \
+                 a multiple of the physical block size!"
+            ),
         }
     }
 }
 
 /// Use this structure to set up the Block Device before booting the kernel.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlockDeviceConfig {
     /// Unique identifier of the drive.
@@ -77,6 +128,29 @@ pub struct BlockDeviceConfig {
     pub is_read_only: bool,
     /// Rate Limiter for I/O operations.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// The engine performing the disk I/O. The default `Sync` engine performs blocking I/O in the
+    /// VMM thread, while the `Async` one submits it through an io_uring.
+    #[serde(default)]
+    pub io_engine: IoEngine,
+    /// The format of the disk image. Defaults to `Raw`.
+    #[serde(default)]
+    pub format: DiskImageFormat,
+    /// Path of a copy-on-write overlay for the drive. If set, the image at `path_on_host` is
+    /// opened read-only, and the writes go to the overlay, which is created if it does not exist.
+    pub overlay_path: Option<PathBuf>,
+    /// The number of request queues of the drive. Defaults to 1.
+    #[serde(default = "default_num_queues")]
+    pub num_queues: u16,
+    /// How the writes of the guest reach the disk. Defaults to `Writeback`, which honors the
+    /// flushes of the guest.
+    #[serde(default)]
+    pub cache_type: CacheType,
+    /// The block sizes reported to the guest. Without them, the guest sees 512-byte blocks.
+    pub topology: Option<BlockTopology>,
+}
+
+fn default_num_queues() -> u16 {
+    1
 }
 
 impl BlockDeviceConfig {
@@ -94,6 +168,29 @@ impl BlockDeviceConfig {
     pub fn path_on_host(&self) -> &PathBuf {
         &self.path_on_host
     }
+
+    /// Returns the path of the overlay, if any.
+    pub fn overlay_path(&self) -> Option<&Path> {
+        self.overlay_path.as_ref().map(PathBuf::as_path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.num_queues == 0 || self.num_queues > BLOCK_MAX_QUEUES {
+            return Err(DriveError::InvalidNumQueues);
+        }
+        if let Some(topology) = self.topology {
+            if !topology.is_valid() {
+                return Err(DriveError::InvalidBlockTopology);
+            }
+        }
+        // The io_uring accesses the file directly, so it needs the guest disk to be the file.
+        if self.io_engine == IoEngine::Async
+            && (self.format != DiskImageFormat::Raw || self.overlay_path.is_some())
+        {
+            return Err(DriveError::AsyncEngineNonRawImage);
+        }
+        Ok(())
+    }
 }
 
 /// Wrapper for the collection that holds all the Block Devices Configs
@@ -150,6 +247,7 @@ impl BlockDeviceConfigs {
     /// the existing entry.
     /// Inserting a secondary root block device will fail.
     pub fn insert(&mut self, block_device_config: BlockDeviceConfig) -> Result<()> {
+        block_device_config.validate()?;
         // If the id of the drive already exists in the list, the operation is update.
         match self.get_index_of_drive_id(&block_device_config.drive_id) {
             Some(index) => self.update(index, block_device_config),
@@ -191,6 +289,44 @@ impl BlockDeviceConfigs {
         Ok(())
     }
 
+    /// Appends the configuration of a block device attached after booting the microVM. Unlike
+    /// `insert`, existing entries are never updated.
+    pub fn insert_hotplugged(&mut self, block_device_config: BlockDeviceConfig) -> Result<()> {
+        if block_device_config.is_root_device {
+            return Err(DriveError::RootBlockDeviceHotplug);
+        }
+        block_device_config.validate()?;
+        if self
+            .get_index_of_drive_id(&block_device_config.drive_id)
+            .is_some()
+        {
+            return Err(DriveError::UpdateNotAllowedPostBoot);
+        }
+        if self
+            .get_index_of_drive_path(&block_device_config.path_on_host)
+            .is_some()
+        {
+            return Err(DriveError::BlockDevicePathAlreadyExists);
+        }
+        self.config_list.push_back(block_device_config);
+
+        Ok(())
+    }
+
+    /// Removes the block device with the given `drive_id` from the list and returns its
+    /// configuration.
+    pub fn remove(&mut self, drive_id: &str) -> Option<BlockDeviceConfig> {
+        let block_device_config = self
+            .get_index_of_drive_id(drive_id)
+            .and_then(|index| self.config_list.remove(index))?;
+        if block_device_config.is_root_device {
+            self.has_root_block = false;
+            self.read_only_root = false;
+            self.has_partuuid_root = false;
+        }
+        Some(block_device_config)
+    }
+
     /// Updates a Block Device Config. The update fails if it would result in two
     /// root block devices.
     fn update(&mut self, mut index: usize, new_config: BlockDeviceConfig) -> Result<()> {
@@ -234,21 +370,6 @@ mod tests {
     use self::tempfile::NamedTempFile;
     use super::*;
 
-    // This implementation is used only in tests.
-    // We cannot directly derive clone because RateLimiter does not implement clone.
-    impl Clone for BlockDeviceConfig {
-        fn clone(&self) -> Self {
-            BlockDeviceConfig {
-                path_on_host: self.path_on_host.clone(),
-                is_root_device: self.is_root_device,
-                partuuid: self.partuuid.clone(),
-                is_read_only: self.is_read_only,
-                drive_id: self.drive_id.clone(),
-                rate_limiter: None,
-            }
-        }
-    }
-
     #[test]
     fn test_create_block_devices_configs() {
         let block_devices_configs = BlockDeviceConfigs::new();
@@ -268,6 +389,12 @@ mod tests {
             is_read_only: false,
             drive_id: dummy_id.clone(),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -300,6 +427,12 @@ mod tests {
             is_read_only: true,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -325,6 +458,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_2 = NamedTempFile::new().unwrap();
@@ -336,6 +475,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -360,6 +505,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_2 = NamedTempFile::new().unwrap();
@@ -371,6 +522,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_3 = NamedTempFile::new().unwrap();
@@ -382,6 +539,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -417,6 +580,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_2 = NamedTempFile::new().unwrap();
@@ -428,6 +597,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_3 = NamedTempFile::new().unwrap();
@@ -439,6 +614,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -475,6 +656,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let dummy_file_2 = NamedTempFile::new().unwrap();
@@ -486,6 +673,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
 
         let mut block_devices_configs = BlockDeviceConfigs::new();
@@ -553,6 +746,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         let root_block_device_new = BlockDeviceConfig {
             path_on_host: dummy_path_2,
@@ -561,6 +760,12 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
         };
         let index1 = block_devices_configs
             .get_index_of_drive_id(&root_block_device_old.drive_id)
@@ -576,4 +781,213 @@ mod tests {
             .is_ok());
         assert!(block_devices_configs.has_partuuid_root);
     }
+
+    #[test]
+    fn test_insert_hotplugged_and_remove() {
+        let root_file = NamedTempFile::new().unwrap();
+        let scratch_file = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            path_on_host: root_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: true,
+            drive_id: String::from("rootfs"),
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        let mut block_devices_configs = BlockDeviceConfigs::new();
+        assert!(block_devices_configs
+            .insert(root_block_device.clone())
+            .is_ok());
+
+        // Root devices, existing IDs and already used paths are rejected.
+        let mut hotplugged = BlockDeviceConfig {
+            drive_id: String::from("scratch"),
+            ..root_block_device.clone()
+        };
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(hotplugged.clone()),
+            Err(DriveError::RootBlockDeviceHotplug)
+        );
+        hotplugged.is_root_device = false;
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(hotplugged.clone()),
+            Err(DriveError::BlockDevicePathAlreadyExists)
+        );
+        hotplugged.path_on_host = scratch_file.path().to_path_buf();
+        hotplugged.drive_id = String::from("rootfs");
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(hotplugged.clone()),
+            Err(DriveError::UpdateNotAllowedPostBoot)
+        );
+
+        hotplugged.drive_id = String::from("scratch");
+        hotplugged.io_engine = IoEngine::Async;
+        hotplugged.format = DiskImageFormat::Qcow2;
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(hotplugged.clone()),
+            Err(DriveError::AsyncEngineNonRawImage)
+        );
+        hotplugged.io_engine = IoEngine::Sync;
+        assert!(block_devices_configs
+            .insert_hotplugged(hotplugged.clone())
+            .is_ok());
+        assert_eq!(block_devices_configs.config_list.len(), 2);
+        assert_eq!(block_devices_configs.config_list[1], hotplugged);
+
+        assert_eq!(block_devices_configs.remove("scratch"), Some(hotplugged));
+        assert!(block_devices_configs.remove("scratch").is_none());
+        assert!(block_devices_configs.has_root_block_device());
+        assert_eq!(
+            block_devices_configs.remove("rootfs"),
+            Some(root_block_device)
+        );
+        assert!(!block_devices_configs.has_root_block_device());
+        assert!(!block_devices_configs.has_read_only_root());
+        assert!(block_devices_configs.config_list.is_empty());
+    }
+
+    #[test]
+    fn test_disk_image_format() {
+        let dummy_file = NamedTempFile::new().unwrap();
+        let mut block_device = BlockDeviceConfig {
+            path_on_host: dummy_file.path().to_path_buf(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+            io_engine: IoEngine::Async,
+            format: DiskImageFormat::Qcow2,
+            overlay_path: None,
+            num_queues: 1,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        let mut block_devices_configs = BlockDeviceConfigs::new();
+
+        // The Async engine only works with raw images, both when creating and updating drives.
+        assert_eq!(
+            block_devices_configs.insert(block_device.clone()),
+            Err(DriveError::AsyncEngineNonRawImage)
+        );
+        block_device.io_engine = IoEngine::Sync;
+        assert!(block_devices_configs.insert(block_device.clone()).is_ok());
+        block_device.io_engine = IoEngine::Async;
+        assert_eq!(
+            block_devices_configs.insert(block_device.clone()),
+            Err(DriveError::AsyncEngineNonRawImage)
+        );
+        assert_eq!(
+            block_devices_configs.config_list[0].format,
+            DiskImageFormat::Qcow2
+        );
+
+        // Nor with overlays.
+        block_device.format = DiskImageFormat::Raw;
+        block_device.overlay_path = Some(PathBuf::from("overlay"));
+        assert_eq!(
+            block_devices_configs.insert(block_device.clone()),
+            Err(DriveError::AsyncEngineNonRawImage)
+        );
+
+        // The format defaults to raw.
+        let json = format!(
+            r#"{{"drive_id": "2", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false}}"#,
+            dummy_file.path().display()
+        );
+        let block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(block_device.format, DiskImageFormat::Raw);
+        assert!(block_device.overlay_path().is_none());
+        assert_eq!(block_device.cache_type, CacheType::Writeback);
+
+        let json = format!(
+            r#"{{"drive_id": "2", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "cache_type": "Writethrough"}}"#,
+            dummy_file.path().display()
+        );
+        let block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(block_device.cache_type, CacheType::Writethrough);
+    }
+
+    #[test]
+    fn test_num_queues() {
+        let dummy_file = NamedTempFile::new().unwrap();
+        let mut block_device = BlockDeviceConfig {
+            path_on_host: dummy_file.path().to_path_buf(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+            io_engine: IoEngine::Sync,
+            format: DiskImageFormat::Raw,
+            overlay_path: None,
+            num_queues: 0,
+            cache_type: CacheType::Writeback,
+            topology: None,
+        };
+        let mut block_devices_configs = BlockDeviceConfigs::new();
+
+        assert_eq!(
+            block_devices_configs.insert(block_device.clone()),
+            Err(DriveError::InvalidNumQueues)
+        );
+        block_device.num_queues = BLOCK_MAX_QUEUES + 1;
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(block_device.clone()),
+            Err(DriveError::InvalidNumQueues)
+        );
+        block_device.num_queues = BLOCK_MAX_QUEUES;
+        assert!(block_devices_configs.insert(block_device.clone()).is_ok());
+
+        // The number of queues defaults to 1.
+        let json = format!(
+            r#"{{"drive_id": "2", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false}}"#,
+            dummy_file.path().display()
+        );
+        let block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(block_device.num_queues, 1);
+    }
+
+    #[test]
+    fn test_block_topology() {
+        let dummy_file = NamedTempFile::new().unwrap();
+        let json = format!(
+            r#"{{"drive_id": "1", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "topology": {{"logical_block_size": 4096,
+                "physical_block_size": 2048}}}}"#,
+            dummy_file.path().display()
+        );
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        let mut block_devices_configs = BlockDeviceConfigs::new();
+
+        // The physical blocks cannot be smaller than the logical ones.
+        assert_eq!(
+            block_devices_configs.insert(block_device.clone()),
+            Err(DriveError::InvalidBlockTopology)
+        );
+        assert_eq!(
+            block_devices_configs.insert_hotplugged(block_device.clone()),
+            Err(DriveError::InvalidBlockTopology)
+        );
+
+        block_device.topology = Some(BlockTopology {
+            logical_block_size: 4096,
+            physical_block_size: 4096,
+            optimal_io_size: 0,
+        });
+        assert!(block_devices_configs.insert(block_device.clone()).is_ok());
+        assert_eq!(
+            block_devices_configs.config_list[0].topology,
+            block_device.topology
+        );
+    }
 }
@@ -14,6 +14,8 @@ use vstate;
 /// The microvm state. When Firecracker starts, the instance state is Uninitialized.
 /// Once start_microvm method is called, the state goes from Uninitialized to Starting.
 /// The state is changed to Running before ending the start_microvm method.
+/// A running microvm can be moved to Paused and back to Running via the Pause and Resume
+/// actions.
 /// Halting and Halted are currently unsupported.
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum InstanceState {
@@ -23,6 +25,8 @@ pub enum InstanceState {
     Starting,
     /// Microvm is running.
     Running,
+    /// Microvm is paused; its vCPUs do not run guest code.
+    Paused,
     /// Microvm received a halt instruction.
     Halting,
     /// Microvm is halted.
@@ -40,6 +44,33 @@ pub struct InstanceInfo {
     pub vmm_version: String,
 }
 
+/// Errors associated with pausing or resuming the instance.
+#[derive(Debug)]
+pub enum PauseResumeError {
+    /// The microvm is not running, so it cannot be paused.
+    MicroVMNotRunning,
+    /// The microvm is not paused, so it cannot be resumed.
+    MicroVMNotPaused,
+    /// Failed to deliver the request to a vCPU thread or to get its response.
+    Vcpu(vstate::Error),
+}
+
+impl Display for PauseResumeError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::PauseResumeError::*;
+        match *self {
+            MicroVMNotRunning => write!(f, "Microvm is not running."),
+            MicroVMNotPaused => write!(f, "Microvm is not paused."),
+            Vcpu(ref err) => {
+                let mut err_msg = format!("{:?}", err);
+                err_msg = err_msg.replace("\"", "");
+
+                write!(f, "Cannot pause or resume vCPU. {}", err_msg)
+            }
+        }
+    }
+}
+
 /// Errors associated with starting the instance.
 // TODO: add error kind to these variants because not all these errors are user or internal.
 #[derive(Debug)]
@@ -49,6 +80,8 @@ pub enum StartMicrovmError {
     ConfigureSystem(arch::Error),
     /// Cannot configure the VM.
     ConfigureVm(vstate::Error),
+    /// Cannot create the timer of the balloon device.
+    CreateBalloonDevice(std::io::Error),
     /// Unable to seek the block device backing file due to invalid permissions or
     /// the file was deleted/corrupted.
     CreateBlockDevice(std::io::Error),
@@ -83,6 +116,8 @@ pub enum StartMicrovmError {
     NetDeviceNotConfigured,
     /// Cannot open the block device backing file.
     OpenBlockDevice(std::io::Error),
+    /// Cannot create the capture file of a network interface.
+    OpenNetCapture(std::io::Error),
     /// Cannot initialize a MMIO Block Device or add a device to the MMIO Bus.
     RegisterBlockDevice(device_manager::mmio::Error),
     /// Cannot add event to Epoll.
@@ -122,6 +157,7 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot configure virtual machine. {}", err_msg)
             }
+            CreateBalloonDevice(ref err) => write!(f, "Cannot create balloon device. {}", err),
             CreateBlockDevice(ref err) => write!(
                 f,
                 "Unable to seek the block device backing file due to invalid permissions or \
@@ -183,6 +219,13 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot open the block device backing file. {}", err_msg)
             }
+            OpenNetCapture(ref err) => {
+                write!(
+                    f,
+                    "Cannot open the capture file of the network interface. {}",
+                    err
+                )
+            }
             RegisterBlockDevice(ref err) => {
                 let mut err_msg = format!("{}", err);
                 err_msg = err_msg.replace("\"", "");
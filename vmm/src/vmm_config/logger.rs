@@ -4,8 +4,10 @@
 extern crate serde_json;
 
 use std::fmt::{Display, Formatter, Result};
+use std::result;
 
 use self::serde_json::Value;
+use logger::LogDestination;
 
 /// Enum used for setting the log level.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -23,14 +25,83 @@ pub enum LoggerLevel {
     Debug,
 }
 
+/// Enum used for setting the format of the log lines.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum LoggerFormat {
+    /// Plain text lines, with the tag enabled by `show_level` and `show_log_origin`.
+    Text,
+    /// One JSON object per line, with the timestamp, the level, the instance ID, the module,
+    /// the file, the line number and the message as separate fields.
+    Json,
+}
+
+/// Enum used for setting where the logs or the metrics are written.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub enum LoggerDestination {
+    /// A named pipe.
+    Fifo {
+        /// The path of the named pipe.
+        path: String,
+    },
+    /// A regular file, which is rotated before it grows over `max_size` bytes.
+    File {
+        /// The path of the file. The rotated files get the `.1` suffix for the latest one, up to
+        /// `.<max_files>`.
+        path: String,
+        /// The size of the file which triggers its rotation, in bytes.
+        max_size: u64,
+        /// The number of rotated files to keep.
+        #[serde(default)]
+        max_files: usize,
+    },
+    /// A Unix datagram socket which receives syslog messages, such as `/dev/log`.
+    Syslog {
+        /// The path of the socket.
+        path: String,
+    },
+    /// A ring buffer in memory, read through the API.
+    RingBuffer {
+        /// The number of lines kept.
+        capacity: usize,
+    },
+}
+
+impl From<LoggerDestination> for LogDestination {
+    fn from(destination: LoggerDestination) -> Self {
+        match destination {
+            LoggerDestination::Fifo { path } => LogDestination::Fifo(path),
+            LoggerDestination::File {
+                path,
+                max_size,
+                max_files,
+            } => LogDestination::File {
+                path,
+                max_size,
+                max_files,
+            },
+            LoggerDestination::Syslog { path } => LogDestination::Syslog(path),
+            LoggerDestination::RingBuffer { capacity } => LogDestination::RingBuffer(capacity),
+        }
+    }
+}
+
 /// Strongly typed structure used to describe the logger.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct LoggerConfig {
-    /// Named pipe used as output for logs.
-    pub log_fifo: String,
-    /// Named pipe used as output for metrics.
-    pub metrics_fifo: String,
+    /// Named pipe used as output for logs. Exclusive with `log_destination`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_fifo: Option<String>,
+    /// Named pipe used as output for metrics. Exclusive with `metrics_destination`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_fifo: Option<String>,
+    /// Output for logs, other than a named pipe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_destination: Option<LoggerDestination>,
+    /// Output for metrics, other than a named pipe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_destination: Option<LoggerDestination>,
     /// The level of the Logger.
     #[serde(default = "default_level")]
     pub level: LoggerLevel,
@@ -40,16 +111,71 @@ pub struct LoggerConfig {
     /// When enabled, the logger will append the origin of the log entry.
     #[serde(default)]
     pub show_log_origin: bool,
+    /// The format of the log lines.
+    #[serde(default = "default_format")]
+    pub format: LoggerFormat,
     /// Additional logging options.
     #[cfg(target_arch = "x86_64")]
     #[serde(default = "default_log_options")]
     pub options: Value,
 }
 
+impl LoggerConfig {
+    /// Returns the output for logs, given by either `log_fifo` or `log_destination`.
+    pub fn log_output(&self) -> result::Result<LoggerDestination, LoggerConfigError> {
+        output("log", &self.log_fifo, &self.log_destination)
+    }
+
+    /// Returns the output for metrics, given by either `metrics_fifo` or `metrics_destination`.
+    pub fn metrics_output(&self) -> result::Result<LoggerDestination, LoggerConfigError> {
+        output("metrics", &self.metrics_fifo, &self.metrics_destination)
+    }
+}
+
+/// Strongly typed structure used to change the logger after it was configured. The fields which
+/// are not given keep their value.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerUpdateConfig {
+    /// The level of the Logger.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LoggerLevel>,
+    /// When enabled, the logger will append to the output the severity of the log entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_level: Option<bool>,
+    /// When enabled, the logger will append the origin of the log entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_log_origin: Option<bool>,
+    /// When enabled, KVM tracks the pages dirtied by the guest and their count is reported in
+    /// the metrics.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dirty_pages: Option<bool>,
+}
+
+fn output(
+    name: &str,
+    fifo: &Option<String>,
+    destination: &Option<LoggerDestination>,
+) -> result::Result<LoggerDestination, LoggerConfigError> {
+    match (fifo, destination) {
+        (Some(path), None) => Ok(LoggerDestination::Fifo { path: path.clone() }),
+        (None, Some(destination)) => Ok(destination.clone()),
+        _ => Err(LoggerConfigError::InitializationFailure(format!(
+            "Exactly one of {}_fifo and {}_destination must be given.",
+            name, name
+        ))),
+    }
+}
+
 fn default_level() -> LoggerLevel {
     LoggerLevel::Warning
 }
 
+fn default_format() -> LoggerFormat {
+    LoggerFormat::Text
+}
+
 fn default_log_options() -> Value {
     Value::Array(vec![])
 }
@@ -61,6 +187,10 @@ pub enum LoggerConfigError {
     InitializationFailure(String),
     /// Cannot flush the metrics.
     FlushMetrics(String),
+    /// The logger was not configured.
+    NotConfigured,
+    /// Cannot change the logger after it was configured.
+    UpdateFailed(String),
 }
 
 impl Display for LoggerConfigError {
@@ -69,6 +199,8 @@ impl Display for LoggerConfigError {
         match *self {
             InitializationFailure(ref err_msg) => write!(f, "{}", err_msg.replace("\"", "")),
             FlushMetrics(ref err_msg) => write!(f, "{}", err_msg.replace("\"", "")),
+            NotConfigured => write!(f, "The logger has not been configured."),
+            UpdateFailed(ref err_msg) => write!(f, "Cannot update the logger. {}", err_msg),
         }
     }
 }
@@ -0,0 +1,117 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm_config::balloon::BalloonConfig;
+use vmm_config::boot_source::BootSourceConfig;
+use vmm_config::drive::BlockDeviceConfig;
+use vmm_config::logger::LoggerConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::net::NetworkInterfaceConfig;
+#[cfg(feature = "vsock")]
+use vmm_config::vsock::VsockDeviceConfig;
+
+/// The location of a virtio device on the MMIO bus.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MmioConfig {
+    /// Start address of the device's MMIO range.
+    pub addr: u64,
+    /// Length of the device's MMIO range.
+    pub len: u64,
+    /// The interrupt line of the device.
+    pub irq: u32,
+}
+
+/// The configuration of a device, together with its location on the MMIO bus.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AttachedDevice<T> {
+    /// The configuration of the device, as provided by the user.
+    #[serde(flatten)]
+    pub config: T,
+    /// The location of the device on the MMIO bus. It is only known after the microVM starts.
+    pub mmio: Option<MmioConfig>,
+}
+
+/// The whole configuration of a microVM, as held by the VMM. Each field is named after the API
+/// resource it describes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MicrovmConfig {
+    /// The boot source, if configured.
+    #[serde(rename = "boot-source")]
+    pub boot_source: Option<BootSourceConfig>,
+    /// The memory and CPU configuration.
+    #[serde(rename = "machine-config")]
+    pub machine_config: VmConfig,
+    /// The block devices, in the order they are attached.
+    pub drives: Vec<AttachedDevice<BlockDeviceConfig>>,
+    /// The network interfaces, in the order they are attached.
+    #[serde(rename = "network-interfaces")]
+    pub network_interfaces: Vec<AttachedDevice<NetworkInterfaceConfig>>,
+    #[cfg(feature = "vsock")]
+    /// The vsock devices.
+    pub vsocks: Vec<AttachedDevice<VsockDeviceConfig>>,
+    /// The balloon device, if configured.
+    pub balloon: Option<AttachedDevice<BalloonConfig>>,
+    /// The logger configuration, if configured.
+    pub logger: Option<LoggerConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use vmm_config::drive::{CacheType, DiskImageFormat, IoEngine};
+
+    use serde_json;
+
+    #[test]
+    fn test_microvm_config_serialization() {
+        let config = MicrovmConfig {
+            boot_source: Some(BootSourceConfig {
+                kernel_image_path: String::from("/foo/kernel"),
+                boot_args: None,
+            }),
+            machine_config: VmConfig::default(),
+            drives: vec![AttachedDevice {
+                config: BlockDeviceConfig {
+                    drive_id: String::from("rootfs"),
+                    path_on_host: PathBuf::from("/foo/rootfs"),
+                    is_root_device: true,
+                    partuuid: None,
+                    is_read_only: false,
+                    rate_limiter: None,
+                    io_engine: IoEngine::Sync,
+                    format: DiskImageFormat::Raw,
+                    overlay_path: None,
+                    num_queues: 1,
+                    cache_type: CacheType::Writeback,
+                    topology: None,
+                },
+                mmio: Some(MmioConfig {
+                    addr: 0xd000_0000,
+                    len: 0x1000,
+                    irq: 5,
+                }),
+            }],
+            network_interfaces: vec![],
+            #[cfg(feature = "vsock")]
+            vsocks: vec![],
+            balloon: None,
+            logger: None,
+        };
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["boot-source"]["kernel_image_path"], "/foo/kernel");
+        assert_eq!(json["machine-config"]["vcpu_count"], 1);
+        // The device configuration is flattened next to its MMIO location.
+        assert_eq!(json["drives"][0]["drive_id"], "rootfs");
+        assert_eq!(json["drives"][0]["path_on_host"], "/foo/rootfs");
+        assert_eq!(json["drives"][0]["mmio"]["addr"], 0xd000_0000_u64);
+        assert_eq!(json["drives"][0]["mmio"]["len"], 0x1000);
+        assert_eq!(json["drives"][0]["mmio"]["irq"], 5);
+        assert!(json["network-interfaces"].as_array().unwrap().is_empty());
+        assert!(json["balloon"].is_null());
+        assert!(json["logger"].is_null());
+    }
+}
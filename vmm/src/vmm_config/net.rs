@@ -2,16 +2,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter, Result};
+use std::io;
+use std::net::Ipv4Addr;
 use std::result;
 
 use super::super::Error as VmmInternalError;
 use super::RateLimiterConfig;
 use devices;
+use devices::virtio::{AntiSpoofing, PcapWriter, NET_MAX_MTU, NET_MAX_QUEUE_PAIRS, NET_MIN_MTU};
+pub use devices::virtio::{
+    FilterAction, FilterProtocol, FilterRule, Ipv4Cidr, PacketFilter, PortRange,
+};
 use net_util::{MacAddr, Tap, TapError};
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
 /// related requests.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
@@ -31,9 +37,30 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
-    /// Handle for a network tap interface created using `host_dev_name`.
+    /// The number of queue pairs of the guest network interface. With more than one queue pair,
+    /// the host interface must be a multi-queue tap, which gets a queue for each pair. Defaults
+    /// to 1.
+    #[serde(default = "default_num_queue_pairs")]
+    pub num_queue_pairs: u16,
+    /// The MTU of the network interface, which is set on the host device and advertised to the
+    /// guest. Frames sent by the guest above the MTU are dropped. If missing, the MTU of the host
+    /// device is left unchanged and the guest uses the standard one.
+    pub mtu: Option<u16>,
+    /// The capture of the frames of the network interface, started before boot.
+    #[serde(skip)]
+    pub capture: Option<NetworkCaptureConfig>,
+    /// If present, the frames sent by the guest with spoofed source addresses are dropped. It
+    /// requires the guest MAC address.
+    pub anti_spoofing: Option<AntiSpoofingConfig>,
+    /// The filter of the frames received from the host interface. All of them go through if
+    /// missing.
+    pub ingress_filter: Option<PacketFilter>,
+    /// The filter of the frames sent to the host interface. All of them go through if missing.
+    pub egress_filter: Option<PacketFilter>,
+    /// Handles for the queues of the network tap interface created using `host_dev_name`, one
+    /// for each queue pair.
     #[serde(skip)]
-    pub tap: Option<Tap>,
+    pub taps: Vec<Tap>,
 }
 
 // Serde does not allow specifying a default value for a field
@@ -43,11 +70,52 @@ fn default_allow_mmds_requests() -> bool {
     false
 }
 
+fn default_num_queue_pairs() -> u16 {
+    1
+}
+
+// The tap device cannot be cloned, so a clone only describes the interface, without owning it.
+impl Clone for NetworkInterfaceConfig {
+    fn clone(&self) -> Self {
+        NetworkInterfaceConfig {
+            iface_id: self.iface_id.clone(),
+            host_dev_name: self.host_dev_name.clone(),
+            guest_mac: self.guest_mac,
+            rx_rate_limiter: self.rx_rate_limiter,
+            tx_rate_limiter: self.tx_rate_limiter,
+            allow_mmds_requests: self.allow_mmds_requests,
+            num_queue_pairs: self.num_queue_pairs,
+            mtu: self.mtu,
+            capture: self.capture.clone(),
+            anti_spoofing: self.anti_spoofing.clone(),
+            ingress_filter: self.ingress_filter.clone(),
+            egress_filter: self.egress_filter.clone(),
+            taps: Vec::new(),
+        }
+    }
+}
+
 impl NetworkInterfaceConfig {
-    /// Returns the tap device if it was configured. This function has side effects as it takes
-    /// the value from `self.tap` and leaves None in its place.
-    pub fn take_tap(&mut self) -> Option<Tap> {
-        self.tap.take()
+    /// Returns the queues of the tap device if it was configured. This function has side
+    /// effects as it takes the value from `self.taps` and leaves an empty list in its place.
+    pub fn take_taps(&mut self) -> Vec<Tap> {
+        ::std::mem::replace(&mut self.taps, Vec::new())
+    }
+
+    // Opens a queue of the tap device for each queue pair. A single queue pair keeps using a
+    // regular tap device, so that existing host setups keep working.
+    fn open_taps(&self) -> result::Result<Vec<Tap>, NetworkInterfaceError> {
+        let host_dev_name = self.host_dev_name.as_str();
+        (0..self.num_queue_pairs)
+            .map(|_| {
+                if self.num_queue_pairs == 1 {
+                    Tap::open_named(host_dev_name)
+                } else {
+                    Tap::open_named_multi_queue(host_dev_name)
+                }
+                .map_err(NetworkInterfaceError::OpenTap)
+            })
+            .collect()
     }
 
     /// Returns a reference to the mac address. It the mac address is not configured, it
@@ -60,10 +128,31 @@ impl NetworkInterfaceConfig {
     pub fn allow_mmds_requests(&self) -> bool {
         self.allow_mmds_requests
     }
+
+    /// Returns the filter of the frames sent by the guest, if the anti-spoofing is configured.
+    pub fn anti_spoofing(&self) -> Option<AntiSpoofing> {
+        match (self.anti_spoofing.as_ref(), self.guest_mac) {
+            (Some(anti_spoofing), Some(guest_mac)) => Some(AntiSpoofing::new(
+                guest_mac,
+                anti_spoofing.allowed_ips.clone(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The source addresses which the frames sent by the guest are checked against. The source MAC
+/// address, and the sender MAC address of the ARP frames, must be the guest MAC address.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AntiSpoofingConfig {
+    /// The source IPv4 addresses of the guest. If present, the IPv4 packets and the ARP frames
+    /// sent from other addresses are dropped.
+    pub allowed_ips: Option<Vec<Ipv4Addr>>,
 }
 
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// and the packet filters can be updated.
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -75,6 +164,38 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// New ingress filter, which replaces the previous one. Left unchanged if missing.
+    pub ingress_filter: Option<PacketFilter>,
+    /// New egress filter, which replaces the previous one. Left unchanged if missing.
+    pub egress_filter: Option<PacketFilter>,
+}
+
+impl NetworkInterfaceUpdateConfig {
+    /// Checks the packet filters of the update.
+    pub fn validate(&self) -> result::Result<(), NetworkInterfaceError> {
+        validate_packet_filters(&self.ingress_filter, &self.egress_filter)
+    }
+}
+
+/// The pcap file which the frames exchanged by a network interface get written to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkCaptureConfig {
+    /// The path of the pcap file, which is replaced if it exists. The rotated files get the `.1`
+    /// suffix for the latest one, up to `.<max_files>`.
+    pub path: String,
+    /// The size of the file which triggers its rotation, in bytes.
+    pub max_size: u64,
+    /// The number of rotated files to keep.
+    #[serde(default)]
+    pub max_files: usize,
+}
+
+impl NetworkCaptureConfig {
+    /// Creates the pcap file.
+    pub fn open(&self) -> io::Result<PcapWriter> {
+        PcapWriter::new(&self.path, self.max_size, self.max_files)
+    }
 }
 
 /// Errors associated with `NetworkInterfaceConfig`.
@@ -90,10 +211,31 @@ pub enum NetworkInterfaceError {
     DeviceIdNotFound,
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// The number of queue pairs is zero or above `NET_MAX_QUEUE_PAIRS`.
+    InvalidNumQueuePairs,
+    /// The MTU is not between `NET_MIN_MTU` and `NET_MAX_MTU`.
+    InvalidMtu,
+    /// Cannot create the capture file.
+    OpenCapture(io::Error),
+    /// The anti-spoofing filter is configured without a guest MAC address.
+    AntiSpoofingWithoutGuestMac,
+    /// A rule of a packet filter has an empty port range, or a port range without the TCP or UDP
+    /// protocol.
+    InvalidPacketFilter,
     /// Error updating (patching) the rate limiters.
     RateLimiterUpdateFailed(devices::Error),
     /// The update is not allowed after booting the microvm.
     UpdateNotAllowedPostBoot,
+    /// All the hot-plug slots are in use.
+    NoHotplugSlot,
+    /// Cannot attach the network interface to the running microVM.
+    HotplugFailed(String),
+    /// Only the network interfaces attached after boot can be detached.
+    NotHotplugged,
+    /// The guest driver has not released the network interface to detach.
+    DeviceInUse,
+    /// Cannot detach the network interface from the running microVM.
+    UnplugFailed(String),
 }
 
 impl Display for NetworkInterfaceError {
@@ -127,10 +269,41 @@ impl Display for NetworkInterfaceError {
                     tap_err
                 )
             }
+            InvalidNumQueuePairs => write!(
+                f,
+                "The number of queue pairs of a network interface must be between 1 and {}.",
+                NET_MAX_QUEUE_PAIRS
+            ),
+            InvalidMtu => write!(
+                f,
+                "The MTU of a network interface must be between {} and {}.",
+                NET_MIN_MTU, NET_MAX_MTU
+            ),
+            OpenCapture(ref e) => write!(f, "Cannot open the capture file. {}", e),
+            AntiSpoofingWithoutGuestMac => write!(
+                f,
+                "The anti-spoofing filter of a network interface requires its guest MAC address."
+            ),
+            InvalidPacketFilter => write!(
+                f,
+                "The port ranges of the packet filter rules must not be empty, and require the \
+                 Tcp or Udp protocol."
+            ),
             RateLimiterUpdateFailed(ref e) => write!(f, "Unable to update rate limiter: {:?}", e),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.",)
             }
+            NoHotplugSlot => write!(f, "No slot is left for attaching the network interface."),
+            HotplugFailed(ref msg) => write!(f, "Cannot attach the network interface. {}", msg),
+            NotHotplugged => write!(
+                f,
+                "Only the network interfaces attached after boot can be detached."
+            ),
+            DeviceInUse => write!(
+                f,
+                "The network interface is in use by the guest, which must unbind its driver first."
+            ),
+            UnplugFailed(ref msg) => write!(f, "Cannot detach the network interface. {}", msg),
         }
     }
 }
@@ -149,6 +322,11 @@ impl NetworkInterfaceConfigs {
         }
     }
 
+    /// Returns an immutable iterator over the network interfaces.
+    pub fn iter(&self) -> ::std::slice::Iter<NetworkInterfaceConfig> {
+        self.if_list.iter()
+    }
+
     /// Returns a mutable iterator over the network interfaces.
     pub fn iter_mut(&mut self) -> ::std::slice::IterMut<NetworkInterfaceConfig> {
         self.if_list.iter_mut()
@@ -171,6 +349,38 @@ impl NetworkInterfaceConfigs {
         }
     }
 
+    /// Appends the configuration of a network interface attached after booting the microVM.
+    /// Unlike `insert`, existing entries are never updated.
+    pub fn insert_hotplugged(
+        &mut self,
+        netif_config: NetworkInterfaceConfig,
+    ) -> result::Result<(), NetworkInterfaceError> {
+        if self.get_index_of_iface_id(&netif_config.iface_id).is_some() {
+            return Err(NetworkInterfaceError::UpdateNotAllowedPostBoot);
+        }
+        self.create(netif_config)
+    }
+
+    /// Removes the network interface with the given `iface_id` from the list and returns its
+    /// configuration.
+    pub fn remove(&mut self, iface_id: &str) -> Option<NetworkInterfaceConfig> {
+        self.get_index_of_iface_id(iface_id)
+            .map(|index| self.if_list.remove(index))
+    }
+
+    /// Returns the mutable configuration of the network interface with the given `iface_id`.
+    pub fn get_mut(&mut self, iface_id: &str) -> Option<&mut NetworkInterfaceConfig> {
+        self.if_list
+            .iter_mut()
+            .find(|netif| netif.iface_id == iface_id)
+    }
+
+    fn get_index_of_iface_id(&self, iface_id: &str) -> Option<usize> {
+        self.if_list
+            .iter()
+            .position(|netif| netif.iface_id == iface_id)
+    }
+
     fn get_index_of_mac(&self, mac: MacAddr) -> Option<usize> {
         self.if_list
             .iter()
@@ -188,6 +398,11 @@ impl NetworkInterfaceConfigs {
         index: usize,
         new_config: &NetworkInterfaceConfig,
     ) -> result::Result<(), NetworkInterfaceError> {
+        validate_num_queue_pairs(new_config)?;
+        validate_mtu(new_config)?;
+        validate_anti_spoofing(new_config)?;
+        validate_packet_filters(&new_config.ingress_filter, &new_config.egress_filter)?;
+
         // Check that the mac address is unique. In order to do so, we search for the
         // network interface that has the same mac address as the one specified in new_config.
         // If the same mac is used in another network interface config, return error.
@@ -217,18 +432,22 @@ impl NetworkInterfaceConfigs {
     ) -> result::Result<(), NetworkInterfaceError> {
         self.validate_update(index, &updated_netif_config)?;
 
-        // We are ignoring the tap field of the network interface we want to update. We are
-        // manually setting this field to a newly created tap (corresponding to the host_dev_name)
-        // or to the old tap device of the network interface we are trying to update.
-        updated_netif_config.tap =
-            if self.if_list[index].host_dev_name != updated_netif_config.host_dev_name {
-                Some(
-                    Tap::open_named(&updated_netif_config.host_dev_name.as_str())
-                        .map_err(NetworkInterfaceError::OpenTap)?,
-                )
-            } else {
-                self.if_list[index].tap.take()
-            };
+        // We are ignoring the taps field of the network interface we want to update. We are
+        // manually setting this field to newly created tap queues (corresponding to the
+        // host_dev_name and to the number of queue pairs) or to the old tap queues of the network
+        // interface we are trying to update.
+        let old_netif_config = &mut self.if_list[index];
+        updated_netif_config.taps = if old_netif_config.host_dev_name
+            != updated_netif_config.host_dev_name
+            || old_netif_config.num_queue_pairs != updated_netif_config.num_queue_pairs
+        {
+            // The old tap queues are closed first, a multi-queue tap cannot be opened with
+            // another number of queues while they are in use.
+            old_netif_config.taps.clear();
+            updated_netif_config.open_taps()?
+        } else {
+            old_netif_config.take_taps()
+        };
         self.if_list[index] = updated_netif_config;
 
         Ok(())
@@ -238,6 +457,11 @@ impl NetworkInterfaceConfigs {
         &self,
         new_config: &NetworkInterfaceConfig,
     ) -> result::Result<(), NetworkInterfaceError> {
+        validate_num_queue_pairs(new_config)?;
+        validate_mtu(new_config)?;
+        validate_anti_spoofing(new_config)?;
+        validate_packet_filters(&new_config.ingress_filter, &new_config.egress_filter)?;
+
         // Check that there is no other interface in the list that has the same mac.
         if new_config.guest_mac.is_some()
             && self
@@ -267,16 +491,58 @@ impl NetworkInterfaceConfigs {
         netif_config: NetworkInterfaceConfig,
     ) -> result::Result<(), NetworkInterfaceError> {
         self.validate_create(&netif_config)?;
-        let tap = Tap::open_named(netif_config.host_dev_name.as_str())
-            .map_err(NetworkInterfaceError::OpenTap)?;
+        let taps = netif_config.open_taps()?;
         self.if_list.push(netif_config);
 
         let index = self.if_list.len() - 1;
-        self.if_list[index].tap = Some(tap);
+        self.if_list[index].taps = taps;
         Ok(())
     }
 }
 
+fn validate_num_queue_pairs(
+    netif_config: &NetworkInterfaceConfig,
+) -> result::Result<(), NetworkInterfaceError> {
+    if netif_config.num_queue_pairs == 0 || netif_config.num_queue_pairs > NET_MAX_QUEUE_PAIRS {
+        return Err(NetworkInterfaceError::InvalidNumQueuePairs);
+    }
+    Ok(())
+}
+
+fn validate_mtu(
+    netif_config: &NetworkInterfaceConfig,
+) -> result::Result<(), NetworkInterfaceError> {
+    match netif_config.mtu {
+        Some(mtu) if mtu < NET_MIN_MTU || mtu > NET_MAX_MTU => {
+            Err(NetworkInterfaceError::InvalidMtu)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_anti_spoofing(
+    netif_config: &NetworkInterfaceConfig,
+) -> result::Result<(), NetworkInterfaceError> {
+    if netif_config.anti_spoofing.is_some() && netif_config.guest_mac.is_none() {
+        return Err(NetworkInterfaceError::AntiSpoofingWithoutGuestMac);
+    }
+    Ok(())
+}
+
+fn validate_packet_filters(
+    ingress_filter: &Option<PacketFilter>,
+    egress_filter: &Option<PacketFilter>,
+) -> result::Result<(), NetworkInterfaceError> {
+    if ingress_filter
+        .iter()
+        .chain(egress_filter.iter())
+        .any(|filter| !filter.is_valid())
+    {
+        return Err(NetworkInterfaceError::InvalidPacketFilter);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
@@ -293,23 +559,13 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
-            tap: None,
-        }
-    }
-
-    // This implementation is used only in tests. We cannot directly derive clone because
-    // Tap and RateLimiter do not implement clone.
-    impl Clone for NetworkInterfaceConfig {
-        fn clone(&self) -> Self {
-            NetworkInterfaceConfig {
-                iface_id: self.iface_id.clone(),
-                host_dev_name: self.host_dev_name.clone(),
-                guest_mac: self.guest_mac,
-                rx_rate_limiter: None,
-                tx_rate_limiter: None,
-                allow_mmds_requests: self.allow_mmds_requests,
-                tap: None,
-            }
+            num_queue_pairs: 1,
+            mtu: None,
+            capture: None,
+            anti_spoofing: None,
+            ingress_filter: None,
+            egress_filter: None,
+            taps: Vec::new(),
         }
     }
 
@@ -466,5 +722,223 @@ mod tests {
             NetworkInterfaceError::UpdateNotAllowedPostBoot,
             NetworkInterfaceError::UpdateNotAllowedPostBoot
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidNumQueuePairs,
+            NetworkInterfaceError::InvalidNumQueuePairs
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidMtu,
+            NetworkInterfaceError::InvalidMtu
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidPacketFilter,
+            NetworkInterfaceError::InvalidPacketFilter
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::AntiSpoofingWithoutGuestMac,
+            NetworkInterfaceError::AntiSpoofingWithoutGuestMac
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::OpenCapture(io::Error::from_raw_os_error(libc::ENOENT)),
+            NetworkInterfaceError::OpenCapture(io::Error::from_raw_os_error(libc::ENOENT))
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::NoHotplugSlot,
+            NetworkInterfaceError::NoHotplugSlot
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::HotplugFailed("foo".to_string()),
+            NetworkInterfaceError::HotplugFailed("foo".to_string())
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::NotHotplugged,
+            NetworkInterfaceError::NotHotplugged
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::DeviceInUse,
+            NetworkInterfaceError::DeviceInUse
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::UnplugFailed("foo".to_string()),
+            NetworkInterfaceError::UnplugFailed("foo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_hotplugged_and_remove() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+
+        let netif_1 = create_netif("id_1", "dev5", "01:23:45:67:89:0a");
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+
+        // Existing interfaces cannot be updated.
+        let netif_1 = create_netif("id_1", "dev6", "01:23:45:67:89:0b");
+        match netif_configs.insert_hotplugged(netif_1) {
+            Err(NetworkInterfaceError::UpdateNotAllowedPostBoot) => (),
+            _ => panic!("Expected an update not allowed post boot error."),
+        }
+
+        // The uniqueness of the host device name is still checked.
+        let netif_2 = create_netif("id_2", "dev5", "01:23:45:67:89:0b");
+        match netif_configs.insert_hotplugged(netif_2) {
+            Err(NetworkInterfaceError::HostDeviceNameInUse(_)) => (),
+            _ => panic!("Expected a host device name in use error."),
+        }
+
+        let netif_2 = create_netif("id_2", "dev6", "01:23:45:67:89:0b");
+        assert!(netif_configs.insert_hotplugged(netif_2).is_ok());
+        assert_eq!(netif_configs.if_list.len(), 2);
+        assert_eq!(netif_configs.get_mut("id_2").unwrap().take_taps().len(), 1);
+
+        assert_eq!(netif_configs.remove("id_1").unwrap().iface_id, "id_1");
+        assert!(netif_configs.remove("id_1").is_none());
+        assert!(netif_configs.get_mut("id_1").is_none());
+        assert_eq!(netif_configs.if_list.len(), 1);
+    }
+
+    #[test]
+    fn test_num_queue_pairs() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+
+        let mut netif_1 = create_netif("id_1", "dev7", "01:23:45:67:89:0a");
+        for &num_queue_pairs in &[0, NET_MAX_QUEUE_PAIRS + 1] {
+            netif_1.num_queue_pairs = num_queue_pairs;
+            match netif_configs.insert(netif_1.clone()) {
+                Err(NetworkInterfaceError::InvalidNumQueuePairs) => (),
+                _ => panic!("Expected an invalid number of queue pairs error."),
+            }
+        }
+
+        // Each queue pair gets a queue of the multi-queue tap.
+        netif_1.num_queue_pairs = 2;
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+        assert_eq!(netif_configs.if_list[0].taps.len(), 2);
+
+        // The tap is opened again when the number of queue pairs changes.
+        netif_1.num_queue_pairs = 1;
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+        assert_eq!(netif_configs.if_list[0].taps.len(), 1);
+
+        netif_1.num_queue_pairs = 0;
+        match netif_configs.insert(netif_1) {
+            Err(NetworkInterfaceError::InvalidNumQueuePairs) => (),
+            _ => panic!("Expected an invalid number of queue pairs error."),
+        }
+        assert_eq!(netif_configs.if_list[0].num_queue_pairs, 1);
+    }
+
+    #[test]
+    fn test_mtu() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+
+        let mut netif_1 = create_netif("id_1", "dev8", "01:23:45:67:89:0a");
+        for &mtu in &[NET_MIN_MTU - 1, NET_MAX_MTU + 1] {
+            netif_1.mtu = Some(mtu);
+            match netif_configs.insert(netif_1.clone()) {
+                Err(NetworkInterfaceError::InvalidMtu) => (),
+                _ => panic!("Expected an invalid MTU error."),
+            }
+        }
+
+        netif_1.mtu = Some(9000);
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+
+        netif_1.mtu = Some(0);
+        match netif_configs.insert(netif_1) {
+            Err(NetworkInterfaceError::InvalidMtu) => (),
+            _ => panic!("Expected an invalid MTU error."),
+        }
+        assert_eq!(netif_configs.if_list[0].mtu, Some(9000));
+    }
+
+    #[test]
+    fn test_anti_spoofing() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+
+        let mut netif_1 = create_netif("id_1", "dev9", "01:23:45:67:89:0b");
+        assert!(netif_1.anti_spoofing().is_none());
+        netif_1.anti_spoofing = Some(AntiSpoofingConfig {
+            allowed_ips: Some(vec![Ipv4Addr::new(10, 0, 0, 2)]),
+        });
+        assert_eq!(
+            netif_1.anti_spoofing(),
+            Some(AntiSpoofing::new(
+                MacAddr::parse_str("01:23:45:67:89:0b").unwrap(),
+                Some(vec![Ipv4Addr::new(10, 0, 0, 2)])
+            ))
+        );
+
+        // The filter needs the guest MAC address.
+        netif_1.guest_mac = None;
+        match netif_configs.insert(netif_1.clone()) {
+            Err(NetworkInterfaceError::AntiSpoofingWithoutGuestMac) => (),
+            _ => panic!("Expected an anti-spoofing without guest MAC error."),
+        }
+        assert!(netif_1.anti_spoofing().is_none());
+
+        netif_1.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0b").unwrap());
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+        netif_1.guest_mac = None;
+        match netif_configs.insert(netif_1) {
+            Err(NetworkInterfaceError::AntiSpoofingWithoutGuestMac) => (),
+            _ => panic!("Expected an anti-spoofing without guest MAC error."),
+        }
+    }
+
+    #[test]
+    fn test_packet_filters() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+
+        let invalid_filter = PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: vec![FilterRule {
+                action: FilterAction::Allow,
+                protocol: Some(FilterProtocol::Icmp),
+                cidr: None,
+                ports: Some(PortRange { start: 1, end: 2 }),
+            }],
+        };
+        let mut netif_1 = create_netif("id_1", "dev10", "01:23:45:67:89:0c");
+        netif_1.egress_filter = Some(invalid_filter.clone());
+        match netif_configs.insert(netif_1.clone()) {
+            Err(NetworkInterfaceError::InvalidPacketFilter) => (),
+            _ => panic!("Expected an invalid packet filter error."),
+        }
+
+        netif_1.egress_filter = None;
+        netif_1.ingress_filter = Some(PacketFilter {
+            default_policy: FilterAction::Deny,
+            rules: Vec::new(),
+        });
+        assert!(netif_configs.insert(netif_1.clone()).is_ok());
+        netif_1.ingress_filter = Some(invalid_filter.clone());
+        match netif_configs.insert(netif_1) {
+            Err(NetworkInterfaceError::InvalidPacketFilter) => (),
+            _ => panic!("Expected an invalid packet filter error."),
+        }
+
+        let mut update = NetworkInterfaceUpdateConfig {
+            iface_id: String::from("id_1"),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            ingress_filter: None,
+            egress_filter: Some(invalid_filter),
+        };
+        match update.validate() {
+            Err(NetworkInterfaceError::InvalidPacketFilter) => (),
+            _ => panic!("Expected an invalid packet filter error."),
+        }
+        update.egress_filter = None;
+        assert!(update.validate().is_ok());
     }
 }
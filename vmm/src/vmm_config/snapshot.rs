@@ -0,0 +1,150 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+use std::io;
+
+use vstate;
+
+/// Strongly typed data structure used for creating or loading a microVM snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Path of the file holding the vCPU and device states.
+    pub snapshot_path: String,
+    /// Path of the file holding the guest memory.
+    pub mem_file_path: String,
+}
+
+/// Errors associated with creating or loading a microVM snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot file cannot be created.
+    CreateSnapshotFile(io::Error),
+    /// The guest memory file cannot be created.
+    CreateMemoryFile(io::Error),
+    /// The devices configured before loading the snapshot do not match the snapshotted ones.
+    DevicesMismatch,
+    /// Cannot save or restore the state of a device.
+    DeviceState(String),
+    /// Snapshots are not supported for microVMs with slots for attaching devices after boot.
+    HotplugSlots,
+    /// The snapshot file does not hold a valid microVM state.
+    InvalidSnapshot(String),
+    /// The size of the guest memory file does not match the memory size of the microVM.
+    MemoryFileSize,
+    /// The microVM was already started, so a snapshot cannot be loaded into it.
+    MicroVMAlreadyRunning,
+    /// The microVM is not paused, so a snapshot cannot be created.
+    MicroVMNotPaused,
+    /// The snapshot file cannot be opened.
+    OpenSnapshotFile(io::Error),
+    /// The guest memory file cannot be opened.
+    OpenMemoryFile(io::Error),
+    /// Cannot read the guest memory from the memory file.
+    ReadMemory(String),
+    /// Cannot save the state of the vCPU with the given index.
+    SaveVcpuState(u8),
+    /// Snapshots are not supported on this architecture.
+    UnsupportedArchitecture,
+    /// One of the configured devices does not support snapshots.
+    UnsupportedDevice(String),
+    /// The snapshot file was written in a format version that is not supported.
+    UnsupportedVersion(u32),
+    /// Failed to communicate with a vCPU thread, or to restore the state of a vCPU.
+    Vcpu(vstate::Error),
+    /// Cannot save or restore the state of the VM (interrupt controllers, PIT, clock).
+    VmState(vstate::Error),
+    /// Cannot write the guest memory to the memory file.
+    WriteMemory(String),
+    /// Cannot serialize or write the microVM state to the snapshot file.
+    WriteSnapshot(String),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::SnapshotError::*;
+        match *self {
+            CreateSnapshotFile(ref e) => write!(f, "Cannot create the snapshot file. {}", e),
+            CreateMemoryFile(ref e) => write!(f, "Cannot create the memory file. {}", e),
+            DevicesMismatch => write!(
+                f,
+                "The configured block devices and network interfaces do not match the ones \
+                 in the snapshot."
+            ),
+            DeviceState(ref msg) => write!(f, "Cannot save or restore device state. {}", msg),
+            HotplugSlots => write!(
+                f,
+                "Snapshots are not supported for microVMs with hot-plug slots."
+            ),
+            InvalidSnapshot(ref msg) => write!(f, "The snapshot file is invalid. {}", msg),
+            MemoryFileSize => write!(
+                f,
+                "The size of the memory file does not match the memory size of the microVM."
+            ),
+            MicroVMAlreadyRunning => write!(
+                f,
+                "A snapshot cannot be loaded after the microVM was started."
+            ),
+            MicroVMNotPaused => write!(f, "Microvm is not paused."),
+            OpenSnapshotFile(ref e) => write!(f, "Cannot open the snapshot file. {}", e),
+            OpenMemoryFile(ref e) => write!(f, "Cannot open the memory file. {}", e),
+            ReadMemory(ref msg) => write!(f, "Cannot read the guest memory. {}", msg),
+            SaveVcpuState(id) => write!(f, "Cannot save the state of vCPU {}.", id),
+            UnsupportedArchitecture => {
+                write!(f, "Snapshots are not supported on this architecture.")
+            }
+            UnsupportedDevice(ref id) => {
+                write!(f, "The device {} does not support snapshots.", id)
+            }
+            UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot format version: {}.", version)
+            }
+            Vcpu(ref err) => {
+                let mut err_msg = format!("{:?}", err);
+                err_msg = err_msg.replace("\"", "");
+
+                write!(f, "Cannot save or restore vCPU state. {}", err_msg)
+            }
+            VmState(ref err) => {
+                let mut err_msg = format!("{:?}", err);
+                err_msg = err_msg.replace("\"", "");
+
+                write!(f, "Cannot save or restore VM state. {}", err_msg)
+            }
+            WriteMemory(ref msg) => write!(f, "Cannot write the guest memory. {}", msg),
+            WriteSnapshot(ref msg) => write!(f, "Cannot write the snapshot file. {}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    #[test]
+    fn test_snapshot_config_deserialization() {
+        let json = r#"{
+            "snapshot_path": "/tmp/snapshot",
+            "mem_file_path": "/tmp/mem"
+        }"#;
+        let config: SnapshotConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            config,
+            SnapshotConfig {
+                snapshot_path: String::from("/tmp/snapshot"),
+                mem_file_path: String::from("/tmp/mem"),
+            }
+        );
+
+        // Unknown fields are rejected.
+        let json = r#"{
+            "snapshot_path": "/tmp/snapshot",
+            "mem_file_path": "/tmp/mem",
+            "foo": "bar"
+        }"#;
+        assert!(serde_json::from_str::<SnapshotConfig>(json).is_err());
+    }
+}
@@ -18,6 +18,8 @@ pub struct VsockDeviceConfig {
 /// Errors associated with `VsockDeviceConfig`.
 #[derive(Debug)]
 pub enum VsockError {
+    /// The vsock device ID was not found.
+    DeviceIdNotFound,
     /// The Context Identifier is already in use.
     GuestCIDAlreadyInUse(u32),
     /// The update is not allowed after booting the microvm.
@@ -28,6 +30,7 @@ impl Display for VsockError {
     fn fmt(&self, f: &mut Formatter) -> Result {
         use self::VsockError::*;
         match *self {
+            DeviceIdNotFound => write!(f, "Invalid vsock device ID - not found."),
             GuestCIDAlreadyInUse(ref cid) => {
                 write!(f, "{}", format!("The guest CID {} is already in use.", cid))
             }
@@ -81,7 +84,7 @@ impl VsockDeviceConfigs {
     }
 
     /// Returns an immutable iterator over the vsock available configurations.
-    pub fn iter(&mut self) -> ::std::slice::Iter<VsockDeviceConfig> {
+    pub fn iter(&self) -> ::std::slice::Iter<VsockDeviceConfig> {
         self.configs.iter()
     }
 }
@@ -6,19 +6,34 @@
 // found in the THIRD-PARTY file.
 
 use std::io;
+#[cfg(target_arch = "x86_64")]
+use std::mem;
+#[cfg(target_arch = "x86_64")]
+use std::os::unix::io::AsRawFd;
 use std::result;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
 
 use super::{KvmContext, TimestampUs};
 use arch;
 #[cfg(target_arch = "x86_64")]
 use cpuid::{c3, filter_cpuid, t2, VmSpec};
 use default_syscalls;
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::{
+    kvm_clock_data, kvm_debugregs, kvm_irqchip, kvm_mp_state, kvm_msr_entry, kvm_pit_state2,
+    kvm_vcpu_events, kvm_xcrs, kvm_xsave, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER,
+    KVM_IRQCHIP_PIC_SLAVE,
+};
 use kvm_bindings::{kvm_pit_config, kvm_userspace_memory_region, KVM_PIT_SPEAKER_DUMMY};
 use kvm_ioctls::*;
 use logger::{LogOption, Metric, LOGGER, METRICS};
 use memory_model::{GuestAddress, GuestMemory, GuestMemoryError};
-use sys_util::EventFd;
+#[cfg(target_arch = "x86_64")]
+use sys_util::{ioctl_with_mut_ref, ioctl_with_ref};
+use sys_util::{register_vcpu_signal_handler, EventFd, Killable};
 #[cfg(target_arch = "x86_64")]
 use vmm_config::machine_config::CpuFeaturesTemplate;
 use vmm_config::machine_config::VmConfig;
@@ -31,6 +46,38 @@ const MAGIC_IOPORT_SIGNAL_GUEST_BOOT_COMPLETE: u64 = 0x03f0;
 const MAGIC_IOPORT_SIGNAL_GUEST_BOOT_COMPLETE: u64 = 0x40000000;
 const MAGIC_VALUE_SIGNAL_GUEST_BOOT_COMPLETE: u8 = 123;
 
+/// Offset from `SIGRTMIN` of the signal used to kick vCPU threads out of `KVM_RUN`.
+pub const VCPU_RTSIG_OFFSET: i32 = 0;
+// How long the VMM thread waits for a vCPU response before kicking the vCPU again.
+const VCPU_RESPONSE_TIMEOUT_MS: u64 = 100;
+// How many times the VMM thread kicks a vCPU before giving up on receiving a response.
+const VCPU_RESPONSE_RETRIES: u32 = 10;
+
+// The vCPU and VM state ioctls which are not wrapped by `kvm_ioctls`.
+#[cfg(target_arch = "x86_64")]
+mod kvm_state_ioctls {
+    use kvm_bindings::*;
+
+    const KVMIO: ::std::os::raw::c_uint = 0xAE;
+
+    ioctl_iowr_nr!(KVM_GET_IRQCHIP, KVMIO, 0x62, kvm_irqchip);
+    ioctl_ior_nr!(KVM_SET_IRQCHIP, KVMIO, 0x63, kvm_irqchip);
+    ioctl_iow_nr!(KVM_SET_CLOCK, KVMIO, 0x7b, kvm_clock_data);
+    ioctl_ior_nr!(KVM_GET_CLOCK, KVMIO, 0x7c, kvm_clock_data);
+    ioctl_ior_nr!(KVM_GET_MP_STATE, KVMIO, 0x98, kvm_mp_state);
+    ioctl_iow_nr!(KVM_SET_MP_STATE, KVMIO, 0x99, kvm_mp_state);
+    ioctl_ior_nr!(KVM_GET_PIT2, KVMIO, 0x9f, kvm_pit_state2);
+    ioctl_iow_nr!(KVM_SET_PIT2, KVMIO, 0xa0, kvm_pit_state2);
+    ioctl_ior_nr!(KVM_GET_VCPU_EVENTS, KVMIO, 0x9f, kvm_vcpu_events);
+    ioctl_iow_nr!(KVM_SET_VCPU_EVENTS, KVMIO, 0xa0, kvm_vcpu_events);
+    ioctl_ior_nr!(KVM_GET_DEBUGREGS, KVMIO, 0xa1, kvm_debugregs);
+    ioctl_iow_nr!(KVM_SET_DEBUGREGS, KVMIO, 0xa2, kvm_debugregs);
+    ioctl_ior_nr!(KVM_GET_XSAVE, KVMIO, 0xa4, kvm_xsave);
+    ioctl_iow_nr!(KVM_SET_XSAVE, KVMIO, 0xa5, kvm_xsave);
+    ioctl_ior_nr!(KVM_GET_XCRS, KVMIO, 0xa6, kvm_xcrs);
+    ioctl_iow_nr!(KVM_SET_XCRS, KVMIO, 0xa7, kvm_xcrs);
+}
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
@@ -81,6 +128,29 @@ pub enum Error {
     VcpuSpawn(io::Error),
     /// Unexpected KVM_RUN exit reason
     VcpuUnhandledKvmExit,
+    /// Cannot register the signal handler used for kicking vCPUs out of KVM_RUN.
+    RegisterSignalHandler(io::Error),
+    /// Cannot send a signal to a vCPU thread.
+    SignalVcpu(io::Error),
+    /// The channel to or from a vCPU thread is disconnected.
+    VcpuChannel,
+    /// The vCPU thread did not respond in time.
+    VcpuResponseTimeout,
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot read the state of the vCPU.
+    SaveVcpuState(io::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot write the state of the vCPU.
+    RestoreVcpuState(io::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot read the state of the VM (interrupt controllers, PIT, clock).
+    SaveVmState(io::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot write the state of the VM (interrupt controllers, PIT, clock).
+    RestoreVmState(io::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// The saved state does not match the layout of the KVM structure it should be loaded into.
+    InvalidState,
     #[cfg(target_arch = "aarch64")]
     /// Error setting up the global interrupt controller.
     SetupGIC(arch::aarch64::gic::Error),
@@ -93,6 +163,104 @@ pub enum Error {
 }
 pub type Result<T> = result::Result<T, Error>;
 
+// Returns the raw bytes of a KVM structure, for storing it in a snapshot.
+#[cfg(target_arch = "x86_64")]
+fn kvm_struct_to_bytes<T: Copy>(value: &T) -> Vec<u8> {
+    // Safe because the KVM structures are plain old data, and the slice covers exactly the
+    // memory of `value`.
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+        .to_vec()
+}
+
+// Builds a KVM structure from raw bytes previously returned by `kvm_struct_to_bytes`.
+#[cfg(target_arch = "x86_64")]
+fn kvm_struct_from_bytes<T: Copy + Default>(bytes: &[u8]) -> Result<T> {
+    if bytes.len() != mem::size_of::<T>() {
+        return Err(Error::InvalidState);
+    }
+    let mut value = T::default();
+    // Safe because the KVM structures are plain old data and we checked that `bytes` has the
+    // size of `T`.
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            &mut value as *mut T as *mut u8,
+            mem::size_of::<T>(),
+        );
+    }
+    Ok(value)
+}
+
+// Issues a KVM ioctl which fills in `value`.
+#[cfg(target_arch = "x86_64")]
+fn kvm_get<F: AsRawFd, T>(fd: &F, req: libc::c_ulong, value: &mut T) -> io::Result<()> {
+    // Safe because the kernel writes at most `size_of::<T>()` bytes, as encoded in `req`, and
+    // we check the return value.
+    let ret = unsafe { ioctl_with_mut_ref(fd, req, value) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Issues a KVM ioctl which reads `value`.
+#[cfg(target_arch = "x86_64")]
+fn kvm_set<F: AsRawFd, T>(fd: &F, req: libc::c_ulong, value: &T) -> io::Result<()> {
+    // Safe because the kernel reads at most `size_of::<T>()` bytes, as encoded in `req`, and
+    // we check the return value.
+    let ret = unsafe { ioctl_with_ref(fd, req, value) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The state of a vCPU, as saved in a snapshot.
+///
+/// The KVM structures are stored as their raw bytes; the MSRs as (index, value) pairs.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VcpuState {
+    /// `kvm_regs`.
+    pub regs: Vec<u8>,
+    /// `kvm_sregs`.
+    pub sregs: Vec<u8>,
+    /// `kvm_fpu`.
+    pub fpu: Vec<u8>,
+    /// `kvm_lapic_state`.
+    pub lapic: Vec<u8>,
+    /// `kvm_xsave`.
+    pub xsave: Vec<u8>,
+    /// `kvm_xcrs`.
+    pub xcrs: Vec<u8>,
+    /// `kvm_vcpu_events`.
+    pub vcpu_events: Vec<u8>,
+    /// `kvm_mp_state`.
+    pub mp_state: Vec<u8>,
+    /// `kvm_debugregs`.
+    pub debugregs: Vec<u8>,
+    /// Model specific registers.
+    pub msrs: Vec<(u32, u64)>,
+}
+
+/// The state of the in-kernel devices of a VM, as saved in a snapshot.
+///
+/// The KVM structures are stored as their raw bytes.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VmState {
+    /// `kvm_irqchip` for the master PIC.
+    pub pic_master: Vec<u8>,
+    /// `kvm_irqchip` for the slave PIC.
+    pub pic_slave: Vec<u8>,
+    /// `kvm_irqchip` for the IOAPIC.
+    pub ioapic: Vec<u8>,
+    /// `kvm_pit_state2`.
+    pub pit: Vec<u8>,
+    /// `kvm_clock_data`.
+    pub clock: Vec<u8>,
+}
+
 /// A wrapper around creating and using a VM.
 pub struct Vm {
     fd: VmFd,
@@ -138,16 +306,41 @@ impl Vm {
         if guest_mem.num_regions() > kvm_context.max_memslots() {
             return Err(Error::NotEnoughMemorySlots);
         }
+        self.set_memory_regions(
+            &guest_mem,
+            LOGGER.flags() & LogOption::LogDirtyPages as usize > 0,
+        )?;
+        self.guest_mem = Some(guest_mem);
+
+        #[cfg(target_arch = "x86_64")]
+        self.fd
+            .set_tss_address(GuestAddress(arch::x86_64::layout::KVM_TSS_ADDRESS).offset())
+            .map_err(Error::VmSetup)?;
+
+        Ok(())
+    }
+
+    /// Turns the KVM dirty page tracking of the guest memory on or off. Does nothing if the guest
+    /// memory was not initialized yet, since `memory_init` follows the logger flags.
+    pub fn set_dirty_page_logging(&self, enabled: bool) -> Result<()> {
+        match self.guest_mem {
+            Some(ref guest_mem) => self.set_memory_regions(guest_mem, enabled),
+            None => Ok(()),
+        }
+    }
+
+    // Registers the regions of the guest memory with KVM, or updates the flags of the regions
+    // which are already registered.
+    fn set_memory_regions(&self, guest_mem: &GuestMemory, log_dirty_pages: bool) -> Result<()> {
+        let flags = if log_dirty_pages {
+            KVM_MEM_LOG_DIRTY_PAGES
+        } else {
+            0
+        };
         guest_mem
             .with_regions(|index, guest_addr, size, host_addr| {
                 info!("Guest memory starts at {:x?}", host_addr);
 
-                let flags = if LOGGER.flags() & LogOption::LogDirtyPages as usize > 0 {
-                    KVM_MEM_LOG_DIRTY_PAGES
-                } else {
-                    0
-                };
-
                 let memory_region = kvm_userspace_memory_region {
                     slot: index as u32,
                     guest_phys_addr: guest_addr.offset() as u64,
@@ -159,15 +352,7 @@ impl Vm {
                 // are not overlapping.
                 unsafe { self.fd.set_user_memory_region(memory_region) }
             })
-            .map_err(Error::SetUserMemoryRegion)?;
-        self.guest_mem = Some(guest_mem);
-
-        #[cfg(target_arch = "x86_64")]
-        self.fd
-            .set_tss_address(GuestAddress(arch::x86_64::layout::KVM_TSS_ADDRESS).offset())
-            .map_err(Error::VmSetup)?;
-
-        Ok(())
+            .map_err(Error::SetUserMemoryRegion)
     }
 
     /// Creates the irq chip and an in-kernel device model for the PIT.
@@ -202,6 +387,153 @@ impl Vm {
     pub fn get_fd(&self) -> &VmFd {
         &self.fd
     }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Saves the state of the in-kernel interrupt controllers, PIT and clock.
+    pub fn save_state(&self) -> Result<VmState> {
+        let get_irqchip = |chip_id| -> Result<Vec<u8>> {
+            let mut irqchip = kvm_irqchip {
+                chip_id,
+                ..Default::default()
+            };
+            kvm_get(&self.fd, kvm_state_ioctls::KVM_GET_IRQCHIP(), &mut irqchip)
+                .map_err(Error::SaveVmState)?;
+            Ok(kvm_struct_to_bytes(&irqchip))
+        };
+        let pic_master = get_irqchip(KVM_IRQCHIP_PIC_MASTER)?;
+        let pic_slave = get_irqchip(KVM_IRQCHIP_PIC_SLAVE)?;
+        let ioapic = get_irqchip(KVM_IRQCHIP_IOAPIC)?;
+
+        let mut pit = kvm_pit_state2::default();
+        kvm_get(&self.fd, kvm_state_ioctls::KVM_GET_PIT2(), &mut pit)
+            .map_err(Error::SaveVmState)?;
+        let mut clock = kvm_clock_data::default();
+        kvm_get(&self.fd, kvm_state_ioctls::KVM_GET_CLOCK(), &mut clock)
+            .map_err(Error::SaveVmState)?;
+
+        Ok(VmState {
+            pic_master,
+            pic_slave,
+            ioapic,
+            pit: kvm_struct_to_bytes(&pit),
+            clock: kvm_struct_to_bytes(&clock),
+        })
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Restores the state of the in-kernel interrupt controllers, PIT and clock.
+    ///
+    /// The interrupt controllers and the PIT need to be created beforehand.
+    pub fn restore_state(&self, state: &VmState) -> Result<()> {
+        for bytes in &[&state.pic_master, &state.pic_slave, &state.ioapic] {
+            let irqchip: kvm_irqchip = kvm_struct_from_bytes(bytes)?;
+            kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_IRQCHIP(), &irqchip)
+                .map_err(Error::RestoreVmState)?;
+        }
+
+        let pit: kvm_pit_state2 = kvm_struct_from_bytes(&state.pit)?;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_PIT2(), &pit).map_err(Error::RestoreVmState)?;
+
+        // The clock goes last so that the guest loses as little time as possible. KVM rejects
+        // any flags on `KVM_SET_CLOCK`, while `KVM_GET_CLOCK` may report some.
+        let mut clock: kvm_clock_data = kvm_struct_from_bytes(&state.clock)?;
+        clock.flags = 0;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_CLOCK(), &clock).map_err(Error::RestoreVmState)
+    }
+}
+
+/// Events sent by the VMM thread to a running vCPU thread.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VcpuEvent {
+    /// Stop running guest code until a `Resume` event is received.
+    Pause,
+    /// Resume running guest code.
+    Resume,
+    #[cfg(target_arch = "x86_64")]
+    /// Save the vCPU state. Only handled while the vCPU is paused.
+    SaveState,
+}
+
+/// Responses sent by a vCPU thread after handling a `VcpuEvent`.
+#[derive(Debug, PartialEq)]
+pub enum VcpuResponse {
+    /// The vCPU thread is parked and no longer runs guest code.
+    Paused,
+    /// The vCPU thread is running guest code again.
+    Resumed,
+    #[cfg(target_arch = "x86_64")]
+    /// The state of the paused vCPU.
+    SavedState(Box<VcpuState>),
+    #[cfg(target_arch = "x86_64")]
+    /// The vCPU state could not be saved, either because the vCPU is not paused or because a
+    /// KVM call failed.
+    SaveStateFailed,
+}
+
+extern "C" fn handle_vcpu_signal(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {}
+
+/// Registers the (no-op) signal handler used for kicking vCPU threads out of `KVM_RUN`.
+///
+/// Without a handler, the default action of a real-time signal would terminate the process.
+pub fn register_vcpu_kick_handler() -> Result<()> {
+    // This is safe because the handler does not touch any state and is async-signal-safe.
+    unsafe { register_vcpu_signal_handler(VCPU_RTSIG_OFFSET, handle_vcpu_signal) }
+        .map_err(Error::RegisterSignalHandler)
+}
+
+/// Handle used by the VMM thread for controlling a running vCPU thread.
+pub struct VcpuHandle {
+    event_sender: Sender<VcpuEvent>,
+    response_receiver: Receiver<VcpuResponse>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl VcpuHandle {
+    /// Creates a handle from the VMM ends of the vCPU channels and the vCPU thread handle.
+    pub fn new(
+        event_sender: Sender<VcpuEvent>,
+        response_receiver: Receiver<VcpuResponse>,
+        thread: thread::JoinHandle<()>,
+    ) -> Self {
+        VcpuHandle {
+            event_sender,
+            response_receiver,
+            thread,
+        }
+    }
+
+    /// Sends `event` to the vCPU thread and kicks it out of `KVM_RUN` so that the event gets
+    /// handled.
+    pub fn send_event(&self, event: VcpuEvent) -> Result<()> {
+        self.event_sender
+            .send(event)
+            .map_err(|_| Error::VcpuChannel)?;
+        self.thread
+            .kill(VCPU_RTSIG_OFFSET)
+            .map_err(Error::SignalVcpu)
+    }
+
+    /// Waits for the vCPU thread to respond to a previously sent event.
+    ///
+    /// The kick signal can be consumed right before the vCPU thread enters `KVM_RUN`, in which
+    /// case the event is only noticed on the next VM exit. To cover this, the vCPU is kicked
+    /// again each time waiting for the response times out.
+    pub fn wait_response(&self) -> Result<VcpuResponse> {
+        for _ in 0..VCPU_RESPONSE_RETRIES {
+            match self
+                .response_receiver
+                .recv_timeout(Duration::from_millis(VCPU_RESPONSE_TIMEOUT_MS))
+            {
+                Ok(response) => return Ok(response),
+                Err(RecvTimeoutError::Timeout) => self
+                    .thread
+                    .kill(VCPU_RTSIG_OFFSET)
+                    .map_err(Error::SignalVcpu)?,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::VcpuChannel),
+            }
+        }
+        Err(Error::VcpuResponseTimeout)
+    }
 }
 
 /// A wrapper around creating and using a kvm-based VCPU.
@@ -255,6 +587,24 @@ impl Vcpu {
         kernel_start_addr: GuestAddress,
         vm: &Vm,
     ) -> Result<()> {
+        self.configure_cpuid(machine_config)?;
+
+        arch::x86_64::regs::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+        // Safe to unwrap because this method is called after the VM is configured
+        let vm_memory = vm
+            .get_memory()
+            .ok_or(Error::GuestMemory(GuestMemoryError::MemoryNotInitialized))?;
+        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.offset() as u64)
+            .map_err(Error::REGSConfiguration)?;
+        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
+        arch::x86_64::regs::setup_sregs(vm_memory, &self.fd).map_err(Error::SREGSConfiguration)?;
+        arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Filters the CPUID according to `machine_config` and sets it on the vCPU.
+    fn configure_cpuid(&mut self, machine_config: &VmConfig) -> Result<()> {
         let cpuid_vm_spec = VmSpec::new(
             self.id,
             machine_config
@@ -283,19 +633,114 @@ impl Vcpu {
 
         self.fd
             .set_cpuid2(&self.cpuid)
-            .map_err(Error::SetSupportedCpusFailed)?;
+            .map_err(Error::SetSupportedCpusFailed)
+    }
 
-        arch::x86_64::regs::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
-        // Safe to unwrap because this method is called after the VM is configured
-        let vm_memory = vm
-            .get_memory()
-            .ok_or(Error::GuestMemory(GuestMemoryError::MemoryNotInitialized))?;
-        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.offset() as u64)
-            .map_err(Error::REGSConfiguration)?;
-        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
-        arch::x86_64::regs::setup_sregs(vm_memory, &self.fd).map_err(Error::SREGSConfiguration)?;
-        arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
-        Ok(())
+    #[cfg(target_arch = "x86_64")]
+    /// Saves the registers, MSRs, LAPIC and other in-kernel state of the vcpu.
+    ///
+    /// The vcpu must not be running guest code while this is called.
+    pub fn save_state(&self) -> Result<VcpuState> {
+        let regs = self.fd.get_regs().map_err(Error::SaveVcpuState)?;
+        let sregs = self.fd.get_sregs().map_err(Error::SaveVcpuState)?;
+        let fpu = self.fd.get_fpu().map_err(Error::SaveVcpuState)?;
+        let lapic = self.fd.get_lapic().map_err(Error::SaveVcpuState)?;
+        let msrs = arch::x86_64::regs::get_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+
+        let mut xsave = kvm_xsave::default();
+        kvm_get(&self.fd, kvm_state_ioctls::KVM_GET_XSAVE(), &mut xsave)
+            .map_err(Error::SaveVcpuState)?;
+        let mut xcrs = kvm_xcrs::default();
+        kvm_get(&self.fd, kvm_state_ioctls::KVM_GET_XCRS(), &mut xcrs)
+            .map_err(Error::SaveVcpuState)?;
+        let mut vcpu_events = kvm_vcpu_events::default();
+        kvm_get(
+            &self.fd,
+            kvm_state_ioctls::KVM_GET_VCPU_EVENTS(),
+            &mut vcpu_events,
+        )
+        .map_err(Error::SaveVcpuState)?;
+        let mut mp_state = kvm_mp_state::default();
+        kvm_get(
+            &self.fd,
+            kvm_state_ioctls::KVM_GET_MP_STATE(),
+            &mut mp_state,
+        )
+        .map_err(Error::SaveVcpuState)?;
+        let mut debugregs = kvm_debugregs::default();
+        kvm_get(
+            &self.fd,
+            kvm_state_ioctls::KVM_GET_DEBUGREGS(),
+            &mut debugregs,
+        )
+        .map_err(Error::SaveVcpuState)?;
+
+        Ok(VcpuState {
+            regs: kvm_struct_to_bytes(&regs),
+            sregs: kvm_struct_to_bytes(&sregs),
+            fpu: kvm_struct_to_bytes(&fpu),
+            lapic: kvm_struct_to_bytes(&lapic),
+            xsave: kvm_struct_to_bytes(&xsave),
+            xcrs: kvm_struct_to_bytes(&xcrs),
+            vcpu_events: kvm_struct_to_bytes(&vcpu_events),
+            mp_state: kvm_struct_to_bytes(&mp_state),
+            debugregs: kvm_struct_to_bytes(&debugregs),
+            msrs: msrs.iter().map(|entry| (entry.index, entry.data)).collect(),
+        })
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Configures the vcpu from a previously saved state, instead of for booting a kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `machine_config` - Specifies necessary info used for the CPUID configuration.
+    /// * `state` - The state returned by `save_state`.
+    pub fn restore_state(&mut self, machine_config: &VmConfig, state: &VcpuState) -> Result<()> {
+        self.configure_cpuid(machine_config)?;
+
+        // The order matters: the special registers depend on the XSAVE state and the XCRs,
+        // and the LAPIC has to be restored before the MSRs (for the TSC deadline timer) and
+        // before the pending events.
+        let regs = kvm_struct_from_bytes(&state.regs)?;
+        self.fd.set_regs(&regs).map_err(Error::RestoreVcpuState)?;
+        let xsave: kvm_xsave = kvm_struct_from_bytes(&state.xsave)?;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_XSAVE(), &xsave)
+            .map_err(Error::RestoreVcpuState)?;
+        let fpu = kvm_struct_from_bytes(&state.fpu)?;
+        self.fd.set_fpu(&fpu).map_err(Error::RestoreVcpuState)?;
+        let xcrs: kvm_xcrs = kvm_struct_from_bytes(&state.xcrs)?;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_XCRS(), &xcrs)
+            .map_err(Error::RestoreVcpuState)?;
+        let sregs = kvm_struct_from_bytes(&state.sregs)?;
+        self.fd.set_sregs(&sregs).map_err(Error::RestoreVcpuState)?;
+        let mp_state: kvm_mp_state = kvm_struct_from_bytes(&state.mp_state)?;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_MP_STATE(), &mp_state)
+            .map_err(Error::RestoreVcpuState)?;
+        let lapic = kvm_struct_from_bytes(&state.lapic)?;
+        self.fd.set_lapic(&lapic).map_err(Error::RestoreVcpuState)?;
+
+        let msrs: Vec<kvm_msr_entry> = state
+            .msrs
+            .iter()
+            .map(|&(index, data)| kvm_msr_entry {
+                index,
+                data,
+                ..Default::default()
+            })
+            .collect();
+        arch::x86_64::regs::set_msrs(&self.fd, &msrs).map_err(Error::MSRSConfiguration)?;
+
+        let vcpu_events: kvm_vcpu_events = kvm_struct_from_bytes(&state.vcpu_events)?;
+        kvm_set(
+            &self.fd,
+            kvm_state_ioctls::KVM_SET_VCPU_EVENTS(),
+            &vcpu_events,
+        )
+        .map_err(Error::RestoreVcpuState)?;
+        let debugregs: kvm_debugregs = kvm_struct_from_bytes(&state.debugregs)?;
+        kvm_set(&self.fd, kvm_state_ioctls::KVM_SET_DEBUGREGS(), &debugregs)
+            .map_err(Error::RestoreVcpuState)
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -420,11 +865,50 @@ impl Vcpu {
         }
     }
 
+    // Parks the vCPU thread until a `Resume` event is received. Returns `false` if the VMM end
+    // of the channels is gone, in which case the vCPU thread should exit.
+    fn paused(
+        &self,
+        event_receiver: &Receiver<VcpuEvent>,
+        response_sender: &Sender<VcpuResponse>,
+    ) -> bool {
+        if response_sender.send(VcpuResponse::Paused).is_err() {
+            return false;
+        }
+        loop {
+            match event_receiver.recv() {
+                Ok(VcpuEvent::Resume) => {
+                    return response_sender.send(VcpuResponse::Resumed).is_ok();
+                }
+                Ok(VcpuEvent::Pause) => {
+                    if response_sender.send(VcpuResponse::Paused).is_err() {
+                        return false;
+                    }
+                }
+                #[cfg(target_arch = "x86_64")]
+                Ok(VcpuEvent::SaveState) => {
+                    let response = match self.save_state() {
+                        Ok(state) => VcpuResponse::SavedState(Box::new(state)),
+                        Err(e) => {
+                            error!("Failed to save the state of vcpu {}: {:?}", self.id, e);
+                            VcpuResponse::SaveStateFailed
+                        }
+                    };
+                    if response_sender.send(response).is_err() {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+    }
+
     /// Main loop of the vCPU thread.
     ///
     ///
     /// Runs the vCPU in KVM context in a loop. Handles KVM_EXITs then goes back in.
-    /// Also registers a signal handler to be able to kick this thread out of KVM_RUN.
+    /// After each exit, checks for events sent by the VMM thread on `event_receiver`; the VMM
+    /// kicks this thread out of KVM_RUN with a signal when it sends one.
     /// Note that the state of the VCPU and associated VM must be setup first for this to do
     /// anything useful.
     pub fn run(
@@ -432,6 +916,8 @@ impl Vcpu {
         thread_barrier: Arc<Barrier>,
         seccomp_level: u32,
         vcpu_exit_evt: EventFd,
+        event_receiver: Receiver<VcpuEvent>,
+        response_sender: Sender<VcpuResponse>,
     ) {
         // Load seccomp filters for this vCPU thread.
         // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
@@ -445,7 +931,29 @@ impl Vcpu {
 
         thread_barrier.wait();
 
-        while self.run_emulation().is_ok() {}
+        while self.run_emulation().is_ok() {
+            match event_receiver.try_recv() {
+                Ok(VcpuEvent::Pause) => {
+                    if !self.paused(&event_receiver, &response_sender) {
+                        break;
+                    }
+                }
+                // Already running; just acknowledge.
+                Ok(VcpuEvent::Resume) => {
+                    if response_sender.send(VcpuResponse::Resumed).is_err() {
+                        break;
+                    }
+                }
+                // The state of a running vCPU would be stale by the time it gets used.
+                #[cfg(target_arch = "x86_64")]
+                Ok(VcpuEvent::SaveState) => {
+                    if response_sender.send(VcpuResponse::SaveStateFailed).is_err() {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+        }
 
         // Nothing we need do for the success case.
         if let Err(e) = vcpu_exit_evt.write(1) {
@@ -460,6 +968,8 @@ mod tests {
     use super::super::devices;
     use super::*;
 
+    use std::sync::mpsc::channel;
+
     // Auxiliary function being used throughout the tests.
     fn setup_vcpu() -> (Vm, Vcpu) {
         let kvm = KvmContext::new().unwrap();
@@ -618,15 +1128,148 @@ mod tests {
         assert!(vcpu.configure(&vm_config, GuestAddress(0), &vm).is_ok());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_kvm_state_ioctl_numbers() {
+        // These have to match the values allowed by the seccomp filters.
+        assert_eq!(kvm_state_ioctls::KVM_GET_IRQCHIP(), 0xc208_ae62);
+        assert_eq!(kvm_state_ioctls::KVM_GET_CLOCK(), 0x8030_ae7c);
+        assert_eq!(kvm_state_ioctls::KVM_GET_MP_STATE(), 0x8004_ae98);
+        assert_eq!(kvm_state_ioctls::KVM_GET_PIT2(), 0x8070_ae9f);
+        assert_eq!(kvm_state_ioctls::KVM_GET_VCPU_EVENTS(), 0x8040_ae9f);
+        assert_eq!(kvm_state_ioctls::KVM_GET_DEBUGREGS(), 0x8080_aea1);
+        assert_eq!(kvm_state_ioctls::KVM_GET_XSAVE(), 0x9000_aea4);
+        assert_eq!(kvm_state_ioctls::KVM_GET_XCRS(), 0x8188_aea6);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vcpu_save_restore_state() {
+        let (vm, mut vcpu) = setup_vcpu();
+        let vm_config = VmConfig::default();
+        assert!(vcpu.configure(&vm_config, GuestAddress(0), &vm).is_ok());
+
+        let mut state = vcpu.save_state().unwrap();
+        assert_eq!(state.regs.len(), mem::size_of::<kvm_bindings::kvm_regs>());
+        assert_eq!(state.xsave.len(), mem::size_of::<kvm_xsave>());
+        assert!(!state.msrs.is_empty());
+
+        // Restore a modified state onto a new vCPU and read it back.
+        let mut regs: kvm_bindings::kvm_regs = kvm_struct_from_bytes(&state.regs).unwrap();
+        regs.rip = 0x1234;
+        state.regs = kvm_struct_to_bytes(&regs);
+        let mut other_vcpu = Vcpu::new(
+            0,
+            &vm,
+            devices::Bus::new(),
+            super::super::TimestampUs::default(),
+        )
+        .unwrap();
+        assert!(other_vcpu.restore_state(&vm_config, &state).is_ok());
+        let restored = other_vcpu.save_state().unwrap();
+        assert_eq!(restored.regs, state.regs);
+        assert_eq!(restored.sregs, state.sregs);
+        assert_eq!(restored.mp_state, state.mp_state);
+
+        // A state with the wrong layout is rejected.
+        state.regs.pop();
+        match other_vcpu.restore_state(&vm_config, &state) {
+            Err(Error::InvalidState) => (),
+            _ => panic!("Expected an invalid state error."),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vm_save_restore_state() {
+        let (vm, _) = setup_vcpu();
+        let mut state = vm.save_state().unwrap();
+        assert_eq!(state.ioapic.len(), mem::size_of::<kvm_irqchip>());
+        assert!(vm.restore_state(&state).is_ok());
+
+        let ioapic: kvm_irqchip = kvm_struct_from_bytes(&state.ioapic).unwrap();
+        assert_eq!(ioapic.chip_id, KVM_IRQCHIP_IOAPIC);
+
+        state.clock.clear();
+        match vm.restore_state(&state) {
+            Err(Error::InvalidState) => (),
+            _ => panic!("Expected an invalid state error."),
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_vcpu_run_failed() {
         let (_, mut vcpu) = setup_vcpu();
+        let (_event_sender, event_receiver) = channel();
+        let (response_sender, _response_receiver) = channel();
         // Setting an invalid seccomp level should panic.
         vcpu.run(
             Arc::new(Barrier::new(1)),
             seccomp::SECCOMP_LEVEL_ADVANCED + 10,
             EventFd::new().unwrap(),
+            event_receiver,
+            response_sender,
         );
     }
+
+    #[test]
+    fn test_vcpu_paused() {
+        let (_, vcpu) = setup_vcpu();
+        let (event_sender, event_receiver) = channel();
+        let (response_sender, response_receiver) = channel();
+
+        // A repeated `Pause` is acknowledged and `Resume` unparks the vCPU.
+        event_sender.send(VcpuEvent::Pause).unwrap();
+        event_sender.send(VcpuEvent::Resume).unwrap();
+        assert!(vcpu.paused(&event_receiver, &response_sender));
+        assert_eq!(response_receiver.try_recv().unwrap(), VcpuResponse::Paused);
+        assert_eq!(response_receiver.try_recv().unwrap(), VcpuResponse::Paused);
+        assert_eq!(response_receiver.try_recv().unwrap(), VcpuResponse::Resumed);
+
+        // The state of a paused vCPU can be saved.
+        #[cfg(target_arch = "x86_64")]
+        {
+            event_sender.send(VcpuEvent::SaveState).unwrap();
+            event_sender.send(VcpuEvent::Resume).unwrap();
+            assert!(vcpu.paused(&event_receiver, &response_sender));
+            assert_eq!(response_receiver.try_recv().unwrap(), VcpuResponse::Paused);
+            match response_receiver.try_recv().unwrap() {
+                VcpuResponse::SavedState(state) => {
+                    assert_eq!(state.regs, vcpu.save_state().unwrap().regs)
+                }
+                _ => panic!("Expected the saved vCPU state."),
+            }
+            assert_eq!(response_receiver.try_recv().unwrap(), VcpuResponse::Resumed);
+        }
+
+        // The vCPU gives up when the VMM end of the channel is gone.
+        drop(event_sender);
+        assert!(!vcpu.paused(&event_receiver, &response_sender));
+    }
+
+    #[test]
+    fn test_vcpu_handle() {
+        assert!(register_vcpu_kick_handler().is_ok());
+
+        let (event_sender, event_receiver) = channel();
+        let (response_sender, response_receiver) = channel();
+        let thread = thread::spawn(move || {
+            while let Ok(event) = event_receiver.recv() {
+                let response = match event {
+                    VcpuEvent::Pause => VcpuResponse::Paused,
+                    VcpuEvent::Resume => VcpuResponse::Resumed,
+                    #[cfg(target_arch = "x86_64")]
+                    VcpuEvent::SaveState => VcpuResponse::SaveStateFailed,
+                };
+                response_sender.send(response).unwrap();
+            }
+        });
+        let handle = VcpuHandle::new(event_sender, response_receiver, thread);
+
+        assert!(handle.send_event(VcpuEvent::Pause).is_ok());
+        assert_eq!(handle.wait_response().unwrap(), VcpuResponse::Paused);
+        assert!(handle.send_event(VcpuEvent::Resume).is_ok());
+        assert_eq!(handle.wait_response().unwrap(), VcpuResponse::Resumed);
+    }
 }